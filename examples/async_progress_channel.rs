@@ -0,0 +1,54 @@
+//! Forwards download progress over a tokio channel to a separate task, as a GUI would.
+use async_trait::async_trait;
+use models_cat::asynchronous::{ModelsCat, Progress, ProgressEvent, ProgressUnit, Repo};
+use models_cat::utils::OpsError;
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+#[derive(Clone)]
+struct ChannelProgress(UnboundedSender<ProgressEvent>);
+
+impl ChannelProgress {
+    fn send(&self, event: ProgressEvent) -> Result<(), OpsError> {
+        self.0
+            .send(event)
+            .map_err(|_| OpsError::HubError("progress receiver dropped".into()))
+    }
+}
+
+#[async_trait]
+impl Progress for ChannelProgress {
+    async fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Start(unit.clone()))
+    }
+
+    async fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Progress(unit.clone()))
+    }
+
+    async fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Finish(unit.clone()))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let (tx, mut rx) = mpsc::unbounded_channel::<ProgressEvent>();
+    let ui = tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let unit = event.unit();
+            println!(
+                "{}: {}/{}",
+                unit.filename(),
+                unit.current(),
+                unit.total_size()
+                    .map_or("?".to_string(), |total| total.to_string())
+            );
+        }
+    });
+
+    let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
+    cat.download_with_progress("config.json", ChannelProgress(tx))
+        .await
+        .unwrap();
+    ui.await.unwrap();
+}
@@ -0,0 +1,19 @@
+//! Pulls a dataset, then scans its parquet files with polars.
+//!
+//! Run with `cargo run --example dataset_to_polars --features polars`.
+use models_cat::hub::{ModelsCat, MultiProgressWrapper};
+use models_cat::repo::Repo;
+use polars::prelude::*;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cat = ModelsCat::new(Repo::new_dataset("DAMO_NLP/yf_dianping"));
+    cat.pull_with_progress(MultiProgressWrapper::default())?;
+
+    let paths = cat.local_files_matching("**/*.parquet")?;
+    println!("found {} parquet files", paths.len());
+    let frame = LazyFrame::scan_parquet_files(paths.into(), ScanArgsParquet::default())?
+        .limit(5)
+        .collect()?;
+    println!("{frame}");
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! Rejects downloads whose leading bytes do not match their extension, before they enter the
+//! cache.
+use models_cat::hub::{HubFileInfo, ModelsCat, ProgressBarWrapper, ValidationError, Validator};
+use models_cat::repo::Repo;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+struct MagicBytes;
+
+impl Validator for MagicBytes {
+    fn validate(&self, temp_path: &Path, info: &HubFileInfo) -> Result<(), ValidationError> {
+        let mut head = [0u8; 9];
+        let mut file = File::open(temp_path).map_err(|e| ValidationError::new(e.to_string()))?;
+        let len = file
+            .read(&mut head)
+            .map_err(|e| ValidationError::new(e.to_string()))?;
+        let head = &head[..len];
+        let valid = match Path::new(&info.path).extension().and_then(|e| e.to_str()) {
+            // A little-endian header length, then the JSON header.
+            Some("safetensors") => head.get(8) == Some(&b'{'),
+            Some("gguf") => head.starts_with(b"GGUF"),
+            // PyTorch checkpoints are zip archives.
+            Some("pt" | "pth" | "zip") => head.starts_with(b"PK\x03\x04"),
+            Some("png") => head.starts_with(b"\x89PNG"),
+            _ => true,
+        };
+        match valid {
+            true => Ok(()),
+            false => Err(ValidationError::new(format!(
+                "content does not look like a {} file",
+                info.path
+            ))),
+        }
+    }
+}
+
+fn main() {
+    let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5")).with_validator(MagicBytes);
+    cat.download_with_progress("model.safetensors", ProgressBarWrapper::default())
+        .unwrap();
+}
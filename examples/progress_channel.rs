@@ -0,0 +1,57 @@
+//! Forwards download progress over a channel to a separate thread, as a GUI would.
+use models_cat::hub::{ModelsCat, Progress, ProgressEvent, ProgressUnit};
+use models_cat::repo::Repo;
+use models_cat::utils::OpsError;
+use std::sync::mpsc::{self, Sender};
+
+#[derive(Clone)]
+struct ChannelProgress(Sender<ProgressEvent>);
+
+impl ChannelProgress {
+    fn send(&self, event: ProgressEvent) -> Result<(), OpsError> {
+        self.0
+            .send(event)
+            .map_err(|_| OpsError::HubError("progress receiver dropped".into()))
+    }
+}
+
+impl Progress for ChannelProgress {
+    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Start(unit.clone()))
+    }
+
+    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Progress(unit.clone()))
+    }
+
+    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.send(ProgressEvent::Finish(unit.clone()))
+    }
+}
+
+fn main() {
+    let (tx, rx) = mpsc::channel::<ProgressEvent>();
+    let ui = std::thread::spawn(move || {
+        for event in rx {
+            let unit = event.unit();
+            match event {
+                ProgressEvent::Start(_) => println!("{}: started", unit.filename()),
+                ProgressEvent::Progress(_) => {
+                    println!(
+                        "{}: {}/{}",
+                        unit.filename(),
+                        unit.current(),
+                        unit.total_size()
+                            .map_or("?".to_string(), |total| total.to_string())
+                    )
+                }
+                ProgressEvent::Finish(_) => println!("{}: done", unit.filename()),
+            }
+        }
+    });
+
+    let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
+    cat.download_with_progress("config.json", ChannelProgress(tx))
+        .unwrap();
+    ui.join().unwrap();
+}
@@ -0,0 +1,49 @@
+//! Serves a model while checking for new revisions in the background, switching to each one
+//! only once it is fully downloaded.
+use models_cat::hub::ModelsCat;
+use models_cat::repo::Repo;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+/// Stands in for a loaded model.
+struct Model {
+    dir: PathBuf,
+}
+
+fn load(dir: &Path) -> Model {
+    Model {
+        dir: dir.to_path_buf(),
+    }
+}
+
+fn main() {
+    let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
+    cat.pull().unwrap();
+    let latest = cat.repo().latest_snapshot().unwrap();
+    let model = Arc::new(RwLock::new(load(&latest)));
+
+    let serving = model.clone();
+    thread::spawn(move || {
+        loop {
+            println!("serving from {}", serving.read().unwrap().dir.display());
+            thread::sleep(Duration::from_secs(10));
+        }
+    });
+
+    loop {
+        thread::sleep(Duration::from_secs(600));
+        match cat.prepare_update() {
+            Ok(Some(prepared)) => {
+                // Load before switching, so requests are served by the old model meanwhile.
+                let next = load(&prepared.path());
+                let dir = prepared.activate().unwrap();
+                *model.write().unwrap() = next;
+                println!("switched to {}", dir.display());
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("update failed, still serving the current revision: {e}"),
+        }
+    }
+}
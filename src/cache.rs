@@ -0,0 +1,255 @@
+//! Discovery of the repos present in a local cache, for tools listing installed models.
+use crate::diagnostics::{dir_size, read_refs};
+use crate::repo::{self, RepoType};
+use crate::utils::OpsError;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// A repo found in the local cache.
+#[derive(Debug, Clone, Serialize)]
+pub struct CachedRepo {
+    /// The repository type.
+    pub repo_type: RepoType,
+    /// The repository ID, such as `BAAI/bge-small-zh-v1.5`.
+    pub repo_id: String,
+    /// The repository cache directory.
+    pub cache_dir: PathBuf,
+    /// Names of the snapshot directories, sorted.
+    pub snapshots: Vec<String>,
+    /// Number of files across all snapshots.
+    pub files: usize,
+    /// Refs found in the cache, mapping each revision name to its commit hash.
+    pub refs: BTreeMap<String, String>,
+    /// Total size in bytes of everything under the repository cache directory.
+    pub size: u64,
+}
+
+/// Everything in a cache directory, returned by [`scan_cache`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheReport {
+    /// The cache directory scanned.
+    pub cache_dir: PathBuf,
+    /// The repos found, sorted by type and ID.
+    pub repos: Vec<CachedRepo>,
+    /// Total size in bytes of the repos.
+    pub size: u64,
+}
+
+/// Scans `cache_dir`, or the default cache directory, for the repos it holds and how much disk
+/// they use, see [`list_cached_repos`].
+///
+/// ```no_run
+/// let report = models_cat::scan_cache(None)?;
+/// for repo in &report.repos {
+///     println!("{:>12} {}", repo.size, repo.repo_id);
+/// }
+/// println!("{:>12} total", report.size);
+/// # Ok::<(), models_cat::OpsError>(())
+/// ```
+pub fn scan_cache(cache_dir: Option<&Path>) -> Result<CacheReport, OpsError> {
+    let cache_dir = cache_dir.map_or_else(repo::default_cache_dir, Path::to_path_buf);
+    let repos = list_cached_repos(Some(&cache_dir))?;
+    Ok(CacheReport {
+        size: repos.iter().map(|r| r.size).sum(),
+        cache_dir,
+        repos,
+    })
+}
+
+/// Lists the repos in `cache_dir`, or in the default cache directory, sorted by type and ID,
+/// each with its total size, as a `du` of the cache would.
+///
+/// Directories named by a custom cache key (see [`crate::ModelsCat::with_cache_key`]) cannot
+/// be mapped back to a repo ID and are left out, as are repo directories whose snapshots
+/// cannot be read, such as a `snapshots` that is a file.
+pub fn list_cached_repos(cache_dir: Option<&Path>) -> Result<Vec<CachedRepo>, OpsError> {
+    let cache_dir = cache_dir.map_or_else(repo::default_cache_dir, Path::to_path_buf);
+    if !cache_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut repos = Vec::new();
+    for entry in std::fs::read_dir(&cache_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let Some((repo_type, repo_id)) = entry
+            .file_name()
+            .to_str()
+            .and_then(repo::parse_cache_dir_name)
+        else {
+            continue;
+        };
+        let dir = entry.path();
+        let snapshots = match snapshot_names(&dir.join("snapshots")) {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                log::warn!("skipping malformed repo cache {}: {e}", dir.display());
+                continue;
+            }
+        };
+        repos.push(CachedRepo {
+            repo_type,
+            repo_id,
+            files: snapshot_file_count(&dir.join("snapshots")),
+            snapshots,
+            refs: read_refs(&dir.join("refs")),
+            size: dir_size(&dir),
+            cache_dir: dir,
+        });
+    }
+    repos.sort_by(|a, b| {
+        (a.repo_type.to_path_part(), &a.repo_id).cmp(&(b.repo_type.to_path_part(), &b.repo_id))
+    });
+    Ok(repos)
+}
+
+/// Entries a repo cache directory may hold besides lock files.
+const REPO_CACHE_ENTRIES: [&str; 11] = [
+    "snapshots",
+    "blobs",
+    "refs",
+    "latest",
+    "latest.path",
+    "complete",
+    "sanitized",
+    "resolved",
+    "provenance",
+    "history.jsonl",
+    "history.jsonl.1",
+];
+
+/// Checks that `dir` looks like a repo cache directory before it is deleted: it must either be
+/// named like one (`models--owner--name`) or hold nothing but the entries the cache creates.
+///
+/// Guards against deleting an unrelated directory, such as the home directory, after a
+/// misconfigured cache directory or cache key.
+pub(crate) fn check_repo_cache_dir(dir: &Path) -> Result<(), OpsError> {
+    let named_like_repo = dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(repo::parse_cache_dir_name)
+        .is_some();
+    if named_like_repo || !dir.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        let known = REPO_CACHE_ENTRIES.contains(&name.as_ref())
+            || name.ends_with(".lock")
+            || (name.starts_with("latest.") && name.ends_with(".tmp"));
+        if !known {
+            return Err(OpsError::RefusingUnsafeDelete(dir.to_path_buf()));
+        }
+    }
+    Ok(())
+}
+
+/// Total size in bytes of everything under `dir`, or 0 when it does not exist.
+pub(crate) fn size_on_disk(dir: &Path) -> Result<u64, OpsError> {
+    if !dir.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(dir) {
+        let entry = entry.map_err(std::io::Error::from)?;
+        if !entry.file_type().is_dir() {
+            size += entry.metadata().map_err(std::io::Error::from)?.len();
+        }
+    }
+    Ok(size)
+}
+
+fn snapshot_file_count(snapshots_dir: &Path) -> usize {
+    walkdir::WalkDir::new(snapshots_dir)
+        .min_depth(2)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .count()
+}
+
+fn snapshot_names(snapshots_dir: &Path) -> Result<Vec<String>, OpsError> {
+    if !snapshots_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(snapshots_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            names.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::Repo;
+
+    #[test]
+    fn test_list_cached_repos() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut model = Repo::new_model("BAAI/bge--small");
+        model.set_cache_dir(cache.path());
+        let snapshot = model.snapshot_path("rev1");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        std::fs::write(snapshot.join("a.txt"), "aaa").unwrap();
+        model.set_revision("v1");
+        model.create_ref("rev1").unwrap();
+        let mut dataset = Repo::new_dataset("DAMO_NLP/yf_dianping");
+        dataset.set_cache_dir(cache.path());
+        std::fs::create_dir_all(dataset.cache_dir()).unwrap();
+        // Neither repos nor repo directories.
+        std::fs::create_dir(cache.path().join("custom-key")).unwrap();
+        std::fs::write(cache.path().join("models--a--b"), "").unwrap();
+        let malformed = cache.path().join("models--a--c");
+        std::fs::create_dir(&malformed).unwrap();
+        std::fs::write(malformed.join("snapshots"), "").unwrap();
+
+        let repos = list_cached_repos(Some(cache.path())).unwrap();
+        assert_eq!(repos.len(), 2);
+        assert_eq!(repos[0].repo_type, RepoType::Dataset);
+        assert_eq!(repos[0].repo_id, "DAMO_NLP/yf_dianping");
+        assert!(repos[0].snapshots.is_empty());
+        assert_eq!(repos[1].repo_id, "BAAI/bge--small");
+        assert_eq!(repos[1].snapshots, ["rev1"]);
+        assert_eq!(repos[1].refs.get("v1").map(String::as_str), Some("rev1"));
+        assert_eq!(repos[1].files, 1);
+        assert_eq!(repos[1].size, 3 + 4);
+
+        let report = scan_cache(Some(cache.path())).unwrap();
+        assert_eq!(report.repos.len(), 2);
+        assert_eq!(report.size, 3 + 4);
+        assert_eq!(size_on_disk(&model.cache_dir()).unwrap(), 3 + 4);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["repos"][0]["repo_type"], "dataset");
+
+        let missing = cache.path().join("missing");
+        assert!(list_cached_repos(Some(&missing)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_repo_cache_dir() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        repo.set_cache_key("custom").unwrap();
+        std::fs::create_dir_all(repo.snapshot_path("rev1")).unwrap();
+        std::fs::write(repo.cache_dir().join("latest.lock"), "").unwrap();
+        assert!(check_repo_cache_dir(&repo.cache_dir()).is_ok());
+
+        // A decoy directory holding unrelated files.
+        let decoy = cache.path().join("home");
+        std::fs::create_dir_all(decoy.join("snapshots")).unwrap();
+        std::fs::write(decoy.join(".bashrc"), "").unwrap();
+        assert!(matches!(
+            check_repo_cache_dir(&decoy),
+            Err(OpsError::RefusingUnsafeDelete(path)) if path == decoy
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+//! Defaults read from a config file, so endpoint, token, proxy and cache settings can be shared
+//! across programs instead of being set in code everywhere.
+//!
+//! The file is read from `$MODELS_CAT_CONFIG`, or `models-cat/config.toml` under the user's
+//! config directory (`~/.config` on Linux). For example:
+//!
+//! ```toml
+//! endpoint = "https://www.modelscope.cn"
+//! proxy = "http://proxy.internal:3128"
+//! cache_dir = "/data/modelscope"
+//! # Transfers at once across every `ModelsCat` of the process.
+//! max_concurrent_transfers = 4
+//! # Bytes read at once when hashing files to verify them.
+//! sha256_buffer_size = 4194304
+//!
+//! [headers]
+//! X-Team = "search"
+//!
+//! # Settings for a single repo, overriding the ones above.
+//! [repos."my-org/private-model"]
+//! token = "ms-..."
+//! ```
+//!
+//! Values set programmatically, such as [`crate::ModelsCat::with_token`] or
+//! [`crate::Repo::set_cache_dir`], take precedence over the file, and the
+//! `MODELS_CAT_CACHE_DIR`, `MODELS_CAT_ENDPOINT` and `MODELS_CAT_TOKEN` environment variables
+//! take precedence over `cache_dir`, `endpoint` and `token`.
+use crate::utils::{ClientOptions, OpsError};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+/// Environment variable overriding the config file location.
+pub(crate) const MODELS_CAT_CONFIG: &str = "MODELS_CAT_CONFIG";
+
+/// Environment variable overriding the configured access token.
+pub(crate) const MODELS_CAT_TOKEN: &str = "MODELS_CAT_TOKEN";
+
+/// The contents of a config file.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Config {
+    /// Settings applied to every repo.
+    #[serde(flatten)]
+    pub defaults: Settings,
+    /// Settings for single repos, keyed by repo ID, overriding [`Config::defaults`].
+    #[serde(default)]
+    pub repos: BTreeMap<String, Settings>,
+    /// The most file transfers running at once across every hub instance of the process.
+    /// Downloads wait for a free slot beyond it.
+    pub max_concurrent_transfers: Option<usize>,
+    /// The read buffer in bytes for hashing files, 1 MiB by default. Files of 64 MiB or more are
+    /// read on a reader thread in chunks of this size, 8 MiB by default.
+    pub sha256_buffer_size: Option<usize>,
+}
+
+/// Settings of a config file, either global or for a single repo.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Settings {
+    /// The hub endpoint.
+    pub endpoint: Option<String>,
+    /// An access token sent as a bearer token.
+    pub token: Option<String>,
+    /// A proxy URL for every request.
+    pub proxy: Option<String>,
+    /// The cache directory.
+    pub cache_dir: Option<PathBuf>,
+    /// Headers added to every request.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// The default location of the config file, honoring `$MODELS_CAT_CONFIG`.
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var(MODELS_CAT_CONFIG) {
+            return Some(PathBuf::from(path));
+        }
+        Some(dirs::config_dir()?.join("models-cat").join("config.toml"))
+    }
+
+    /// Reads the config file at [`Config::default_path`]; a missing file yields an empty config.
+    pub fn load() -> Result<Self, OpsError> {
+        match Self::default_path() {
+            Some(path) if path.exists() => Self::from_path(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Reads the config file at `path`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, OpsError> {
+        let path = path.as_ref();
+        Self::parse(&std::fs::read_to_string(path)?)
+            .map_err(|e| OpsError::BuildError(format!("{}: {e}", path.display())))
+    }
+
+    /// Parses the contents of a config file.
+    pub fn parse(text: &str) -> Result<Self, OpsError> {
+        toml::from_str(text).map_err(|e| OpsError::BuildError(e.to_string()))
+    }
+
+    /// The settings applying to `repo_id`: its own section layered over the defaults.
+    pub fn for_repo(&self, repo_id: &str) -> Settings {
+        let mut settings = self.defaults.clone();
+        if let Some(repo) = self.repos.get(repo_id) {
+            let repo = repo.clone();
+            settings.endpoint = repo.endpoint.or(settings.endpoint);
+            settings.token = repo.token.or(settings.token);
+            settings.proxy = repo.proxy.or(settings.proxy);
+            settings.cache_dir = repo.cache_dir.or(settings.cache_dir);
+            settings.headers.extend(repo.headers);
+        }
+        settings
+    }
+}
+
+impl Settings {
+    /// Client options with this proxy and these headers, if any are set.
+    pub(crate) fn client_options(&self) -> Option<ClientOptions> {
+        if self.proxy.is_none() && self.headers.is_empty() {
+            return None;
+        }
+        Some(ClientOptions {
+            proxy: self.proxy.clone(),
+            headers: self.headers.clone().into_iter().collect(),
+            ..Default::default()
+        })
+    }
+}
+
+/// The config file read once per process. An unreadable file is reported once and ignored, so
+/// infallible constructors such as [`crate::ModelsCat::new`] keep working.
+static GLOBAL: LazyLock<Config> = LazyLock::new(|| {
+    // Tests run against an empty config rather than that of whoever runs them.
+    if cfg!(test) {
+        return Config::default();
+    }
+    Config::load().unwrap_or_else(|e| {
+        log::warn!("ignoring models-cat config: {e}");
+        Config::default()
+    })
+});
+
+/// The settings applying to every repo from the process-wide config file.
+pub(crate) fn defaults() -> Settings {
+    GLOBAL.defaults.clone()
+}
+
+/// The settings applying to `repo_id` from the process-wide config file.
+pub(crate) fn settings_for(repo_id: &str) -> Settings {
+    GLOBAL.for_repo(repo_id)
+}
+
+/// The access token: `$MODELS_CAT_TOKEN` when set and not empty, then `configured`, the token
+/// of the config file.
+pub(crate) fn default_token(configured: Option<String>) -> Option<String> {
+    std::env::var(MODELS_CAT_TOKEN)
+        .ok()
+        .filter(|token| !token.trim().is_empty())
+        .or(configured)
+}
+
+/// The process-wide limit on concurrent transfers from the config file.
+pub(crate) fn max_concurrent_transfers() -> Option<usize> {
+    GLOBAL.max_concurrent_transfers
+}
+
+/// The process-wide read buffer for hashing from the config file.
+pub(crate) fn sha256_buffer_size() -> Option<usize> {
+    GLOBAL.sha256_buffer_size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_layer() {
+        let config = Config::parse(
+            r#"
+            endpoint = "https://mirror.example"
+            token = "global"
+            max_concurrent_transfers = 4
+            sha256_buffer_size = 65536
+            [headers]
+            X-Team = "search"
+            [repos."org/private"]
+            token = "private"
+            cache_dir = "/data/private"
+            [repos."org/private".headers]
+            X-Repo = "1"
+            "#,
+        )
+        .unwrap();
+
+        let other = config.for_repo("org/other");
+        assert_eq!(other.endpoint.as_deref(), Some("https://mirror.example"));
+        assert_eq!(other.token.as_deref(), Some("global"));
+        assert_eq!(other.cache_dir, None);
+        assert_eq!(config.max_concurrent_transfers, Some(4));
+        assert_eq!(config.sha256_buffer_size, Some(65536));
+
+        let private = config.for_repo("org/private");
+        assert_eq!(private.endpoint.as_deref(), Some("https://mirror.example"));
+        assert_eq!(private.token.as_deref(), Some("private"));
+        assert_eq!(private.cache_dir, Some(PathBuf::from("/data/private")));
+        assert_eq!(private.headers.len(), 2);
+        let options = private.client_options().unwrap();
+        assert!(options.headers.contains(&("X-Repo".into(), "1".into())));
+        assert!(Settings::default().client_options().is_none());
+        assert!(Config::parse("endpoint = 1").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! A global, cross-repo index of verified files keyed by sha256.
+//!
+//! Mirrored repos often contain byte-identical files. When deduplication is enabled, every
+//! verified download is recorded in `<cache_root>/dedup-index.jsonl`, and later downloads of a
+//! file with the same digest are satisfied by hard linking (or copying) the existing file
+//! instead of hitting the network.
+//!
+//! The index is an append-only JSON lines file: a crash can at worst leave a truncated last line,
+//! which is skipped on read. Removals append tombstones, and the file is compacted (rewritten
+//! atomically) once dead records outnumber live ones. All writes happen under an [`FsLock`] at
+//! the cache root.
+//!
+//! Hard links keep the data alive as long as any repo references it, so removing one repo never
+//! breaks another; removals only tombstone the index entries so lookups stop pointing at them.
+
+use crate::fslock::FsLock;
+use crate::utils::{self, OpsError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+const INDEX_FILE: &str = "dedup-index.jsonl";
+const LOCK_NAME: &str = "dedup-index";
+/// Compaction is skipped while the index is small, whatever the dead/live ratio.
+const COMPACTION_MIN_RECORDS: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct IndexRecord {
+    sha256: String,
+    path: PathBuf,
+    size: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    removed: bool,
+}
+
+/// Handle to the dedup index of one cache root.
+pub(crate) struct DedupIndex {
+    root: PathBuf,
+}
+
+impl DedupIndex {
+    pub(crate) fn new(cache_root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: cache_root.into(),
+        }
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.root.join(INDEX_FILE)
+    }
+
+    /// Finds an indexed file with the given digest and size, verifying its content before
+    /// handing it out. Entries whose file vanished or changed are ignored.
+    pub(crate) fn lookup(
+        &self,
+        sha256: &str,
+        size: u64,
+        exclude: &Path,
+    ) -> Result<Option<PathBuf>, OpsError> {
+        let (live, _) = self.read_live()?;
+        let Some(candidates) = live.get(sha256) else {
+            return Ok(None);
+        };
+        for record in candidates {
+            if record.path == exclude || record.size != size {
+                continue;
+            }
+            match std::fs::metadata(&record.path) {
+                Ok(meta) if meta.is_file() && meta.len() == size => {}
+                _ => continue,
+            }
+            if utils::sha256(&record.path)? == sha256 {
+                return Ok(Some(record.path.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Records a verified file in the index.
+    pub(crate) fn record(&self, sha256: &str, path: &Path, size: u64) -> Result<(), OpsError> {
+        self.append(&[IndexRecord {
+            sha256: sha256.to_string(),
+            path: path.to_path_buf(),
+            size,
+            removed: false,
+        }])
+    }
+
+    /// Tombstones every indexed path that is `path` or lies below it.
+    pub(crate) fn forget(&self, path: &Path) -> Result<(), OpsError> {
+        if !std::fs::exists(self.index_path())? {
+            return Ok(());
+        }
+        let (live, _) = self.read_live()?;
+        let tombstones: Vec<IndexRecord> = live
+            .into_values()
+            .flatten()
+            .filter(|r| r.path.starts_with(path))
+            .map(|r| IndexRecord { removed: true, ..r })
+            .collect();
+        if tombstones.is_empty() {
+            return Ok(());
+        }
+        self.append(&tombstones)
+    }
+
+    fn append(&self, records: &[IndexRecord]) -> Result<(), OpsError> {
+        std::fs::create_dir_all(&self.root)?;
+        let mut lock = FsLock::lock(self.root.join(LOCK_NAME))?;
+        let result = self.append_locked(records);
+        lock.unlock();
+        result
+    }
+
+    fn append_locked(&self, records: &[IndexRecord]) -> Result<(), OpsError> {
+        let mut lines = String::new();
+        if ends_with_partial_line(&self.index_path())? {
+            // Terminate a line left behind by an interrupted write so the new records parse.
+            lines.push('\n');
+        }
+        for record in records {
+            lines.push_str(&serde_json::to_string(record).map_err(std::io::Error::other)?);
+            lines.push('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.index_path())?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()?;
+
+        let (live, total) = self.read_live()?;
+        let live_count = live.values().map(Vec::len).sum::<usize>();
+        if total >= COMPACTION_MIN_RECORDS && total > live_count * 2 {
+            self.compact_locked(live)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the index with only the live records.
+    fn compact_locked(&self, live: HashMap<String, Vec<IndexRecord>>) -> Result<(), OpsError> {
+        let mut temp = NamedTempFile::new_in(&self.root)?;
+        for record in live.into_values().flatten() {
+            if !std::fs::exists(&record.path)? {
+                continue;
+            }
+            writeln!(
+                temp,
+                "{}",
+                serde_json::to_string(&record).map_err(std::io::Error::other)?
+            )?;
+        }
+        temp.as_file().sync_data()?;
+        temp.persist(self.index_path())
+            .map_err(|e| OpsError::IoError(e.error))?;
+        Ok(())
+    }
+
+    /// Replays the log and returns the live records grouped by digest, along with the total
+    /// number of well-formed records read. Malformed lines are skipped.
+    fn read_live(&self) -> Result<(HashMap<String, Vec<IndexRecord>>, usize), OpsError> {
+        let file = match std::fs::File::open(self.index_path()) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok((HashMap::new(), 0));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut live: HashMap<String, Vec<IndexRecord>> = HashMap::new();
+        let mut total = 0;
+        for line in BufReader::new(file).lines() {
+            let Some(record) = line
+                .ok()
+                .and_then(|l| serde_json::from_str::<IndexRecord>(&l).ok())
+            else {
+                continue;
+            };
+            total += 1;
+            let entries = live.entry(record.sha256.clone()).or_default();
+            entries.retain(|r| r.path != record.path);
+            if !record.removed {
+                entries.push(record);
+            }
+        }
+        live.retain(|_, entries| !entries.is_empty());
+        Ok((live, total))
+    }
+}
+
+/// Satisfies `filepath` from an identical, already verified file recorded in the index of
+/// `cache_root`. Returns whether the file was placed.
+pub(crate) fn try_link(
+    cache_root: &Path,
+    sha256: Option<&str>,
+    size: u64,
+    filepath: &Path,
+) -> Result<bool, OpsError> {
+    let Some(sha256) = sha256 else {
+        return Ok(false);
+    };
+    let index = DedupIndex::new(cache_root);
+    let Some(source) = index.lookup(sha256, size, filepath)? else {
+        return Ok(false);
+    };
+    link_or_copy(&source, filepath)?;
+    index.record(sha256, filepath, size)?;
+    Ok(true)
+}
+
+/// Records a freshly downloaded file in the index of `cache_root` once its digest checks out.
+pub(crate) fn record_download(
+    cache_root: &Path,
+    sha256: Option<&str>,
+    filepath: &Path,
+) -> Result<(), OpsError> {
+    let Some(sha256) = sha256 else {
+        return Ok(());
+    };
+    if utils::sha256(filepath)? == sha256 {
+        let size = std::fs::metadata(filepath)?.len();
+        DedupIndex::new(cache_root).record(sha256, filepath, size)?;
+    }
+    Ok(())
+}
+
+fn ends_with_partial_line(path: &Path) -> Result<bool, OpsError> {
+    let mut file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+    if file.metadata()?.len() == 0 {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::End(-1))?;
+    let mut last = [0u8; 1];
+    file.read_exact(&mut last)?;
+    Ok(last[0] != b'\n')
+}
+
+/// Places `src` at `dst` as a hard link, falling back to a copy when linking is not possible
+/// (e.g. across filesystems). The destination is replaced atomically.
+///
+/// A symlinked `src`, such as a snapshot file linking to its blob, is followed, so `dst` does
+/// not end up a symlink relative to the wrong directory.
+pub(crate) fn link_or_copy(src: &Path, dst: &Path) -> Result<(), OpsError> {
+    let src = &std::fs::canonicalize(src)?;
+    let parent = dst
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let temp = NamedTempFile::new_in(parent)?;
+    let temp_path = temp.into_temp_path();
+    std::fs::remove_file(&temp_path)?;
+    if std::fs::hard_link(src, &temp_path).is_err() {
+        std::fs::copy(src, &temp_path)?;
+    }
+    temp_path
+        .persist(dst)
+        .map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &[u8]) -> String {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+        utils::sha256(path).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_and_link_across_repos() {
+        let root = tempfile::tempdir().unwrap();
+        let index = DedupIndex::new(root.path());
+        let src = root.path().join("models--a--x/snapshots/1/model.bin");
+        let dst = root.path().join("models--b--x/snapshots/2/model.bin");
+        let sha = write(&src, b"identical weights");
+
+        assert!(index.lookup(&sha, 17, &dst).unwrap().is_none());
+        index.record(&sha, &src, 17).unwrap();
+        let found = index.lookup(&sha, 17, &dst).unwrap().unwrap();
+        assert_eq!(found, src);
+        assert!(index.lookup(&sha, 18, &dst).unwrap().is_none());
+
+        link_or_copy(&found, &dst).unwrap();
+        assert_eq!(std::fs::read(&dst).unwrap(), b"identical weights");
+    }
+
+    #[test]
+    fn test_forget_and_removed_repo_keeps_linked_copy() {
+        let root = tempfile::tempdir().unwrap();
+        let index = DedupIndex::new(root.path());
+        let repo_a = root.path().join("models--a--x");
+        let src = repo_a.join("snapshots/1/model.bin");
+        let dst = root.path().join("models--b--x/snapshots/2/model.bin");
+        let sha = write(&src, b"shared");
+        index.record(&sha, &src, 6).unwrap();
+        link_or_copy(&src, &dst).unwrap();
+        index.record(&sha, &dst, 6).unwrap();
+
+        std::fs::remove_dir_all(&repo_a).unwrap();
+        index.forget(&repo_a).unwrap();
+
+        assert_eq!(std::fs::read(&dst).unwrap(), b"shared");
+        assert_eq!(index.lookup(&sha, 6, Path::new("")).unwrap(), Some(dst));
+    }
+
+    #[test]
+    fn test_tolerates_corrupt_lines_and_compacts() {
+        let root = tempfile::tempdir().unwrap();
+        let index = DedupIndex::new(root.path());
+        let src = root.path().join("file.bin");
+        let sha = write(&src, b"data");
+        std::fs::write(index.index_path(), "{\"sha256\": \"trunc").unwrap();
+
+        for _ in 0..COMPACTION_MIN_RECORDS {
+            index.record(&sha, &src, 4).unwrap();
+            index.forget(&src).unwrap();
+        }
+        index.record(&sha, &src, 4).unwrap();
+
+        let lines = std::fs::read_to_string(index.index_path()).unwrap();
+        assert!(lines.lines().count() < COMPACTION_MIN_RECORDS);
+        assert_eq!(index.lookup(&sha, 4, Path::new("")).unwrap(), Some(src));
+    }
+}
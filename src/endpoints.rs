@@ -0,0 +1,164 @@
+//! Hub endpoints: named presets for the ModelScope regions, and a validated [`Endpoint`].
+//!
+//! Every default endpoint of the crate derives from [`MODELSCOPE_CN`], overridden by the
+//! `MODELS_CAT_ENDPOINT` environment variable, then the [config file](crate::config).
+use crate::utils::OpsError;
+use reqwest::Url;
+use std::fmt;
+use std::str::FromStr;
+
+/// ModelScope in mainland China, the default endpoint.
+pub const MODELSCOPE_CN: &str = "https://www.modelscope.cn";
+
+/// The international ModelScope site.
+pub const MODELSCOPE_INTL: &str = "https://www.modelscope.ai";
+
+/// Environment variable overriding the default endpoint.
+pub(crate) const MODELS_CAT_ENDPOINT: &str = "MODELS_CAT_ENDPOINT";
+
+/// A hub endpoint, validated to be an `http` or `https` URL with a host and without query or
+/// fragment. A trailing `/` is dropped, so paths can be appended as is.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint(String);
+
+impl Endpoint {
+    /// Creates a new `Endpoint` from `url`, such as `https://mirror.example` or
+    /// `http://10.0.0.1:8080/modelscope`.
+    pub fn new(url: impl AsRef<str>) -> Result<Self, OpsError> {
+        let url = url.as_ref().trim();
+        let invalid = |why: &str| OpsError::BuildError(format!("invalid endpoint {url}: {why}"));
+        let parsed = Url::parse(url).map_err(|e| invalid(&e.to_string()))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(invalid("the scheme must be http or https"));
+        }
+        if parsed.host_str().is_none_or(str::is_empty) {
+            return Err(invalid("no host"));
+        }
+        if parsed.query().is_some() || parsed.fragment().is_some() {
+            return Err(invalid("no query or fragment allowed"));
+        }
+        Ok(Self(url.trim_end_matches('/').to_string()))
+    }
+
+    /// The endpoint URL, without a trailing `/`.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Self {
+        Self(MODELSCOPE_CN.to_string())
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<str> for Endpoint {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = OpsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<&str> for Endpoint {
+    type Error = OpsError;
+
+    fn try_from(url: &str) -> Result<Self, Self::Error> {
+        Self::new(url)
+    }
+}
+
+impl TryFrom<String> for Endpoint {
+    type Error = OpsError;
+
+    fn try_from(url: String) -> Result<Self, Self::Error> {
+        Self::new(url)
+    }
+}
+
+impl From<Endpoint> for String {
+    fn from(endpoint: Endpoint) -> Self {
+        endpoint.0
+    }
+}
+
+/// The endpoint set by the `MODELS_CAT_ENDPOINT` environment variable, if any.
+pub fn from_env() -> Result<Option<Endpoint>, OpsError> {
+    std::env::var(MODELS_CAT_ENDPOINT)
+        .ok()
+        .map(Endpoint::new)
+        .transpose()
+}
+
+/// `url` with surrounding whitespace and trailing `/` dropped, and `https://` prefixed when it
+/// has no scheme, for the constructors taking an endpoint they cannot reject, such as
+/// [`crate::ModelsCat::new_with_endpoint`].
+pub(crate) fn normalize(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    match url.contains("://") {
+        true => url.to_string(),
+        false => format!("https://{url}"),
+    }
+}
+
+/// The default endpoint: `$MODELS_CAT_ENDPOINT`, then `configured`, the endpoint of the config
+/// file, then [`MODELSCOPE_CN`].
+pub(crate) fn default_endpoint(configured: Option<String>) -> String {
+    let from_env = from_env()
+        .inspect_err(|e| log::warn!("ignoring {MODELS_CAT_ENDPOINT}: {e}"))
+        .ok()
+        .flatten();
+    match from_env {
+        Some(endpoint) => endpoint.into(),
+        None => configured.map_or_else(|| MODELSCOPE_CN.to_string(), |url| normalize(&url)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoint_validation() {
+        assert_eq!(Endpoint::default().as_str(), MODELSCOPE_CN);
+        assert_eq!(
+            Endpoint::new("https://mirror.example/").unwrap().as_str(),
+            "https://mirror.example"
+        );
+        let endpoint: Endpoint = "http://10.0.0.1:8080/modelscope".parse().unwrap();
+        assert_eq!(endpoint.to_string(), "http://10.0.0.1:8080/modelscope");
+        for url in [
+            "www.modelscope.cn",
+            "ftp://mirror.example",
+            "https://",
+            "https://mirror.example?token=1",
+            "https://mirror.example#top",
+        ] {
+            assert!(Endpoint::new(url).is_err(), "{url}");
+        }
+        assert!(Endpoint::new(MODELSCOPE_INTL).is_ok());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("https://mirror.local/"), "https://mirror.local");
+        assert_eq!(normalize(" mirror.local// "), "https://mirror.local");
+        assert_eq!(
+            normalize("http://10.0.0.1:8080/ms/"),
+            "http://10.0.0.1:8080/ms"
+        );
+        assert_eq!(normalize(MODELSCOPE_CN), MODELSCOPE_CN);
+    }
+}
@@ -2,7 +2,7 @@
 
 use crate::utils::OpsError;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub struct FsLock {
     file: std::fs::File,
@@ -13,28 +13,184 @@ impl FsLock {
     pub fn lock(path: PathBuf) -> Result<FsLock, OpsError> {
         let mut path = path.to_path_buf();
         path.set_extension("lock");
-        let file = File::create(path.clone())?;
-        let mut res = lock(&file);
-        for _ in 0..5 {
-            if res == 0 {
-                break;
+        for attempt in 0..=5 {
+            if attempt > 0 {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+            if let Some(file) = lock_path(&path)? {
+                return Ok(Self { file, path });
             }
-            std::thread::sleep(std::time::Duration::from_secs(1));
-            res = lock(&file);
-        }
-        if res != 0 {
-            Err(OpsError::LockAcquisition(path))
-        } else {
-            Ok(Self { file, path })
         }
+        Err(OpsError::LockAcquisition(path))
+    }
+
+    /// Takes the lock at `path` if no one holds it, without waiting.
+    pub fn try_lock(path: PathBuf) -> Result<Option<FsLock>, OpsError> {
+        let mut path = path.to_path_buf();
+        path.set_extension("lock");
+        Ok(lock_path(&path)?.map(|file| Self { file, path }))
     }
 
     pub fn unlock(&mut self) {
-        unlock(&self.file);
+        // Removed before unlocking, so whoever opened the file meanwhile finds it gone once it
+        // gets the lock, and locks the next one instead.
         std::fs::remove_file(&self.path).unwrap();
+        unlock(&self.file);
     }
 }
 
+/// Creates the lock file at `path` and locks it, or returns `None` when someone holds it.
+///
+/// The holder removes the file before releasing it, possibly after it was opened here: the
+/// lock only counts while the file is still the one at `path`, else the next one is locked.
+fn lock_path(path: &Path) -> Result<Option<File>, OpsError> {
+    loop {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(e) if is_delete_pending(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if lock(&file) != 0 {
+            return Ok(None);
+        }
+        if is_linked(&file, path) {
+            return Ok(Some(file));
+        }
+    }
+}
+
+/// Whether `file` is still the file at `path`.
+#[cfg(target_family = "unix")]
+fn is_linked(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), std::fs::metadata(path)) {
+        (Ok(locked), Ok(linked)) => (locked.dev(), locked.ino()) == (linked.dev(), linked.ino()),
+        _ => false,
+    }
+}
+
+/// Whether `file` is still the file at `path`. A file removed while open keeps its name until
+/// it is closed, so creating the next one fails rather than locking a stale file.
+#[cfg(not(target_family = "unix"))]
+fn is_linked(_: &File, _: &Path) -> bool {
+    true
+}
+
+/// Whether creating the lock file failed because the holder removed it while someone still had
+/// it open, which leaves its name taken until then: the lock counts as held, and is retried.
+#[cfg(target_family = "windows")]
+fn is_delete_pending(e: &std::io::Error) -> bool {
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    e.kind() == std::io::ErrorKind::PermissionDenied
+        || e.raw_os_error() == Some(ERROR_ACCESS_DENIED)
+}
+
+/// Whether creating the lock file failed because the holder removed it while someone still had
+/// it open. A removed file frees its name at once here, so this never happens.
+#[cfg(not(target_family = "windows"))]
+fn is_delete_pending(_: &std::io::Error) -> bool {
+    false
+}
+
+/// How [`AsyncFsLock::acquire`] retries a lock held by someone else.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy)]
+pub struct LockOptions {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// The wait between two attempts.
+    pub retry_interval: std::time::Duration,
+}
+
+#[cfg(feature = "tokio")]
+impl Default for LockOptions {
+    /// The same retries as [`FsLock::lock`].
+    fn default() -> Self {
+        Self {
+            retries: 5,
+            retry_interval: std::time::Duration::from_secs(1),
+        }
+    }
+}
+
+/// [`FsLock`] for async code: waits without blocking the runtime, and hands out a guard
+/// releasing the lock when dropped, including when the task holding it is cancelled.
+#[cfg(feature = "tokio")]
+pub struct AsyncFsLock;
+
+#[cfg(feature = "tokio")]
+impl AsyncFsLock {
+    /// Locks `path` with the extension `lock`, like [`FsLock::lock`]. The file system calls
+    /// run on the blocking thread pool.
+    pub async fn acquire(path: PathBuf, opts: LockOptions) -> Result<LockGuard, OpsError> {
+        let mut path = path;
+        path.set_extension("lock");
+        for attempt in 0..=opts.retries {
+            if attempt > 0 {
+                tokio::time::sleep(opts.retry_interval).await;
+            }
+            let locked = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || lock_path(&path))
+                    .await
+                    .map_err(|e| OpsError::hub("lock task failed", e))??
+            };
+            if let Some(file) = locked {
+                return Ok(LockGuard {
+                    file: Some(file),
+                    path,
+                });
+            }
+        }
+        Err(OpsError::LockAcquisition(path))
+    }
+}
+
+/// A lock taken with [`AsyncFsLock::acquire`], released when dropped.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct LockGuard {
+    file: Option<File>,
+    path: PathBuf,
+}
+
+#[cfg(feature = "tokio")]
+impl LockGuard {
+    /// Releases the lock and waits until it is released, so the next [`AsyncFsLock::acquire`]
+    /// finds it free, unlike dropping the guard.
+    pub async fn release(mut self) -> Result<(), OpsError> {
+        let Some(file) = self.file.take() else {
+            return Ok(());
+        };
+        let path = std::mem::take(&mut self.path);
+        tokio::task::spawn_blocking(move || release(file, &path))
+            .await
+            .map_err(|e| OpsError::hub("lock task failed", e))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for LockGuard {
+    /// Releases the lock without waiting, on the blocking thread pool when inside a runtime.
+    fn drop(&mut self) {
+        let Some(file) = self.file.take() else {
+            return;
+        };
+        let path = std::mem::take(&mut self.path);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(move || release(file, &path))),
+            Err(_) => release(file, &path),
+        }
+    }
+}
+
+/// Removes `file` and unlocks it, like [`FsLock::unlock`].
+#[cfg(feature = "tokio")]
+fn release(file: File, path: &Path) {
+    let _ = std::fs::remove_file(path);
+    unlock(&file);
+}
+
 #[cfg(target_family = "unix")]
 mod unix {
     use std::os::fd::AsRawFd;
@@ -93,9 +249,50 @@ use other::{lock, unlock};
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     #[test]
     fn test_lock_unlock() {
         let mut lock = crate::fslock::FsLock::lock(std::path::PathBuf::from("test.lock")).unwrap();
         lock.unlock();
     }
+
+    #[test]
+    fn test_unlock_with_waiter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        let mut holder = FsLock::lock(path.clone()).unwrap();
+        let waiter = {
+            let path = path.clone();
+            std::thread::spawn(move || FsLock::lock(path))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        holder.unlock();
+
+        // The waiter holds the file at the path, so no one else gets the lock.
+        let mut held = waiter.join().unwrap().unwrap();
+        assert!(FsLock::try_lock(path.clone()).unwrap().is_none());
+        held.unlock();
+        assert!(FsLock::try_lock(path).unwrap().is_some());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_guard_dropped_with_waiter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("snapshot");
+        let opts = LockOptions {
+            retries: 100,
+            retry_interval: std::time::Duration::from_millis(10),
+        };
+        let guard = AsyncFsLock::acquire(path.clone(), opts).await.unwrap();
+        let waiter = tokio::spawn(AsyncFsLock::acquire(path.clone(), opts));
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        drop(guard);
+
+        let held = waiter.await.unwrap().unwrap();
+        assert!(FsLock::try_lock(path.clone()).unwrap().is_none());
+        held.release().await.unwrap();
+        assert!(FsLock::try_lock(path).unwrap().is_some());
+    }
 }
@@ -0,0 +1,100 @@
+//! Streaming of repo files past the cache: into a tar archive, for shipping a repo as a single
+//! artifact, or into any writer, see [`crate::hub::ModelsCat::download_to_writer`].
+use super::ms_hub::FileInfo;
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+
+/// Compression of the archive written by [`crate::hub::ModelsCat::pull_to_tar`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveCompression {
+    /// A plain `.tar` archive.
+    #[default]
+    None,
+    /// A gzipped `.tar.gz` archive.
+    Gzip,
+}
+
+/// The tar header of `fileinfo`, with its listed size and commit date.
+pub(crate) fn header(fileinfo: &FileInfo) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(fileinfo.size as u64);
+    header.set_mode(0o644);
+    header.set_mtime(fileinfo.committed_date.max(0) as u64);
+    header
+}
+
+/// Checks a download streamed past the cache against its listing, failing once it is over, or
+/// ends short of, the listed size, or does not match the listed sha256.
+pub(crate) struct Verifier<'a> {
+    fileinfo: &'a FileInfo,
+    hasher: Sha256,
+    read: u64,
+}
+
+impl<'a> Verifier<'a> {
+    pub(crate) fn new(fileinfo: &'a FileInfo) -> Self {
+        Self {
+            fileinfo,
+            hasher: Sha256::new(),
+            read: 0,
+        }
+    }
+
+    /// Takes in the next bytes of the download.
+    pub(crate) fn update(&mut self, data: &[u8]) -> io::Result<()> {
+        self.read += data.len() as u64;
+        if self.read > self.fileinfo.size as u64 {
+            return Err(self.mismatch("size"));
+        }
+        self.hasher.update(data);
+        Ok(())
+    }
+
+    /// Checks the download once it ended.
+    pub(crate) fn finish(&self) -> io::Result<()> {
+        if self.read < self.fileinfo.size as u64 {
+            return Err(self.mismatch("size"));
+        }
+        if let Some(ref sha256) = self.fileinfo.sha256
+            && format!("{:x}", self.hasher.clone().finalize()) != *sha256
+        {
+            return Err(self.mismatch("sha256"));
+        }
+        Ok(())
+    }
+
+    fn mismatch(&self, what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not match its listed {what}", self.fileinfo.path),
+        )
+    }
+}
+
+/// Passes a download through to the tar encoder, checked by a [`Verifier`]. The tar header is
+/// written before the body, so a mismatch must fail the archive rather than produce a corrupt
+/// entry.
+pub(crate) struct VerifyingReader<'a, R> {
+    inner: R,
+    verifier: Verifier<'a>,
+}
+
+impl<'a, R: Read> VerifyingReader<'a, R> {
+    pub(crate) fn new(inner: R, fileinfo: &'a FileInfo) -> Self {
+        Self {
+            inner,
+            verifier: Verifier::new(fileinfo),
+        }
+    }
+}
+
+impl<R: Read> Read for VerifyingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        match n {
+            0 => self.verifier.finish()?,
+            _ => self.verifier.update(&buf[..n])?,
+        }
+        Ok(n)
+    }
+}
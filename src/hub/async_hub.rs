@@ -1,157 +1,2132 @@
 //! Asynchronous hub for downloading
-use super::ms_hub::asynchronous;
-use crate::fslock;
-use crate::repo::Repo;
-use crate::utils::{self, ASYNC_CLIENT, OpsError};
-use async_trait::async_trait;
-use indicatif::{
-    MultiProgress as MultiProgressBar, ProgressBar, ProgressFinish, ProgressState, ProgressStyle,
+pub use super::progress_bridge::{AsyncProgressAdapter, BlockingProgressAdapter};
+pub use super::{
+    CacheValidation, DownloadOptions, EnsureRequest, ExportMode, FileChange, FileMetadata,
+    FileStatus, HistoryEntry, HistoryOperation, HistoryOutcome, HubFileInfo, ImportOutcome,
+    ImportReport, InvalidPathPolicy, Logger, PreparedSnapshot, ProgressErrorPolicy, ProgressEvent,
+    ProgressUnit, Provenance, ProvenanceRecord, PruneReport, PullOptions, RateLimitPacing,
+    RateLimitStatus, ReadyModel, RetryPolicy, SegmentedProgress, SyncReport, UpdateStatus,
+    ValidationError, VerifyReport,
+};
+
+use super::archive;
+use super::blobs;
+use super::export;
+use super::history;
+use super::import;
+use super::matching;
+use super::memory_cache::MemoryCache;
+use super::ms_hub::{ApiResponse, FileInfo, asynchronous};
+use super::offline;
+use super::paths::{self, PathRules};
+use super::prepared;
+use super::provenance;
+use super::prune;
+use super::quarantine::{self, QuarantineReport};
+use super::ready;
+use super::resolved::{self, HeadCheck, ResolvedFile};
+use super::retry;
+use super::staging::{self, CHECKPOINT_INTERVAL, Recovery, StagedFile};
+use super::throttle::Throttle;
+use super::transfer_slots::{self, TransferSlot, TransferSlots};
+use super::update;
+use super::validate;
+use super::verify;
+use super::{
+    Checked, Listing, Placement, PullReport, Resolution, Target, cached_listing, check,
+    completed_files, file_bar, fit_bar, group_by_revision, is_complete, is_verified,
+    latest_revision, link_into_latest, mark_complete, recheck, resume_bar, resumes_at,
+    snapshot_file, unlinked_into_latest, unmark_complete, update_latest,
 };
+use crate::cache;
+use crate::config;
+use crate::dedup;
+use crate::diagnostics::Diagnostics;
+use crate::endpoints::{self, Endpoint};
+use crate::fslock::{AsyncFsLock, LockGuard, LockOptions};
+use crate::repo::{Repo, Revision};
+use crate::utils::{ASYNC_CLIENT, ClientOptions, OpsError};
+use async_trait::async_trait;
+use futures::StreamExt;
+use indicatif::{MultiProgress as MultiProgressBar, ProgressBar};
+use reqwest::StatusCode;
+use reqwest::header::RANGE;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
-use std::path::PathBuf;
-use tokio::io::AsyncWriteExt;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
 
 /// A struct representing a models management system, which provides asynchronous operations.
 pub struct ModelsCat {
     endpoint: String,
     repo: Repo,
+    global_dedup: bool,
+    blob_store: bool,
+    memory_cache: Option<Arc<MemoryCache>>,
+    logger: Option<Mutex<Logger>>,
+    invalid_paths: InvalidPathPolicy,
+    validation: CacheValidation,
+    progress_errors: ProgressErrorPolicy,
+    path_rules: PathRules,
+    token: Option<String>,
+    client: reqwest::Client,
+    client_options: ClientOptions,
+    listing: Listing,
+    write_buffer_size: usize,
+    history_size_limit: u64,
+    rate_limit: Mutex<Option<RateLimitStatus>>,
+    pacing: Option<RateLimitPacing>,
+    transfer_slots: Option<Arc<TransferSlots>>,
+    quarantine: Option<u64>,
+    offline: bool,
+    max_concurrency: usize,
+    retry: RetryPolicy,
+    cancel: Option<Arc<AtomicBool>>,
+    throttle: Option<Arc<Throttle>>,
+    validators: Vec<Arc<dyn Validator>>,
 }
 
 impl ModelsCat {
     /// Creates a new instance of `ModelsCat` with the specified repository.
     pub fn new(repo: Repo) -> Self {
+        let endpoint = endpoints::default_endpoint(config::settings_for(repo.repo_id()).endpoint);
+        Self::new_with_endpoint(repo, endpoint)
+    }
+
+    /// Creates a new `ModelsCat` instance from anything convertible into a [`Repo`], such as a
+    /// bare `owner/name` model ID or a built `Repo`.
+    pub fn try_new(repo: impl TryInto<Repo, Error: Into<OpsError>>) -> Result<Self, OpsError> {
+        Ok(Self::new(repo.try_into().map_err(Into::into)?))
+    }
+
+    /// Creates a new `ModelsCat` instance with a custom endpoint, used for listings as well as
+    /// downloads. A trailing `/` is dropped, and `https://` is assumed when no scheme is given.
+    pub fn new_with_endpoint(repo: Repo, endpoint: String) -> Self {
+        let endpoint = endpoints::normalize(&endpoint);
+        let settings = config::settings_for(repo.repo_id());
+        let (client_options, client) = settings
+            .client_options()
+            .and_then(|options| {
+                let client = options
+                    .async_client()
+                    .inspect_err(|e| log::warn!("ignoring configured client settings: {e}"))
+                    .ok()?;
+                Some((options, client))
+            })
+            .unwrap_or_else(|| (ClientOptions::default(), ASYNC_CLIENT.clone()));
         Self {
             repo,
-            endpoint: "https://www.modelscope.cn".to_string(),
+            endpoint,
+            global_dedup: false,
+            blob_store: false,
+            memory_cache: None,
+            logger: None,
+            invalid_paths: InvalidPathPolicy::default(),
+            validation: CacheValidation::default(),
+            progress_errors: ProgressErrorPolicy::default(),
+            path_rules: PathRules::local(),
+            token: config::default_token(settings.token),
+            client,
+            client_options,
+            listing: Listing::default(),
+            write_buffer_size: super::DEFAULT_WRITE_BUFFER_SIZE,
+            history_size_limit: history::DEFAULT_HISTORY_SIZE_LIMIT,
+            rate_limit: Mutex::new(None),
+            pacing: None,
+            transfer_slots: transfer_slots::global(),
+            quarantine: None,
+            offline: offline::from_env(),
+            max_concurrency: 1,
+            retry: RetryPolicy::default(),
+            cancel: None,
+            throttle: None,
+            validators: Vec::new(),
         }
     }
 
-    /// Creates a new `ModelsCat` instance with a custom endpoint.
-    pub fn new_with_endpoint(repo: Repo, endpoint: String) -> Self {
-        Self { repo, endpoint }
+    /// Enables the global, cross-repo dedup index (disabled by default).
+    ///
+    /// See [`crate::hub::ModelsCat::with_global_dedup`].
+    pub fn with_global_dedup(mut self, enabled: bool) -> Self {
+        self.global_dedup = enabled;
+        self
+    }
+
+    /// Stores downloads once per content in the repo, like the Hugging Face cache (disabled by
+    /// default).
+    ///
+    /// See [`crate::hub::ModelsCat::with_blob_store`].
+    pub fn with_blob_store(mut self, enabled: bool) -> Self {
+        self.blob_store = enabled;
+        self
+    }
+
+    /// Derives the cache directory name of the repository with `cache_key`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_cache_key`].
+    pub fn with_cache_key(mut self, cache_key: impl Fn(&Repo) -> String) -> Result<Self, OpsError> {
+        let key = cache_key(&self.repo);
+        self.repo.set_cache_key(&key)?;
+        Ok(self)
+    }
+
+    /// Keeps up to `capacity` bytes of files read through `fetch_bytes` and `get_json` in memory.
+    ///
+    /// See [`crate::hub::ModelsCat::with_memory_cache`].
+    pub fn with_memory_cache(mut self, capacity: usize) -> Self {
+        self.memory_cache = Some(Arc::new(MemoryCache::new(capacity)));
+        self
+    }
+
+    /// Sends a line for each event of the download and pull flow to `logger`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_logger`].
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(Mutex::new(logger));
+        self
+    }
+
+    /// Sets what pulls and downloads do with hub paths invalid on the local filesystem.
+    ///
+    /// See [`crate::hub::ModelsCat::with_invalid_path_policy`].
+    pub fn with_invalid_path_policy(mut self, policy: InvalidPathPolicy) -> Self {
+        self.invalid_paths = policy;
+        self
+    }
+
+    /// Sets how cached files are confirmed as current before being downloaded again.
+    ///
+    /// See [`crate::hub::ModelsCat::with_cache_validation`].
+    pub fn with_cache_validation(mut self, validation: CacheValidation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Sets what downloads do when a progress callback returns an error.
+    ///
+    /// See [`crate::hub::ModelsCat::with_progress_error_policy`].
+    pub fn with_progress_error_policy(mut self, policy: ProgressErrorPolicy) -> Self {
+        self.progress_errors = policy;
+        self
+    }
+
+    /// Sends every request to `endpoint`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_endpoint`].
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Authenticates every request with `token`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_token`].
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Sets how many bytes of a download are buffered before they are written to disk.
+    ///
+    /// See [`crate::hub::ModelsCat::with_write_buffer_size`].
+    pub fn with_write_buffer_size(mut self, bytes: usize) -> Self {
+        self.write_buffer_size = bytes.max(1);
+        self
+    }
+
+    /// Sets the size of the history log, past which it is rotated.
+    ///
+    /// See [`crate::hub::ModelsCat::with_history_size_limit`].
+    pub fn with_history_size_limit(mut self, bytes: u64) -> Self {
+        self.history_size_limit = bytes;
+        self
+    }
+
+    /// Waits before requests while the hub reports few requests left.
+    ///
+    /// See [`crate::hub::ModelsCat::with_rate_limit_pacing`].
+    pub fn with_rate_limit_pacing(mut self, pacing: RateLimitPacing) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+
+    /// Keeps downloads whose sha256 is not the listed one in the quarantine area of the cache.
+    ///
+    /// See [`crate::hub::ModelsCat::with_quarantine`].
+    pub fn with_quarantine(mut self, max_bytes: u64) -> Self {
+        self.quarantine = Some(max_bytes);
+        self
+    }
+
+    /// Whether to work from the cache only, never querying the hub.
+    ///
+    /// See [`crate::hub::ModelsCat::with_offline`].
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Downloads up to `max_concurrency` files of a pull at once, each reporting to its own
+    /// clone of the progress. Defaults to 1; 0 counts as 1. A failed file does not stop the
+    /// others, and the pull then fails with [`OpsError::PullFailed`].
+    ///
+    /// See [`crate::hub::ModelsCat::with_max_concurrency`].
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Downloads a file again after a transport error, `429 Too Many Requests` or a `5xx`
+    /// status, waiting as `policy` says.
+    ///
+    /// See [`crate::hub::ModelsCat::with_retry`].
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Cancels every download of this instance, failing it with [`OpsError::Cancelled`], while
+    /// `flag` is set.
+    ///
+    /// See [`crate::hub::ModelsCat::with_cancel_flag`].
+    pub fn with_cancel_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Caps the download throughput of this instance at `bytes_per_sec`, shared by its
+    /// concurrent downloads. 0 means unlimited, the default.
+    ///
+    /// See [`crate::hub::ModelsCat::with_rate_limit`].
+    pub fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.throttle = Throttle::new(bytes_per_sec).map(Arc::new);
+        self
+    }
+
+    /// Retries a failed download up to `retries` times (3 by default).
+    ///
+    /// See [`crate::hub::ModelsCat::with_retries`].
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retry.max_retries = retries;
+        self
+    }
+
+    /// Runs `validator` on every downloaded file before it is moved into the snapshot.
+    ///
+    /// See [`crate::hub::ModelsCat::with_validator`].
+    pub fn with_validator(mut self, validator: impl Validator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Sends requests with `client` instead of the shared default one.
+    ///
+    /// See [`crate::hub::ModelsCat::with_client`].
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends requests with a client built from `options` instead of the shared default one.
+    ///
+    /// See [`crate::hub::ModelsCat::with_client_options`].
+    pub fn with_client_options(mut self, options: &ClientOptions) -> Result<Self, OpsError> {
+        self.client = options.async_client()?;
+        self.client_options = options.clone();
+        Ok(self)
+    }
+
+    /// Fails requests with [`OpsError::Timeout`] when the response, or the next data of its
+    /// body, takes longer than `timeout`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, OpsError> {
+        self.client_options.timeout = Some(timeout);
+        self.client = self.client_options.async_client()?;
+        Ok(self)
+    }
+
+    /// Fails requests with [`OpsError::Timeout`] when connecting takes longer than `timeout`.
+    ///
+    /// See [`crate::hub::ModelsCat::with_connect_timeout`].
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Result<Self, OpsError> {
+        self.client_options.connect_timeout = Some(timeout);
+        self.client = self.client_options.async_client()?;
+        Ok(self)
     }
 
     /// Retrieves the repository configuration.
+    ///
+    /// See [`crate::hub::ModelsCat::repo`].
     pub fn repo(&self) -> &Repo {
         &self.repo
     }
 
-    /// Retrieves the endpoint URL.
-    pub fn endpoint(&self) -> &str {
-        &self.endpoint
+    /// Switches to `revision`, dropping the cached listing and the memory cache.
+    ///
+    /// See [`crate::hub::ModelsCat::set_revision`].
+    pub fn set_revision(&mut self, revision: impl Into<Revision>) {
+        self.repo.set_revision(revision);
+        self.invalidate();
+    }
+
+    /// Moves the cache to `cache_dir`, dropping the cached listing and the memory cache.
+    ///
+    /// See [`crate::hub::ModelsCat::set_cache_dir`].
+    pub fn set_cache_dir(&mut self, cache_dir: impl Into<PathBuf>) {
+        self.repo.set_cache_dir(cache_dir);
+        self.invalidate();
+    }
+
+    fn invalidate(&mut self) {
+        *self.listing.get_mut().unwrap() = None;
+        if let Some(ref cache) = self.memory_cache {
+            cache.clear();
+        }
+    }
+
+    /// Retrieves the endpoint URL.
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Collects diagnostics for support requests, including repo-specific cache details.
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::for_repo(&self.repo, &self.endpoint)
+    }
+
+    /// Pull a repo, processing files in a stable order.
+    ///
+    /// See [`crate::hub::ModelsCat::pull`].
+    pub async fn pull(&self) -> Result<PullReport, OpsError> {
+        self.inner_pull(None::<MultiProgressWrapper>, &PullOptions::default())
+            .await
+    }
+
+    /// Pull a repo with a progress
+    pub async fn pull_with_progress(
+        &self,
+        progress: impl Progress,
+    ) -> Result<PullReport, OpsError> {
+        self.inner_pull(Some(progress), &PullOptions::default())
+            .await
+    }
+
+    /// Pulls the entire repository like [`ModelsCat::pull_with_progress`], until `token` is
+    /// cancelled. A cancelled pull keeps the files it completed, removes the one in transfer
+    /// and fails with [`OpsError::Cancelled`] naming the repo.
+    pub async fn pull_with_cancel(
+        &self,
+        token: CancellationToken,
+        progress: Option<impl Progress>,
+    ) -> Result<PullReport, OpsError> {
+        let options = PullOptions::default();
+        tokio::select! {
+            biased;
+            () = token.cancelled() => Err(OpsError::Cancelled {
+                file: self.repo.repo_id().to_string(),
+            }),
+            result = self.inner_pull(progress, &options) => result,
+        }
+    }
+
+    /// Pulls the entire repository, with `options` overriding instance settings for this pull
+    /// only.
+    ///
+    /// See [`crate::hub::ModelsCat::pull_with_options`].
+    pub async fn pull_with_options(&self, options: &PullOptions) -> Result<PullReport, OpsError> {
+        self.inner_pull(None::<MultiProgressWrapper>, options).await
+    }
+
+    /// Pulls only the files matching one of the `allow` globs and none of the `ignore` globs.
+    ///
+    /// See [`crate::hub::ModelsCat::pull_with_patterns`].
+    pub async fn pull_with_patterns(
+        &self,
+        allow: &[&str],
+        ignore: &[&str],
+    ) -> Result<PullReport, OpsError> {
+        let options = PullOptions::default()
+            .with_allow_patterns(allow.iter().copied())
+            .with_ignore_patterns(ignore.iter().copied());
+        self.pull_with_options(&options).await
+    }
+
+    /// Pulls the entire repository, then deletes the cached files the hub no longer lists.
+    ///
+    /// See [`crate::hub::ModelsCat::sync`].
+    pub async fn sync(&self, dry_run: bool) -> Result<SyncReport, OpsError> {
+        let blobs = self.repo_files(true).await?.blobs();
+        if dry_run {
+            let (deleted, deleted_bytes) = update::remove_unlisted(&self.repo, &blobs, true)?;
+            return Ok(SyncReport {
+                dry_run,
+                pull: update::plan_pull(&self.repo, blobs, self.validation)?,
+                deleted,
+                deleted_bytes,
+            });
+        }
+        let result = self
+            .pull_files(blobs.clone(), None::<MultiProgressWrapper>, None)
+            .await;
+        let (files, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        let pull = result?;
+
+        let result = update::remove_unlisted(&self.repo, &blobs, false);
+        let (deleted, deleted_bytes) = match result {
+            Ok((ref deleted, bytes)) => (deleted.clone(), bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        if !deleted.is_empty() || result.is_err() {
+            self.record_history(
+                HistoryOperation::Remove,
+                deleted.clone(),
+                deleted_bytes,
+                &result,
+            );
+        }
+        result?;
+        if !deleted.is_empty() {
+            if let Some(ref cache) = self.memory_cache {
+                cache.clear();
+            }
+            blobs::prune(&self.repo)?;
+        }
+        Ok(SyncReport {
+            dry_run,
+            pull,
+            deleted,
+            deleted_bytes,
+        })
+    }
+
+    /// Makes `files`, or every file of the repo when empty, cached and current, and returns
+    /// where they are.
+    ///
+    /// See [`crate::hub::ModelsCat::ensure_ready`].
+    pub(crate) async fn ensure_ready(
+        &self,
+        files: &[String],
+        progress: Option<impl Progress>,
+    ) -> Result<ReadyModel, OpsError> {
+        if self.offline {
+            let miss = OpsError::OfflineMiss {
+                file: self.repo.repo_id().to_string(),
+            };
+            return ready::offline(&self.repo, files, miss);
+        }
+        let listing = match self.repo_files(true).await {
+            Ok(listing) => listing,
+            Err(e @ (OpsError::RequestError(_) | OpsError::Timeout { .. })) => {
+                return ready::offline(&self.repo, files, e);
+            }
+            Err(e) => return Err(e),
+        };
+        let blobs = ready::select(listing.blobs(), files)?;
+        let result = if files.is_empty() {
+            self.pull_files(blobs.clone(), progress, None).await
+        } else {
+            self.fill_snapshots(blobs.clone(), progress, None)
+                .await
+                .map(|(report, _)| report)
+        };
+        let (downloaded, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, downloaded, bytes, &result);
+        result?;
+        ready::online(&self.repo, &blobs)
+    }
+
+    async fn inner_pull(
+        &self,
+        progress: Option<impl Progress>,
+        options: &PullOptions,
+    ) -> Result<PullReport, OpsError> {
+        let result = self.pull_listed(progress, options).await;
+        let (files, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        result
+    }
+
+    async fn pull_listed(
+        &self,
+        progress: Option<impl Progress>,
+        options: &PullOptions,
+    ) -> Result<PullReport, OpsError> {
+        options.validate()?;
+        if self.offline {
+            return offline::pull(&self.repo);
+        }
+        let (blobs, hidden) = options.select(self.repo_files(true).await?.blobs())?;
+        let mut report = self.pull_files(blobs, progress, options.validation).await?;
+        report.hidden = hidden;
+        Ok(report)
+    }
+
+    /// Places `blobs` in their snapshots, confirming cached copies with `validation`, or the
+    /// instance default when `None`, then points `latest` at the newest one.
+    async fn pull_files(
+        &self,
+        blobs: Vec<FileInfo>,
+        progress: Option<impl Progress>,
+        validation: Option<CacheValidation>,
+    ) -> Result<PullReport, OpsError> {
+        let (report, latest) = self.fill_snapshots(blobs, progress, validation).await?;
+        if let Some(revision) = latest {
+            if let Some(ref cache) = self.memory_cache
+                && !prepared::is_active(&self.repo, &revision)
+            {
+                cache.clear();
+            }
+            update_latest(&self.repo, &revision)?;
+            self.log(format_args!("latest -> {revision}"));
+        }
+        Ok(report)
+    }
+
+    /// Places `blobs` in their snapshots and returns the newest revision, leaving the ref and
+    /// `latest` link of the repo untouched.
+    async fn fill_snapshots(
+        &self,
+        blobs: Vec<FileInfo>,
+        mut progress: Option<impl Progress>,
+        validation: Option<CacheValidation>,
+    ) -> Result<(PullReport, Option<String>), OpsError> {
+        let validation = validation.unwrap_or(self.validation);
+        let mut plan = paths::plan(blobs, self.invalid_paths, self.path_rules)?;
+        let blobs = std::mem::take(&mut plan.files);
+        let latest = latest_revision(&blobs);
+        let mut report = PullReport {
+            invalid: plan.invalid.clone(),
+            validation,
+            ..Default::default()
+        };
+        let groups = group_by_revision(blobs.clone());
+        let queued = self.queue_files(&groups, &plan, &mut progress).await?;
+        let mut failures = Vec::new();
+        for (revision, files) in groups {
+            let snapshot_path = self.repo.snapshot_path(&revision);
+            self.reconcile_staging(&snapshot_path)?;
+            std::fs::create_dir_all(&snapshot_path)?;
+            let target = Target {
+                snapshot_path: &snapshot_path,
+                plan: &plan,
+                validation,
+                queued: &queued,
+            };
+            if self.max_concurrency > 1 && files.len() > 1 {
+                let placed = self.place_concurrently(&target, &files, &progress).await?;
+                if !self.record_placements(&mut report, &files, placed, &mut failures)? {
+                    continue;
+                }
+            } else {
+                for fileinfo in &files {
+                    let filepath = target.filepath(fileinfo);
+                    let placed = match lock_unless_current(
+                        snapshot_path.clone(),
+                        &filepath,
+                        fileinfo,
+                        validation,
+                    )
+                    .await?
+                    {
+                        None => self.skip(&target, fileinfo, &mut progress).await,
+                        Some(lock) => {
+                            let placed = self.place_listed(&target, fileinfo, &mut progress).await;
+                            lock.release().await?;
+                            placed
+                        }
+                    };
+                    self.record_placement(&mut report, fileinfo, Some(placed))?;
+                }
+            }
+            paths::record_sanitized(&self.repo, &revision, &files, &plan.sanitized)?;
+            mark_complete(&self.repo, &revision, &files)?;
+        }
+        if !failures.is_empty() {
+            return Err(OpsError::PullFailed { failures });
+        }
+        if let Some(ref latest) = latest {
+            let older = unlinked_into_latest(&self.repo, latest, &blobs, &plan);
+            if !older.is_empty() {
+                let snapshot = self.repo.snapshot_path(latest);
+                let lock = AsyncFsLock::acquire(snapshot, LockOptions::default()).await?;
+                let linked = link_into_latest(&self.repo, latest, &older, &plan);
+                lock.release().await?;
+                linked?;
+            }
+        }
+
+        report.rate_limit = self.rate_limit_status();
+        Ok((report, latest))
+    }
+
+    /// Places `files` with up to [`ModelsCat::with_max_concurrency`] downloads at once, each
+    /// with its own clone of `progress`, locking the snapshot once for all of them.
+    ///
+    /// See [`crate::hub::ModelsCat::place_concurrently`].
+    async fn place_concurrently(
+        &self,
+        target: &Target<'_>,
+        files: &[FileInfo],
+        progress: &Option<impl Progress>,
+    ) -> Result<Vec<Option<Result<Placement, OpsError>>>, OpsError> {
+        let mut placed: Vec<_> = (0..files.len()).map(|_| None).collect();
+        let mut checked = Vec::new();
+        let checks: Vec<_> = futures::stream::iter(files)
+            .map(|fileinfo| check_blocking(target.filepath(fileinfo), fileinfo, target.validation))
+            .buffered(self.max_concurrency)
+            .collect()
+            .await;
+        for (i, check) in checks.into_iter().enumerate() {
+            match check {
+                Ok(check) => checked.push((i, check)),
+                Err(e) => placed[i] = Some(Err(e)),
+            }
+        }
+        let locked = !checked.iter().all(|(_, check)| check.current);
+        let lock = match locked {
+            true => Some(
+                AsyncFsLock::acquire(target.snapshot_path.to_path_buf(), LockOptions::default())
+                    .await?,
+            ),
+            false => None,
+        };
+        let cancelled = AtomicBool::new(false);
+        let mut placing = futures::stream::iter(checked)
+            .map(|(i, check)| {
+                let mut progress = progress.clone();
+                let cancelled = &cancelled;
+                async move {
+                    if cancelled.load(Ordering::SeqCst) {
+                        return (i, None);
+                    }
+                    let fileinfo = &files[i];
+                    let current = match locked {
+                        true => {
+                            let filepath = target.filepath(fileinfo);
+                            recheck_blocking(filepath, fileinfo, target.validation, check).await
+                        }
+                        false => Ok(check.current),
+                    };
+                    let result = match current {
+                        Ok(true) => self.skip(target, fileinfo, &mut progress).await,
+                        Ok(false) => self.place_listed(target, fileinfo, &mut progress).await,
+                        Err(e) => Err(e),
+                    };
+                    if matches!(result, Err(OpsError::Cancelled { .. })) {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }
+                    (i, Some(result))
+                }
+            })
+            .buffer_unordered(self.max_concurrency);
+        while let Some((i, result)) = placing.next().await {
+            placed[i] = result;
+        }
+        drop(placing);
+        if let Some(lock) = lock {
+            lock.release().await?;
+        }
+        Ok(placed)
+    }
+
+    /// Records what became of the `files` of a concurrent placement, returning whether every
+    /// file was placed.
+    ///
+    /// See [`crate::hub::ModelsCat::record_placements`].
+    fn record_placements(
+        &self,
+        report: &mut PullReport,
+        files: &[FileInfo],
+        placed: Vec<Option<Result<Placement, OpsError>>>,
+        failures: &mut Vec<(String, OpsError)>,
+    ) -> Result<bool, OpsError> {
+        let mut complete = true;
+        for (fileinfo, placed) in files.iter().zip(placed) {
+            match placed {
+                Some(Err(e @ OpsError::Cancelled { .. })) => return Err(e),
+                Some(Err(e)) => {
+                    self.log(format_args!("failed {}: {e}", fileinfo.path));
+                    failures.push((fileinfo.path.clone(), e));
+                    complete = false;
+                }
+                placed => self.record_placement(report, fileinfo, placed)?,
+            }
+        }
+        Ok(complete)
+    }
+
+    /// Adds what became of `fileinfo` to `report`, or fails with its error.
+    fn record_placement(
+        &self,
+        report: &mut PullReport,
+        fileinfo: &FileInfo,
+        placed: Option<Result<Placement, OpsError>>,
+    ) -> Result<(), OpsError> {
+        match placed {
+            None => {}
+            Some(Err(e)) => {
+                self.log(format_args!("failed {}: {e}", fileinfo.path));
+                return Err(e);
+            }
+            Some(Ok(Placement::Skipped)) => {
+                self.log(format_args!("skipped {}: up to date", fileinfo.path));
+                report.skipped.push(fileinfo.path.clone());
+            }
+            Some(Ok(Placement::Deduplicated)) => {
+                self.log(format_args!("deduplicated {}", fileinfo.path));
+                report.record_deduplicated(&fileinfo.path, fileinfo.size as u64);
+            }
+            Some(Ok(Placement::Downloaded { progress_degraded })) => {
+                self.log(format_args!("downloaded {}", fileinfo.path));
+                report.downloaded.push(fileinfo.path.clone());
+                report.downloaded_bytes += fileinfo.size as u64;
+                if progress_degraded {
+                    report.progress_degraded.push(fileinfo.path.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the current `fileinfo` in place, completing its queued progress.
+    async fn skip(
+        &self,
+        target: &Target<'_>,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<Placement, OpsError> {
+        if target.queued.contains(&fileinfo.path) {
+            self.finish_queued(fileinfo, progress).await?;
+        }
+        Ok(Placement::Skipped)
+    }
+
+    /// Places `fileinfo` in its snapshot, which the caller has locked, by linking a copy of it
+    /// from elsewhere in the cache or else downloading it.
+    async fn place_listed(
+        &self,
+        target: &Target<'_>,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<Placement, OpsError> {
+        let hub_revision = &fileinfo.revision;
+        let filepath = target.filepath(fileinfo);
+        if self.link_identical(fileinfo, &filepath)? {
+            provenance::forget(&self.repo, hub_revision, &fileinfo.path)?;
+            if target.queued.contains(&fileinfo.path) {
+                self.finish_queued(fileinfo, progress).await?;
+            }
+            return Ok(Placement::Deduplicated);
+        }
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            fileinfo.path
+        );
+        let (record, progress_degraded) = self
+            .download_file(&file_url, &filepath, &HubFileInfo::from(fileinfo), progress)
+            .await?;
+        provenance::record(&self.repo, hub_revision, &fileinfo.path, record)?;
+        if self.global_dedup {
+            dedup::record_download(
+                self.repo.cache_home(),
+                fileinfo.sha256.as_deref(),
+                &filepath,
+            )?;
+        }
+        Ok(Placement::Downloaded { progress_degraded })
+    }
+
+    /// Pulls the newest revision into its own snapshot, leaving the ref and `latest` link on
+    /// the current one, or returns `None` when `latest` already points at it.
+    ///
+    /// See [`crate::hub::ModelsCat::prepare_update`].
+    pub async fn prepare_update(&self) -> Result<Option<PreparedSnapshot>, OpsError> {
+        let result = self.prepare_listed().await;
+        let (files, bytes) = match result {
+            Ok(Some(ref prepared)) => (
+                prepared.report.downloaded.clone(),
+                prepared.report.downloaded_bytes,
+            ),
+            _ => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        result
+    }
+
+    async fn prepare_listed(&self) -> Result<Option<PreparedSnapshot>, OpsError> {
+        let (blobs, hidden) =
+            PullOptions::default().select(self.repo_files(true).await?.blobs())?;
+        if latest_revision(&blobs).is_none_or(|revision| prepared::is_active(&self.repo, &revision))
+        {
+            return Ok(None);
+        }
+        let (mut report, latest) = self
+            .fill_snapshots(blobs, None::<MultiProgressWrapper>, None)
+            .await?;
+        let Some(revision) = latest else {
+            return Ok(None);
+        };
+        report.hidden = hidden;
+        self.log(format_args!("prepared {revision}"));
+        Ok(Some(PreparedSnapshot {
+            repo: self.repo.clone(),
+            revision,
+            report,
+            memory_cache: self.memory_cache.clone(),
+        }))
+    }
+
+    /// Download a file from the repository, returning its path in the snapshot.
+    pub async fn download(&self, filename: &str) -> Result<PathBuf, OpsError> {
+        Ok(self
+            .inner_download(
+                filename,
+                None::<ProgressBarWrapper>,
+                &DownloadOptions::default(),
+            )
+            .await?
+            .path)
+    }
+
+    /// Download a file from the repository with a progress, returning its path in the snapshot.
+    pub async fn download_with_progress(
+        &self,
+        filename: &str,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        Ok(self
+            .inner_download(filename, Some(progress), &DownloadOptions::default())
+            .await?
+            .path)
+    }
+
+    /// Downloads a file from the repository like [`ModelsCat::download_with_progress`], until
+    /// `token` is cancelled. A cancelled download stops reading, removes what was transferred,
+    /// releases its lock and fails with [`OpsError::Cancelled`].
+    pub async fn download_with_cancel(
+        &self,
+        filename: &str,
+        token: CancellationToken,
+        progress: Option<impl Progress>,
+    ) -> Result<PathBuf, OpsError> {
+        let options = DownloadOptions::default();
+        tokio::select! {
+            biased;
+            () = token.cancelled() => Err(OpsError::Cancelled {
+                file: filename.to_string(),
+            }),
+            result = self.inner_download(filename, progress, &options) => {
+                Ok(result?.path)
+            }
+        }
+    }
+
+    /// Makes sure `filename` is cached and current, downloading it if needed, and returns its
+    /// local path and hub metadata.
+    ///
+    /// See [`crate::hub::ModelsCat::resolve`].
+    pub async fn resolve(&self, filename: &str) -> Result<Resolution, OpsError> {
+        self.inner_download(
+            filename,
+            None::<ProgressBarWrapper>,
+            &DownloadOptions::default(),
+        )
+        .await
+    }
+
+    /// Downloads a specific file from the hub, with `options` overriding instance settings for
+    /// this download only.
+    ///
+    /// See [`crate::hub::ModelsCat::download_with_options`].
+    pub async fn download_with_options(
+        &self,
+        filename: &str,
+        options: DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        Ok(self
+            .inner_download(filename, None::<ProgressBarWrapper>, &options)
+            .await?
+            .path)
+    }
+
+    /// Downloads a specific file from the hub like [`ModelsCat::download_with_options`], with
+    /// progress tracking.
+    ///
+    /// See [`crate::hub::ModelsCat::download_with_options_and_progress`].
+    pub async fn download_with_options_and_progress(
+        &self,
+        filename: &str,
+        options: DownloadOptions,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        Ok(self
+            .inner_download(filename, Some(progress), &options)
+            .await?
+            .path)
+    }
+
+    /// Whether the cached copy of `filename` is current under `validation`.
+    ///
+    /// See [`crate::hub::ModelsCat::verify_with_level`].
+    pub async fn verify_with_level(
+        &self,
+        filename: &str,
+        validation: CacheValidation,
+    ) -> Result<bool, OpsError> {
+        let repo_files = self.repo_files(false).await?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let mut filepath = self.repo.snapshot_path(&fileinfo.revision);
+        for part in local_name.split('/') {
+            filepath.push(part);
+        }
+        is_verified(&filepath, fileinfo, validation)
+    }
+
+    /// Checks every cached file of the repo against the listing.
+    ///
+    /// See [`crate::hub::ModelsCat::verify`].
+    pub async fn verify(&self) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(None::<ProgressBarWrapper>, false).await
+    }
+
+    /// Checks every cached file of the repo against the listing, with progress tracking.
+    ///
+    /// See [`crate::hub::ModelsCat::verify_with_progress`].
+    pub async fn verify_with_progress(
+        &self,
+        progress: impl Progress,
+    ) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(Some(progress), false).await
+    }
+
+    /// Compares the cache with the current hub listing without downloading anything.
+    ///
+    /// See [`crate::hub::ModelsCat::check_for_update`].
+    pub async fn check_for_update(&self) -> Result<UpdateStatus, OpsError> {
+        update::check(&self.repo, self.repo_files(true).await?.blobs())
+    }
+
+    /// Checks the cached copy of `filename` against the listing.
+    ///
+    /// See [`crate::hub::ModelsCat::verify_file`].
+    pub async fn verify_file(&self, filename: &str) -> Result<FileStatus, OpsError> {
+        let repo_files = self.repo_files(false).await?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let filepath = snapshot_file(&self.repo.snapshot_path(&fileinfo.revision), &local_name);
+        verify::status(&filepath, fileinfo)
+    }
+
+    /// Verifies the repo, then downloads the missing and corrupt files again.
+    ///
+    /// See [`crate::hub::ModelsCat::repair`].
+    pub async fn repair(&self) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(None::<ProgressBarWrapper>, true).await
+    }
+
+    /// Repairs the repo with progress tracking.
+    ///
+    /// See [`crate::hub::ModelsCat::repair_with_progress`].
+    pub async fn repair_with_progress(
+        &self,
+        progress: impl Progress,
+    ) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(Some(progress), true).await
+    }
+
+    /// Where and when the cached copy of `filename` was downloaded.
+    ///
+    /// See [`crate::hub::ModelsCat::provenance`].
+    pub async fn provenance(&self, filename: &str) -> Result<Provenance, OpsError> {
+        let revision = match resolved::cached(&self.repo, filename) {
+            Some((_, file)) => file.revision,
+            None => {
+                let repo_files = self.repo_files(false).await?;
+                let fileinfo = repo_files.get_file_info(filename)?;
+                let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+                let mut filepath = self.repo.snapshot_path(&fileinfo.revision);
+                for part in local_name.split('/') {
+                    filepath.push(part);
+                }
+                if !filepath.is_file() {
+                    return Err(OpsError::HubError(format!("{filename} is not cached")));
+                }
+                fileinfo.revision.clone()
+            }
+        };
+        Ok(provenance::lookup(&self.repo, &revision, filename))
+    }
+
+    /// Downloads a file if needed and returns its contents.
+    ///
+    /// See [`crate::hub::ModelsCat::fetch_bytes`].
+    pub async fn fetch_bytes(&self, filename: &str) -> Result<Arc<[u8]>, OpsError> {
+        if let Some(ref cache) = self.memory_cache {
+            // Keyed by the commit the file resolves to, so a branch that moved on the hub is not
+            // served from its previous commit.
+            let commit = match self.offline {
+                true => offline::resolve(&self.repo, filename)?.revision,
+                false => self
+                    .repo_files(false)
+                    .await?
+                    .get_file_info(filename)?
+                    .revision
+                    .clone(),
+            };
+            if let Some(bytes) = cache.get(&commit, filename) {
+                return Ok(bytes);
+            }
+        }
+        let resolution = self
+            .inner_download(
+                filename,
+                None::<ProgressBarWrapper>,
+                &DownloadOptions::default(),
+            )
+            .await?;
+        let bytes: Arc<[u8]> = tokio::fs::read(&resolution.path).await?.into();
+        if let Some(ref cache) = self.memory_cache {
+            cache.insert(&resolution.revision, filename, bytes.clone());
+        }
+        Ok(bytes)
+    }
+
+    /// Downloads a JSON file if needed and deserializes it.
+    ///
+    /// See [`crate::hub::ModelsCat::get_json`].
+    pub async fn get_json<T: DeserializeOwned>(&self, filename: &str) -> Result<T, OpsError> {
+        let bytes = self.fetch_bytes(filename).await?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| OpsError::hub(format!("failed to parse {filename}"), e))
+    }
+
+    /// Downloads a file straight into `dir` under its hub-relative filename or
+    /// `options.save_as`, without touching the cache.
+    ///
+    /// See [`crate::hub::ModelsCat::download_to`].
+    pub async fn download_to(
+        &self,
+        filename: &str,
+        dir: impl AsRef<Path>,
+        options: DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        let name = options.save_as.as_deref().unwrap_or(filename);
+        if options.bypass_cache {
+            let repo_files = self.repo_files(false).await?;
+            let fileinfo = repo_files.get_file_info(filename)?;
+            let filepath = export::join_relative(dir.as_ref(), name)?;
+            return self.download_direct(fileinfo, &filepath, &options).await;
+        }
+        let filepath = self
+            .inner_download(filename, None::<ProgressBarWrapper>, &options)
+            .await?
+            .path;
+        export::export_file(&filepath, dir.as_ref(), name, options.export_mode)
+    }
+
+    /// Streams `filename` from the hub into `writer` without touching the cache, and returns
+    /// the bytes written.
+    ///
+    /// See [`crate::hub::ModelsCat::download_to_writer`].
+    pub async fn download_to_writer(
+        &self,
+        filename: &str,
+        mut writer: impl AsyncWrite + Unpin,
+        mut progress: Option<impl Progress>,
+    ) -> Result<u64, OpsError> {
+        if self.offline {
+            return Err(OpsError::OfflineMiss {
+                file: filename.to_string(),
+            });
+        }
+        let repo_files = self.repo_files(false).await?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            fileinfo.path
+        );
+        if let Some(prg) = progress.as_mut() {
+            let unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            self.progress_errors
+                .handle(filename, prg.on_queued(std::slice::from_ref(&unit)).await)?;
+        }
+
+        let _slot = self.transfer_slot(filename).await;
+        let mut response = self.get_file(&file_url, 0).await?;
+        let mut unit = match response.content_length() {
+            Some(content_length) => ProgressUnit::new(fileinfo.path.clone(), content_length),
+            None => ProgressUnit::without_total(fileinfo.path.clone()),
+        };
+        let mut reporting = true;
+        if let Some(prg) = progress.as_mut() {
+            reporting = self
+                .progress_errors
+                .handle(filename, prg.on_start(&unit).await)?;
+        }
+        let mut verifier = archive::Verifier::new(fileinfo);
+        let mut written = 0;
+        while let Some(chunk) = response.chunk().await? {
+            verifier.update(&chunk)?;
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            if let Some(throttle) = &self.throttle {
+                throttle.pace_async(chunk.len() as u64).await;
+            }
+            self.check_cancelled(filename)?;
+            if reporting && let Some(prg) = progress.as_mut() {
+                unit.update(written);
+                reporting = self
+                    .progress_errors
+                    .handle_control(filename, prg.on_progress_control(&unit).await)?;
+            }
+        }
+        writer.flush().await?;
+        if let Err(e) = verifier.finish() {
+            self.log(format_args!("failed {filename}: {e}"));
+            return Err(e.into());
+        }
+        if reporting && let Some(prg) = progress.as_mut() {
+            self.progress_errors
+                .handle(filename, prg.on_finish(&unit).await)?;
+        }
+        self.log(format_args!("streamed {filename}"));
+        Ok(written)
+    }
+
+    /// Downloads every file of the repository straight into `dir`, bypassing the cache.
+    ///
+    /// See [`crate::hub::ModelsCat::pull_to`].
+    pub async fn pull_to(&self, dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, OpsError> {
+        let blobs = self.repo_files(true).await?.blobs();
+        let plan = paths::plan(blobs, self.invalid_paths, self.path_rules)?;
+        let options = DownloadOptions::default();
+        let mut written = Vec::new();
+        for fileinfo in &plan.files {
+            let filepath = export::join_relative(dir.as_ref(), plan.local_path(fileinfo))?;
+            written.push(self.download_direct(fileinfo, &filepath, &options).await?);
+        }
+        Ok(written)
+    }
+
+    /// Downloads `fileinfo` to `filepath` outside the cache.
+    ///
+    /// See [`crate::hub::ModelsCat::download_direct`].
+    async fn download_direct(
+        &self,
+        fileinfo: &FileInfo,
+        filepath: &Path,
+        options: &DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        if self.offline {
+            return Err(OpsError::OfflineMiss {
+                file: fileinfo.path.clone(),
+            });
+        }
+        let mut file = HubFileInfo::from(fileinfo);
+        if !options.verify {
+            file.sha256 = None;
+        }
+        if !options.resume {
+            StagedFile::discard(filepath, &file)?;
+        }
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            fileinfo.path
+        );
+        let downloaded = self
+            .download_file(&file_url, filepath, &file, &mut None::<ProgressBarWrapper>)
+            .await;
+        if let Err(e) = downloaded {
+            self.log(format_args!("failed {}: {e}", fileinfo.path));
+            return Err(e);
+        }
+        self.log(format_args!(
+            "downloaded {} to {}",
+            fileinfo.path,
+            filepath.display()
+        ));
+        Ok(filepath.to_path_buf())
+    }
+
+    /// Places every file of the last pulled snapshot in `dir` under `mode`, renaming the
+    /// hub-relative filenames found in `rename`.
+    ///
+    /// See [`crate::hub::ModelsCat::export_to`].
+    pub fn export_to(
+        &self,
+        dir: impl AsRef<Path>,
+        rename: &BTreeMap<String, String>,
+        mode: ExportMode,
+    ) -> Result<Vec<PathBuf>, OpsError> {
+        let snapshot = self
+            .repo
+            .latest_snapshot()
+            .ok_or_else(|| OpsError::HubError("No snapshot pulled yet".into()))?;
+        let revision = snapshot
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let hub_names = paths::hub_names(&self.repo, &revision);
+        export::export_snapshot(&snapshot, dir.as_ref(), rename, &hub_names, mode)
+    }
+
+    /// Places every file of the cached snapshot in `dir` under `mode`.
+    ///
+    /// See [`crate::hub::ModelsCat::export_snapshot`].
+    pub fn export_snapshot(
+        &self,
+        dir: impl AsRef<Path>,
+        mode: ExportMode,
+        overwrite: bool,
+    ) -> Result<Vec<PathBuf>, OpsError> {
+        export::materialize(&self.repo, dir.as_ref(), mode, overwrite)
+    }
+
+    /// Absolute paths of the cached files whose hub-relative filename matches the glob
+    /// `pattern`, sorted by filename.
+    ///
+    /// See [`crate::hub::ModelsCat::local_files_matching`].
+    pub fn local_files_matching(&self, pattern: &str) -> Result<Vec<PathBuf>, OpsError> {
+        let listing = cached_listing(&self.listing);
+        matching::local_files_matching(&self.repo, pattern, listing.as_deref())
+    }
+
+    /// Places `fileinfo` at `filepath` from an identical file already in the cache.
+    ///
+    /// See [`crate::hub::ModelsCat::link_identical`].
+    fn link_identical(&self, fileinfo: &FileInfo, filepath: &Path) -> Result<bool, OpsError> {
+        let sha256 = fileinfo.sha256.as_deref();
+        let size = fileinfo.size as u64;
+        Ok(
+            (self.blob_store && blobs::try_link(&self.repo, sha256, size, filepath)?)
+                || (self.global_dedup
+                    && dedup::try_link(self.repo.cache_home(), sha256, size, filepath)?),
+        )
+    }
+
+    async fn inner_download(
+        &self,
+        filename: &str,
+        progress: Option<impl Progress>,
+        options: &DownloadOptions,
+    ) -> Result<Resolution, OpsError> {
+        let result = self.place_file(filename, progress, options).await;
+        let bytes = match result {
+            Ok(ref resolution) if resolution.downloaded => resolution.size,
+            _ => 0,
+        };
+        let files = vec![filename.to_string()];
+        self.record_history(HistoryOperation::Download, files, bytes, &result);
+        result
+    }
+
+    async fn place_file(
+        &self,
+        filename: &str,
+        mut progress: Option<impl Progress>,
+        options: &DownloadOptions,
+    ) -> Result<Resolution, OpsError> {
+        let force = options.force_download;
+        if self.offline {
+            if force {
+                return Err(OpsError::OfflineMiss {
+                    file: filename.to_string(),
+                });
+            }
+            return offline::resolve(&self.repo, filename);
+        }
+        // An explicit request to hash is not satisfied by a HEAD request.
+        let hash = options.validation == Some(CacheValidation::Sha256);
+        let validation = options.validation.unwrap_or(self.validation);
+        let mut etag = None;
+        if !hash && !force {
+            match self.head_check(filename).await {
+                HeadCheck::Current(filepath, file) => {
+                    self.log(format_args!("skipped {filename}: up to date"));
+                    return Ok(file.resolution(filepath, false));
+                }
+                HeadCheck::Unconfirmed(reported) => etag = reported,
+            }
+        }
+        let repo_files = self.repo_files(false).await?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let hub_revision = fileinfo.revision.clone();
+        let commit = latest_revision(&repo_files.data.files);
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let mut resolved_file = ResolvedFile {
+            revision: hub_revision.clone(),
+            local_path: local_name.clone(),
+            size: fileinfo.size as u64,
+            sha256: fileinfo.sha256.clone(),
+            etag,
+        };
+        if local_name != fileinfo.path {
+            let sanitized = HashMap::from([(fileinfo.path.clone(), local_name.clone())]);
+            let files = std::slice::from_ref(fileinfo);
+            paths::record_sanitized(&self.repo, &hub_revision, files, &sanitized)?;
+        }
+
+        let snapshot_path = self.repo.snapshot_path(&hub_revision);
+        std::fs::create_dir_all(&snapshot_path)?;
+        self.reconcile_staging(&snapshot_path)?;
+        let filepath = snapshot_file(&snapshot_path, &local_name);
+
+        let current = match force {
+            true => Some(AsyncFsLock::acquire(snapshot_path, LockOptions::default()).await?),
+            false => lock_unless_current(snapshot_path, &filepath, fileinfo, validation).await?,
+        };
+        let Some(lock) = current else {
+            self.repo.create_ref(&hub_revision)?;
+            let resolution = resolved_file.resolution(filepath, false);
+            resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+            self.log(format_args!("skipped {filename}: up to date"));
+            return Ok(resolution);
+        };
+        // A forced download must not link the suspect copy back in place.
+        if !force && self.link_identical(fileinfo, &filepath)? {
+            provenance::forget(&self.repo, &hub_revision, filename)?;
+            lock.release().await?;
+            let resolution = resolved_file.resolution(filepath, false);
+            resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+            self.log(format_args!("deduplicated {filename}"));
+            return Ok(resolution);
+        }
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            filename
+        );
+
+        let mut file = HubFileInfo::from(fileinfo);
+        // Without a listed sha256, the download is written without checking it.
+        if !options.verify {
+            file.sha256 = None;
+        }
+        if !options.resume {
+            StagedFile::discard(&filepath, &file)?;
+        }
+        if let Some(prg) = progress.as_mut() {
+            let unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            self.progress_errors
+                .handle(filename, prg.on_queued(std::slice::from_ref(&unit)).await)?;
+        }
+        let (record, progress_degraded) = match self
+            .download_file(&file_url, &filepath, &file, &mut progress)
+            .await
+        {
+            Ok(downloaded) => downloaded,
+            Err(e) => {
+                self.log(format_args!("failed {filename}: {e}"));
+                return Err(e);
+            }
+        };
+        if record.etag.is_some() {
+            resolved_file.etag = record.etag.clone();
+        }
+        provenance::record(&self.repo, &hub_revision, &fileinfo.path, record)?;
+        // An unchecked download is neither trusted as complete nor offered to other repos.
+        match options.verify {
+            true => mark_complete(&self.repo, &hub_revision, std::slice::from_ref(fileinfo))?,
+            false => unmark_complete(&self.repo, &hub_revision, &fileinfo.path)?,
+        }
+        self.log(format_args!("downloaded {filename}"));
+        if self.global_dedup && options.verify {
+            dedup::record_download(
+                self.repo.cache_home(),
+                fileinfo.sha256.as_deref(),
+                &filepath,
+            )?;
+        }
+
+        lock.release().await?;
+        self.repo.create_ref(&hub_revision)?;
+        let mut resolution = resolved_file.resolution(filepath, true);
+        resolution.progress_degraded = progress_degraded;
+        resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+        Ok(resolution)
+    }
+
+    /// Announces the files of `groups` a pull may download to `progress`, before any
+    /// transfer starts. Files a finished pull recorded as complete are left out. Returns the
+    /// announced paths.
+    async fn queue_files(
+        &self,
+        groups: &BTreeMap<String, Vec<FileInfo>>,
+        plan: &paths::PathPlan,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<HashSet<String>, OpsError> {
+        let Some(prg) = progress.as_mut() else {
+            return Ok(HashSet::new());
+        };
+        let mut units = Vec::new();
+        for (revision, files) in groups {
+            let completed = completed_files(&self.repo, revision);
+            for fileinfo in files {
+                let snapshot_path = self.repo.snapshot_path(&fileinfo.revision);
+                let filepath = snapshot_file(&snapshot_path, plan.local_path(fileinfo));
+                if !is_complete(&completed, &filepath, fileinfo) {
+                    units.push(ProgressUnit::new(
+                        fileinfo.path.clone(),
+                        fileinfo.size as u64,
+                    ));
+                }
+            }
+        }
+        self.progress_errors
+            .handle(self.repo.repo_id(), prg.on_queued(&units).await)?;
+        Ok(units.into_iter().map(|unit| unit.filename).collect())
+    }
+
+    async fn inner_verify(
+        &self,
+        mut progress: Option<impl Progress>,
+        repair: bool,
+    ) -> Result<VerifyReport, OpsError> {
+        let (checks, extra) = verify::plan(&self.repo, self.repo_files(false).await?.blobs())?;
+        let mut report = VerifyReport {
+            extra,
+            ..Default::default()
+        };
+        if let Some(prg) = progress.as_mut() {
+            let units: Vec<ProgressUnit> = checks
+                .iter()
+                .map(|c| ProgressUnit::new(c.fileinfo.path.clone(), c.fileinfo.size as u64))
+                .collect();
+            self.progress_errors
+                .handle(self.repo.repo_id(), prg.on_queued(&units).await)?;
+        }
+        let mut broken = Vec::new();
+        for check in checks {
+            let status = self.check_cached(&check, &mut progress).await?;
+            report.record(&check.fileinfo.path, status);
+            if status == FileStatus::Corrupt && repair {
+                verify::remove_corrupt(&check.filepath)?;
+            }
+            if status != FileStatus::Verified {
+                broken.push(check.fileinfo);
+            }
+        }
+        if repair && !broken.is_empty() {
+            let (pulled, _) = self
+                .fill_snapshots(broken, progress, Some(CacheValidation::Sha256))
+                .await?;
+            report.repaired = [pulled.downloaded, pulled.deduplicated].concat();
+            report.repaired.sort();
+        }
+        Ok(report)
+    }
+
+    /// The status of the cached copy of `check`, reporting its hashing to `progress`.
+    ///
+    /// See [`crate::hub::ModelsCat::check_cached`].
+    async fn check_cached(
+        &self,
+        check: &verify::Check,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<FileStatus, OpsError> {
+        let (fileinfo, filepath) = (&check.fileinfo, &check.filepath);
+        if let Some(status) = verify::status_by_size(filepath, fileinfo)? {
+            self.finish_queued(fileinfo, progress).await?;
+            return Ok(status);
+        }
+        let Some(prg) = progress.as_mut() else {
+            return verify::status(filepath, fileinfo);
+        };
+        let mut unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+        let mut reporting = self
+            .progress_errors
+            .handle(&fileinfo.path, prg.on_start(&unit).await)?;
+        let mut hashing = verify::Hashing::open(filepath)?;
+        while let Some(hashed) = hashing.step()? {
+            if reporting {
+                unit.update(hashed);
+                reporting = self
+                    .progress_errors
+                    .handle_control(&fileinfo.path, prg.on_progress_control(&unit).await)?;
+            }
+        }
+        if reporting {
+            self.progress_errors
+                .handle(&fileinfo.path, prg.on_finish(&unit).await)?;
+        }
+        Ok(match hashing.matches(fileinfo) {
+            true => FileStatus::Verified,
+            false => FileStatus::Corrupt,
+        })
+    }
+
+    /// Reports a queued file found current or deduplicated as finished, without a start.
+    async fn finish_queued(
+        &self,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(), OpsError> {
+        if let Some(prg) = progress.as_mut() {
+            let mut unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            unit.update(fileinfo.size as u64);
+            self.progress_errors
+                .handle(&fileinfo.path, prg.on_finish(&unit).await)?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [`OpsError::Cancelled`] once the flag of [`ModelsCat::with_cancel_flag`]
+    /// is set.
+    fn check_cancelled(&self, filename: &str) -> Result<(), OpsError> {
+        match &self.cancel {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(OpsError::Cancelled {
+                file: filename.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a slot of the process-wide transfer limit, if one is configured.
+    ///
+    /// See [`crate::hub::ModelsCat::transfer_slot`].
+    async fn transfer_slot(&self, filename: &str) -> Option<TransferSlot> {
+        let slots = self.transfer_slots.as_ref()?;
+        if let Some(slot) = slots.try_acquire() {
+            return Some(slot);
+        }
+        self.log(format_args!("waiting for a transfer slot for {filename}"));
+        Some(slots.acquire_async().await)
+    }
+
+    /// Recovers or discards the downloads interrupted in `snapshot_path`.
+    ///
+    /// See [`crate::hub::ModelsCat::reconcile_staging`].
+    fn reconcile_staging(&self, snapshot_path: &Path) -> Result<(), OpsError> {
+        for (path, recovery) in staging::reconcile(snapshot_path)? {
+            match recovery {
+                Recovery::Promoted => self.log(format_args!("recovered {}", path.display())),
+                Recovery::Discarded => self.log(format_args!("discarded {}", path.display())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads a file from a URL with progress tracking, retrying transient failures.
+    ///
+    /// See [`crate::hub::ModelsCat::download_file`].
+    async fn download_file(
+        &self,
+        file_url: &str,
+        filepath: &Path,
+        file: &HubFileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(ProvenanceRecord, bool), OpsError> {
+        let mut retries = 0;
+        loop {
+            let err = match self.download_once(file_url, filepath, file, progress).await {
+                Ok(downloaded) => return Ok(downloaded),
+                Err(e) => e,
+            };
+            let Some(delay) = self.retry.delay(retries, &err) else {
+                return Err(err);
+            };
+            retries += 1;
+            self.log(format_args!("retrying {} in {delay:?}: {err}", file.path));
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Downloads a file from a URL with progress tracking, once.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_url` - The URL of the file to download
+    /// * `filepath` - The destination path where the file will be saved
+    /// * `file` - The listed file, whose `path` is the full filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`
+    /// * `progress` - Optional progress tracker implementing the `Progress` trait
+    ///
+    /// Returns the provenance of the download, and whether progress reporting stopped early
+    /// under [`ProgressErrorPolicy::Ignore`].
+    async fn download_once(
+        &self,
+        file_url: &str,
+        filepath: &Path,
+        file: &HubFileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(ProvenanceRecord, bool), OpsError> {
+        let parent = filepath
+            .parent() // 直接获取父目录
+            .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+        tokio::fs::create_dir_all(parent).await?;
+        // Picks up where an interrupted download left off. Removed along with its sidecar if
+        // the download fails other than by a dropped connection.
+        let mut staged = match StagedFile::resume(filepath, file)? {
+            Some(staged) => staged,
+            None => StagedFile::create(filepath, file)?,
+        };
+        let mut offset = staged.written();
+
+        let _slot = self.transfer_slot(&file.path).await;
+        if offset > 0 {
+            self.log(format_args!("resuming {} at {offset} bytes", file.path));
+        }
+        let mut response = match self.get_file(file_url, offset).await {
+            Ok(response) => response,
+            Err(e) if offset > 0 => {
+                staged.suspend(offset)?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        if offset > 0 && !resumes_at(response.status(), response.headers(), offset) {
+            staged.restart()?;
+            offset = 0;
+            if response.status() != StatusCode::OK {
+                response = self.get_file(file_url, 0).await?;
+            }
+        }
+        let served_by = response.url().to_string();
+        let etag = resolved::etag(response.headers());
+        let headers = quarantine::headers(response.headers());
+        // Gateways using chunked transfer encoding send no length, leaving the size unknown to
+        // progress until the end.
+        let mut unit = match response.content_length() {
+            Some(content_length) => ProgressUnit::new(file.path.clone(), offset + content_length),
+            None => ProgressUnit::without_total(file.path.clone()),
+        };
+        unit.update(offset);
+        let mut reporting = true;
+        if let Some(prg) = progress.as_mut() {
+            reporting = self
+                .progress_errors
+                .handle(&file.path, prg.on_start(&unit).await)?;
+        }
+
+        let mut downloaded = offset;
+        let mut hasher = match offset {
+            0 => Sha256::new(),
+            _ => staged.hasher()?,
+        };
+        {
+            let mut staged_file = tokio::fs::OpenOptions::new()
+                .append(true)
+                .open(staged.path())
+                .await?;
+            let mut buf_write =
+                tokio::io::BufWriter::with_capacity(self.write_buffer_size, &mut staged_file);
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(e) => {
+                        // Keeps what arrived for the next try to resume from.
+                        buf_write.flush().await?;
+                        drop(buf_write);
+                        staged.suspend(downloaded)?;
+                        return Err(e.into());
+                    }
+                };
+                buf_write.write_all(&chunk).await?;
+                hasher.update(&chunk);
+                let previous = downloaded;
+                downloaded += chunk.len() as u64;
+                if downloaded / CHECKPOINT_INTERVAL > previous / CHECKPOINT_INTERVAL {
+                    buf_write.flush().await?;
+                    staged.checkpoint(downloaded)?;
+                }
+
+                if let Some(throttle) = &self.throttle {
+                    throttle.pace_async(chunk.len() as u64).await;
+                }
+                self.check_cancelled(&file.path)?;
+                if reporting && let Some(prg) = progress.as_mut() {
+                    unit.update(downloaded);
+                    reporting = self
+                        .progress_errors
+                        .handle_control(&file.path, prg.on_progress_control(&unit).await)?;
+                }
+            }
+            buf_write.flush().await?;
+            // The blob must be durable before it replaces the old one, so readers only ever see
+            // the complete old or the complete new file.
+            staged_file.sync_all().await?;
+        }
+        let sha256 = format!("{:x}", hasher.finalize());
+        if file
+            .sha256
+            .as_ref()
+            .is_some_and(|expected| *expected != sha256)
+        {
+            return Err(self.reject_download(staged, file, sha256, served_by, headers));
+        }
+        // A rejected download is deleted along with `staged`.
+        for validator in &self.validators {
+            validator
+                .validate(staged.path(), file)
+                .await
+                .map_err(|e| validate::rejected(file, e))?;
+        }
+        // Lets a crash before the rename be recovered from, see `staging::reconcile`.
+        staged.checkpoint(downloaded)?;
+        // With the blob store, the download becomes the blob its snapshot file links to.
+        match self.blob_store && filepath.starts_with(self.repo.cache_dir()) {
+            true => blobs::store(&self.repo, &sha256, staged, filepath)?,
+            false => staged.persist(filepath)?,
+        }
+
+        if reporting && let Some(prg) = progress.as_mut() {
+            reporting = self
+                .progress_errors
+                .handle(&file.path, prg.on_finish(&unit).await)?;
+        }
+        let record = ProvenanceRecord::new(&self.endpoint, &served_by, sha256, etag);
+        Ok((record, !reporting))
+    }
+
+    /// Sends the GET request of a download, for the bytes from `offset` on when it is not 0.
+    ///
+    /// See [`crate::hub::ModelsCat::get_file`].
+    async fn get_file(&self, file_url: &str, offset: u64) -> Result<reqwest::Response, OpsError> {
+        self.pace().await;
+        let mut request = self.client.get(file_url);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+        let response = request.send().await?;
+        self.observe_rate_limit(RateLimitStatus::from_headers(response.headers()));
+        if let Some(e) = OpsError::unauthorized(response.status(), file_url)
+            .or_else(|| retry::status_error(response.status(), response.headers(), file_url))
+        {
+            return Err(e);
+        }
+        Ok(response)
+    }
+
+    /// The error of a download whose sha256 is not the listed one.
+    ///
+    /// See [`crate::hub::ModelsCat::reject_download`].
+    fn reject_download(
+        &self,
+        staged: StagedFile,
+        file: &HubFileInfo,
+        actual: String,
+        url: String,
+        headers: Vec<(String, String)>,
+    ) -> OpsError {
+        let report = QuarantineReport {
+            file: file.path.clone(),
+            revision: file.revision.clone(),
+            expected: file.sha256.clone().unwrap_or_default(),
+            actual,
+            url,
+            endpoint: self.endpoint.clone(),
+            headers,
+        };
+        let err = quarantine::mismatch(
+            self.repo.cache_home(),
+            self.quarantine,
+            staged,
+            file,
+            report,
+        );
+        if let OpsError::ChecksumMismatch {
+            quarantined: Some(ref path),
+            ..
+        } = err
+        {
+            self.log(format_args!(
+                "quarantined {} at {}",
+                file.path,
+                path.display()
+            ));
+        }
+        err
+    }
+
+    /// Confirms a file placed by an earlier download with a HEAD request.
+    ///
+    /// See [`crate::hub::ModelsCat::head_check`].
+    async fn head_check(&self, filename: &str) -> HeadCheck {
+        let Some((filepath, file)) = resolved::cached(&self.repo, filename) else {
+            return HeadCheck::Unconfirmed(None);
+        };
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            filename
+        );
+        self.pace().await;
+        let mut request = self.client.head(file_url);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        let Ok(response) = request.send().await.and_then(|r| r.error_for_status()) else {
+            return HeadCheck::Unconfirmed(None);
+        };
+        self.observe_rate_limit(RateLimitStatus::from_headers(response.headers()));
+        if !resolved::matches(&file, response.headers()) {
+            return HeadCheck::Unconfirmed(resolved::etag(response.headers()));
+        }
+        HeadCheck::Current(filepath, file)
+    }
+
+    /// Fetches the repo listing, reusing the previous one for up to [`super::LISTING_TTL`] unless
+    /// `refresh` is set.
+    async fn repo_files(&self, refresh: bool) -> Result<Arc<ApiResponse>, OpsError> {
+        if !refresh && let Some(files) = cached_listing(&self.listing) {
+            return Ok(files);
+        }
+        if self.offline {
+            return Err(OpsError::HubError(format!(
+                "the listing of {} is not fetched offline",
+                self.repo.repo_id()
+            )));
+        }
+        self.pace().await;
+        let files = Arc::new(
+            asynchronous::get_repo_files(
+                &self.client,
+                &self.endpoint,
+                self.token.as_deref(),
+                &self.repo,
+            )
+            .await?,
+        );
+        self.observe_rate_limit(files.rate_limit);
+        *self.listing.lock().unwrap() = Some((Instant::now(), files.clone()));
+        Ok(files)
+    }
+
+    /// The last `limit` pulls, downloads and removals of the repo, oldest first.
+    ///
+    /// See [`crate::hub::ModelsCat::history`].
+    pub fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>, OpsError> {
+        history::read(&self.repo, limit)
+    }
+
+    /// Moves the cached files of every snapshot into the blob store, returning the bytes freed.
+    ///
+    /// See [`crate::hub::ModelsCat::migrate_to_blob_store`].
+    pub fn migrate_to_blob_store(&self) -> Result<u64, OpsError> {
+        blobs::migrate(&self.repo)
     }
 
-    /// Pull a repo
-    pub async fn pull(&self) -> Result<(), OpsError> {
-        self.inner_pull(None::<MultiProgressWrapper>).await
+    /// Removes the quarantine area of the cache, returning the bytes freed.
+    ///
+    /// See [`crate::hub::ModelsCat::clear_quarantine`].
+    pub fn clear_quarantine(&self) -> Result<u64, OpsError> {
+        quarantine::clear(self.repo.cache_home())
     }
 
-    /// Pull a repo with a progress
-    pub async fn pull_with_progress(&self, progress: impl Progress) -> Result<(), OpsError> {
-        self.inner_pull(Some(progress)).await
+    /// Appends an operation to the history log. Failing to record it does not fail the
+    /// operation.
+    fn record_history<T>(
+        &self,
+        operation: HistoryOperation,
+        files: Vec<String>,
+        bytes: u64,
+        result: &Result<T, OpsError>,
+    ) {
+        let entry = HistoryEntry::new(&self.repo, operation, files, bytes, result);
+        if let Err(e) = history::append(&self.repo, &entry, self.history_size_limit) {
+            log::warn!("failed to record history: {e}");
+        }
     }
 
-    async fn inner_pull(&self, mut progress: Option<impl Progress>) -> Result<(), OpsError> {
-        let blobs = asynchronous::get_blob_files(&self.repo).await?;
-        for fileinfo in blobs {
-            let hub_revision = fileinfo.revision.clone();
-            let snapshot_path = self.repo.snapshot_path(&hub_revision);
-            std::fs::create_dir_all(&snapshot_path)?;
-            let filepath = {
-                let mut filepath = snapshot_path.clone();
-                for part in fileinfo.path.split("/") {
-                    filepath.push(part);
-                }
-                filepath
-            };
+    /// The rate limit last reported by the hub.
+    ///
+    /// See [`crate::hub::ModelsCat::rate_limit_status`].
+    pub fn rate_limit_status(&self) -> Option<RateLimitStatus> {
+        *self.rate_limit.lock().unwrap()
+    }
 
-            let mut lock = fslock::FsLock::lock(snapshot_path)?;
-            if std::fs::exists(&filepath)? {
-                if let Some(ref file_sha256) = fileinfo.sha256 {
-                    if &utils::sha256(&filepath)? == file_sha256 {
-                        continue;
-                    }
-                }
-            }
-            let file_url = format!(
-                "{}/{}/{}",
-                self.endpoint,
-                self.repo.url_path_with_resolve(),
-                fileinfo.path.clone()
-            );
+    fn observe_rate_limit(&self, status: Option<RateLimitStatus>) {
+        if status.is_some() {
+            *self.rate_limit.lock().unwrap() = status;
+        }
+    }
 
-            download_file(&file_url, &filepath, &fileinfo.path, &mut progress).await?;
-            lock.unlock();
+    /// Waits as [`ModelsCat::with_rate_limit_pacing`] requires before a request.
+    async fn pace(&self) {
+        if let Some(wait) = self.pacing_delay() {
+            tokio::time::sleep(wait).await;
         }
+    }
 
-        Ok(())
+    fn pacing_delay(&self) -> Option<std::time::Duration> {
+        let wait = self
+            .pacing?
+            .delay(&self.rate_limit_status()?, std::time::SystemTime::now())?;
+        self.log(format_args!(
+            "rate limit nearly reached, waiting {:.1}s",
+            wait.as_secs_f64()
+        ));
+        Some(wait)
     }
 
-    /// Download a file from the repository.
-    pub async fn download(&self, filename: &str) -> Result<(), OpsError> {
-        self.inner_download(filename, None::<ProgressBarWrapper>)
-            .await?;
-        Ok(())
+    fn log(&self, line: fmt::Arguments) {
+        if let Some(ref logger) = self.logger {
+            (logger.lock().unwrap())(&line.to_string());
+        }
     }
 
-    /// Download a file from the repository with a progress.
-    pub async fn download_with_progress(
+    /// Places a file downloaded outside of models-cat in the cache as the hub file
+    /// `as_filename`.
+    ///
+    /// See [`crate::hub::ModelsCat::import_file`].
+    pub async fn import_file(
         &self,
-        filename: &str,
-        progress: impl Progress,
-    ) -> Result<(), OpsError> {
-        self.inner_download(filename, Some(progress)).await?;
-        Ok(())
+        local: &Path,
+        as_filename: &str,
+    ) -> Result<ImportOutcome, OpsError> {
+        let result = self.import_listed(local, as_filename).await;
+        let bytes = match result {
+            Ok(ref outcome) => std::fs::metadata(outcome.path()).map_or(0, |m| m.len()),
+            Err(_) => 0,
+        };
+        let files = vec![as_filename.to_string()];
+        self.record_history(HistoryOperation::Import, files, bytes, &result);
+        result
     }
 
-    async fn inner_download(
+    async fn import_listed(
         &self,
-        filename: &str,
-        mut progress: Option<impl Progress>,
-    ) -> Result<(), OpsError> {
-        let repo_files = asynchronous::get_repo_files(&self.repo).await?;
-        let fileinfo = repo_files.get_file_info(filename)?;
-        let hub_revision = fileinfo.revision.clone();
+        local: &Path,
+        as_filename: &str,
+    ) -> Result<ImportOutcome, OpsError> {
+        let repo_files = self.repo_files(false).await?;
+        let fileinfo = repo_files.get_file_info(as_filename)?;
+        let commit = latest_revision(&repo_files.data.files);
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        import::import(
+            &self.repo,
+            local,
+            fileinfo,
+            &local_name,
+            commit.as_deref(),
+            self.global_dedup,
+        )
+    }
 
-        let snapshot_path = self.repo.snapshot_path(&hub_revision);
-        std::fs::create_dir_all(&snapshot_path)?;
-        let filepath = {
-            let mut filepath = snapshot_path.clone();
-            for part in fileinfo.path.split("/") {
-                filepath.push(part);
-            }
-            filepath
+    /// Imports every file under `dir` matching a hub file.
+    ///
+    /// See [`crate::hub::ModelsCat::import_dir`].
+    pub async fn import_dir(&self, dir: &Path) -> Result<ImportReport, OpsError> {
+        let result = self.import_dir_listed(dir).await;
+        let (files, bytes) = match result {
+            Ok(ref report) => (
+                report
+                    .imported
+                    .iter()
+                    .map(|(file, _)| file.clone())
+                    .collect(),
+                report
+                    .imported
+                    .iter()
+                    .map(|(_, outcome)| std::fs::metadata(outcome.path()).map_or(0, |m| m.len()))
+                    .sum(),
+            ),
+            Err(_) => (Vec::new(), 0),
         };
+        self.record_history(HistoryOperation::Import, files, bytes, &result);
+        result
+    }
 
-        let mut lock = fslock::FsLock::lock(snapshot_path.clone())?;
-
-        if std::fs::exists(&filepath)? {
-            if let Some(ref file_sha256) = fileinfo.sha256 {
-                if &utils::sha256(&filepath)? == file_sha256 {
-                    lock.unlock();
-                    return Ok(());
+    async fn import_dir_listed(&self, dir: &Path) -> Result<ImportReport, OpsError> {
+        let repo_files = self.repo_files(true).await?;
+        let blobs = repo_files.blobs();
+        let commit = latest_revision(&blobs);
+        let files = import::match_dir(dir, &blobs)?;
+        let mut report = ImportReport {
+            unknown: files.unknown,
+            ..Default::default()
+        };
+        for (local, fileinfo) in files.matched {
+            let imported = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)
+                .and_then(|local_name| {
+                    import::import(
+                        &self.repo,
+                        &local,
+                        fileinfo,
+                        &local_name,
+                        commit.as_deref(),
+                        self.global_dedup,
+                    )
+                });
+            match imported {
+                Ok(outcome) => {
+                    self.log(format_args!("imported {}", fileinfo.path));
+                    report.imported.push((fileinfo.path.clone(), outcome));
+                }
+                Err(e @ OpsError::ChecksumMismatch { .. }) => {
+                    self.log(format_args!("rejected {}: {e}", fileinfo.path));
+                    report.rejected.push((fileinfo.path.clone(), e));
                 }
+                Err(e) => return Err(e),
             }
         }
-        let file_url = format!(
-            "{}/{}/{}",
-            self.endpoint,
-            self.repo.url_path_with_resolve(),
-            filename
-        );
-
-        download_file(&file_url, &filepath, filename, &mut progress).await?;
-
-        lock.unlock();
-        Ok(())
+        let (selected, _) = PullOptions::default().select(blobs)?;
+        report.complete = import::activate_if_complete(&self.repo, &selected)?;
+        Ok(report)
     }
 
     /// List files in the remote repo
     pub async fn list_hub_files(&self) -> Result<Vec<String>, OpsError> {
-        let files = asynchronous::get_blob_files(&self.repo).await?;
+        let files = self.repo_files(true).await?.blobs();
         Ok(files.iter().map(|f| f.path.clone()).collect())
     }
 
+    /// Lists the files of the remote repo with their hub metadata.
+    ///
+    /// See [`crate::hub::ModelsCat::list_hub_files_detailed`].
+    pub async fn list_hub_files_detailed(&self) -> Result<Vec<FileMetadata>, OpsError> {
+        let files = self.repo_files(true).await?.blobs();
+        Ok(files.into_iter().map(FileMetadata::from).collect())
+    }
+
+    /// Lists the files of the remote repo that a pull with the same patterns would pull.
+    ///
+    /// See [`crate::hub::ModelsCat::list_hub_files_with_patterns`].
+    pub async fn list_hub_files_with_patterns(
+        &self,
+        allow: &[&str],
+        ignore: &[&str],
+    ) -> Result<Vec<String>, OpsError> {
+        let options = PullOptions::default()
+            .with_allow_patterns(allow.iter().copied())
+            .with_ignore_patterns(ignore.iter().copied());
+        let files = options.filter(self.repo_files(true).await?.blobs())?;
+        Ok(files.into_iter().map(|f| f.path).collect())
+    }
+
     /// List files in the local repo
     pub async fn list_local_files(&self) -> Result<Vec<String>, OpsError> {
         let base_path = self.repo.cache_dir().join("snapshots");
@@ -163,11 +2138,11 @@ impl ModelsCat {
             .into_iter()
             .filter_map(|e| e.ok())
         {
-            if entry.file_type().is_file() {
+            if !entry.file_type().is_dir() {
                 let rel_path = entry
                     .path()
                     .strip_prefix(&base_path)
-                    .map_err(|e| OpsError::HubError(e.to_string()))?
+                    .map_err(|e| OpsError::hub("file outside the snapshots directory", e))?
                     .components()
                     .skip(1) // 跳过commit hash目录
                     .collect::<PathBuf>();
@@ -179,14 +2154,72 @@ impl ModelsCat {
         Ok(files)
     }
 
+    /// Removes what the repo cache no longer needs and returns what was freed.
+    ///
+    /// See [`crate::hub::ModelsCat::prune`].
+    pub async fn prune(&self, dry_run: bool) -> Result<PruneReport, OpsError> {
+        let keep = prune::referenced(&self.repo, &self.repo_files(false).await?.blobs());
+        prune::prune(&self.repo, &keep, dry_run)
+    }
+
+    /// Estimates the bytes a pull would transfer from the listed sizes.
+    ///
+    /// See [`crate::hub::ModelsCat::estimated_pull_size`].
+    pub async fn estimated_pull_size(&self) -> Result<u64, OpsError> {
+        self.estimated_pull_size_with_options(&PullOptions::default(), false)
+            .await
+    }
+
+    /// Estimates the bytes a pull with `options` would transfer.
+    ///
+    /// See [`crate::hub::ModelsCat::estimated_pull_size_with_options`].
+    pub async fn estimated_pull_size_with_options(
+        &self,
+        options: &PullOptions,
+        subtract_cached: bool,
+    ) -> Result<u64, OpsError> {
+        let (blobs, _) = options.select(self.repo_files(true).await?.blobs())?;
+        verify::pending_bytes(&self.repo, blobs, subtract_cached)
+    }
+
+    /// Total size in bytes of the repo cache directory.
+    ///
+    /// See [`crate::hub::ModelsCat::size_on_disk`].
+    pub fn size_on_disk(&self) -> Result<u64, OpsError> {
+        cache::size_on_disk(&self.repo.cache_dir())
+    }
+
     /// Remove all files in the local repo.
+    ///
+    /// See [`crate::hub::ModelsCat::remove_all`].
     pub async fn remove_all(&self) -> Result<(), OpsError> {
-        tokio::fs::remove_dir_all(self.repo.cache_dir()).await?;
+        cache::check_repo_cache_dir(&self.repo.cache_dir())?;
+        self.remove_all_unchecked().await
+    }
+
+    /// Remove the repo cache directory without checking what it holds.
+    ///
+    /// See [`crate::hub::ModelsCat::remove_all_unchecked`].
+    pub async fn remove_all_unchecked(&self) -> Result<(), OpsError> {
+        match tokio::fs::remove_dir_all(self.repo.cache_dir()).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        dedup::DedupIndex::new(self.repo.cache_home()).forget(&self.repo.cache_dir())?;
         Ok(())
     }
 
     /// Remove a file from the local repo.
     pub async fn remove(&self, filename: &str) -> Result<(), OpsError> {
+        let mut bytes = 0;
+        let result = self.remove_copies(filename, &mut bytes).await;
+        let files = vec![filename.to_string()];
+        self.record_history(HistoryOperation::Remove, files, bytes, &result);
+        result
+    }
+
+    /// Removes every cached copy of `filename`, adding their sizes to `bytes`.
+    async fn remove_copies(&self, filename: &str, bytes: &mut u64) -> Result<(), OpsError> {
         let base_path = self.repo.cache_dir().join("snapshots");
 
         for entry in walkdir::WalkDir::new(&base_path)
@@ -195,125 +2228,36 @@ impl ModelsCat {
             .into_iter()
             .filter_map(|e| e.ok())
         {
-            if entry.file_type().is_file() {
+            if !entry.file_type().is_dir() {
                 let rel_path = entry
                     .path()
                     .strip_prefix(&base_path)
-                    .map_err(|e| OpsError::HubError(e.to_string()))?
+                    .map_err(|e| OpsError::hub("file outside the snapshots directory", e))?
                     .components()
                     .skip(1) // 跳过commit hash目录
                     .collect::<PathBuf>();
 
                 if filename == rel_path.to_string_lossy().replace('\\', "/") {
+                    *bytes += std::fs::metadata(entry.path()).map_or(0, |m| m.len());
                     tokio::fs::remove_file(entry.path()).await?;
+                    dedup::DedupIndex::new(self.repo.cache_home()).forget(entry.path())?;
                 }
             }
         }
+        blobs::prune(&self.repo)?;
 
         Ok(())
     }
 }
 
-/// Downloads a file from a URL with progress tracking.
-///
-/// # Arguments
-///
-/// * `file_url` - The URL of the file to download
-/// * `filepath` - The destination path where the file will be saved
-/// * `filename` - The full filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`
-/// * `progress` - Optional progress tracker implementing the `Progress` trait
-async fn download_file(
-    file_url: &str,
-    filepath: &PathBuf,
-    filename: &str,
-    progress: &mut Option<impl Progress>,
-) -> Result<(), OpsError> {
-    let parent = filepath
-        .parent() // 直接获取父目录
-        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
-    tokio::fs::create_dir_all(parent).await?;
-
-    let mut response = ASYNC_CLIENT.get(file_url).send().await?;
-    let total_size = if let Some(content_length) = response.content_length() {
-        content_length
-    } else {
-        return Err(OpsError::HubError("content_length is not available".into()));
-    };
-
-    let mut unit = ProgressUnit::new(filename.to_string(), total_size);
-    if let Some(prg) = progress.as_mut() {
-        prg.on_start(&unit).await?;
-    }
-
-    let mut downloaded: u64 = 0;
-    let realname = filepath
-        .file_name()
-        .ok_or(OpsError::HubError("Invalid file path".into()))?
-        .to_str()
-        .ok_or(OpsError::HubError("Invalid file path".into()))?;
-    let temp_filepath = parent.join(format!("{}.tmp", realname));
-    {
-        let mut temp_file = tokio::fs::File::create(&temp_filepath).await?;
-        let mut buf_write = tokio::io::BufWriter::new(&mut temp_file);
-        while let Some(chunk) = response.chunk().await? {
-            buf_write.write_all(&chunk).await?;
-            downloaded += chunk.len() as u64;
-
-            if let Some(prg) = progress.as_mut() {
-                unit.update(downloaded);
-                prg.on_progress(&unit).await?;
-            }
-        }
-        buf_write.flush().await?;
-    }
-    tokio::fs::rename(&temp_filepath, filepath).await?;
-
-    if let Some(prg) = progress.as_mut() {
-        prg.on_finish(&unit).await?;
-    }
-    Ok(())
-}
-
-/// Represents a unit of progress for tracking file downloads.
+/// A check run on every downloaded file, see [`ModelsCat::with_validator`].
 ///
-/// This struct holds information about the file being downloaded,
-/// including its name, total size, and current progress.
-#[derive(Default, Clone)]
-pub struct ProgressUnit {
-    filename: String,
-    total_size: u64,
-    current: u64,
-}
-
-impl ProgressUnit {
-    /// Creates a new `ProgressUnit` instance.
-    pub fn new(filename: String, total_size: u64) -> Self {
-        Self {
-            filename,
-            total_size,
-            ..Default::default()
-        }
-    }
-
-    /// Updates the current progress of the download.
-    pub fn update(&mut self, current: u64) {
-        self.current = current;
-    }
-
-    /// Retrieves the filename of the file being downloaded.
-    pub fn filename(&self) -> &str {
-        &self.filename
-    }
-
-    /// Retrieves the total size of the file in bytes.
-    pub fn total_size(&self) -> u64 {
-        self.total_size
-    }
-
-    /// Retrieves the current number of bytes downloaded.
-    pub fn current(&self) -> u64 {
-        self.current
-    }
+/// The asynchronous counterpart of [`crate::hub::Validator`].
+#[async_trait]
+pub trait Validator: Send + Sync {
+    /// Checks the fully written download at `temp_path`. An error keeps the file out of the
+    /// cache.
+    async fn validate(&self, temp_path: &Path, info: &HubFileInfo) -> Result<(), ValidationError>;
 }
 
 /// A trait defining the behavior for progress tracking during file downloads.
@@ -330,6 +2274,29 @@ pub trait Progress: Clone + Send + Sync + 'static {
 
     /// Called when a download finishes.
     async fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+
+    /// Called by downloads in place of [`Progress::on_progress`], which stop on
+    /// [`ControlFlow::Break`] and fail with [`OpsError::Cancelled`]. Calls
+    /// [`Progress::on_progress`] and continues by default.
+    ///
+    /// See [`crate::hub::Progress::on_progress_control`].
+    async fn on_progress_control(
+        &mut self,
+        unit: &ProgressUnit,
+    ) -> Result<ControlFlow<()>, OpsError> {
+        self.on_progress(unit).await?;
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Called once before any download of a pull, or of a single file, starts, with every
+    /// file that may be downloaded and its listed size, e.g. to lay out all bars upfront.
+    ///
+    /// A queued file found current or deduplicated once its turn comes gets
+    /// [`Progress::on_finish`] at its full size, without [`Progress::on_start`]. Does nothing
+    /// by default.
+    async fn on_queued(&mut self, _units: &[ProgressUnit]) -> Result<(), OpsError> {
+        Ok(())
+    }
 }
 
 /// A wrapper around a single [`ProgressBar`] for tracking progress during file downloads.
@@ -345,14 +2312,7 @@ impl Progress for ProgressBarWrapper {
     ///
     /// Initializes the progress bar with the total size of the file being downloaded.
     async fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        let pb = ProgressBar::new(unit.total_size()).with_finish(ProgressFinish::AndLeave);
-        let filename = unit.filename().to_string();
-        pb.set_style(ProgressStyle::with_template("{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
-        pb.set_prefix(filename);
-        self.0 = Some(pb);
+        self.0 = Some(resume_bar(self.0.take(), unit).unwrap_or_else(|| file_bar(unit)));
         Ok(())
     }
 
@@ -384,6 +2344,8 @@ impl Progress for ProgressBarWrapper {
 #[derive(Default, Clone)]
 pub struct MultiProgressWrapper {
     current_bar: Option<ProgressBar>,
+    /// Bars created for queued files, by filename, until their download starts.
+    queued: HashMap<String, ProgressBar>,
     inner: MultiProgressBar,
 }
 
@@ -392,6 +2354,7 @@ impl MultiProgressWrapper {
     pub fn new() -> Self {
         Self {
             current_bar: None,
+            queued: HashMap::new(),
             inner: MultiProgressBar::new(),
         }
     }
@@ -399,19 +2362,30 @@ impl MultiProgressWrapper {
 
 #[async_trait]
 impl Progress for MultiProgressWrapper {
+    /// Called before any download of a pull or download starts.
+    ///
+    /// Adds a bar for every queued file, so all of them are laid out upfront.
+    async fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+        for unit in units {
+            let pb = self.inner.add(file_bar(unit));
+            self.queued.insert(unit.filename().to_string(), pb);
+        }
+        Ok(())
+    }
+
     /// Called when a download starts.
     ///
     /// Initializes a new progress bar within the multi-progress bar system.
     async fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        let pb = ProgressBar::new(unit.total_size()).with_finish(ProgressFinish::AndLeave);
-        self.current_bar = Some(self.inner.add(pb.clone()));
-
-        let filename = unit.filename().to_string();
-        pb.set_style(ProgressStyle::with_template("{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
-        pb.set_prefix(filename);
+        let pb = match self.queued.remove(unit.filename()) {
+            Some(pb) => {
+                fit_bar(&pb, unit);
+                pb
+            }
+            None => resume_bar(self.current_bar.take(), unit)
+                .unwrap_or_else(|| self.inner.add(file_bar(unit))),
+        };
+        self.current_bar = Some(pb);
         Ok(())
     }
 
@@ -427,20 +2401,234 @@ impl Progress for MultiProgressWrapper {
 
     /// Called when a download finishes.
     ///
-    /// Ensures the current progress bar reflects the final downloaded bytes.
+    /// Ensures the current progress bar reflects the final downloaded bytes, or completes the
+    /// bar of a queued file that needed no download.
     async fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        if let Some(ref pb) = self.current_bar {
+        if let Some(pb) = self.queued.remove(unit.filename()) {
+            pb.set_position(unit.current());
+        } else if let Some(ref pb) = self.current_bar {
             pb.set_position(unit.current());
         }
         Ok(())
     }
 }
 
+/// Takes the snapshot lock to place `filepath`, or returns `None` when the file is already
+/// current.
+///
+/// See [`super::lock_unless_current`]; the lock is released when the guard is dropped, even if
+/// the download holding it is cancelled.
+async fn lock_unless_current(
+    snapshot_path: PathBuf,
+    filepath: &Path,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+) -> Result<Option<LockGuard>, OpsError> {
+    let checked = check_blocking(filepath.to_path_buf(), fileinfo, validation).await?;
+    if checked.current {
+        return Ok(None);
+    }
+    let lock = AsyncFsLock::acquire(snapshot_path, LockOptions::default()).await?;
+    if recheck_blocking(filepath.to_path_buf(), fileinfo, validation, checked).await? {
+        lock.release().await?;
+        return Ok(None);
+    }
+    Ok(Some(lock))
+}
+
+/// Checks whether the file at `filepath` is current on the blocking thread pool, as hashing a
+/// large file takes a while.
+///
+/// See [`super::check`].
+async fn check_blocking(
+    filepath: PathBuf,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+) -> Result<Checked, OpsError> {
+    let fileinfo = fileinfo.clone();
+    tokio::task::spawn_blocking(move || check(&filepath, &fileinfo, validation))
+        .await
+        .map_err(|e| OpsError::hub("hash task failed", e))?
+}
+
+/// Whether the file at `filepath`, found `checked` before its snapshot was locked, is current
+/// now, checked on the blocking thread pool.
+///
+/// See [`super::recheck`].
+async fn recheck_blocking(
+    filepath: PathBuf,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+    checked: Checked,
+) -> Result<bool, OpsError> {
+    let fileinfo = fileinfo.clone();
+    tokio::task::spawn_blocking(move || recheck(&filepath, &fileinfo, validation, checked))
+        .await
+        .map_err(|e| OpsError::hub("hash task failed", e))?
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use tokio::test;
 
+    /// Downloads a 256 MiB file with tokio's default 8 KiB buffer and with the default write
+    /// buffer size, printing the throughput of each. Run with
+    /// `cargo test --release --features tokio write_buffer -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    async fn test_write_buffer_throughput() {
+        use crate::test_server::{MockServer, Response};
+
+        let content = vec![7u8; 256 << 20];
+        let server = MockServer::start(move |_| Response::ok(content.clone()));
+        let url = format!("{}/blob", server.url());
+        let dir = tempfile::tempdir().unwrap();
+        let file = HubFileInfo::from(&FileInfo::test_blob("blob", &[], "master"));
+        for buffer_size in [8 * 1024, super::super::DEFAULT_WRITE_BUFFER_SIZE] {
+            let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"))
+                .with_write_buffer_size(buffer_size);
+            let start = Instant::now();
+            cat.download_file(
+                &url,
+                &dir.path().join("blob"),
+                &file,
+                &mut None::<ProgressBarWrapper>,
+            )
+            .await
+            .unwrap();
+            let elapsed = start.elapsed();
+            let throughput = 256.0 / elapsed.as_secs_f64();
+            println!("buffer {buffer_size}: {elapsed:?}, {throughput:.0} MiB/s");
+        }
+    }
+
+    #[test]
+    async fn test_cancelled_download_releases_lock() {
+        use crate::test_server::{MockServer, Response};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::Duration;
+
+        let listing = super::super::ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let transferring = Arc::new(AtomicBool::new(false));
+        let started = transferring.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            started.store(true, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_secs(2));
+            Response::ok(b"aaa".to_vec())
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+        let snapshot = cat.repo().snapshot_path("rev1");
+
+        // Drive the download until it holds the snapshot lock and waits on the transfer.
+        let mut download = Box::pin(cat.download("a.txt"));
+        tokio::select! {
+            _ = &mut download => panic!("the download finished"),
+            _ = async {
+                while !transferring.load(Ordering::SeqCst) {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            } => {}
+        }
+        let once = LockOptions {
+            retries: 0,
+            ..Default::default()
+        };
+        let held = AsyncFsLock::acquire(snapshot.clone(), once).await;
+        assert!(matches!(held, Err(OpsError::LockAcquisition(_))));
+
+        drop(download);
+        let start = Instant::now();
+        let prompt = LockOptions {
+            retries: 50,
+            retry_interval: Duration::from_millis(10),
+        };
+        let lock = AsyncFsLock::acquire(snapshot, prompt).await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        lock.release().await.unwrap();
+    }
+
+    #[test]
+    async fn test_transfer_slots_shared_across_instances() {
+        use std::sync::atomic::Ordering;
+        let (server, peak) = transfer_slots::test_hub();
+        let slots = Arc::new(TransferSlots::new(2));
+        let caches: Vec<_> = (0..6).map(|_| tempfile::tempdir().unwrap()).collect();
+        let downloads = caches.iter().map(|cache| {
+            let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+            repo.set_cache_dir(cache.path());
+            let mut cat = ModelsCat::new_with_endpoint(repo, server.url());
+            cat.transfer_slots = Some(slots.clone());
+            async move { cat.download("a.txt").await.unwrap() }
+        });
+        futures::future::join_all(downloads).await;
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    /// Fails every progress update, counting the calls it receives.
+    #[derive(Clone, Default)]
+    struct FailingProgress(Arc<std::sync::atomic::AtomicU64>);
+
+    #[async_trait]
+    impl Progress for FailingProgress {
+        async fn on_start(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            Ok(())
+        }
+
+        async fn on_progress(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Err(OpsError::HubError("progress bar gone".into()))
+        }
+
+        async fn on_finish(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    async fn test_progress_error_policy() {
+        let cache = tempfile::tempdir().unwrap();
+        let content = vec![1u8; 64 * 1024];
+        let (server, _) = super::super::ms_hub::test_hub(&[("a.bin", &content)], "rev1");
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+        let progress = FailingProgress::default();
+        assert!(cat.download_with_progress("a.bin", progress).await.is_err());
+
+        let cat = cat.with_progress_error_policy(ProgressErrorPolicy::Ignore);
+        let progress = FailingProgress::default();
+        let report = cat.pull_with_progress(progress.clone()).await.unwrap();
+        assert_eq!(report.progress_degraded, ["a.bin"]);
+        assert_eq!(progress.0.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert_eq!(std::fs::read(filepath).unwrap(), content);
+    }
+
+    #[test]
+    async fn test_download_writes_ref() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = super::super::ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+        cat.download("a.txt").await.unwrap();
+        let ref_path = cat.repo().ref_path().unwrap();
+        assert_eq!(std::fs::read_to_string(&ref_path).unwrap(), "rev1");
+
+        std::fs::remove_file(&ref_path).unwrap();
+        let options = DownloadOptions::default().with_validation(CacheValidation::Sha256);
+        cat.download_with_options("a.txt", options).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&ref_path).unwrap(), "rev1");
+    }
+
     #[test]
     async fn test_download() {
         let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
@@ -493,4 +2681,372 @@ mod tests {
         let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
         cat.remove("pytorch_model.bin").await.unwrap();
     }
+
+    #[test]
+    async fn test_token() {
+        use crate::test_server::{MockServer, Response};
+
+        let cache = tempfile::tempdir().unwrap();
+        let listing = super::super::ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            match (
+                req.header("Authorization"),
+                req.path.contains("/repo/files?"),
+            ) {
+                (Some("Bearer secret"), true) => Response::ok(listing.clone()),
+                (Some("Bearer secret"), false) => Response::ok(b"aaa".to_vec()),
+                _ => Response::status(401),
+            }
+        });
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+
+        let cat = ModelsCat::new_with_endpoint(repo.clone(), server.url());
+        assert!(matches!(
+            cat.download("a.txt").await,
+            Err(OpsError::Unauthorized { status: 401, .. })
+        ));
+        let cat = ModelsCat::new_with_endpoint(repo, server.url()).with_token("secret");
+        cat.download("a.txt").await.unwrap();
+    }
+
+    #[test]
+    async fn test_download_to_writer() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = super::super::ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+
+        let mut sink = Vec::new();
+        let written = cat
+            .download_to_writer("a.txt", &mut sink, None::<ProgressBarWrapper>)
+            .await
+            .unwrap();
+        assert_eq!((written, sink.as_slice()), (3, b"aaa".as_slice()));
+        assert!(!cat.repo().cache_dir().exists());
+
+        let offline = cat.with_offline(true);
+        assert!(matches!(
+            offline
+                .download_to_writer("a.txt", Vec::new(), None::<ProgressBarWrapper>)
+                .await,
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
+    }
+
+    #[test]
+    async fn test_offline() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = super::super::ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo.clone(), server.url());
+        cat.pull().await.unwrap();
+        let before = hits.load(std::sync::atomic::Ordering::SeqCst);
+        let out = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            cat.with_offline(true)
+                .download_to("a.txt", out.path(), DownloadOptions::default())
+                .await,
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
+
+        let offline = ModelsCat::new_with_endpoint(repo, server.url()).with_offline(true);
+        let a = offline.resolve("a.txt").await.unwrap();
+        assert_eq!(std::fs::read(&a.path).unwrap(), b"aaa");
+        assert_eq!(offline.pull().await.unwrap().skipped, ["a.txt"]);
+        assert!(matches!(
+            offline.download("b.txt").await,
+            Err(OpsError::OfflineMiss { .. })
+        ));
+        let force = DownloadOptions::default().with_force_download(true);
+        assert!(matches!(
+            offline.download_with_options("a.txt", force).await,
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), before);
+    }
+
+    #[test]
+    async fn test_resume_download() {
+        use crate::test_server::{MockServer, Response};
+
+        let content = b"0123456789";
+        let listing = super::super::ms_hub::test_listing(&[("a.bin", content)], "rev1");
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            let range = req.header("Range").map(str::to_string);
+            seen.lock().unwrap().push(range.clone());
+            match range {
+                Some(range) => {
+                    let offset: usize = range["bytes=".len()..range.len() - 1].parse().unwrap();
+                    Response::status(206)
+                        .header("Content-Range", format!("bytes {offset}-9/10"))
+                        .body(&content[offset..])
+                }
+                None => Response::ok(&content[..4]).truncated(content.len() as u64),
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url()).with_retries(0);
+
+        assert!(cat.download("a.bin").await.is_err());
+        let filepath = cat.download("a.bin").await.unwrap();
+        assert_eq!(filepath, cat.repo().snapshot_path("rev1").join("a.bin"));
+        assert_eq!(std::fs::read(&filepath).unwrap(), content);
+        assert_eq!(*ranges.lock().unwrap(), [None, Some("bytes=4-".into())]);
+    }
+
+    #[test]
+    async fn test_retry() {
+        use crate::test_server::{MockServer, Response};
+
+        let content = b"0123456789";
+        let listing = super::super::ms_hub::test_listing(&[("a.bin", content)], "rev1");
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            let mut seen = seen.lock().unwrap();
+            seen.push(req.header("Range").map(str::to_string));
+            match seen.len() {
+                1 => Response::status(503),
+                2 => Response::ok(&content[..4]).truncated(content.len() as u64),
+                _ => Response::status(206)
+                    .header("Content-Range", "bytes 4-9/10")
+                    .body(&content[4..]),
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url()).with_retry(RetryPolicy {
+            max_retries: 2,
+            initial_backoff: std::time::Duration::from_millis(10),
+            max_backoff: std::time::Duration::from_millis(50),
+        });
+
+        cat.download("a.bin").await.unwrap();
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert_eq!(std::fs::read(&filepath).unwrap(), content);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, None, Some("bytes=4-".into())]
+        );
+    }
+
+    #[test]
+    async fn test_max_concurrency() {
+        use crate::test_server::{MockServer, Response};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        let listing = super::super::ms_hub::test_listing(
+            &[
+                ("a.txt", b"a"),
+                ("b.txt", b"b"),
+                ("c.txt", b"c"),
+                ("d.txt", b"d"),
+            ],
+            "rev1",
+        );
+        let in_flight = AtomicUsize::new(0);
+        let peak = Arc::new(AtomicUsize::new(0));
+        let observed = peak.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            observed.fetch_max(
+                in_flight.fetch_add(1, Ordering::SeqCst) + 1,
+                Ordering::SeqCst,
+            );
+            std::thread::sleep(Duration::from_millis(100));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            match req.path.rsplit('/').next().unwrap() {
+                "d.txt" => Response::status(404),
+                name => Response::ok(name.as_bytes()[..1].to_vec()),
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url()).with_max_concurrency(3);
+
+        let failures = match cat.pull_with_progress(MultiProgressWrapper::new()).await {
+            Err(OpsError::PullFailed { failures }) => failures,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "d.txt");
+        let snapshot = cat.repo().snapshot_path("rev1");
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            assert_eq!(
+                std::fs::read(snapshot.join(name)).unwrap(),
+                &name.as_bytes()[..1]
+            );
+        }
+        assert!(!std::fs::exists(snapshot.with_extension("lock")).unwrap());
+    }
+
+    #[test]
+    async fn test_timeout() {
+        use crate::test_server::{MockServer, Response};
+
+        let content = b"0123456789";
+        let listing = super::super::ms_hub::test_listing(&[("a.bin", content)], "rev1");
+        let hits = Arc::new(Mutex::new(0));
+        let counter = hits.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            *counter.lock().unwrap() += 1;
+            match req.header("Range") {
+                None => Response::ok(&content[..4])
+                    .truncated(content.len() as u64)
+                    .stalled(Duration::from_secs(2)),
+                Some(_) => Response::status(206)
+                    .header("Content-Range", "bytes 4-9/10")
+                    .body(&content[4..]),
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url())
+            .with_timeout(Duration::from_millis(200))
+            .unwrap()
+            .with_retry(RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(50),
+            });
+
+        // The stalled body times out, and the retry resumes after what arrived.
+        cat.download("a.bin").await.unwrap();
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert_eq!(std::fs::read(&filepath).unwrap(), content);
+        assert_eq!(*hits.lock().unwrap(), 2);
+    }
+
+    #[test]
+    async fn test_download_with_cancel() {
+        use crate::test_server::{MockServer, Response};
+
+        let listing = super::super::ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            Response::ok(b"a".to_vec())
+                .truncated(3)
+                .stalled(Duration::from_secs(2))
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        });
+
+        let start = Instant::now();
+        let result = cat
+            .download_with_cancel("a.txt", token, None::<ProgressBarWrapper>)
+            .await;
+        assert!(matches!(result, Err(OpsError::Cancelled { file }) if file == "a.txt"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert!(
+            walkdir::WalkDir::new(&snapshot)
+                .min_depth(1)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .all(|e| e.file_type().is_dir())
+        );
+        let once = LockOptions {
+            retries: 0,
+            ..Default::default()
+        };
+        AsyncFsLock::acquire(snapshot, once).await.unwrap();
+    }
+
+    #[test]
+    async fn test_pull_with_cancel() {
+        use crate::test_server::{MockServer, Response};
+
+        let listing =
+            super::super::ms_hub::test_listing(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            if req.path.contains("a.txt") {
+                return Response::ok(b"aaa".to_vec());
+            }
+            Response::ok(b"b".to_vec())
+                .truncated(3)
+                .stalled(Duration::from_secs(2))
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        });
+
+        let result = cat
+            .pull_with_cancel(token, None::<MultiProgressWrapper>)
+            .await;
+        assert!(matches!(result, Err(OpsError::Cancelled { .. })));
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(std::fs::read(snapshot.join("a.txt")).unwrap(), b"aaa");
+        assert!(!snapshot.join("b.txt").exists());
+    }
+
+    #[test]
+    async fn test_quarantine() {
+        use crate::test_server::{MockServer, Response};
+
+        let cache = tempfile::tempdir().unwrap();
+        let listing = super::super::ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            Response::ok(b"aab".to_vec())
+        });
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url()).with_quarantine(1024);
+        let quarantined = match cat.download("a.txt").await {
+            Err(OpsError::ChecksumMismatch {
+                quarantined: Some(path),
+                ..
+            }) => path,
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(std::fs::read(&quarantined).unwrap(), b"aab");
+        assert!(quarantined.starts_with(quarantine::dir(cache.path())));
+        assert!(!cat.repo().snapshot_path("rev1").join("a.txt").exists());
+        assert!(cat.clear_quarantine().unwrap() > 0);
+    }
 }
@@ -0,0 +1,342 @@
+//! The content-addressed blob store of a repo, enabled with
+//! [`crate::hub::ModelsCat::with_blob_store`], laid out like the Hugging Face cache.
+//!
+//! Downloads are moved to `blobs/<sha256>` and the snapshot gets a relative symlink to the
+//! blob at their path, so identical files of several revisions are stored once. Where
+//! symlinks cannot be created, such as on Windows without developer mode, the snapshot gets a
+//! hard link or a copy of the blob instead.
+use super::staging::{self, StagedFile};
+use crate::dedup;
+use crate::fslock::FsLock;
+use crate::repo::Repo;
+use crate::utils::{self, OpsError};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use tempfile::NamedTempFile;
+
+const BLOBS_DIR: &str = "blobs";
+
+/// Satisfies `filepath` from the blob of `sha256` when one of `size` bytes is stored. Returns
+/// whether the file was placed.
+///
+/// A `filepath` already linking to the blob is left for a download to replace both, since it
+/// is only asked for again when its content failed validation.
+pub(crate) fn try_link(
+    repo: &Repo,
+    sha256: Option<&str>,
+    size: u64,
+    filepath: &Path,
+) -> Result<bool, OpsError> {
+    let Some(sha256) = sha256 else {
+        return Ok(false);
+    };
+    let blob = repo.blob_path(sha256);
+    match std::fs::metadata(&blob) {
+        Ok(meta) if meta.is_file() && meta.len() == size => {}
+        _ => return Ok(false),
+    }
+    if std::fs::canonicalize(filepath).is_ok_and(|path| Some(path) == blob.canonicalize().ok()) {
+        return Ok(false);
+    }
+    link(repo, &blob, filepath)?;
+    Ok(true)
+}
+
+/// Moves the verified download `staged` to the blob of its `sha256`, replacing a blob that
+/// failed validation, then links `filepath` to it.
+///
+/// The link replaces `filepath` atomically, so readers see the old or the new file but never
+/// none. The blob `filepath` linked to before is removed once no snapshot links to it.
+pub(crate) fn store(
+    repo: &Repo,
+    sha256: &str,
+    staged: StagedFile,
+    filepath: &Path,
+) -> Result<(), OpsError> {
+    let blob = repo.blob_path(sha256);
+    let previous = linked_blob(repo, filepath);
+    std::fs::create_dir_all(repo.cache_dir().join(BLOBS_DIR))?;
+    staged.persist(&blob)?;
+    link(repo, &blob, filepath)?;
+    if let Some(previous) = previous
+        && previous != std::fs::canonicalize(&blob)?
+        && !is_linked(repo, &previous)
+    {
+        std::fs::remove_file(previous)?;
+    }
+    Ok(())
+}
+
+/// Stores the plain file at `filepath` as the blob of its `sha256` and links it back, like
+/// [`store`], keeping `filepath` in place throughout.
+fn adopt(repo: &Repo, sha256: &str, filepath: &Path) -> Result<(), OpsError> {
+    let blob = repo.blob_path(sha256);
+    dedup::link_or_copy(filepath, &blob)?;
+    link(repo, &blob, filepath)
+}
+
+/// The blob of the store that `filepath` links to, if any.
+fn linked_blob(repo: &Repo, filepath: &Path) -> Option<PathBuf> {
+    if !filepath.is_symlink() {
+        return None;
+    }
+    let blobs_dir = std::fs::canonicalize(repo.cache_dir().join(BLOBS_DIR)).ok()?;
+    std::fs::canonicalize(filepath)
+        .ok()
+        .filter(|blob| blob.parent() == Some(blobs_dir.as_path()))
+}
+
+/// Whether a snapshot of `repo` links to `blob`, a canonical path.
+fn is_linked(repo: &Repo, blob: &Path) -> bool {
+    walkdir::WalkDir::new(repo.cache_dir().join("snapshots"))
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path_is_symlink())
+        .any(|e| std::fs::canonicalize(e.path()).is_ok_and(|path| path == blob))
+}
+
+/// Removes the blobs no snapshot links to anymore, such as after
+/// [`crate::hub::ModelsCat::remove`].
+pub(crate) fn prune(repo: &Repo) -> Result<(), OpsError> {
+    for blob in unlinked(repo, &[])? {
+        std::fs::remove_file(blob)?;
+    }
+    Ok(())
+}
+
+/// The blobs no snapshot links to, leaving out the links in the snapshots `dropped`.
+pub(crate) fn unlinked(repo: &Repo, dropped: &[PathBuf]) -> Result<Vec<PathBuf>, OpsError> {
+    let blobs_dir = repo.cache_dir().join(BLOBS_DIR);
+    if !blobs_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let linked: HashSet<PathBuf> = walkdir::WalkDir::new(repo.cache_dir().join("snapshots"))
+        .into_iter()
+        .filter_entry(|e| !dropped.iter().any(|d| d == e.path()))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path_is_symlink())
+        .filter_map(|e| std::fs::canonicalize(e.path()).ok())
+        .collect();
+    let mut unlinked = Vec::new();
+    for entry in std::fs::read_dir(&blobs_dir)? {
+        let path = std::fs::canonicalize(entry?.path())?;
+        if !linked.contains(&path) {
+            unlinked.push(path);
+        }
+    }
+    Ok(unlinked)
+}
+
+/// Moves the files of every snapshot of `repo` into the blob store and links them back, see
+/// [`crate::hub::ModelsCat::migrate_to_blob_store`]. Returns the bytes freed by the files
+/// whose content was stored already.
+///
+/// Each snapshot is migrated under its lock, waiting for a download holding it.
+pub(crate) fn migrate(repo: &Repo) -> Result<u64, OpsError> {
+    let snapshots_dir = repo.cache_dir().join("snapshots");
+    if !snapshots_dir.is_dir() {
+        return Ok(0);
+    }
+    let mut freed = 0;
+    for entry in std::fs::read_dir(&snapshots_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let mut lock = FsLock::lock(entry.path())?;
+        let result = migrate_snapshot(repo, &entry.path());
+        lock.unlock();
+        freed += result?;
+    }
+    Ok(freed)
+}
+
+/// Moves the plain files of `snapshot`, locked by the caller, into the blob store. Links are
+/// left alone, and so are the staged downloads.
+fn migrate_snapshot(repo: &Repo, snapshot: &Path) -> Result<u64, OpsError> {
+    let files: Vec<PathBuf> = walkdir::WalkDir::new(snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && !staging::is_staged(e.path()))
+        .map(|e| e.into_path())
+        .collect();
+    let mut freed = 0;
+    for file in files {
+        let sha256 = utils::sha256(&file)?;
+        let size = std::fs::metadata(&file)?.len();
+        match try_link(repo, Some(&sha256), size, &file)? {
+            true => freed += size,
+            false => adopt(repo, &sha256, &file)?,
+        }
+    }
+    Ok(freed)
+}
+
+/// Places a link to `blob` at `filepath`, replacing what is there atomically.
+fn link(repo: &Repo, blob: &Path, filepath: &Path) -> Result<(), OpsError> {
+    let parent = filepath
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let temp = NamedTempFile::new_in(parent)?.into_temp_path();
+    std::fs::remove_file(&temp)?;
+    if symlink(&relative_target(repo, parent, blob), &temp).is_err() {
+        return dedup::link_or_copy(blob, filepath);
+    }
+    temp.persist(filepath)
+        .map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// The path of `blob` relative to `dir`, a directory of a snapshot of `repo`, so the cache
+/// keeps working after it is moved.
+fn relative_target(repo: &Repo, dir: &Path, blob: &Path) -> PathBuf {
+    let cache_dir = repo.cache_dir();
+    let (Ok(up), Ok(down)) = (dir.strip_prefix(&cache_dir), blob.strip_prefix(&cache_dir)) else {
+        return blob.to_path_buf();
+    };
+    let mut target: PathBuf = up
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .map(|_| Component::ParentDir)
+        .collect();
+    target.push(down);
+    target
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+pub(crate) fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn symlink(_: &Path, _: &Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::validate::HubFileInfo;
+
+    /// A finished download of `content` staged for `filepath`.
+    fn staged(filepath: &Path, content: &[u8]) -> StagedFile {
+        std::fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+        let file = HubFileInfo {
+            path: "model.onnx".into(),
+            revision: "rev1".into(),
+            size: content.len() as u64,
+            sha256: None,
+        };
+        let staged = StagedFile::create(filepath, &file).unwrap();
+        std::fs::write(staged.path(), content).unwrap();
+        staged
+    }
+
+    #[test]
+    fn test_store_link_and_prune() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let old = repo.snapshot_path("rev1").join("onnx/model.onnx");
+        let new = repo.snapshot_path("rev2").join("model.onnx");
+
+        store(&repo, "abc", staged(&old, b"weights"), &old).unwrap();
+        assert!(try_link(&repo, Some("abc"), 7, &new).unwrap());
+        assert!(!try_link(&repo, Some("abc"), 8, &new).unwrap());
+        assert!(!try_link(&repo, None, 7, &new).unwrap());
+        assert!(!try_link(&repo, Some("abc"), 7, &old).unwrap());
+        let blob = repo.blob_path("abc");
+        for path in [&old, &new] {
+            assert_eq!(std::fs::read(path).unwrap(), b"weights");
+            assert_eq!(
+                std::fs::canonicalize(path).unwrap(),
+                blob.canonicalize().unwrap()
+            );
+        }
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(&old).unwrap(),
+            Path::new("../../../blobs/abc")
+        );
+
+        std::fs::remove_file(&old).unwrap();
+        prune(&repo).unwrap();
+        assert!(blob.exists());
+        std::fs::remove_file(&new).unwrap();
+        prune(&repo).unwrap();
+        assert!(!blob.exists());
+    }
+
+    #[test]
+    fn test_store_swaps_link() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let filepath = repo.snapshot_path("rev1").join("model.onnx");
+        let other = repo.snapshot_path("rev2").join("model.onnx");
+        store(&repo, "abc", staged(&filepath, b"weights"), &filepath).unwrap();
+        assert!(try_link(&repo, Some("abc"), 7, &other).unwrap());
+
+        // Readers find a complete file at the path throughout every swap.
+        let done = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let reader = {
+            let (filepath, done) = (filepath.clone(), done.clone());
+            std::thread::spawn(move || {
+                while !done.load(std::sync::atomic::Ordering::SeqCst) {
+                    let content = std::fs::read(&filepath).unwrap();
+                    assert!(content == b"weights" || content == b"updated");
+                }
+            })
+        };
+        for i in 0..50 {
+            let (sha256, content) = match i % 2 {
+                0 => ("def", b"updated"),
+                _ => ("abc", b"weights"),
+            };
+            store(&repo, sha256, staged(&filepath, content), &filepath).unwrap();
+        }
+        done.store(true, std::sync::atomic::Ordering::SeqCst);
+        reader.join().unwrap();
+
+        // The old blob is kept while another snapshot links to it, then removed.
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"weights");
+        store(&repo, "def", staged(&filepath, b"updated"), &filepath).unwrap();
+        assert!(repo.blob_path("abc").exists());
+        std::fs::remove_file(&other).unwrap();
+        store(&repo, "abc", staged(&filepath, b"weights"), &filepath).unwrap();
+        assert!(!repo.blob_path("def").exists());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let files = [
+            repo.snapshot_path("rev1").join("model.onnx"),
+            repo.snapshot_path("rev2").join("onnx/model.onnx"),
+        ];
+        for file in &files {
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, b"weights").unwrap();
+        }
+
+        assert_eq!(migrate(&repo).unwrap(), 7);
+        let blob = repo.blob_path(&utils::sha256(&files[0]).unwrap());
+        for file in &files {
+            assert_eq!(std::fs::read(file).unwrap(), b"weights");
+            assert_eq!(
+                std::fs::canonicalize(file).unwrap(),
+                blob.canonicalize().unwrap()
+            );
+        }
+        assert_eq!(migrate(&repo).unwrap(), 0);
+    }
+}
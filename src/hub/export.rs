@@ -0,0 +1,462 @@
+//! Copies of cached files placed outside the cache, optionally under local aliases.
+//!
+//! The snapshot itself always keeps hub-relative names so it can be verified against the hub;
+//! renaming only ever happens on the way out.
+use super::{CacheValidation, blobs, matching, staging};
+use crate::dedup;
+use crate::repo::Repo;
+use crate::utils::{self, OpsError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// How [`crate::hub::ModelsCat::export_snapshot`], [`crate::hub::ModelsCat::export_to`] and
+/// [`crate::hub::ModelsCat::download_to`] place cached files outside the cache.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum ExportMode {
+    /// Independent copies of the files, which can be written to without touching the cache.
+    #[default]
+    Copy,
+    /// Hard links to the cached files, or copies where linking is not possible, such as
+    /// across filesystems. Writing to a linked file changes the cached one too.
+    Hardlink,
+    /// Symlinks to the cached files, which break once the snapshot is removed from the cache.
+    Symlink,
+}
+
+/// Options for [`crate::hub::ModelsCat::download_with_options`] and
+/// [`crate::hub::ModelsCat::download_to`].
+///
+/// Built from [`DownloadOptions::default`] with the `with_*` methods, or, with the `serde`
+/// feature, loaded from app config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct DownloadOptions {
+    /// The name to store the file under in the target directory, such as `model.safetensors`
+    /// or `weights/model.safetensors`. Defaults to the hub-relative filename.
+    pub save_as: Option<String>,
+    /// How [`crate::hub::ModelsCat::download_to`] places the cached file in the target
+    /// directory when [`DownloadOptions::bypass_cache`] is off. Copies by default.
+    pub export_mode: ExportMode,
+    /// Overrides the instance's [`CacheValidation`] for this download only.
+    pub validation: Option<CacheValidation>,
+    /// Downloads the file again even when the cached copy is current, replacing it
+    /// atomically. Off by default.
+    pub force_download: bool,
+    /// Checks the download against the listed sha256 before it replaces the cached copy. On
+    /// by default; a download that skipped the check is not recorded as complete.
+    pub verify: bool,
+    /// Picks up where an interrupted download of the file left off. On by default; when off,
+    /// what an earlier download left behind is discarded first.
+    pub resume: bool,
+    /// Downloads straight into the target directory of [`crate::hub::ModelsCat::download_to`],
+    /// leaving the cache untouched. On by default; when off, the file is downloaded into the
+    /// cache and placed in the directory from there.
+    pub bypass_cache: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            save_as: None,
+            export_mode: ExportMode::Copy,
+            validation: None,
+            force_download: false,
+            verify: true,
+            resume: true,
+            bypass_cache: true,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Stores the file under `name` instead of its hub-relative filename.
+    pub fn with_save_as(mut self, name: impl Into<String>) -> Self {
+        self.save_as = Some(name.into());
+        self
+    }
+
+    /// Places the cached file in the target directory under `mode`.
+    pub fn with_export_mode(mut self, mode: ExportMode) -> Self {
+        self.export_mode = mode;
+        self
+    }
+
+    /// Confirms a cached copy with `validation` instead of the instance default.
+    pub fn with_validation(mut self, validation: CacheValidation) -> Self {
+        self.validation = Some(validation);
+        self
+    }
+
+    /// Downloads the file again even when the cached copy is current.
+    pub fn with_force_download(mut self, force_download: bool) -> Self {
+        self.force_download = force_download;
+        self
+    }
+
+    /// Whether to check the download against the listed sha256.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Whether to resume an interrupted download of the file.
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Whether to download straight into the target directory, without the cache.
+    pub fn with_bypass_cache(mut self, bypass_cache: bool) -> Self {
+        self.bypass_cache = bypass_cache;
+        self
+    }
+
+    /// Checks the options, as downloads do before starting: `save_as` must be a relative path
+    /// without `.` or `..` components.
+    pub fn validate(&self) -> Result<(), OpsError> {
+        match self.save_as {
+            Some(ref name) if !is_relative_name(name) => Err(OpsError::BuildError(format!(
+                "invalid `save_as` `{name}`: must be a relative path without `.` or `..`"
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Places the cached file `src` at `dir/name` under `mode`.
+pub(crate) fn export_file(
+    src: &Path,
+    dir: &Path,
+    name: &str,
+    mode: ExportMode,
+) -> Result<PathBuf, OpsError> {
+    let dst = join_relative(dir, name)?;
+    place(src, &dst, mode)?;
+    Ok(dst)
+}
+
+/// Places every file of `snapshot` under `dir` under `mode`, renaming the hub-relative paths
+/// found in `rename`. Returns the exported paths in hub-relative order. Staged downloads left
+/// in the snapshot are not exported.
+///
+/// Files stored under sanitized names are looked up in `hub_names` so `rename` can use their
+/// hub paths; unless renamed they keep their sanitized, locally valid names.
+pub(crate) fn export_snapshot(
+    snapshot: &Path,
+    dir: &Path,
+    rename: &BTreeMap<String, String>,
+    hub_names: &HashMap<String, String>,
+    mode: ExportMode,
+) -> Result<Vec<PathBuf>, OpsError> {
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_dir() && !staging::is_staged(entry.path()) {
+            let rel_path = entry
+                .path()
+                .strip_prefix(snapshot)
+                .map_err(|e| OpsError::hub("file outside the snapshot", e))?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let hub_path = hub_names.get(&rel_path).unwrap_or(&rel_path).clone();
+            files.push((hub_path, rel_path, entry.into_path()));
+        }
+    }
+    files.sort();
+
+    if let Some(unknown) = rename
+        .keys()
+        .find(|name| !files.iter().any(|(hub_path, _, _)| hub_path == *name))
+    {
+        return Err(OpsError::BuildError(format!(
+            "cannot rename `{unknown}`: not in the snapshot"
+        )));
+    }
+    let mut targets = HashSet::new();
+    for (hub_path, rel_path, _) in &files {
+        let target = rename.get(hub_path).unwrap_or(rel_path);
+        if !targets.insert(target.as_str()) {
+            return Err(OpsError::BuildError(format!(
+                "conflicting export alias `{target}`"
+            )));
+        }
+    }
+
+    files
+        .iter()
+        .map(|(hub_path, rel_path, src)| {
+            let target = rename.get(hub_path).unwrap_or(rel_path);
+            export_file(src, dir, target, mode)
+        })
+        .collect()
+}
+
+/// Places every file of the snapshot the revision of `repo` resolves to offline in `dir` under
+/// `mode`, keeping their local relative paths. Returns the placed paths, sorted.
+///
+/// Existing files of other content are only replaced with `overwrite`; they are all checked
+/// before anything is placed.
+pub(crate) fn materialize(
+    repo: &Repo,
+    dir: &Path,
+    mode: ExportMode,
+    overwrite: bool,
+) -> Result<Vec<PathBuf>, OpsError> {
+    let snapshot = matching::offline_snapshot(repo).ok_or_else(|| {
+        OpsError::HubError(format!(
+            "No snapshot of {} cached for revision {}, pull it first",
+            repo.repo_id(),
+            repo.revision().as_str()
+        ))
+    })?;
+    let mut files = Vec::new();
+    for entry in walkdir::WalkDir::new(&snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_dir() || staging::is_staged(entry.path()) {
+            continue;
+        }
+        let name = matching::relative_name(&snapshot, entry.path())?;
+        files.push((join_relative(dir, &name)?, entry.into_path()));
+    }
+    files.sort();
+
+    if !overwrite {
+        for (dst, src) in &files {
+            if std::fs::symlink_metadata(dst).is_ok() && !is_same(src, dst)? {
+                return Err(OpsError::BuildError(format!(
+                    "refusing to overwrite `{}`: it differs from the cached file",
+                    dst.display()
+                )));
+            }
+        }
+    }
+    for (dst, src) in &files {
+        place(src, dst, mode)?;
+    }
+    Ok(files.into_iter().map(|(dst, _)| dst).collect())
+}
+
+/// Whether the existing `dst` is, or holds the same content as, the cached `src`.
+fn is_same(src: &Path, dst: &Path) -> Result<bool, OpsError> {
+    // A dangling symlink has no content to compare.
+    let Ok(metadata) = std::fs::metadata(dst) else {
+        return Ok(false);
+    };
+    if std::fs::canonicalize(dst)? == std::fs::canonicalize(src)? {
+        return Ok(true);
+    }
+    Ok(metadata.len() == std::fs::metadata(src)?.len()
+        && utils::sha256(dst)? == utils::sha256(src)?)
+}
+
+/// Places the cached `src` at `dst` under `mode`, replacing what is there atomically.
+fn place(src: &Path, dst: &Path, mode: ExportMode) -> Result<(), OpsError> {
+    if mode == ExportMode::Hardlink {
+        return dedup::link_or_copy(src, dst);
+    }
+    // Follows a link to a blob, so a symlink does not point at another symlink.
+    let src = std::path::absolute(std::fs::canonicalize(src)?)?;
+    let parent = dst
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let temp = NamedTempFile::new_in(parent)?.into_temp_path();
+    std::fs::remove_file(&temp)?;
+    match mode {
+        ExportMode::Symlink => blobs::symlink(&src, &temp)?,
+        _ => {
+            std::fs::copy(&src, &temp)?;
+        }
+    }
+    temp.persist(dst).map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// Joins a `/`-separated relative name onto `dir`, rejecting names that escape it.
+pub(crate) fn join_relative(dir: &Path, name: &str) -> Result<PathBuf, OpsError> {
+    if !is_relative_name(name) {
+        return Err(OpsError::BuildError(format!(
+            "invalid export name `{name}`"
+        )));
+    }
+    Ok(name
+        .split('/')
+        .fold(dir.to_path_buf(), |path, part| path.join(part)))
+}
+
+/// Whether every `/`-separated part of `name` is a single normal path component.
+fn is_relative_name(name: &str) -> bool {
+    name.split('/').all(|part| {
+        let mut components = Path::new(part).components();
+        matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_snapshot_rename() {
+        let snapshot = tempfile::tempdir().unwrap();
+        std::fs::write(snapshot.path().join("a.txt"), "aaa").unwrap();
+        std::fs::create_dir(snapshot.path().join("b")).unwrap();
+        std::fs::write(snapshot.path().join("b/c.txt"), "ccc").unwrap();
+        let out = tempfile::tempdir().unwrap();
+
+        let rename = BTreeMap::from([("b/c.txt".to_string(), "c.txt".to_string())]);
+        let none = HashMap::new();
+        let paths = export_snapshot(
+            snapshot.path(),
+            out.path(),
+            &rename,
+            &none,
+            ExportMode::Copy,
+        )
+        .unwrap();
+        assert_eq!(
+            paths,
+            vec![out.path().join("a.txt"), out.path().join("c.txt")]
+        );
+        assert_eq!(std::fs::read(out.path().join("c.txt")).unwrap(), b"ccc");
+        assert!(snapshot.path().join("b/c.txt").exists());
+
+        let conflict = BTreeMap::from([("b/c.txt".to_string(), "a.txt".to_string())]);
+        assert!(matches!(
+            export_snapshot(
+                snapshot.path(),
+                out.path(),
+                &conflict,
+                &none,
+                ExportMode::Copy
+            ),
+            Err(OpsError::BuildError(_))
+        ));
+        let unknown = BTreeMap::from([("x.txt".to_string(), "y.txt".to_string())]);
+        assert!(
+            export_snapshot(
+                snapshot.path(),
+                out.path(),
+                &unknown,
+                &none,
+                ExportMode::Copy
+            )
+            .is_err()
+        );
+
+        // Sanitized names are renamed through their hub paths.
+        let names = HashMap::from([("a.txt".to_string(), "a:txt".to_string())]);
+        let rename = BTreeMap::from([("a:txt".to_string(), "d.txt".to_string())]);
+        let paths = export_snapshot(
+            snapshot.path(),
+            out.path(),
+            &rename,
+            &names,
+            ExportMode::Copy,
+        )
+        .unwrap();
+        assert_eq!(paths[0], out.path().join("d.txt"));
+        for invalid in ["../a.txt", "/a.txt", "a//b", ""] {
+            assert!(
+                export_file(
+                    &snapshot.path().join("a.txt"),
+                    out.path(),
+                    invalid,
+                    ExportMode::Copy
+                )
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_export_snapshot_skips_staged() {
+        let snapshot = tempfile::tempdir().unwrap();
+        std::fs::write(snapshot.path().join("a.txt"), "aaa").unwrap();
+        // A download interrupted by a crash leaves its staged file and sidecar behind.
+        std::fs::write(snapshot.path().join("b.txt.partial.0123456789ab"), "bb").unwrap();
+        std::fs::write(
+            snapshot.path().join("b.txt.partial.0123456789ab.json"),
+            "{}",
+        )
+        .unwrap();
+        let out = tempfile::tempdir().unwrap();
+
+        let none = HashMap::new();
+        let paths = export_snapshot(
+            snapshot.path(),
+            out.path(),
+            &BTreeMap::new(),
+            &none,
+            ExportMode::Copy,
+        )
+        .unwrap();
+        assert_eq!(paths, vec![out.path().join("a.txt")]);
+        assert_eq!(std::fs::read_dir(out.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_validate_options() {
+        let options = DownloadOptions::default().with_save_as("weights/model.bin");
+        assert!(options.validate().is_ok());
+        for invalid in ["../a.txt", "/a.txt", "a/./b", ""] {
+            let err = DownloadOptions::default()
+                .with_save_as(invalid)
+                .validate()
+                .unwrap_err();
+            let expected = format!(
+                "invalid `save_as` `{invalid}`: must be a relative path without `.` or `..`"
+            );
+            assert!(matches!(err, OpsError::BuildError(ref message) if *message == expected));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_options_serde() {
+        let options = DownloadOptions::default()
+            .with_save_as("weights/model.bin")
+            .with_validation(CacheValidation::CommitDate);
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(
+            json,
+            r#"{"save_as":"weights/model.bin","export_mode":"copy","validation":"commit_date","force_download":false,"verify":true,"resume":true,"bypass_cache":true}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<DownloadOptions>(&json).unwrap(),
+            options
+        );
+        let toml = toml::to_string(&options).unwrap();
+        assert_eq!(toml::from_str::<DownloadOptions>(&toml).unwrap(), options);
+
+        // Omitted fields take their default, unknown ones are rejected.
+        assert_eq!(
+            toml::from_str::<DownloadOptions>("").unwrap(),
+            DownloadOptions::default()
+        );
+        let err = toml::from_str::<DownloadOptions>("save_to = \"a.txt\"").unwrap_err();
+        assert!(err.message().contains("unknown field `save_to`"));
+        let err = toml::from_str::<DownloadOptions>("validation = \"md5\"").unwrap_err();
+        assert!(err.message().contains("unknown variant `md5`"));
+    }
+}
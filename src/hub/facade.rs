@@ -0,0 +1,288 @@
+//! A long-lived asynchronous entry point sharing configuration and per-repo state across calls.
+use super::PullReport;
+use super::async_hub::{ModelsCat, Progress};
+use crate::repo::{Repo, RepoType};
+use crate::utils::OpsError;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+
+/// Number of repos whose [`ModelsCat`] a [`Hub`] keeps by default.
+const DEFAULT_CAPACITY: usize = 16;
+
+/// A configured hub client for asynchronous shortcuts.
+///
+/// Unlike the free functions, which each start from scratch, a `Hub` keeps the [`ModelsCat`] of
+/// recently used repos, so repeated calls share their listing cache and configuration.
+///
+/// ```no_run
+/// # async fn run() -> Result<(), models_cat::OpsError> {
+/// use models_cat::asynchronous::Hub;
+/// let hub = Hub::builder().token("my-token").build()?;
+/// hub.download_model("BAAI/bge-small-zh-v1.5", "config.json").await?;
+/// hub.download_model("BAAI/bge-small-zh-v1.5", "tokenizer.json").await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Hub {
+    endpoint: Option<String>,
+    token: Option<String>,
+    cache_dir: Option<PathBuf>,
+    capacity: usize,
+    /// From least to most recently used.
+    cats: Mutex<VecDeque<Arc<ModelsCat>>>,
+}
+
+/// Builder for [`Hub`].
+#[derive(Debug, Clone)]
+pub struct HubBuilder {
+    endpoint: Option<String>,
+    token: Option<String>,
+    cache_dir: Option<PathBuf>,
+    capacity: usize,
+}
+
+impl Default for HubBuilder {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            token: None,
+            cache_dir: None,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl HubBuilder {
+    /// Sets the hub endpoint of every repo. Defaults to the endpoint each repo resolves to on
+    /// its own, from `MODELS_CAT_ENDPOINT`, the [config file](crate::config) or else
+    /// [ModelScope](https://www.modelscope.cn).
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Authenticates every request with `token`.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Sets the cache directory, defaulting to the `MODELS_CAT_CACHE_DIR` environment variable
+    /// or `~/.cache/modelscope/hub`.
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Sets how many repos are kept warm, 16 by default.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Builds the [`Hub`], validating the configuration.
+    pub fn build(self) -> Result<Hub, OpsError> {
+        if let Some(ref endpoint) = self.endpoint {
+            reqwest::Url::parse(endpoint)
+                .map_err(|e| OpsError::BuildError(format!("invalid endpoint {endpoint}: {e}")))?;
+        }
+        if self.capacity == 0 {
+            return Err(OpsError::BuildError("capacity must be at least 1".into()));
+        }
+        Ok(Hub {
+            endpoint: self.endpoint.as_deref().map(crate::endpoints::normalize),
+            token: self.token,
+            cache_dir: self.cache_dir,
+            capacity: self.capacity,
+            cats: Mutex::new(VecDeque::new()),
+        })
+    }
+}
+
+impl Hub {
+    /// Starts building a [`Hub`].
+    pub fn builder() -> HubBuilder {
+        HubBuilder::default()
+    }
+
+    /// Returns the [`ModelsCat`] of `repo`, reusing the one from an earlier call when possible.
+    pub fn repo(&self, mut repo: Repo) -> Arc<ModelsCat> {
+        if let Some(ref cache_dir) = self.cache_dir {
+            repo.set_cache_dir(cache_dir);
+        }
+        let mut cats = self.cats.lock().unwrap();
+        let cached = cats.iter().position(|cat| {
+            cat.repo().cache_dir() == repo.cache_dir() && cat.repo().revision() == repo.revision()
+        });
+        let cat = match cached {
+            Some(pos) => cats.remove(pos).unwrap(),
+            None => {
+                let mut cat = match self.endpoint {
+                    Some(ref endpoint) => ModelsCat::new_with_endpoint(repo, endpoint.clone()),
+                    None => ModelsCat::new(repo),
+                };
+                if let Some(ref token) = self.token {
+                    cat = cat.with_token(token);
+                }
+                Arc::new(cat)
+            }
+        };
+        cats.push_back(cat.clone());
+        while cats.len() > self.capacity {
+            cats.pop_front();
+        }
+        cat
+    }
+
+    /// Downloads a file of a model repo.
+    pub async fn download_model(&self, repo_id: &str, filename: &str) -> Result<PathBuf, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Model)?)
+            .download(filename)
+            .await
+    }
+
+    /// Downloads a file of a model repo with progress.
+    pub async fn download_model_with_progress(
+        &self,
+        repo_id: &str,
+        filename: &str,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Model)?)
+            .download_with_progress(filename, progress)
+            .await
+    }
+
+    /// Downloads a file of a dataset repo.
+    pub async fn download_dataset(
+        &self,
+        repo_id: &str,
+        filename: &str,
+    ) -> Result<PathBuf, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Dataset)?)
+            .download(filename)
+            .await
+    }
+
+    /// Downloads a file of a dataset repo with progress.
+    pub async fn download_dataset_with_progress(
+        &self,
+        repo_id: &str,
+        filename: &str,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Dataset)?)
+            .download_with_progress(filename, progress)
+            .await
+    }
+
+    /// Pulls a model repo.
+    pub async fn pull_model(&self, repo_id: &str) -> Result<PullReport, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Model)?)
+            .pull()
+            .await
+    }
+
+    /// Pulls a dataset repo.
+    pub async fn pull_dataset(&self, repo_id: &str) -> Result<PullReport, OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Dataset)?)
+            .pull()
+            .await
+    }
+
+    /// Removes a local model repo.
+    pub async fn remove_model_repo(&self, repo_id: &str) -> Result<(), OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Model)?)
+            .remove_all()
+            .await
+    }
+
+    /// Removes a local dataset repo.
+    pub async fn remove_dataset_repo(&self, repo_id: &str) -> Result<(), OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Dataset)?)
+            .remove_all()
+            .await
+    }
+
+    /// Removes a file of a local model repo.
+    pub async fn remove_model_file(&self, repo_id: &str, filename: &str) -> Result<(), OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Model)?)
+            .remove(filename)
+            .await
+    }
+
+    /// Removes a file of a local dataset repo.
+    pub async fn remove_dataset_file(&self, repo_id: &str, filename: &str) -> Result<(), OpsError> {
+        self.repo(Repo::try_new(repo_id, RepoType::Dataset)?)
+            .remove(filename)
+            .await
+    }
+}
+
+/// The [`Hub`] behind the free functions of [`crate::asynchronous`].
+pub(crate) fn default_hub() -> &'static Hub {
+    static DEFAULT_HUB: LazyLock<Hub> =
+        LazyLock::new(|| Hub::builder().build().expect("default hub config is valid"));
+    &DEFAULT_HUB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hub::ms_hub;
+    use std::sync::atomic::Ordering;
+
+    #[tokio::test]
+    async fn test_listing_fetched_once() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let hub = Hub::builder()
+            .endpoint(server.url())
+            .cache_dir(cache.path())
+            .token("secret")
+            .build()
+            .unwrap();
+
+        hub.download_model("BAAI/bge-small-zh-v1.5", "a.txt")
+            .await
+            .unwrap();
+        hub.download_model("BAAI/bge-small-zh-v1.5", "b.txt")
+            .await
+            .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(Arc::ptr_eq(
+            &hub.repo(Repo::new_model("BAAI/bge-small-zh-v1.5")),
+            &hub.repo(Repo::new_model("BAAI/bge-small-zh-v1.5"))
+        ));
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        assert!(Hub::builder().endpoint("not a url").build().is_err());
+        assert!(Hub::builder().capacity(0).build().is_err());
+
+        let hub = Hub::builder().capacity(1).build().unwrap();
+        let a = hub.repo(Repo::new_model("a/a"));
+        hub.repo(Repo::new_model("b/b"));
+        assert!(!Arc::ptr_eq(&a, &hub.repo(Repo::new_model("a/a"))));
+    }
+
+    #[test]
+    fn test_endpoint_per_repo() {
+        // Without an endpoint, each repo resolves its own, as `ModelsCat::new` does.
+        let hub = Hub::builder().build().unwrap();
+        let repo = Repo::new_model("a/a");
+        assert_eq!(
+            hub.repo(repo.clone()).endpoint(),
+            ModelsCat::new(repo).endpoint()
+        );
+
+        let hub = Hub::builder()
+            .endpoint("https://mirror.example/")
+            .build()
+            .unwrap();
+        let cat = hub.repo(Repo::new_model("a/a"));
+        assert_eq!(cat.endpoint(), "https://mirror.example");
+    }
+}
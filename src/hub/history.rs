@@ -0,0 +1,208 @@
+//! A log of the pulls, downloads and removals of a repo, kept as JSON lines under its cache
+//! directory, for finding out when a model changed.
+use crate::fslock::FsLock;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The default size of the history log, past which it is rotated.
+pub(crate) const DEFAULT_HISTORY_SIZE_LIMIT: u64 = 1024 * 1024;
+
+/// An operation recorded in the history log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryOperation {
+    /// A pull of the whole repo.
+    Pull,
+    /// A download of a single file.
+    Download,
+    /// A removal of a single file.
+    Remove,
+    /// An import of local files, see [`crate::hub::ModelsCat::import_file`].
+    Import,
+}
+
+/// How a recorded operation ended.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum HistoryOutcome {
+    /// The operation succeeded.
+    Success,
+    /// The operation failed with `error`.
+    Failure {
+        /// The error the operation returned.
+        error: String,
+    },
+}
+
+/// A record of the history log, see [`crate::hub::ModelsCat::history`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// When the operation ended, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// What was done.
+    pub operation: HistoryOperation,
+    /// The repo ID.
+    pub repo: String,
+    /// The revision the repo was configured with, such as `master`.
+    pub revision: String,
+    /// The files downloaded or removed.
+    pub files: Vec<String>,
+    /// The bytes downloaded or removed.
+    pub bytes: u64,
+    /// How the operation ended.
+    pub outcome: HistoryOutcome,
+}
+
+impl HistoryEntry {
+    pub(crate) fn new<T>(
+        repo: &Repo,
+        operation: HistoryOperation,
+        files: Vec<String>,
+        bytes: u64,
+        result: &Result<T, OpsError>,
+    ) -> Self {
+        Self {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            operation,
+            repo: repo.repo_id().to_string(),
+            revision: repo.revision().as_str().to_string(),
+            files,
+            bytes,
+            outcome: match result {
+                Ok(_) => HistoryOutcome::Success,
+                Err(e) => HistoryOutcome::Failure {
+                    error: e.to_string(),
+                },
+            },
+        }
+    }
+}
+
+fn log_path(repo: &Repo) -> PathBuf {
+    repo.cache_dir().join("history.jsonl")
+}
+
+fn rotated_path(log: &Path) -> PathBuf {
+    log.with_extension("jsonl.1")
+}
+
+/// Appends `entry` to the history log of `repo`, first moving the log aside when the entry
+/// would take it over `size_limit` bytes. Only the latest rotated log is kept.
+///
+/// Nothing is logged for a repo without a cache directory, such as after a failed first
+/// download, so the log never makes it show up as cached.
+pub(crate) fn append(repo: &Repo, entry: &HistoryEntry, size_limit: u64) -> Result<(), OpsError> {
+    if !repo.cache_dir().is_dir() {
+        return Ok(());
+    }
+    let path = log_path(repo);
+    let mut line =
+        serde_json::to_vec(entry).map_err(|e| OpsError::hub("failed to write history", e))?;
+    line.push(b'\n');
+
+    let mut lock = FsLock::lock(path.clone())?;
+    let result = (|| -> Result<(), OpsError> {
+        let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+        if size > 0 && size + line.len() as u64 > size_limit {
+            std::fs::rename(&path, rotated_path(&path))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+        // Start on a new line after a line cut short by a crash, so only that one is lost.
+        let mut last = [b'\n'];
+        if file.seek(SeekFrom::End(0))? > 0 {
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+        }
+        if last[0] != b'\n' {
+            line.insert(0, b'\n');
+        }
+        file.write_all(&line)?;
+        Ok(())
+    })();
+    lock.unlock();
+    result
+}
+
+/// The last `limit` entries of the history log of `repo`, oldest first. Lines that cannot be
+/// parsed, such as one cut short by a crash, are skipped with a warning.
+pub(crate) fn read(repo: &Repo, limit: usize) -> Result<Vec<HistoryEntry>, OpsError> {
+    let path = log_path(repo);
+    let mut entries = Vec::new();
+    for log in [rotated_path(&path), path] {
+        let content = match std::fs::read_to_string(&log) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for (number, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => log::warn!("skipping line {} of {}: {e}", number + 1, log.display()),
+            }
+        }
+    }
+    let skip = entries.len().saturating_sub(limit);
+    Ok(entries.split_off(skip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_and_corrupt_lines() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let entry = |file: &str| {
+            let files = vec![file.to_string()];
+            HistoryEntry::new(&repo, HistoryOperation::Download, files, 3, &Ok(()))
+        };
+        let size = serde_json::to_vec(&entry("a.txt")).unwrap().len() as u64 + 1;
+
+        // A repo that was never cached gets no directory just for its log.
+        append(&repo, &entry("a.txt"), size).unwrap();
+        assert!(!repo.cache_dir().exists());
+
+        std::fs::create_dir_all(repo.cache_dir()).unwrap();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            append(&repo, &entry(file), 2 * size).unwrap();
+        }
+        // The third entry rotated the first two out of the current log.
+        let path = log_path(&repo);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        let files: Vec<_> = read(&repo, 10)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.files[0].clone())
+            .collect();
+        assert_eq!(files, ["a.txt", "b.txt", "c.txt"]);
+
+        // A line cut short by a crash is skipped, and does not swallow the next entry.
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        log.write_all(b"{\"timestamp\": 1, \"operat").unwrap();
+        drop(log);
+        append(&repo, &entry("d.txt"), 10 * size).unwrap();
+        let entries = read(&repo, 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].files, ["c.txt"]);
+        assert_eq!(entries[1].files, ["d.txt"]);
+    }
+}
@@ -0,0 +1,193 @@
+//! Files downloaded outside of models-cat, such as with a browser or aria2, placed in the cache
+//! once they check out against the listing, so they are not downloaded again.
+use super::ms_hub::FileInfo;
+use super::resolved::{self, ResolvedFile};
+use super::{
+    completed_files, group_by_revision, is_complete, latest_revision, mark_complete, paths,
+    provenance, snapshot_file, update_latest,
+};
+use crate::dedup;
+use crate::fslock::FsLock;
+use crate::repo::Repo;
+use crate::utils::{self, OpsError};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// How an imported file was checked, see [`crate::hub::ModelsCat::import_file`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The sha256 and size match the listing. Holds the path of the file in the cache.
+    Verified(PathBuf),
+    /// The hub lists no sha256 for the file, so only its size was compared. Holds the path of
+    /// the file in the cache.
+    SizeMatched(PathBuf),
+}
+
+impl ImportOutcome {
+    /// The path of the imported file in the cache.
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Verified(path) | Self::SizeMatched(path) => path,
+        }
+    }
+}
+
+/// What [`crate::hub::ModelsCat::import_dir`] did.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    /// Hub filenames imported, with how each was checked.
+    pub imported: Vec<(String, ImportOutcome)>,
+    /// Hub filenames whose local file does not match the listing, with the error.
+    pub rejected: Vec<(String, OpsError)>,
+    /// Local files matching no hub filename.
+    pub unknown: Vec<PathBuf>,
+    /// Whether every file a pull would place is now cached, in which case the ref and `latest`
+    /// link of the repo point at the newest snapshot.
+    pub complete: bool,
+}
+
+/// Checks `local` against the listed size and sha256 of `fileinfo`, then places it at
+/// `local_name` in the snapshot of its revision, as a hard link when possible.
+///
+/// The file is recorded as complete and resolved like a download, so pulls and downloads
+/// find it current.
+pub(crate) fn import(
+    repo: &Repo,
+    local: &Path,
+    fileinfo: &FileInfo,
+    local_name: &str,
+    commit: Option<&str>,
+    global_dedup: bool,
+) -> Result<ImportOutcome, OpsError> {
+    let size = std::fs::metadata(local)?.len();
+    if size != fileinfo.size as u64 {
+        return Err(OpsError::ChecksumMismatch {
+            file: fileinfo.path.clone(),
+            expected: format!("{} bytes", fileinfo.size),
+            actual: format!("{size} bytes"),
+            quarantined: None,
+        });
+    }
+    if let Some(ref expected) = fileinfo.sha256 {
+        let actual = utils::sha256(local)?;
+        if &actual != expected {
+            return Err(OpsError::ChecksumMismatch {
+                file: fileinfo.path.clone(),
+                expected: expected.clone(),
+                actual,
+                quarantined: None,
+            });
+        }
+    }
+
+    let revision = &fileinfo.revision;
+    if local_name != fileinfo.path {
+        let sanitized = HashMap::from([(fileinfo.path.clone(), local_name.to_string())]);
+        paths::record_sanitized(repo, revision, std::slice::from_ref(fileinfo), &sanitized)?;
+    }
+    let snapshot_path = repo.snapshot_path(revision);
+    std::fs::create_dir_all(&snapshot_path)?;
+    let filepath = snapshot_file(&snapshot_path, local_name);
+    let mut lock = FsLock::lock(snapshot_path)?;
+    let placed = dedup::link_or_copy(local, &filepath);
+    lock.unlock();
+    placed?;
+
+    provenance::forget(repo, revision, &fileinfo.path)?;
+    if global_dedup {
+        dedup::record_download(repo.cache_home(), fileinfo.sha256.as_deref(), &filepath)?;
+    }
+    mark_complete(repo, revision, std::slice::from_ref(fileinfo))?;
+    let resolved_file = ResolvedFile {
+        revision: revision.clone(),
+        local_path: local_name.to_string(),
+        size,
+        sha256: fileinfo.sha256.clone(),
+        etag: None,
+    };
+    resolved::record(repo, &fileinfo.path, resolved_file, commit)?;
+    Ok(match fileinfo.sha256 {
+        Some(_) => ImportOutcome::Verified(filepath),
+        None => ImportOutcome::SizeMatched(filepath),
+    })
+}
+
+/// The files of a directory paired with listed files, see [`match_dir`].
+pub(crate) struct DirMatch<'a> {
+    /// Local files with the listed file each stands for.
+    pub(crate) matched: Vec<(PathBuf, &'a FileInfo)>,
+    /// Local files matching no listed file.
+    pub(crate) unknown: Vec<PathBuf>,
+}
+
+/// Pairs the files under `dir` with the listed `blobs`: by their path relative to `dir`, or
+/// else by their file name when a single listed file has it.
+pub(crate) fn match_dir<'a>(dir: &Path, blobs: &'a [FileInfo]) -> Result<DirMatch<'a>, OpsError> {
+    let by_path: HashMap<&str, &FileInfo> = blobs.iter().map(|f| (f.path.as_str(), f)).collect();
+    let mut by_name: HashMap<&str, Vec<&FileInfo>> = HashMap::new();
+    for fileinfo in blobs {
+        let name = fileinfo.path.rsplit('/').next().unwrap_or(&fileinfo.path);
+        by_name.entry(name).or_default().push(fileinfo);
+    }
+
+    let (mut matched, mut unknown) = (Vec::new(), Vec::new());
+    let mut seen = HashSet::new();
+    for entry in walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry
+            .path()
+            .strip_prefix(dir)
+            .map_err(|e| OpsError::hub("file outside the import directory", e))?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let name = entry.file_name().to_string_lossy();
+        let fileinfo = by_path.get(relative.as_str()).copied().or_else(|| {
+            match by_name.get(name.as_ref()).map(Vec::as_slice) {
+                Some([fileinfo]) => Some(*fileinfo),
+                _ => None,
+            }
+        });
+        match fileinfo {
+            Some(fileinfo) if seen.insert(&fileinfo.path) => {
+                matched.push((entry.into_path(), fileinfo))
+            }
+            _ => unknown.push(entry.into_path()),
+        }
+    }
+    Ok(DirMatch { matched, unknown })
+}
+
+/// Points the ref and `latest` link of `repo` at the newest revision of `blobs` once every
+/// one of them is cached, as a pull would. Returns whether it did.
+pub(crate) fn activate_if_complete(repo: &Repo, blobs: &[FileInfo]) -> Result<bool, OpsError> {
+    let Some(latest) = latest_revision(blobs) else {
+        return Ok(false);
+    };
+    for (revision, files) in group_by_revision(blobs.to_vec()) {
+        let completed = completed_files(repo, &revision);
+        let local_names: HashMap<String, String> = paths::hub_names(repo, &revision)
+            .into_iter()
+            .map(|(local, hub)| (hub, local))
+            .collect();
+        let snapshot_path = repo.snapshot_path(&revision);
+        for fileinfo in &files {
+            let local_name = local_names.get(&fileinfo.path).unwrap_or(&fileinfo.path);
+            if !is_complete(
+                &completed,
+                &snapshot_file(&snapshot_path, local_name),
+                fileinfo,
+            ) {
+                return Ok(false);
+            }
+        }
+    }
+    update_latest(repo, &latest)?;
+    Ok(true)
+}
@@ -0,0 +1,182 @@
+//! Lookup of cached files by glob, for feeding whole sets of files, such as the parquet shards
+//! of a dataset, to other libraries.
+use super::ms_hub::{ApiResponse, FileInfo};
+use super::paths;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use glob::{MatchOptions, Pattern};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+/// `*` and `?` stay within one path segment; `**` crosses them.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// Options of pull filters: as with huggingface_hub's `allow_patterns` and `ignore_patterns`,
+/// `*` also matches `/`.
+const FILTER_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: false,
+    require_literal_leading_dot: false,
+};
+
+/// The patterns of a pull filter. A pattern ending in `/` stands for everything below that
+/// directory.
+pub(crate) fn filter_patterns(patterns: &[String]) -> Result<Vec<Pattern>, OpsError> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let glob = match pattern.ends_with('/') {
+                true => format!("{pattern}*"),
+                false => pattern.clone(),
+            };
+            Pattern::new(&glob)
+                .map_err(|e| OpsError::BuildError(format!("invalid pattern `{pattern}`: {e}")))
+        })
+        .collect()
+}
+
+/// Whether the hub filename `path` passes the filter of `allow`, unless empty, then `ignore`.
+pub(crate) fn filter_matches(allow: &[Pattern], ignore: &[Pattern], path: &str) -> bool {
+    let matches = |patterns: &[Pattern]| {
+        patterns
+            .iter()
+            .any(|p| p.matches_with(path, FILTER_OPTIONS))
+    };
+    (allow.is_empty() || matches(allow)) && !matches(ignore)
+}
+
+/// Absolute paths of the cached files of `repo` whose hub-relative filename matches `pattern`,
+/// sorted by filename.
+///
+/// Without a listing, the files are looked up in the snapshot the revision's ref points at, or
+/// else the last pulled one. With a listing, every matching hub file must be in place in its
+/// snapshot, and the ones that are not are reported.
+pub(crate) fn local_files_matching(
+    repo: &Repo,
+    pattern: &str,
+    listing: Option<&ApiResponse>,
+) -> Result<Vec<PathBuf>, OpsError> {
+    let pattern = Pattern::new(pattern)
+        .map_err(|e| OpsError::hub(format!("invalid pattern {pattern}"), e))?;
+    let files = match listing {
+        Some(listing) => listed_files(repo, &pattern, listing)?,
+        None => snapshot_files(repo, &pattern)?,
+    };
+    files
+        .into_values()
+        .map(|path| Ok(std::path::absolute(path)?))
+        .collect()
+}
+
+fn listed_files(
+    repo: &Repo,
+    pattern: &Pattern,
+    listing: &ApiResponse,
+) -> Result<BTreeMap<String, PathBuf>, OpsError> {
+    let matched: Vec<_> = listing
+        .blobs()
+        .into_iter()
+        .filter(|fileinfo| pattern.matches_with(&fileinfo.path, MATCH_OPTIONS))
+        .collect();
+    cached_paths(repo, &matched).map_err(|missing| {
+        OpsError::HubError(format!(
+            "{} files matching {pattern} are not cached yet, pull them first: {}",
+            missing.len(),
+            missing.join(", ")
+        ))
+    })
+}
+
+/// The paths of the listed `blobs` in their snapshots, by hub filename, or the hub filenames
+/// of the ones that are not in place.
+pub(crate) fn cached_paths(
+    repo: &Repo,
+    blobs: &[FileInfo],
+) -> Result<BTreeMap<String, PathBuf>, Vec<String>> {
+    let mut local_names: HashMap<String, HashMap<String, String>> = HashMap::new();
+    let mut files = BTreeMap::new();
+    let mut missing = Vec::new();
+    for fileinfo in blobs {
+        let local_names = local_names
+            .entry(fileinfo.revision.clone())
+            .or_insert_with(|| {
+                paths::hub_names(repo, &fileinfo.revision)
+                    .into_iter()
+                    .map(|(local, hub)| (hub, local))
+                    .collect()
+            });
+        let local_name = local_names.get(&fileinfo.path).unwrap_or(&fileinfo.path);
+        let mut filepath = repo.snapshot_path(&fileinfo.revision);
+        for part in local_name.split('/') {
+            filepath.push(part);
+        }
+        if std::fs::metadata(&filepath).is_ok_and(|m| m.len() == fileinfo.size as u64) {
+            files.insert(fileinfo.path.clone(), filepath);
+        } else {
+            missing.push(fileinfo.path.clone());
+        }
+    }
+    if missing.is_empty() {
+        Ok(files)
+    } else {
+        Err(missing)
+    }
+}
+
+/// The snapshot the revision of `repo` resolves to without network access: the one its ref
+/// points at, or else the last pulled one.
+pub(crate) fn offline_snapshot(repo: &Repo) -> Option<PathBuf> {
+    repo.revision_snapshot()
+        .filter(|snapshot| snapshot.is_dir())
+        .or_else(|| repo.latest_snapshot())
+        .filter(|snapshot| snapshot.is_dir())
+}
+
+/// The cached files of the [`offline_snapshot`] of `repo` whose hub filename matches
+/// `pattern`, by hub filename.
+pub(crate) fn snapshot_files(
+    repo: &Repo,
+    pattern: &Pattern,
+) -> Result<BTreeMap<String, PathBuf>, OpsError> {
+    let snapshot = offline_snapshot(repo).ok_or_else(|| {
+        OpsError::HubError(format!(
+            "No snapshot of {} cached for revision {}, pull it first",
+            repo.repo_id(),
+            repo.revision().as_str()
+        ))
+    })?;
+    let revision = snapshot
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let hub_names = paths::hub_names(repo, &revision);
+
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(&snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let local_name = relative_name(&snapshot, entry.path())?;
+        let hub_name = hub_names.get(&local_name).unwrap_or(&local_name).clone();
+        if pattern.matches_with(&hub_name, MATCH_OPTIONS) {
+            files.insert(hub_name, entry.into_path());
+        }
+    }
+    Ok(files)
+}
+
+pub(crate) fn relative_name(snapshot: &Path, path: &Path) -> Result<String, OpsError> {
+    Ok(path
+        .strip_prefix(snapshot)
+        .map_err(|e| OpsError::hub("file outside the snapshot", e))?
+        .to_string_lossy()
+        .replace('\\', "/"))
+}
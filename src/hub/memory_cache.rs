@@ -0,0 +1,98 @@
+//! A size-capped in-memory LRU of small file contents.
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Cache key: the commit the file resolves to and its hub-relative filename.
+type Key = (String, String);
+
+/// Raw bytes of fetched files, evicting the least recently used entries beyond `capacity` bytes.
+pub(crate) struct MemoryCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<Key, Arc<[u8]>>,
+    /// Keys from least to most recently used.
+    order: VecDeque<Key>,
+    size: usize,
+}
+
+impl MemoryCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub(crate) fn get(&self, commit: &str, filename: &str) -> Option<Arc<[u8]>> {
+        let mut inner = self.inner.lock().unwrap();
+        let key = (commit.to_string(), filename.to_string());
+        let bytes = inner.entries.get(&key)?.clone();
+        inner.touch(&key);
+        Some(bytes)
+    }
+
+    /// Stores `bytes`, unless they alone exceed the capacity.
+    pub(crate) fn insert(&self, commit: &str, filename: &str, bytes: Arc<[u8]>) {
+        if bytes.len() > self.capacity {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let key = (commit.to_string(), filename.to_string());
+        if let Some(old) = inner.entries.insert(key.clone(), bytes.clone()) {
+            inner.size -= old.len();
+        }
+        inner.size += bytes.len();
+        inner.touch(&key);
+        while inner.size > self.capacity {
+            let Some(oldest) = inner.order.pop_front() else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.size -= evicted.len();
+            }
+        }
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.inner.lock().unwrap() = Inner::default();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+}
+
+impl Inner {
+    fn touch(&mut self, key: &Key) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(key.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = MemoryCache::new(6);
+        cache.insert("master", "a", Arc::from(&b"aaa"[..]));
+        cache.insert("master", "b", Arc::from(&b"bbb"[..]));
+        assert!(cache.get("master", "a").is_some());
+        cache.insert("master", "c", Arc::from(&b"ccc"[..]));
+
+        assert_eq!(cache.get("master", "a").as_deref(), Some(&b"aaa"[..]));
+        assert!(cache.get("master", "b").is_none());
+        assert!(cache.get("v1", "a").is_none());
+        cache.insert("master", "big", Arc::from(&b"too large"[..]));
+        assert!(cache.get("master", "big").is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -3,30 +3,296 @@
 //! It includes both synchronous and asynchronous operations, depending on the feature flags enabled.
 //!
 //! For examaple:
-//! ```
-//! use hub::ModelsCat;
-//! use hub::Repo;
+//! ```no_run
+//! use models_cat::hub::{ModelsCat, ProgressBarWrapper};
+//! use models_cat::repo::Repo;
 //! fn main() -> Result<(), Box<dyn std::error::Error>> {
 //!     let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
-//!     cat.download_with_progress("model.safetensors", hub::ProgressBarWrapper::default())?;
+//!     cat.download_with_progress("model.safetensors", ProgressBarWrapper::default())?;
 //!     Ok(())
 //! }
 //! ```
+mod archive;
 #[cfg(feature = "tokio")]
 pub mod async_hub;
+mod blobs;
+mod export;
+#[cfg(feature = "tokio")]
+pub(crate) mod facade;
+mod history;
+mod import;
+mod matching;
+mod memory_cache;
 mod ms_hub;
+mod offline;
+mod paths;
+mod prepared;
+#[cfg(feature = "tokio")]
+mod progress_bridge;
+mod provenance;
+mod prune;
+mod quarantine;
+mod rate_limit;
+mod ready;
+mod report;
+mod resolved;
+mod retry;
+mod staging;
+mod throttle;
+mod transfer_slots;
+mod update;
+mod validate;
+mod verify;
+
+pub use archive::ArchiveCompression;
+pub use export::{DownloadOptions, ExportMode};
+pub use history::{HistoryEntry, HistoryOperation, HistoryOutcome};
+pub use import::{ImportOutcome, ImportReport};
+pub use paths::InvalidPathPolicy;
+pub use prepared::PreparedSnapshot;
+pub use provenance::{Provenance, ProvenanceRecord};
+pub use rate_limit::{RateLimitPacing, RateLimitStatus};
+pub use ready::{EnsureRequest, ReadyModel};
+pub use report::{
+    FileChange, FileMetadata, FileStatus, PruneReport, PullReport, Resolution, SyncReport,
+    UpdateStatus, VerifyReport,
+};
+pub use retry::RetryPolicy;
+pub use validate::{HubFileInfo, ValidationError, Validator};
+
+#[cfg(test)]
+pub(crate) use ms_hub::test_hub;
+pub(crate) use offline::MODELS_CAT_OFFLINE;
 
+use crate::cache;
+use crate::config;
+use crate::dedup;
+use crate::diagnostics::Diagnostics;
+use crate::endpoints::{self, Endpoint};
 use crate::fslock;
-use crate::repo::Repo;
-use crate::utils::{self, BLOCKING_CLIENT, OpsError};
+use crate::repo::{Repo, Revision};
+use crate::utils::{self, BLOCKING_CLIENT, ClientOptions, OpsError};
 use indicatif::{
     MultiProgress as MultiProgressBar, ProgressBar, ProgressFinish, ProgressState, ProgressStyle,
 };
-use ms_hub::synchronous;
+use memory_cache::MemoryCache;
+use ms_hub::{ApiResponse, FileInfo, synchronous};
+use paths::PathRules;
+use quarantine::QuarantineReport;
+use reqwest::StatusCode;
+use reqwest::blocking;
+use reqwest::header::{CONTENT_RANGE, HeaderMap, RANGE};
+use resolved::{HeadCheck, ResolvedFile};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use staging::{CHECKPOINT_INTERVAL, Recovery, StagedFile};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tempfile::NamedTempFile;
+use throttle::Throttle;
+use transfer_slots::{TransferSlot, TransferSlots};
+
+/// Bytes of a download buffered in memory before they are written to the file, see
+/// [`ModelsCat::with_write_buffer_size`].
+pub(crate) const DEFAULT_WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How long a repo listing is reused by downloads before it is fetched again; pulls always fetch
+/// a fresh one.
+pub(crate) const LISTING_TTL: Duration = Duration::from_secs(60);
+
+/// The last repo listing and when it was fetched.
+pub(crate) type Listing = Mutex<Option<(Instant, Arc<ApiResponse>)>>;
+
+/// Returns the cached listing if it is younger than [`LISTING_TTL`].
+pub(crate) fn cached_listing(listing: &Listing) -> Option<Arc<ApiResponse>> {
+    match *listing.lock().unwrap() {
+        Some((fetched, ref files)) if fetched.elapsed() < LISTING_TTL => Some(files.clone()),
+        _ => None,
+    }
+}
+
+/// A callback receiving log lines, see [`ModelsCat::with_logger`].
+pub type Logger = Box<dyn FnMut(&str) + Send>;
+
+/// Settings of a single pull, see [`ModelsCat::pull_with_options`].
+///
+/// Built from [`PullOptions::default`] with the `with_*` methods. With the `serde` feature, it
+/// can also be loaded from app config, where omitted fields take their default:
+///
+/// ```
+/// # #[cfg(feature = "serde")] {
+/// use models_cat::hub::{CacheValidation, PullOptions};
+///
+/// let options: PullOptions = toml::from_str("validation = \"commit_date\"").unwrap();
+/// assert_eq!(options.validation, Some(CacheValidation::CommitDate));
+/// # }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct PullOptions {
+    /// Overrides the instance's [`CacheValidation`] for this pull only.
+    pub validation: Option<CacheValidation>,
+    /// Leaves out hidden files, whose path has a component starting with `.`, such as
+    /// `.gitattributes` or `.mdl/meta`. Defaults to `false`, pulling every file.
+    pub skip_hidden: bool,
+    /// Pulls only the files matching one of these globs, such as `*.safetensors`, or every
+    /// file when empty, the default. As with huggingface_hub, `*` also matches `/`, and a
+    /// pattern ending in `/` matches everything below that directory.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub allow_patterns: Vec<String>,
+    /// Leaves out the files matching one of these globs, after `allow_patterns` is applied.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub ignore_patterns: Vec<String>,
+}
+
+impl PullOptions {
+    /// Confirms cached copies with `validation` instead of the instance default.
+    pub fn with_validation(mut self, validation: CacheValidation) -> Self {
+        self.validation = Some(validation);
+        self
+    }
+
+    /// Leaves out hidden files when `skip` is `true`.
+    pub fn with_skip_hidden(mut self, skip: bool) -> Self {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /// Pulls only the files matching one of `patterns`, see [`PullOptions::allow_patterns`].
+    pub fn with_allow_patterns(
+        mut self,
+        patterns: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.allow_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Leaves out the files matching one of `patterns`, see [`PullOptions::ignore_patterns`].
+    pub fn with_ignore_patterns(
+        mut self,
+        patterns: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.ignore_patterns = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Checks the options, as pulls do before starting: every pattern must be a valid glob.
+    pub fn validate(&self) -> Result<(), OpsError> {
+        matching::filter_patterns(&self.allow_patterns)?;
+        matching::filter_patterns(&self.ignore_patterns)?;
+        Ok(())
+    }
+
+    /// The files among `blobs` passing `allow_patterns` and `ignore_patterns`.
+    pub(crate) fn filter(&self, blobs: Vec<FileInfo>) -> Result<Vec<FileInfo>, OpsError> {
+        let allow = matching::filter_patterns(&self.allow_patterns)?;
+        let ignore = matching::filter_patterns(&self.ignore_patterns)?;
+        Ok(blobs
+            .into_iter()
+            .filter(|f| matching::filter_matches(&allow, &ignore, &f.path))
+            .collect())
+    }
+
+    /// Splits `blobs` into the files to pull and the paths of the hidden files left out.
+    /// Fails when the patterns leave out every file, which is more likely a typo than intended.
+    pub(crate) fn select(
+        &self,
+        blobs: Vec<FileInfo>,
+    ) -> Result<(Vec<FileInfo>, Vec<String>), OpsError> {
+        let listed = blobs.len();
+        let blobs = self.filter(blobs)?;
+        if blobs.is_empty() && listed > 0 {
+            return Err(OpsError::HubError(format!(
+                "none of the {listed} files matches the allow patterns {:?} without matching the ignore patterns {:?}",
+                self.allow_patterns, self.ignore_patterns
+            )));
+        }
+        if !self.skip_hidden {
+            return Ok((blobs, Vec::new()));
+        }
+        let (hidden, blobs): (Vec<_>, Vec<_>) = blobs
+            .into_iter()
+            .partition(|f| f.path.split('/').any(|part| part.starts_with('.')));
+        Ok((blobs, hidden.into_iter().map(|f| f.path).collect()))
+    }
+}
+
+/// How a cached file is confirmed as current before it would be downloaded again, see
+/// [`ModelsCat::with_cache_validation`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum CacheValidation {
+    /// Hash the file and compare it with the listed sha256, or compare sizes when the listing
+    /// carries no sha256.
+    #[default]
+    Sha256,
+    /// Compare sizes, and download again when the file was committed after the local copy was
+    /// last modified. Skips hashing, at the risk of keeping a stale file of the same size.
+    CommitDate,
+}
+
+/// What downloads and pulls do when a [`Progress`] callback returns an error, see
+/// [`ModelsCat::with_progress_error_policy`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressErrorPolicy {
+    /// Fail the download with the callback's error, discarding what was transferred.
+    #[default]
+    Abort,
+    /// Log the error and complete the download, without invoking the callback again for the
+    /// rest of the file. Affected files are reported in [`PullReport::progress_degraded`] and
+    /// [`Resolution::progress_degraded`].
+    Ignore,
+}
+
+impl ProgressErrorPolicy {
+    /// Applies the policy to the `result` of a progress callback for `filename`. Returns whether
+    /// the callback is still to be invoked for the file.
+    pub(crate) fn handle(
+        self,
+        filename: &str,
+        result: Result<(), OpsError>,
+    ) -> Result<bool, OpsError> {
+        match (self, result) {
+            (_, Ok(())) => Ok(true),
+            (Self::Abort, Err(e)) => Err(e),
+            (Self::Ignore, Err(e)) => {
+                log::warn!("progress reporting for {filename} stopped: {e}");
+                Ok(false)
+            }
+        }
+    }
+
+    /// Applies the policy like [`ProgressErrorPolicy::handle`] to the `result` of
+    /// [`Progress::on_progress_control`], failing with [`OpsError::Cancelled`] on a break.
+    pub(crate) fn handle_control(
+        self,
+        filename: &str,
+        result: Result<ControlFlow<()>, OpsError>,
+    ) -> Result<bool, OpsError> {
+        match result {
+            Ok(ControlFlow::Break(())) => Err(OpsError::Cancelled {
+                file: filename.to_string(),
+            }),
+            Ok(ControlFlow::Continue(())) => Ok(true),
+            Err(e) => self.handle(filename, Err(e)),
+        }
+    }
+}
 
 /// A struct representing a models management system for downloading, pulling, and managing files from a hub.
 ///
@@ -35,431 +301,5244 @@ use tempfile::NamedTempFile;
 /// - Downloading specific files with or without progress tracking.
 /// - Listing hub files and local cached files.
 /// - Removing files or clearing the entire cache.
+///
+/// This is the only synchronous `ModelsCat`; the crate root re-exports it:
+///
+/// ```
+/// use models_cat::{ModelsCat, Repo};
+///
+/// fn load(_: &models_cat::hub::ModelsCat) {}
+/// load(&ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5")));
+/// ```
 pub struct ModelsCat {
     endpoint: String,
     repo: Repo,
+    global_dedup: bool,
+    blob_store: bool,
+    memory_cache: Option<Arc<MemoryCache>>,
+    logger: Option<Mutex<Logger>>,
+    invalid_paths: InvalidPathPolicy,
+    validation: CacheValidation,
+    progress_errors: ProgressErrorPolicy,
+    path_rules: PathRules,
+    token: Option<String>,
+    client: blocking::Client,
+    client_options: ClientOptions,
+    listing: Listing,
+    write_buffer_size: usize,
+    history_size_limit: u64,
+    rate_limit: Mutex<Option<RateLimitStatus>>,
+    pacing: Option<RateLimitPacing>,
+    transfer_slots: Option<Arc<TransferSlots>>,
+    quarantine: Option<u64>,
+    offline: bool,
+    validators: Vec<Arc<dyn Validator>>,
+    max_concurrency: usize,
+    retry: RetryPolicy,
+    cancel: Option<Arc<AtomicBool>>,
+    throttle: Option<Arc<Throttle>>,
 }
 
 impl ModelsCat {
-    /// Creates a new `ModelsCat` instance with default [endpoint](https://www.modelscope.cn).
+    /// Creates a new `ModelsCat` instance with the default endpoint:
+    /// `$MODELS_CAT_ENDPOINT`, then the config file, then [`endpoints::MODELSCOPE_CN`].
+    ///
+    /// The instance takes `repo` over; see [`ModelsCat::repo`] for changing it afterwards.
+    ///
+    /// The token defaults to `$MODELS_CAT_TOKEN`, and token, proxy and headers to the
+    /// [config file](crate::config), when one is present.
     pub fn new(repo: Repo) -> Self {
+        let endpoint = endpoints::default_endpoint(config::settings_for(repo.repo_id()).endpoint);
+        Self::new_with_endpoint(repo, endpoint)
+    }
+
+    /// Creates a new `ModelsCat` instance from anything convertible into a [`Repo`], such as a
+    /// bare `owner/name` model ID or a built `Repo`.
+    pub fn try_new(repo: impl TryInto<Repo, Error: Into<OpsError>>) -> Result<Self, OpsError> {
+        Ok(Self::new(repo.try_into().map_err(Into::into)?))
+    }
+
+    /// Creates a new `ModelsCat` instance with a custom endpoint, used for listings as well as
+    /// downloads. A trailing `/` is dropped, and `https://` is assumed when no scheme is given.
+    pub fn new_with_endpoint(repo: Repo, endpoint: String) -> Self {
+        let endpoint = endpoints::normalize(&endpoint);
+        let settings = config::settings_for(repo.repo_id());
+        let (client_options, client) = settings
+            .client_options()
+            .and_then(|options| {
+                let client = options
+                    .blocking_client()
+                    .inspect_err(|e| log::warn!("ignoring configured client settings: {e}"))
+                    .ok()?;
+                Some((options, client))
+            })
+            .unwrap_or_else(|| (ClientOptions::default(), BLOCKING_CLIENT.clone()));
         Self {
             repo,
-            endpoint: "https://www.modelscope.cn".to_string(),
+            endpoint,
+            global_dedup: false,
+            blob_store: false,
+            memory_cache: None,
+            logger: None,
+            invalid_paths: InvalidPathPolicy::default(),
+            validation: CacheValidation::default(),
+            progress_errors: ProgressErrorPolicy::default(),
+            path_rules: PathRules::local(),
+            token: config::default_token(settings.token),
+            client,
+            client_options,
+            listing: Listing::default(),
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            history_size_limit: history::DEFAULT_HISTORY_SIZE_LIMIT,
+            rate_limit: Mutex::new(None),
+            pacing: None,
+            transfer_slots: transfer_slots::global(),
+            quarantine: None,
+            offline: offline::from_env(),
+            validators: Vec::new(),
+            max_concurrency: 1,
+            retry: RetryPolicy::default(),
+            cancel: None,
+            throttle: None,
         }
     }
 
-    /// Creates a new `ModelsCat` instance with a custom endpoint.
-    pub fn new_with_endpoint(repo: Repo, endpoint: String) -> Self {
-        Self { repo, endpoint }
+    /// Enables the global, cross-repo dedup index (disabled by default).
+    ///
+    /// Verified downloads are recorded in an index at the cache root, and files whose sha256 is
+    /// already present in another repo of the same cache are hard linked (or copied) instead of
+    /// downloaded again.
+    pub fn with_global_dedup(mut self, enabled: bool) -> Self {
+        self.global_dedup = enabled;
+        self
+    }
+
+    /// Stores downloads once per content in the repo, like the Hugging Face cache (disabled by
+    /// default).
+    ///
+    /// Each verified download is moved to `blobs/<sha256>` in the repo cache directory and
+    /// linked from its snapshot with a relative symlink, or a hard link or copy where symlinks
+    /// are unavailable. A file whose sha256 is already stored is linked instead of downloaded,
+    /// so revisions sharing a file keep one copy. [`ModelsCat::remove`] deletes a blob once no
+    /// snapshot links to it.
+    pub fn with_blob_store(mut self, enabled: bool) -> Self {
+        self.blob_store = enabled;
+        self
+    }
+
+    /// Derives the cache directory name of the repository with `cache_key`, overriding the
+    /// default `{type}--{owner}--{name}` scheme.
+    ///
+    /// Mapping several repo IDs to the same key lets mirrored repos share one cache directory,
+    /// so identical content is downloaded only once. Fails with [`OpsError::BuildError`] when the
+    /// key is not a single directory name, see [`Repo::set_cache_key`].
+    pub fn with_cache_key(mut self, cache_key: impl Fn(&Repo) -> String) -> Result<Self, OpsError> {
+        let key = cache_key(&self.repo);
+        self.repo.set_cache_key(&key)?;
+        Ok(self)
+    }
+
+    /// Keeps up to `capacity` bytes of files read through [`ModelsCat::fetch_bytes`] and
+    /// [`ModelsCat::get_json`] in memory (disabled by default), so repeated reads of small files
+    /// such as `config.json` skip the download and the disk.
+    ///
+    /// Entries are keyed by the commit each file resolves to in the repo listing, so a branch
+    /// that moved on the hub is read again once the listing is fetched anew, and dropped when a
+    /// pull moves the repo to a new revision.
+    pub fn with_memory_cache(mut self, capacity: usize) -> Self {
+        self.memory_cache = Some(Arc::new(MemoryCache::new(capacity)));
+        self
+    }
+
+    /// Sends a line for each event of the download and pull flow (downloaded, skipped,
+    /// deduplicated and failed files, `latest` updates) to `logger`.
+    ///
+    /// A lightweight alternative to `log`/`tracing` integration for apps that just want the
+    /// event stream, e.g. `Box::new(|line| eprintln!("{line}"))`.
+    pub fn with_logger(mut self, logger: Logger) -> Self {
+        self.logger = Some(Mutex::new(logger));
+        self
+    }
+
+    /// Sets what pulls and downloads do with hub paths that cannot be stored on the local
+    /// filesystem, such as names containing `:` on Windows or names differing only by case on
+    /// macOS and Windows. Defaults to [`InvalidPathPolicy::Error`].
+    ///
+    /// Every listed path is checked before anything is downloaded.
+    pub fn with_invalid_path_policy(mut self, policy: InvalidPathPolicy) -> Self {
+        self.invalid_paths = policy;
+        self
+    }
+
+    /// Sets how cached files are confirmed as current before being downloaded again. Defaults
+    /// to [`CacheValidation::Sha256`].
+    pub fn with_cache_validation(mut self, validation: CacheValidation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    /// Sets what downloads do when a progress callback returns an error. Defaults to
+    /// [`ProgressErrorPolicy::Abort`].
+    pub fn with_progress_error_policy(mut self, policy: ProgressErrorPolicy) -> Self {
+        self.progress_errors = policy;
+        self
+    }
+
+    /// Sends every request to `endpoint`, such as [`endpoints::MODELSCOPE_INTL`] parsed into
+    /// an [`Endpoint`].
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Authenticates every request with `token`, sent as a bearer token, including the
+    /// listing and file downloads. Overrides `$MODELS_CAT_TOKEN` and the config file.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Sets how many bytes of a download are buffered before they are written to disk
+    /// (1 MiB by default). Small network chunks are coalesced into writes of this size.
+    pub fn with_write_buffer_size(mut self, bytes: usize) -> Self {
+        self.write_buffer_size = bytes.max(1);
+        self
+    }
+
+    /// Sets the size of the history log, past which it is rotated (1 MiB by default). See
+    /// [`ModelsCat::history`].
+    pub fn with_history_size_limit(mut self, bytes: u64) -> Self {
+        self.history_size_limit = bytes;
+        self
+    }
+
+    /// Waits before each request to the hub while it reports `pacing.min_remaining` requests
+    /// or fewer left, until its rate-limit window ends or for at most `pacing.max_wait`.
+    /// Disabled by default, in which case requests run into the hub's `429` responses instead.
+    pub fn with_rate_limit_pacing(mut self, pacing: RateLimitPacing) -> Self {
+        self.pacing = Some(pacing);
+        self
+    }
+
+    /// Keeps downloads whose sha256 is not the listed one under `<cache_home>/quarantine`,
+    /// along with a JSON report of the expected and actual sha256, the URL, the endpoint and
+    /// the response headers, instead of deleting them. Disabled by default.
+    ///
+    /// The [`OpsError::ChecksumMismatch`] of the download holds the quarantined path. The
+    /// oldest entries are evicted to keep the area within `max_bytes`, and a download larger
+    /// than that is deleted as usual. See [`ModelsCat::clear_quarantine`].
+    pub fn with_quarantine(mut self, max_bytes: u64) -> Self {
+        self.quarantine = Some(max_bytes);
+        self
+    }
+
+    /// Whether to work from the cache only, never querying the hub. Defaults to the
+    /// `MODELS_CAT_OFFLINE` environment variable being `1`, `true`, `yes` or `on`.
+    ///
+    /// Offline, downloads return the cached copy, placed by an earlier download or in the
+    /// snapshot the revision's ref points at, and fail with [`OpsError::OfflineMiss`] when there
+    /// is none. A pull succeeds once an earlier pull completed the snapshot, reporting every
+    /// file as skipped. Anything else needing the listing fails.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// Downloads up to `max_concurrency` files of a pull at once, each worker reporting to its
+    /// own clone of the progress, so [`MultiProgressWrapper`] draws a bar per worker. Defaults
+    /// to 1, downloading one file after another; 0 counts as 1.
+    ///
+    /// The snapshot being filled stays locked while its files download, and the process-wide
+    /// transfer limit of the [config file](crate::config) still applies. Reports keep the
+    /// stable order of [`ModelsCat::pull`].
+    ///
+    /// A file that fails does not stop the others: the pull places every file it can, then
+    /// fails with [`OpsError::PullFailed`] listing each failed file and its error. A
+    /// cancellation still stops the pull.
+    pub fn with_max_concurrency(mut self, max_concurrency: usize) -> Self {
+        self.max_concurrency = max_concurrency.max(1);
+        self
+    }
+
+    /// Downloads a file again after a transport error, `429 Too Many Requests` or a `5xx`
+    /// status, waiting as `policy` says. Defaults to [`RetryPolicy::default`]; other errors,
+    /// such as `404 Not Found`, fail the download right away.
+    ///
+    /// A retry resumes from the bytes already received when the hub supports ranges, and
+    /// progress continues from there rather than counting them again.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Cancels every download of this instance while `flag` is set: each stops before its next
+    /// chunk, removes what was transferred and fails with [`OpsError::Cancelled`]. Clear the
+    /// flag again before further downloads.
+    pub fn with_cancel_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Caps the download throughput of this instance at `bytes_per_sec`, pausing between the
+    /// chunks of its downloads; concurrent downloads of a pull share the cap. 0 means
+    /// unlimited, the default.
+    ///
+    /// Progress keeps reporting the bytes as they are written. Requests for listings and
+    /// metadata are not limited.
+    pub fn with_rate_limit(mut self, bytes_per_sec: u64) -> Self {
+        self.throttle = Throttle::new(bytes_per_sec).map(Arc::new);
+        self
+    }
+
+    /// Retries a failed download up to `retries` times (3 by default), keeping the backoff of
+    /// [`ModelsCat::with_retry`]. 0 fails on the first error.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retry.max_retries = retries;
+        self
+    }
+
+    /// Runs `validator` on every downloaded file, after it is fully written and before it is
+    /// moved into the snapshot, e.g. to scan it for viruses. A rejected file is deleted and the
+    /// download fails with [`OpsError::ValidationFailed`].
+    ///
+    /// Validators added by repeated calls run in the order they were added, up to the first
+    /// rejection.
+    pub fn with_validator(mut self, validator: impl Validator + 'static) -> Self {
+        self.validators.push(Arc::new(validator));
+        self
+    }
+
+    /// Sends requests with `client` instead of the shared default one, such as a client with
+    /// custom TLS roots, a proxy or timeouts. It is used as is: the user agent, redirect policy
+    /// and configured client settings of the default client are not applied to it.
+    pub fn with_client(mut self, client: blocking::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Sends requests with a client built from `options` instead of the shared default one.
+    pub fn with_client_options(mut self, options: &ClientOptions) -> Result<Self, OpsError> {
+        self.client = options.blocking_client()?;
+        self.client_options = options.clone();
+        Ok(self)
+    }
+
+    /// Fails requests with [`OpsError::Timeout`] when the response, or the next data of its
+    /// body, takes longer than `timeout`, see [`ClientOptions::timeout`]. Listings, HEAD checks
+    /// and downloads are all covered; downloads retry a timeout under the
+    /// [`RetryPolicy`].
+    ///
+    /// Rebuilds the client from the client options in use, replacing one set with
+    /// [`ModelsCat::with_client`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Result<Self, OpsError> {
+        self.client_options.timeout = Some(timeout);
+        self.client = self.client_options.blocking_client()?;
+        Ok(self)
+    }
+
+    /// Fails requests with [`OpsError::Timeout`] when connecting takes longer than `timeout`.
+    ///
+    /// Rebuilds the client like [`ModelsCat::with_timeout`].
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Result<Self, OpsError> {
+        self.client_options.connect_timeout = Some(timeout);
+        self.client = self.client_options.blocking_client()?;
+        Ok(self)
     }
 
     /// Retrieves the repository configuration.
+    ///
+    /// The instance owns its repo, copied at construction: changes to other clones of the repo
+    /// are not seen. Use [`ModelsCat::set_revision`] and [`ModelsCat::set_cache_dir`] instead.
     pub fn repo(&self) -> &Repo {
         &self.repo
     }
 
+    /// Switches to `revision`, dropping the cached listing and the memory cache so nothing of
+    /// the previous revision is served.
+    pub fn set_revision(&mut self, revision: impl Into<Revision>) {
+        self.repo.set_revision(revision);
+        self.invalidate();
+    }
+
+    /// Moves the cache to `cache_dir`, dropping the cached listing and the memory cache.
+    pub fn set_cache_dir(&mut self, cache_dir: impl Into<PathBuf>) {
+        self.repo.set_cache_dir(cache_dir);
+        self.invalidate();
+    }
+
+    fn invalidate(&mut self) {
+        *self.listing.get_mut().unwrap() = None;
+        if let Some(ref cache) = self.memory_cache {
+            cache.clear();
+        }
+    }
+
     /// Retrieves the endpoint URL.
     pub fn endpoint(&self) -> &str {
         &self.endpoint
     }
 
+    /// Collects diagnostics for support requests, including repo-specific cache details.
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::for_repo(&self.repo, &self.endpoint)
+    }
+
     /// Pulls the entire repository without progress tracking.
-    pub fn pull(&self) -> Result<(), OpsError> {
-        self.inner_pull(None::<MultiProgressWrapper>)
+    ///
+    /// Files are processed in a stable order, whatever order the hub lists them in: grouped by
+    /// the snapshot revision they belong to, in ascending order, and by path within each
+    /// group. Logs, progress events and reports follow this order.
+    pub fn pull(&self) -> Result<PullReport, OpsError> {
+        self.inner_pull(None::<MultiProgressWrapper>, &PullOptions::default())
     }
 
     /// Pulls the entire repository with progress tracking.
-    pub fn pull_with_progress(&self, progress: impl Progress) -> Result<(), OpsError> {
-        self.inner_pull(Some(progress))
+    pub fn pull_with_progress(&self, progress: impl Progress) -> Result<PullReport, OpsError> {
+        self.inner_pull(Some(progress), &PullOptions::default())
     }
 
-    fn inner_pull(&self, mut progress: Option<impl Progress>) -> Result<(), OpsError> {
-        let blobs = synchronous::get_blob_files(&self.repo)?;
-        for fileinfo in blobs {
-            let hub_revision = fileinfo.revision.clone();
-            let snapshot_path = self.repo.snapshot_path(&hub_revision);
-            std::fs::create_dir_all(&snapshot_path)?;
-            let filepath = {
-                let mut filepath = snapshot_path.clone();
-                for part in fileinfo.path.split("/") {
-                    filepath.push(part);
-                }
-                filepath
-            };
+    /// Pulls the entire repository, with `options` overriding instance settings for this pull
+    /// only.
+    pub fn pull_with_options(&self, options: &PullOptions) -> Result<PullReport, OpsError> {
+        self.inner_pull(None::<MultiProgressWrapper>, options)
+    }
 
-            let mut lock = fslock::FsLock::lock(snapshot_path)?;
-            if std::fs::exists(&filepath)? {
-                if let Some(ref file_sha256) = fileinfo.sha256 {
-                    if &utils::sha256(&filepath)? == file_sha256 {
-                        continue;
-                    }
-                }
-            }
-            let file_url = format!(
-                "{}/{}/{}",
-                self.endpoint,
-                self.repo.url_path_with_resolve(),
-                fileinfo.path.clone()
+    /// Pulls only the files matching one of the `allow` globs, or every file when empty, and
+    /// none of the `ignore` globs, such as `pull_with_patterns(&["*.safetensors", "*.json"],
+    /// &[])`. Fails when no file is left. See [`PullOptions::allow_patterns`].
+    pub fn pull_with_patterns(
+        &self,
+        allow: &[&str],
+        ignore: &[&str],
+    ) -> Result<PullReport, OpsError> {
+        let options = PullOptions::default()
+            .with_allow_patterns(allow.iter().copied())
+            .with_ignore_patterns(ignore.iter().copied());
+        self.pull_with_options(&options)
+    }
+
+    /// Pulls the entire repository like [`ModelsCat::pull`], then deletes the cached files the
+    /// hub no longer lists, such as weights removed upstream, with the directories left empty.
+    /// With `dry_run`, nothing is downloaded or deleted; the report tells what would be.
+    ///
+    /// Only the snapshots of the listed revisions are cleaned, each under its lock, and nothing
+    /// outside them is touched. Snapshots of revisions no longer listed are left to
+    /// [`ModelsCat::prune`].
+    pub fn sync(&self, dry_run: bool) -> Result<SyncReport, OpsError> {
+        let blobs = self.repo_files(true)?.blobs();
+        if dry_run {
+            let (deleted, deleted_bytes) = update::remove_unlisted(&self.repo, &blobs, true)?;
+            return Ok(SyncReport {
+                dry_run,
+                pull: update::plan_pull(&self.repo, blobs, self.validation)?,
+                deleted,
+                deleted_bytes,
+            });
+        }
+        let result = self.pull_files(blobs.clone(), None::<MultiProgressWrapper>, None);
+        let (files, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        let pull = result?;
+
+        let result = update::remove_unlisted(&self.repo, &blobs, false);
+        let (deleted, deleted_bytes) = match result {
+            Ok((ref deleted, bytes)) => (deleted.clone(), bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        if !deleted.is_empty() || result.is_err() {
+            self.record_history(
+                HistoryOperation::Remove,
+                deleted.clone(),
+                deleted_bytes,
+                &result,
             );
+        }
+        result?;
+        if !deleted.is_empty() {
+            if let Some(ref cache) = self.memory_cache {
+                cache.clear();
+            }
+            blobs::prune(&self.repo)?;
+        }
+        Ok(SyncReport {
+            dry_run,
+            pull,
+            deleted,
+            deleted_bytes,
+        })
+    }
 
-            download_file(&file_url, &filepath, &fileinfo.path, &mut progress)?;
-            lock.unlock();
+    /// Makes `files`, or every file of the repo when empty, cached and current, and returns
+    /// where they are. Pulling every file also points `latest` at the newest snapshot. Falls
+    /// back to the cached snapshot when the hub cannot be reached, see [`crate::ensure_ready`].
+    pub(crate) fn ensure_ready(
+        &self,
+        files: &[String],
+        progress: Option<impl Progress>,
+    ) -> Result<ReadyModel, OpsError> {
+        if self.offline {
+            let miss = OpsError::OfflineMiss {
+                file: self.repo.repo_id().to_string(),
+            };
+            return ready::offline(&self.repo, files, miss);
         }
+        let listing = match self.repo_files(true) {
+            Ok(listing) => listing,
+            Err(e @ (OpsError::RequestError(_) | OpsError::Timeout { .. })) => {
+                return ready::offline(&self.repo, files, e);
+            }
+            Err(e) => return Err(e),
+        };
+        let blobs = ready::select(listing.blobs(), files)?;
+        let result = if files.is_empty() {
+            self.pull_files(blobs.clone(), progress, None)
+        } else {
+            self.fill_snapshots(blobs.clone(), progress, None)
+                .map(|(report, _)| report)
+        };
+        let (downloaded, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, downloaded, bytes, &result);
+        result?;
+        ready::online(&self.repo, &blobs)
+    }
 
-        Ok(())
+    fn inner_pull(
+        &self,
+        progress: Option<impl Progress>,
+        options: &PullOptions,
+    ) -> Result<PullReport, OpsError> {
+        let result = self.pull_listed(progress, options);
+        let (files, bytes) = match result {
+            Ok(ref report) => (report.downloaded.clone(), report.downloaded_bytes),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        result
     }
 
-    /// Downloads a specific file from the hub without progress tracking.
-    /// The filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`.
-    pub fn download(&self, filename: &str) -> Result<(), OpsError> {
-        self.inner_download(filename, None::<ProgressBarWrapper>)
+    fn pull_listed(
+        &self,
+        progress: Option<impl Progress>,
+        options: &PullOptions,
+    ) -> Result<PullReport, OpsError> {
+        options.validate()?;
+        if self.offline {
+            return offline::pull(&self.repo);
+        }
+        let (blobs, hidden) = options.select(self.repo_files(true)?.blobs())?;
+        let mut report = self.pull_files(blobs, progress, options.validation)?;
+        report.hidden = hidden;
+        Ok(report)
     }
 
-    /// Downloads a specific file from the hub with progress tracking.
-    /// The filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`.
-    pub fn download_with_progress(
+    /// Places `blobs` in their snapshots, confirming cached copies with `validation`, or the
+    /// instance default when `None`, then points `latest` at the newest one.
+    fn pull_files(
         &self,
-        filename: &str,
-        progress: impl Progress,
-    ) -> Result<(), OpsError> {
-        self.inner_download(filename, Some(progress))
+        blobs: Vec<FileInfo>,
+        progress: Option<impl Progress>,
+        validation: Option<CacheValidation>,
+    ) -> Result<PullReport, OpsError> {
+        let (report, latest) = self.fill_snapshots(blobs, progress, validation)?;
+        if let Some(revision) = latest {
+            if let Some(ref cache) = self.memory_cache
+                && !prepared::is_active(&self.repo, &revision)
+            {
+                cache.clear();
+            }
+            update_latest(&self.repo, &revision)?;
+            self.log(format_args!("latest -> {revision}"));
+        }
+        Ok(report)
     }
 
-    fn inner_download(
+    /// Places `blobs` in their snapshots and returns the newest revision, leaving the ref and
+    /// `latest` link of the repo untouched.
+    fn fill_snapshots(
         &self,
-        filename: &str,
+        blobs: Vec<FileInfo>,
         mut progress: Option<impl Progress>,
-    ) -> Result<(), OpsError> {
-        let repo_files = synchronous::get_repo_files(&self.repo)?;
-        let fileinfo = repo_files.get_file_info(filename)?;
-        let hub_revision = fileinfo.revision.clone();
+        validation: Option<CacheValidation>,
+    ) -> Result<(PullReport, Option<String>), OpsError> {
+        let validation = validation.unwrap_or(self.validation);
+        let mut plan = paths::plan(blobs, self.invalid_paths, self.path_rules)?;
+        let blobs = std::mem::take(&mut plan.files);
+        let latest = latest_revision(&blobs);
+        let mut report = PullReport {
+            invalid: plan.invalid.clone(),
+            validation,
+            ..Default::default()
+        };
+        let groups = group_by_revision(blobs.clone());
+        let queued = self.queue_files(&groups, &plan, &mut progress)?;
+        let mut failures = Vec::new();
+        for (revision, files) in groups {
+            let snapshot_path = self.repo.snapshot_path(&revision);
+            self.reconcile_staging(&snapshot_path)?;
+            std::fs::create_dir_all(&snapshot_path)?;
+            let target = Target {
+                snapshot_path: &snapshot_path,
+                plan: &plan,
+                validation,
+                queued: &queued,
+            };
+            if self.max_concurrency > 1 && files.len() > 1 {
+                let placed = self.place_concurrently(&target, &files, &progress)?;
+                if !self.record_placements(&mut report, &files, placed, &mut failures)? {
+                    continue;
+                }
+            } else {
+                for fileinfo in &files {
+                    let filepath = target.filepath(fileinfo);
+                    let placed = match lock_unless_current(
+                        snapshot_path.clone(),
+                        &filepath,
+                        fileinfo,
+                        validation,
+                    )? {
+                        None => target.skip(self, fileinfo, &mut progress),
+                        Some(mut lock) => {
+                            let placed = self.place_listed(&target, fileinfo, &mut progress);
+                            lock.unlock();
+                            placed
+                        }
+                    };
+                    self.record_placement(&mut report, fileinfo, Some(placed))?;
+                }
+            }
+            paths::record_sanitized(&self.repo, &revision, &files, &plan.sanitized)?;
+            mark_complete(&self.repo, &revision, &files)?;
+        }
+        if !failures.is_empty() {
+            return Err(OpsError::PullFailed { failures });
+        }
+        if let Some(ref latest) = latest {
+            let older = unlinked_into_latest(&self.repo, latest, &blobs, &plan);
+            if !older.is_empty() {
+                let mut lock = fslock::FsLock::lock(self.repo.snapshot_path(latest))?;
+                let linked = link_into_latest(&self.repo, latest, &older, &plan);
+                lock.unlock();
+                linked?;
+            }
+        }
 
-        let snapshot_path = self.repo.snapshot_path(&hub_revision);
-        std::fs::create_dir_all(&snapshot_path)?;
-        let filepath = {
-            let mut filepath = snapshot_path.clone();
-            for part in fileinfo.path.split("/") {
-                filepath.push(part);
+        report.rate_limit = self.rate_limit_status();
+        Ok((report, latest))
+    }
+
+    /// Records what became of the `files` of a concurrent placement, adding their errors to
+    /// `failures` rather than failing on the first one. Returns whether every file was placed,
+    /// or fails right away with a cancellation.
+    fn record_placements(
+        &self,
+        report: &mut PullReport,
+        files: &[FileInfo],
+        placed: Vec<Option<Result<Placement, OpsError>>>,
+        failures: &mut Vec<(String, OpsError)>,
+    ) -> Result<bool, OpsError> {
+        let mut complete = true;
+        for (fileinfo, placed) in files.iter().zip(placed) {
+            match placed {
+                Some(Err(e @ OpsError::Cancelled { .. })) => return Err(e),
+                Some(Err(e)) => {
+                    self.log(format_args!("failed {}: {e}", fileinfo.path));
+                    failures.push((fileinfo.path.clone(), e));
+                    complete = false;
+                }
+                placed => self.record_placement(report, fileinfo, placed)?,
+            }
+        }
+        Ok(complete)
+    }
+
+    /// Places `files` with up to [`ModelsCat::with_max_concurrency`] workers, each with its own
+    /// clone of `progress`, locking the snapshot once for all of them. A failed file does not
+    /// stop the others. Returns what became of each file, in order, or `None` for those left
+    /// alone once a download was cancelled.
+    ///
+    /// The files are checked before locking, as in [`lock_unless_current`], so hashing large
+    /// cached files never blocks processes writing the snapshot, and the lock is not taken at
+    /// all when every file is current.
+    fn place_concurrently(
+        &self,
+        target: &Target,
+        files: &[FileInfo],
+        progress: &Option<impl Progress>,
+    ) -> Result<Vec<Option<Result<Placement, OpsError>>>, OpsError> {
+        let mut placed: Vec<_> = (0..files.len()).map(|_| None).collect();
+        let mut checked = Vec::new();
+        let checks = self.run_concurrently(files, progress, |fileinfo, _| target.check(fileinfo));
+        for (i, check) in checks.into_iter().enumerate() {
+            match check {
+                Some(Ok(check)) => checked.push((i, check)),
+                Some(Err(e)) => placed[i] = Some(Err(e)),
+                None => {}
             }
-            filepath
+        }
+        let locked = !checked.iter().all(|(_, check)| check.current);
+        let mut lock = match locked {
+            true => Some(fslock::FsLock::lock(target.snapshot_path.to_path_buf())?),
+            false => None,
         };
+        let results = self.run_concurrently(&checked, progress, |&(i, check), progress| {
+            let fileinfo = &files[i];
+            let current = match locked {
+                true => target.recheck(fileinfo, check)?,
+                false => check.current,
+            };
+            match current {
+                true => target.skip(self, fileinfo, progress),
+                false => self.place_listed(target, fileinfo, progress),
+            }
+        });
+        if let Some(ref mut lock) = lock {
+            lock.unlock();
+        }
+        for (&(i, _), result) in checked.iter().zip(results) {
+            placed[i] = result;
+        }
+        Ok(placed)
+    }
 
-        let mut lock = fslock::FsLock::lock(snapshot_path.clone())?;
+    /// Runs `run` on each of `items` with up to [`ModelsCat::with_max_concurrency`] workers,
+    /// each with its own clone of `progress`. A failed item does not stop the others. Returns
+    /// the result of each item, in order, or `None` for those left alone once one was
+    /// cancelled.
+    fn run_concurrently<I: Sync, T: Send, P: Progress>(
+        &self,
+        items: &[I],
+        progress: &Option<P>,
+        run: impl Fn(&I, &mut Option<P>) -> Result<T, OpsError> + Sync,
+    ) -> Vec<Option<Result<T, OpsError>>> {
+        let next = AtomicUsize::new(0);
+        let cancelled = AtomicBool::new(false);
+        let results = Mutex::new((0..items.len()).map(|_| None).collect::<Vec<_>>());
+        std::thread::scope(|scope| {
+            for _ in 0..self.max_concurrency.min(items.len()) {
+                let mut progress = progress.clone();
+                let (next, cancelled, results, run) = (&next, &cancelled, &results, &run);
+                scope.spawn(move || {
+                    while !cancelled.load(Ordering::SeqCst) {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        let Some(item) = items.get(i) else {
+                            break;
+                        };
+                        let result = run(item, &mut progress);
+                        if matches!(result, Err(OpsError::Cancelled { .. })) {
+                            cancelled.store(true, Ordering::SeqCst);
+                        }
+                        results.lock().unwrap()[i] = Some(result);
+                    }
+                });
+            }
+        });
+        results.into_inner().unwrap()
+    }
 
-        if std::fs::exists(&filepath)? {
-            if let Some(ref file_sha256) = fileinfo.sha256 {
-                if &utils::sha256(&filepath)? == file_sha256 {
-                    lock.unlock();
-                    return Ok(());
-                }
+    /// Places `fileinfo` in its snapshot, which the caller has locked, by linking a copy of it
+    /// from elsewhere in the cache or else downloading it.
+    fn place_listed(
+        &self,
+        target: &Target,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<Placement, OpsError> {
+        let hub_revision = &fileinfo.revision;
+        let filepath = target.filepath(fileinfo);
+        if self.link_identical(fileinfo, &filepath)? {
+            provenance::forget(&self.repo, hub_revision, &fileinfo.path)?;
+            if target.queued.contains(&fileinfo.path) {
+                self.finish_queued(fileinfo, progress)?;
             }
+            return Ok(Placement::Deduplicated);
         }
         let file_url = format!(
             "{}/{}/{}",
             self.endpoint,
             self.repo.url_path_with_resolve(),
-            filename
+            fileinfo.path
         );
+        let (record, progress_degraded) =
+            self.download_file(&file_url, &filepath, &HubFileInfo::from(fileinfo), progress)?;
+        provenance::record(&self.repo, hub_revision, &fileinfo.path, record)?;
+        if self.global_dedup {
+            dedup::record_download(
+                self.repo.cache_home(),
+                fileinfo.sha256.as_deref(),
+                &filepath,
+            )?;
+        }
+        Ok(Placement::Downloaded { progress_degraded })
+    }
 
-        download_file(&file_url, &filepath, filename, &mut progress)?;
+    /// Places `fileinfo` at `filepath` from an identical file already in the cache: a blob of
+    /// the repo with [`ModelsCat::with_blob_store`], or a file of another repo with
+    /// [`ModelsCat::with_global_dedup`]. Returns whether the file was placed.
+    fn link_identical(&self, fileinfo: &FileInfo, filepath: &Path) -> Result<bool, OpsError> {
+        let sha256 = fileinfo.sha256.as_deref();
+        let size = fileinfo.size as u64;
+        Ok(
+            (self.blob_store && blobs::try_link(&self.repo, sha256, size, filepath)?)
+                || (self.global_dedup
+                    && dedup::try_link(self.repo.cache_home(), sha256, size, filepath)?),
+        )
+    }
 
-        lock.unlock();
+    /// Logs what became of `fileinfo` and adds it to `report`, returning the error of a file
+    /// that failed. `None` stands for a file left alone after an earlier one failed.
+    fn record_placement(
+        &self,
+        report: &mut PullReport,
+        fileinfo: &FileInfo,
+        placed: Option<Result<Placement, OpsError>>,
+    ) -> Result<(), OpsError> {
+        match placed {
+            None => {}
+            Some(Err(e)) => {
+                self.log(format_args!("failed {}: {e}", fileinfo.path));
+                return Err(e);
+            }
+            Some(Ok(Placement::Skipped)) => {
+                self.log(format_args!("skipped {}: up to date", fileinfo.path));
+                report.skipped.push(fileinfo.path.clone());
+            }
+            Some(Ok(Placement::Deduplicated)) => {
+                self.log(format_args!("deduplicated {}", fileinfo.path));
+                report.record_deduplicated(&fileinfo.path, fileinfo.size as u64);
+            }
+            Some(Ok(Placement::Downloaded { progress_degraded })) => {
+                self.log(format_args!("downloaded {}", fileinfo.path));
+                report.downloaded.push(fileinfo.path.clone());
+                report.downloaded_bytes += fileinfo.size as u64;
+                if progress_degraded {
+                    report.progress_degraded.push(fileinfo.path.clone());
+                }
+            }
+        }
         Ok(())
     }
 
-    /// List files in the remote repo
-    pub fn list_hub_files(&self) -> Result<Vec<String>, OpsError> {
-        let files = synchronous::get_blob_files(&self.repo)?;
-        Ok(files.iter().map(|f| f.path.clone()).collect())
+    /// Pulls the newest revision into its own snapshot, leaving the ref and `latest` link on
+    /// the current one, or returns `None` when `latest` already points at it.
+    ///
+    /// Serving processes keep loading from the current snapshot while the new one downloads,
+    /// then switch to it with [`PreparedSnapshot::activate`], so they never see a mix of
+    /// both. The snapshot is complete when returned, and a failed pull leaves nothing to
+    /// activate. The previous snapshot is kept on disk.
+    pub fn prepare_update(&self) -> Result<Option<PreparedSnapshot>, OpsError> {
+        let result = self.prepare_listed();
+        let (files, bytes) = match result {
+            Ok(Some(ref prepared)) => (
+                prepared.report.downloaded.clone(),
+                prepared.report.downloaded_bytes,
+            ),
+            _ => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Pull, files, bytes, &result);
+        result
     }
 
-    /// List files in the local repo
-    pub fn list_local_files(&self) -> Result<Vec<String>, OpsError> {
-        let base_path = self.repo.cache_dir().join("snapshots");
-        let mut files = Vec::new();
-
-        for entry in walkdir::WalkDir::new(&base_path)
-            .min_depth(2) // 跳过snapshots根目录
-            .max_depth(10) // 限制遍历深度 // 限制遍历深度：repo_path/<snapshot>/<file_path>
-            .into_iter()
-            .filter_map(|e| e.ok())
+    fn prepare_listed(&self) -> Result<Option<PreparedSnapshot>, OpsError> {
+        let (blobs, hidden) = PullOptions::default().select(self.repo_files(true)?.blobs())?;
+        if latest_revision(&blobs).is_none_or(|revision| prepared::is_active(&self.repo, &revision))
         {
-            if entry.file_type().is_file() {
-                let rel_path = entry
-                    .path()
+            return Ok(None);
+        }
+        let (mut report, latest) =
+            self.fill_snapshots(blobs, None::<MultiProgressWrapper>, None)?;
+        let Some(revision) = latest else {
+            return Ok(None);
+        };
+        report.hidden = hidden;
+        self.log(format_args!("prepared {revision}"));
+        Ok(Some(PreparedSnapshot {
+            repo: self.repo.clone(),
+            revision,
+            report,
+            memory_cache: self.memory_cache.clone(),
+        }))
+    }
+
+    /// Downloads a specific file from the hub without progress tracking, and returns its path
+    /// in the snapshot.
+    /// The filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`.
+    pub fn download(&self, filename: &str) -> Result<PathBuf, OpsError> {
+        Ok(self
+            .inner_download(
+                filename,
+                None::<ProgressBarWrapper>,
+                &DownloadOptions::default(),
+            )?
+            .path)
+    }
+
+    /// Downloads a specific file from the hub with progress tracking, and returns its path in
+    /// the snapshot.
+    /// The filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`.
+    pub fn download_with_progress(
+        &self,
+        filename: &str,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        Ok(self
+            .inner_download(filename, Some(progress), &DownloadOptions::default())?
+            .path)
+    }
+
+    /// Makes sure `filename` is cached and current, downloading it if needed, and returns its
+    /// local path along with its size, sha256 and revision, and whether it was downloaded by
+    /// this call.
+    pub fn resolve(&self, filename: &str) -> Result<Resolution, OpsError> {
+        self.inner_download(
+            filename,
+            None::<ProgressBarWrapper>,
+            &DownloadOptions::default(),
+        )
+    }
+
+    /// Downloads a specific file from the hub, with `options` overriding instance settings for
+    /// this download only, and returns its path in the snapshot. [`DownloadOptions::save_as`]
+    /// only applies to [`ModelsCat::download_to`].
+    ///
+    /// An explicit [`CacheValidation::Sha256`] hashes the cached copy even when a HEAD request
+    /// would otherwise confirm it. [`DownloadOptions::force_download`] downloads the file again
+    /// even when the cached copy is current, such as to restore a file edited in place.
+    pub fn download_with_options(
+        &self,
+        filename: &str,
+        options: DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        Ok(self
+            .inner_download(filename, None::<ProgressBarWrapper>, &options)?
+            .path)
+    }
+
+    /// Downloads a specific file from the hub like [`ModelsCat::download_with_options`], with
+    /// progress tracking.
+    pub fn download_with_options_and_progress(
+        &self,
+        filename: &str,
+        options: DownloadOptions,
+        progress: impl Progress,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        Ok(self
+            .inner_download(filename, Some(progress), &options)?
+            .path)
+    }
+
+    /// Whether the cached copy of `filename` is current under `validation`, regardless of the
+    /// instance default. Uses the listing, but never downloads.
+    pub fn verify_with_level(
+        &self,
+        filename: &str,
+        validation: CacheValidation,
+    ) -> Result<bool, OpsError> {
+        let repo_files = self.repo_files(false)?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let mut filepath = self.repo.snapshot_path(&fileinfo.revision);
+        for part in local_name.split('/') {
+            filepath.push(part);
+        }
+        is_verified(&filepath, fileinfo, validation)
+    }
+
+    /// Checks every cached file of the repo against the listing, for an integrity audit after a
+    /// pull. Reports which listed files are verified, missing or corrupt, comparing sha256s, or
+    /// sizes when the hub lists no sha256, and which files in the snapshots are not listed.
+    ///
+    /// Hashes every file, so it takes a while for large repos; nothing is downloaded.
+    pub fn verify(&self) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(None::<ProgressBarWrapper>, false)
+    }
+
+    /// Checks every cached file of the repo against the listing, reporting the hashing of each
+    /// file to `progress`.
+    pub fn verify_with_progress(&self, progress: impl Progress) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(Some(progress), false)
+    }
+
+    /// Compares the cache with the current hub listing without downloading anything, to decide
+    /// whether a pull is due. Lists the files added, modified and removed on the hub since
+    /// the snapshot the configured revision resolves to.
+    ///
+    /// Cached files not recorded as complete by a pull are hashed.
+    pub fn check_for_update(&self) -> Result<UpdateStatus, OpsError> {
+        update::check(&self.repo, self.repo_files(true)?.blobs())
+    }
+
+    /// Checks the cached copy of `filename` against the listing, like [`ModelsCat::verify`].
+    pub fn verify_file(&self, filename: &str) -> Result<FileStatus, OpsError> {
+        let repo_files = self.repo_files(false)?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let filepath = snapshot_file(&self.repo.snapshot_path(&fileinfo.revision), &local_name);
+        verify::status(&filepath, fileinfo)
+    }
+
+    /// Verifies the repo like [`ModelsCat::verify`], then downloads the missing and corrupt
+    /// files again, listing them in [`VerifyReport::repaired`]. Extra files are left alone.
+    pub fn repair(&self) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(None::<ProgressBarWrapper>, true)
+    }
+
+    /// Repairs the repo like [`ModelsCat::repair`], reporting the hashing and the downloads to
+    /// `progress`.
+    pub fn repair_with_progress(&self, progress: impl Progress) -> Result<VerifyReport, OpsError> {
+        self.inner_verify(Some(progress), true)
+    }
+
+    /// Where and when the cached copy of `filename` was downloaded, such as from the official
+    /// hub or a mirror. Files downloaded by versions without provenance tracking report
+    /// [`Provenance::Unknown`].
+    ///
+    /// Files placed by a download are looked up offline; others go through the listing.
+    pub fn provenance(&self, filename: &str) -> Result<Provenance, OpsError> {
+        let revision = match resolved::cached(&self.repo, filename) {
+            Some((_, file)) => file.revision,
+            None => {
+                let repo_files = self.repo_files(false)?;
+                let fileinfo = repo_files.get_file_info(filename)?;
+                let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+                let mut filepath = self.repo.snapshot_path(&fileinfo.revision);
+                for part in local_name.split('/') {
+                    filepath.push(part);
+                }
+                if !filepath.is_file() {
+                    return Err(OpsError::HubError(format!("{filename} is not cached")));
+                }
+                fileinfo.revision.clone()
+            }
+        };
+        Ok(provenance::lookup(&self.repo, &revision, filename))
+    }
+
+    /// Downloads a file if needed and returns its contents, served from the memory cache when
+    /// enabled.
+    pub fn fetch_bytes(&self, filename: &str) -> Result<Arc<[u8]>, OpsError> {
+        if let Some(ref cache) = self.memory_cache {
+            // Keyed by the commit the file resolves to, so a branch that moved on the hub is not
+            // served from its previous commit.
+            let commit = match self.offline {
+                true => offline::resolve(&self.repo, filename)?.revision,
+                false => self
+                    .repo_files(false)?
+                    .get_file_info(filename)?
+                    .revision
+                    .clone(),
+            };
+            if let Some(bytes) = cache.get(&commit, filename) {
+                return Ok(bytes);
+            }
+        }
+        let resolution = self.inner_download(
+            filename,
+            None::<ProgressBarWrapper>,
+            &DownloadOptions::default(),
+        )?;
+        let bytes: Arc<[u8]> = std::fs::read(&resolution.path)?.into();
+        if let Some(ref cache) = self.memory_cache {
+            cache.insert(&resolution.revision, filename, bytes.clone());
+        }
+        Ok(bytes)
+    }
+
+    /// Downloads a JSON file, such as `config.json`, if needed and deserializes it.
+    pub fn get_json<T: DeserializeOwned>(&self, filename: &str) -> Result<T, OpsError> {
+        let bytes = self.fetch_bytes(filename)?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| OpsError::hub(format!("failed to parse {filename}"), e))
+    }
+
+    /// Downloads a file straight into `dir` under its hub-relative filename or
+    /// `options.save_as`, without touching the cache, and returns the path of the written file.
+    ///
+    /// Like [`ModelsCat::pull_to`], the file is staged next to its target, checked against its
+    /// listed sha256 and renamed into place. With [`DownloadOptions::bypass_cache`] off, it is
+    /// downloaded into the cache instead, or found current there, and placed in `dir` as a copy
+    /// unless `options.export_mode` says otherwise; the cached snapshot always keeps
+    /// hub-relative names, the alias only applies to `dir`.
+    pub fn download_to(
+        &self,
+        filename: &str,
+        dir: impl AsRef<Path>,
+        options: DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        options.validate()?;
+        let name = options.save_as.as_deref().unwrap_or(filename);
+        if options.bypass_cache {
+            let repo_files = self.repo_files(false)?;
+            let fileinfo = repo_files.get_file_info(filename)?;
+            let filepath = export::join_relative(dir.as_ref(), name)?;
+            return self.download_direct(fileinfo, &filepath, &options);
+        }
+        let filepath = self
+            .inner_download(filename, None::<ProgressBarWrapper>, &options)?
+            .path;
+        export::export_file(&filepath, dir.as_ref(), name, options.export_mode)
+    }
+
+    /// Downloads every file of the repository straight into `dir`, keeping the subdirectories
+    /// of their hub-relative filenames, and returns the written paths.
+    ///
+    /// Bypasses the cache entirely, for deploying into an artifacts directory: each file is
+    /// staged next to its target, checked against its listed sha256 and renamed into place,
+    /// replacing what is there. Nothing is skipped as up to date.
+    pub fn pull_to(&self, dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, OpsError> {
+        let blobs = self.repo_files(true)?.blobs();
+        let plan = paths::plan(blobs, self.invalid_paths, self.path_rules)?;
+        let options = DownloadOptions::default();
+        let mut written = Vec::new();
+        for fileinfo in &plan.files {
+            let filepath = export::join_relative(dir.as_ref(), plan.local_path(fileinfo))?;
+            written.push(self.download_direct(fileinfo, &filepath, &options)?);
+        }
+        Ok(written)
+    }
+
+    /// Downloads `fileinfo` to `filepath` outside the cache, honoring the `verify` and `resume`
+    /// of `options`.
+    fn download_direct(
+        &self,
+        fileinfo: &FileInfo,
+        filepath: &Path,
+        options: &DownloadOptions,
+    ) -> Result<PathBuf, OpsError> {
+        if self.offline {
+            return Err(OpsError::OfflineMiss {
+                file: fileinfo.path.clone(),
+            });
+        }
+        let mut file = HubFileInfo::from(fileinfo);
+        if !options.verify {
+            file.sha256 = None;
+        }
+        if !options.resume {
+            StagedFile::discard(filepath, &file)?;
+        }
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            fileinfo.path
+        );
+        if let Err(e) =
+            self.download_file(&file_url, filepath, &file, &mut None::<ProgressBarWrapper>)
+        {
+            self.log(format_args!("failed {}: {e}", fileinfo.path));
+            return Err(e);
+        }
+        self.log(format_args!(
+            "downloaded {} to {}",
+            fileinfo.path,
+            filepath.display()
+        ));
+        Ok(filepath.to_path_buf())
+    }
+
+    /// Places every file of the last pulled snapshot in `dir` under `mode`, renaming the
+    /// hub-relative filenames found in `rename`. Returns the placed paths, sorted by
+    /// hub-relative filename.
+    ///
+    /// Two files mapped to the same name are rejected. The cached snapshot always keeps
+    /// hub-relative names. Only [`ExportMode::Copy`] gives files that can be written to without
+    /// changing the cache.
+    pub fn export_to(
+        &self,
+        dir: impl AsRef<Path>,
+        rename: &BTreeMap<String, String>,
+        mode: ExportMode,
+    ) -> Result<Vec<PathBuf>, OpsError> {
+        let snapshot = self
+            .repo
+            .latest_snapshot()
+            .ok_or_else(|| OpsError::HubError("No snapshot pulled yet".into()))?;
+        let revision = snapshot
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let hub_names = paths::hub_names(&self.repo, &revision);
+        export::export_snapshot(&snapshot, dir.as_ref(), rename, &hub_names, mode)
+    }
+
+    /// Places every file of the cached snapshot in `dir` under `mode`, as a flat directory for
+    /// frameworks such as llama.cpp, keeping subdirectories such as `gguf/model.gguf`. Returns
+    /// the placed paths, sorted.
+    ///
+    /// Works offline on the snapshot the revision's ref points at, or else the last pulled one.
+    /// Existing files that differ from the cached ones are left alone and fail the export,
+    /// unless `overwrite` is set.
+    pub fn export_snapshot(
+        &self,
+        dir: impl AsRef<Path>,
+        mode: ExportMode,
+        overwrite: bool,
+    ) -> Result<Vec<PathBuf>, OpsError> {
+        export::materialize(&self.repo, dir.as_ref(), mode, overwrite)
+    }
+
+    /// Streams `filename` from the hub into `writer`, such as straight into a parser or into
+    /// memory, without touching the cache, and returns the bytes written. Reports progress to
+    /// `progress` like [`ModelsCat::download_with_progress`].
+    ///
+    /// The download is checked against its listed size and sha256 as it streams. A mismatch
+    /// returns an error after `writer` already received the bytes, so the caller must discard
+    /// them. Failed transfers are not retried, as what was written cannot be taken back.
+    pub fn download_to_writer(
+        &self,
+        filename: &str,
+        mut writer: impl Write,
+        mut progress: Option<impl Progress>,
+    ) -> Result<u64, OpsError> {
+        if self.offline {
+            return Err(OpsError::OfflineMiss {
+                file: filename.to_string(),
+            });
+        }
+        let repo_files = self.repo_files(false)?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            fileinfo.path
+        );
+        if let Some(prg) = progress.as_mut() {
+            let unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            self.progress_errors
+                .handle(filename, prg.on_queued(std::slice::from_ref(&unit)))?;
+        }
+
+        let _slot = self.transfer_slot(filename);
+        let response = self.get_file(&file_url, 0)?;
+        let mut unit = match response.content_length() {
+            Some(content_length) => ProgressUnit::new(fileinfo.path.clone(), content_length),
+            None => ProgressUnit::without_total(fileinfo.path.clone()),
+        };
+        let mut reporting = true;
+        if let Some(prg) = progress.as_mut() {
+            reporting = self.progress_errors.handle(filename, prg.on_start(&unit))?;
+        }
+        let mut verifier = archive::Verifier::new(fileinfo);
+        let mut buf_read = io::BufReader::new(response);
+        let mut buf = vec![0u8; 8192];
+        let mut written = 0;
+        loop {
+            let len = buf_read.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            verifier.update(&buf[..len])?;
+            writer.write_all(&buf[..len])?;
+            written += len as u64;
+            if let Some(throttle) = &self.throttle {
+                throttle.pace(len as u64);
+            }
+            self.check_cancelled(filename)?;
+            if reporting && let Some(prg) = progress.as_mut() {
+                unit.update(written);
+                reporting = self
+                    .progress_errors
+                    .handle_control(filename, prg.on_progress_control(&unit))?;
+            }
+        }
+        writer.flush()?;
+        if let Err(e) = verifier.finish() {
+            self.log(format_args!("failed {filename}: {e}"));
+            return Err(e.into());
+        }
+        if reporting && let Some(prg) = progress.as_mut() {
+            self.progress_errors
+                .handle(filename, prg.on_finish(&unit))?;
+        }
+        self.log(format_args!("streamed {filename}"));
+        Ok(written)
+    }
+
+    /// Downloads every file of the repository into a tar archive written to `writer`, without
+    /// placing them in the cache. Returns the archived hub-relative filenames.
+    ///
+    /// Each file streams from the hub through the encoder and is checked against its listed
+    /// size and sha256; a mismatch fails the pull, leaving a truncated archive in `writer`.
+    pub fn pull_to_tar(
+        &self,
+        writer: impl Write,
+        compression: ArchiveCompression,
+    ) -> Result<Vec<String>, OpsError> {
+        let blobs = self.repo_files(true)?.blobs();
+        match compression {
+            ArchiveCompression::None => {
+                self.write_tar(writer, &blobs)?;
+            }
+            ArchiveCompression::Gzip => {
+                let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+                self.write_tar(encoder, &blobs)?.finish()?;
+            }
+        }
+        Ok(blobs.into_iter().map(|f| f.path).collect())
+    }
+
+    fn write_tar<W: Write>(&self, writer: W, blobs: &[FileInfo]) -> Result<W, OpsError> {
+        let mut builder = tar::Builder::new(writer);
+        for fileinfo in blobs {
+            let file_url = format!(
+                "{}/{}/{}",
+                self.endpoint,
+                self.repo.url_path_with_resolve(),
+                fileinfo.path
+            );
+            let response = self.get_file(&file_url, 0)?.error_for_status()?;
+            let reader = archive::VerifyingReader::new(response, fileinfo);
+            let result =
+                builder.append_data(&mut archive::header(fileinfo), &fileinfo.path, reader);
+            if let Err(e) = result {
+                self.log(format_args!("failed {}: {e}", fileinfo.path));
+                return Err(e.into());
+            }
+            self.log(format_args!("archived {}", fileinfo.path));
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    /// Absolute paths of the cached files whose hub-relative filename matches the glob
+    /// `pattern`, such as `data/*.parquet` or `**/*.parquet`, sorted by filename.
+    ///
+    /// Works offline: files are looked up in the snapshot the revision's ref points at, or else
+    /// the last pulled one, and an error is returned when there is none. When the listing was
+    /// fetched moments ago, as by a pull in this process, every matching hub file must also be
+    /// in place; the error names the ones that are not cached yet.
+    pub fn local_files_matching(&self, pattern: &str) -> Result<Vec<PathBuf>, OpsError> {
+        let listing = cached_listing(&self.listing);
+        matching::local_files_matching(&self.repo, pattern, listing.as_deref())
+    }
+
+    fn inner_download(
+        &self,
+        filename: &str,
+        progress: Option<impl Progress>,
+        options: &DownloadOptions,
+    ) -> Result<Resolution, OpsError> {
+        let result = self.place_file(filename, progress, options);
+        let bytes = match result {
+            Ok(ref resolution) if resolution.downloaded => resolution.size,
+            _ => 0,
+        };
+        let files = vec![filename.to_string()];
+        self.record_history(HistoryOperation::Download, files, bytes, &result);
+        result
+    }
+
+    fn place_file(
+        &self,
+        filename: &str,
+        mut progress: Option<impl Progress>,
+        options: &DownloadOptions,
+    ) -> Result<Resolution, OpsError> {
+        let force = options.force_download;
+        if self.offline {
+            if force {
+                return Err(OpsError::OfflineMiss {
+                    file: filename.to_string(),
+                });
+            }
+            return offline::resolve(&self.repo, filename);
+        }
+        // An explicit request to hash is not satisfied by a HEAD request.
+        let hash = options.validation == Some(CacheValidation::Sha256);
+        let validation = options.validation.unwrap_or(self.validation);
+        let mut etag = None;
+        if !hash && !force {
+            match self.head_check(filename) {
+                HeadCheck::Current(filepath, file) => {
+                    self.log(format_args!("skipped {filename}: up to date"));
+                    return Ok(file.resolution(filepath, false));
+                }
+                HeadCheck::Unconfirmed(reported) => etag = reported,
+            }
+        }
+        let repo_files = self.repo_files(false)?;
+        let fileinfo = repo_files.get_file_info(filename)?;
+        let hub_revision = fileinfo.revision.clone();
+        let commit = latest_revision(&repo_files.data.files);
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        let mut resolved_file = ResolvedFile {
+            revision: hub_revision.clone(),
+            local_path: local_name.clone(),
+            size: fileinfo.size as u64,
+            sha256: fileinfo.sha256.clone(),
+            etag,
+        };
+        if local_name != fileinfo.path {
+            let sanitized = HashMap::from([(fileinfo.path.clone(), local_name.clone())]);
+            let files = std::slice::from_ref(fileinfo);
+            paths::record_sanitized(&self.repo, &hub_revision, files, &sanitized)?;
+        }
+
+        let snapshot_path = self.repo.snapshot_path(&hub_revision);
+        std::fs::create_dir_all(&snapshot_path)?;
+        self.reconcile_staging(&snapshot_path)?;
+        let filepath = snapshot_file(&snapshot_path, &local_name);
+
+        let current = match force {
+            true => Some(fslock::FsLock::lock(snapshot_path)?),
+            false => lock_unless_current(snapshot_path, &filepath, fileinfo, validation)?,
+        };
+        let Some(mut lock) = current else {
+            self.repo.create_ref(&hub_revision)?;
+            let resolution = resolved_file.resolution(filepath, false);
+            resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+            self.log(format_args!("skipped {filename}: up to date"));
+            return Ok(resolution);
+        };
+        // A forced download must not link the suspect copy back in place.
+        if !force && self.link_identical(fileinfo, &filepath)? {
+            provenance::forget(&self.repo, &hub_revision, filename)?;
+            lock.unlock();
+            let resolution = resolved_file.resolution(filepath, false);
+            resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+            self.log(format_args!("deduplicated {filename}"));
+            return Ok(resolution);
+        }
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            filename
+        );
+
+        let mut file = HubFileInfo::from(fileinfo);
+        // Without a listed sha256, the download is written without checking it.
+        if !options.verify {
+            file.sha256 = None;
+        }
+        if !options.resume {
+            StagedFile::discard(&filepath, &file)?;
+        }
+        if let Some(prg) = progress.as_mut() {
+            let unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            self.progress_errors
+                .handle(filename, prg.on_queued(std::slice::from_ref(&unit)))?;
+        }
+        let (record, progress_degraded) =
+            match self.download_file(&file_url, &filepath, &file, &mut progress) {
+                Ok(downloaded) => downloaded,
+                Err(e) => {
+                    self.log(format_args!("failed {filename}: {e}"));
+                    return Err(e);
+                }
+            };
+        if record.etag.is_some() {
+            resolved_file.etag = record.etag.clone();
+        }
+        provenance::record(&self.repo, &hub_revision, &fileinfo.path, record)?;
+        // An unchecked download is neither trusted as complete nor offered to other repos.
+        match options.verify {
+            true => mark_complete(&self.repo, &hub_revision, std::slice::from_ref(fileinfo))?,
+            false => unmark_complete(&self.repo, &hub_revision, &fileinfo.path)?,
+        }
+        self.log(format_args!("downloaded {filename}"));
+        if self.global_dedup && options.verify {
+            dedup::record_download(
+                self.repo.cache_home(),
+                fileinfo.sha256.as_deref(),
+                &filepath,
+            )?;
+        }
+
+        lock.unlock();
+        self.repo.create_ref(&hub_revision)?;
+        let mut resolution = resolved_file.resolution(filepath, true);
+        resolution.progress_degraded = progress_degraded;
+        resolved::record(&self.repo, filename, resolved_file, commit.as_deref())?;
+        Ok(resolution)
+    }
+
+    /// Announces the files of `groups` a pull may download to `progress`, before any
+    /// transfer starts. Files a finished pull recorded as complete are left out. Returns the
+    /// announced paths.
+    fn queue_files(
+        &self,
+        groups: &BTreeMap<String, Vec<FileInfo>>,
+        plan: &paths::PathPlan,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<HashSet<String>, OpsError> {
+        let Some(prg) = progress.as_mut() else {
+            return Ok(HashSet::new());
+        };
+        let mut units = Vec::new();
+        for (revision, files) in groups {
+            let completed = completed_files(&self.repo, revision);
+            for fileinfo in files {
+                let snapshot_path = self.repo.snapshot_path(&fileinfo.revision);
+                let filepath = snapshot_file(&snapshot_path, plan.local_path(fileinfo));
+                if !is_complete(&completed, &filepath, fileinfo) {
+                    units.push(ProgressUnit::new(
+                        fileinfo.path.clone(),
+                        fileinfo.size as u64,
+                    ));
+                }
+            }
+        }
+        self.progress_errors
+            .handle(self.repo.repo_id(), prg.on_queued(&units))?;
+        Ok(units.into_iter().map(|unit| unit.filename).collect())
+    }
+
+    fn inner_verify(
+        &self,
+        mut progress: Option<impl Progress>,
+        repair: bool,
+    ) -> Result<VerifyReport, OpsError> {
+        let (checks, extra) = verify::plan(&self.repo, self.repo_files(false)?.blobs())?;
+        let mut report = VerifyReport {
+            extra,
+            ..Default::default()
+        };
+        if let Some(prg) = progress.as_mut() {
+            let units: Vec<ProgressUnit> = checks
+                .iter()
+                .map(|c| ProgressUnit::new(c.fileinfo.path.clone(), c.fileinfo.size as u64))
+                .collect();
+            self.progress_errors
+                .handle(self.repo.repo_id(), prg.on_queued(&units))?;
+        }
+        let mut broken = Vec::new();
+        for check in checks {
+            let status = self.check_cached(&check, &mut progress)?;
+            report.record(&check.fileinfo.path, status);
+            if status == FileStatus::Corrupt && repair {
+                verify::remove_corrupt(&check.filepath)?;
+            }
+            if status != FileStatus::Verified {
+                broken.push(check.fileinfo);
+            }
+        }
+        if repair && !broken.is_empty() {
+            let (pulled, _) =
+                self.fill_snapshots(broken, progress, Some(CacheValidation::Sha256))?;
+            report.repaired = [pulled.downloaded, pulled.deduplicated].concat();
+            report.repaired.sort();
+        }
+        Ok(report)
+    }
+
+    /// The status of the cached copy of `check`, reporting its hashing to `progress`.
+    fn check_cached(
+        &self,
+        check: &verify::Check,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<FileStatus, OpsError> {
+        let (fileinfo, filepath) = (&check.fileinfo, &check.filepath);
+        if let Some(status) = verify::status_by_size(filepath, fileinfo)? {
+            self.finish_queued(fileinfo, progress)?;
+            return Ok(status);
+        }
+        let Some(prg) = progress.as_mut() else {
+            return verify::status(filepath, fileinfo);
+        };
+        let mut unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+        let mut reporting = self
+            .progress_errors
+            .handle(&fileinfo.path, prg.on_start(&unit))?;
+        let mut hashing = verify::Hashing::open(filepath)?;
+        while let Some(hashed) = hashing.step()? {
+            if reporting {
+                unit.update(hashed);
+                reporting = self
+                    .progress_errors
+                    .handle_control(&fileinfo.path, prg.on_progress_control(&unit))?;
+            }
+        }
+        if reporting {
+            self.progress_errors
+                .handle(&fileinfo.path, prg.on_finish(&unit))?;
+        }
+        Ok(match hashing.matches(fileinfo) {
+            true => FileStatus::Verified,
+            false => FileStatus::Corrupt,
+        })
+    }
+
+    /// Reports a queued file found current or deduplicated as finished, without a start.
+    fn finish_queued(
+        &self,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(), OpsError> {
+        if let Some(prg) = progress.as_mut() {
+            let mut unit = ProgressUnit::new(fileinfo.path.clone(), fileinfo.size as u64);
+            unit.update(fileinfo.size as u64);
+            self.progress_errors
+                .handle(&fileinfo.path, prg.on_finish(&unit))?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [`OpsError::Cancelled`] once the flag of [`ModelsCat::with_cancel_flag`]
+    /// is set.
+    fn check_cancelled(&self, filename: &str) -> Result<(), OpsError> {
+        match &self.cancel {
+            Some(flag) if flag.load(Ordering::Relaxed) => Err(OpsError::Cancelled {
+                file: filename.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes a slot of the process-wide transfer limit, if one is configured, blocking until
+    /// one is free.
+    fn transfer_slot(&self, filename: &str) -> Option<TransferSlot> {
+        let slots = self.transfer_slots.as_ref()?;
+        if let Some(slot) = slots.try_acquire() {
+            return Some(slot);
+        }
+        self.log(format_args!("waiting for a transfer slot for {filename}"));
+        Some(slots.acquire())
+    }
+
+    /// Recovers or discards the downloads interrupted in `snapshot_path`, such as by a crash.
+    fn reconcile_staging(&self, snapshot_path: &Path) -> Result<(), OpsError> {
+        for (path, recovery) in staging::reconcile(snapshot_path)? {
+            match recovery {
+                Recovery::Promoted => self.log(format_args!("recovered {}", path.display())),
+                Recovery::Discarded => self.log(format_args!("discarded {}", path.display())),
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads a file from a URL with progress tracking, retrying transient failures under
+    /// [`ModelsCat::with_retry`]. See [`ModelsCat::download_once`].
+    fn download_file(
+        &self,
+        file_url: &str,
+        filepath: &Path,
+        file: &HubFileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(ProvenanceRecord, bool), OpsError> {
+        let mut retries = 0;
+        loop {
+            let err = match self.download_once(file_url, filepath, file, progress) {
+                Ok(downloaded) => return Ok(downloaded),
+                Err(e) => e,
+            };
+            let Some(delay) = self.retry.delay(retries, &err) else {
+                return Err(err);
+            };
+            retries += 1;
+            self.log(format_args!("retrying {} in {delay:?}: {err}", file.path));
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Downloads a file from a URL with progress tracking, once.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_url` - The URL of the file to download
+    /// * `filepath` - The destination path where the file will be saved
+    /// * `file` - The listed file, whose `path` is the full filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`
+    /// * `progress` - Optional progress tracker implementing the `Progress` trait
+    ///
+    /// Returns the provenance of the download, and whether progress reporting stopped early
+    /// under [`ProgressErrorPolicy::Ignore`].
+    fn download_once(
+        &self,
+        file_url: &str,
+        filepath: &Path,
+        file: &HubFileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<(ProvenanceRecord, bool), OpsError> {
+        let parent = filepath
+            .parent() // 直接获取父目录
+            .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+        std::fs::create_dir_all(parent)?;
+        // Picks up where an interrupted download left off. Removed along with its sidecar if
+        // the download fails other than by a dropped connection.
+        let mut staged = match StagedFile::resume(filepath, file)? {
+            Some(staged) => staged,
+            None => StagedFile::create(filepath, file)?,
+        };
+        let mut offset = staged.written();
+
+        let _slot = self.transfer_slot(&file.path);
+        if offset > 0 {
+            self.log(format_args!("resuming {} at {offset} bytes", file.path));
+        }
+        let mut response = match self.get_file(file_url, offset) {
+            Ok(response) => response,
+            Err(e) if offset > 0 => {
+                staged.suspend(offset)?;
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        if offset > 0 && !resumes_at(response.status(), response.headers(), offset) {
+            staged.restart()?;
+            offset = 0;
+            // Anything but the whole file, such as a range the server cannot satisfy, is asked
+            // for again without one.
+            if response.status() != StatusCode::OK {
+                response = self.get_file(file_url, 0)?;
+            }
+        }
+        let served_by = response.url().to_string();
+        let etag = resolved::etag(response.headers());
+        let headers = quarantine::headers(response.headers());
+        // Gateways using chunked transfer encoding send no length, leaving the size unknown to
+        // progress until the end.
+        let mut unit = match response.content_length() {
+            Some(content_length) => ProgressUnit::new(file.path.clone(), offset + content_length),
+            None => ProgressUnit::without_total(file.path.clone()),
+        };
+        unit.update(offset);
+        let mut reporting = true;
+        if let Some(prg) = progress.as_mut() {
+            reporting = self
+                .progress_errors
+                .handle(&file.path, prg.on_start(&unit))?;
+        }
+
+        let mut downloaded = offset;
+        let mut hasher = match offset {
+            0 => Sha256::new(),
+            _ => staged.hasher()?,
+        };
+        let staged_file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(staged.path())?;
+        let mut buf_write = io::BufWriter::with_capacity(self.write_buffer_size, staged_file);
+        let mut buf_read = io::BufReader::new(response);
+        let mut buf = vec![0u8; 8192];
+
+        loop {
+            let len = match buf_read.read(&mut buf) {
+                Ok(len) => len,
+                Err(e) => {
+                    // Keeps what arrived for the next try to resume from.
+                    buf_write.flush()?;
+                    drop(buf_write);
+                    staged.suspend(downloaded)?;
+                    return Err(e.into());
+                }
+            };
+            if len == 0 {
+                break;
+            }
+            buf_write.write_all(&buf[..len])?;
+            hasher.update(&buf[..len]);
+            let previous = downloaded;
+            downloaded += len as u64;
+            if downloaded / CHECKPOINT_INTERVAL > previous / CHECKPOINT_INTERVAL {
+                buf_write.flush()?;
+                staged.checkpoint(downloaded)?;
+            }
+
+            if let Some(throttle) = &self.throttle {
+                throttle.pace(len as u64);
+            }
+            self.check_cancelled(&file.path)?;
+            if reporting && let Some(prg) = progress.as_mut() {
+                unit.update(downloaded);
+                reporting = self
+                    .progress_errors
+                    .handle_control(&file.path, prg.on_progress_control(&unit))?;
+            }
+        }
+
+        buf_write.flush()?;
+        // The blob must be durable before it replaces the old one, so readers only ever see the
+        // complete old or the complete new file.
+        buf_write.get_ref().sync_all()?;
+        drop(buf_write);
+        let sha256 = format!("{:x}", hasher.finalize());
+        if file
+            .sha256
+            .as_ref()
+            .is_some_and(|expected| *expected != sha256)
+        {
+            return Err(self.reject_download(staged, file, sha256, served_by, headers));
+        }
+        // A rejected download is deleted along with `staged`.
+        validate::run(&self.validators, staged.path(), file)?;
+        // Lets a crash before the rename be recovered from, see `staging::reconcile`.
+        staged.checkpoint(downloaded)?;
+        // With the blob store, the download becomes the blob its snapshot file links to.
+        match self.blob_store && filepath.starts_with(self.repo.cache_dir()) {
+            true => blobs::store(&self.repo, &sha256, staged, filepath)?,
+            false => staged.persist(filepath)?,
+        }
+
+        if reporting && let Some(prg) = progress.as_mut() {
+            reporting = self
+                .progress_errors
+                .handle(&file.path, prg.on_finish(&unit))?;
+        }
+        let record = ProvenanceRecord::new(&self.endpoint, &served_by, sha256, etag);
+        Ok((record, !reporting))
+    }
+
+    /// Sends the GET request of a download, for the bytes from `offset` on when it is not 0.
+    fn get_file(&self, file_url: &str, offset: u64) -> Result<blocking::Response, OpsError> {
+        self.pace();
+        let mut request = self.client.get(file_url);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={offset}-"));
+        }
+        let response = request.send()?;
+        self.observe_rate_limit(RateLimitStatus::from_headers(response.headers()));
+        if let Some(e) = OpsError::unauthorized(response.status(), file_url)
+            .or_else(|| retry::status_error(response.status(), response.headers(), file_url))
+        {
+            return Err(e);
+        }
+        Ok(response)
+    }
+
+    /// The error of a download whose sha256 is not the listed one, quarantining it first when
+    /// enabled with [`ModelsCat::with_quarantine`].
+    fn reject_download(
+        &self,
+        staged: StagedFile,
+        file: &HubFileInfo,
+        actual: String,
+        url: String,
+        headers: Vec<(String, String)>,
+    ) -> OpsError {
+        let report = QuarantineReport {
+            file: file.path.clone(),
+            revision: file.revision.clone(),
+            expected: file.sha256.clone().unwrap_or_default(),
+            actual,
+            url,
+            endpoint: self.endpoint.clone(),
+            headers,
+        };
+        let err = quarantine::mismatch(
+            self.repo.cache_home(),
+            self.quarantine,
+            staged,
+            file,
+            report,
+        );
+        if let OpsError::ChecksumMismatch {
+            quarantined: Some(ref path),
+            ..
+        } = err
+        {
+            self.log(format_args!(
+                "quarantined {} at {}",
+                file.path,
+                path.display()
+            ));
+        }
+        err
+    }
+
+    /// Confirms a file placed by an earlier download with a HEAD request against its resolve
+    /// URL, which is much cheaper than the listing of a large repo. The file is current when
+    /// its size and recorded ETag still match; a file recorded without an ETag, and any
+    /// failure, falls back to the listing.
+    fn head_check(&self, filename: &str) -> HeadCheck {
+        let Some((filepath, file)) = resolved::cached(&self.repo, filename) else {
+            return HeadCheck::Unconfirmed(None);
+        };
+        let file_url = format!(
+            "{}/{}/{}",
+            self.endpoint,
+            self.repo.url_path_with_resolve(),
+            filename
+        );
+        self.pace();
+        let mut request = self.client.head(file_url);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        let Ok(response) = request.send().and_then(|r| r.error_for_status()) else {
+            return HeadCheck::Unconfirmed(None);
+        };
+        self.observe_rate_limit(RateLimitStatus::from_headers(response.headers()));
+        if !resolved::matches(&file, response.headers()) {
+            return HeadCheck::Unconfirmed(resolved::etag(response.headers()));
+        }
+        HeadCheck::Current(filepath, file)
+    }
+
+    /// Fetches the repo listing, reusing the previous one for up to [`LISTING_TTL`] unless
+    /// `refresh` is set.
+    fn repo_files(&self, refresh: bool) -> Result<Arc<ApiResponse>, OpsError> {
+        if !refresh && let Some(files) = cached_listing(&self.listing) {
+            return Ok(files);
+        }
+        if self.offline {
+            return Err(OpsError::HubError(format!(
+                "the listing of {} is not fetched offline",
+                self.repo.repo_id()
+            )));
+        }
+        self.pace();
+        let files = Arc::new(synchronous::get_repo_files(
+            &self.client,
+            &self.endpoint,
+            self.token.as_deref(),
+            &self.repo,
+        )?);
+        self.observe_rate_limit(files.rate_limit);
+        *self.listing.lock().unwrap() = Some((Instant::now(), files.clone()));
+        Ok(files)
+    }
+
+    /// The last `limit` pulls, downloads and removals of the repo, oldest first, for finding out
+    /// when a model changed.
+    ///
+    /// Every operation appends a record to `history.jsonl` in the repo cache directory, which
+    /// is rotated past [`ModelsCat::with_history_size_limit`]; only the last rotated log is
+    /// kept. Records that cannot be parsed are skipped with a warning.
+    pub fn history(&self, limit: usize) -> Result<Vec<HistoryEntry>, OpsError> {
+        history::read(&self.repo, limit)
+    }
+
+    /// Moves the cached files of every snapshot into the blob store and links them back, as
+    /// [`ModelsCat::with_blob_store`] lays out new downloads. Returns the bytes freed by the
+    /// files whose content another snapshot already stored.
+    ///
+    /// Files already linking to a blob are left alone, so this can be run again safely.
+    pub fn migrate_to_blob_store(&self) -> Result<u64, OpsError> {
+        blobs::migrate(&self.repo)
+    }
+
+    /// Removes the quarantine area of the cache, see [`ModelsCat::with_quarantine`]. Returns
+    /// the bytes freed.
+    ///
+    /// The area is shared by every repo of the cache.
+    pub fn clear_quarantine(&self) -> Result<u64, OpsError> {
+        quarantine::clear(self.repo.cache_home())
+    }
+
+    /// Appends an operation to the history log. Failing to record it does not fail the
+    /// operation.
+    fn record_history<T>(
+        &self,
+        operation: HistoryOperation,
+        files: Vec<String>,
+        bytes: u64,
+        result: &Result<T, OpsError>,
+    ) {
+        let entry = HistoryEntry::new(&self.repo, operation, files, bytes, result);
+        if let Err(e) = history::append(&self.repo, &entry, self.history_size_limit) {
+            log::warn!("failed to record history: {e}");
+        }
+    }
+
+    /// The rate limit last reported by the hub in the headers of a listing or download
+    /// response, or `None` when the hub has reported none yet.
+    ///
+    /// Long-running jobs such as mirrors can use it to pace themselves; see also
+    /// [`ModelsCat::with_rate_limit_pacing`].
+    pub fn rate_limit_status(&self) -> Option<RateLimitStatus> {
+        *self.rate_limit.lock().unwrap()
+    }
+
+    fn observe_rate_limit(&self, status: Option<RateLimitStatus>) {
+        if status.is_some() {
+            *self.rate_limit.lock().unwrap() = status;
+        }
+    }
+
+    /// Waits as [`ModelsCat::with_rate_limit_pacing`] requires before a request.
+    fn pace(&self) {
+        if let Some(wait) = self.pacing_delay() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    fn pacing_delay(&self) -> Option<std::time::Duration> {
+        let wait = self
+            .pacing?
+            .delay(&self.rate_limit_status()?, std::time::SystemTime::now())?;
+        self.log(format_args!(
+            "rate limit nearly reached, waiting {:.1}s",
+            wait.as_secs_f64()
+        ));
+        Some(wait)
+    }
+
+    fn log(&self, line: fmt::Arguments) {
+        if let Some(ref logger) = self.logger {
+            (logger.lock().unwrap())(&line.to_string());
+        }
+    }
+
+    /// Places a file downloaded outside of models-cat, such as with a browser, in the cache as
+    /// the hub file `as_filename`, so it is not downloaded again.
+    ///
+    /// The file must have the listed size and, when the hub lists one, sha256; otherwise
+    /// nothing is placed and [`OpsError::ChecksumMismatch`] is returned. It is hard linked into
+    /// the snapshot when possible, and copied otherwise.
+    pub fn import_file(&self, local: &Path, as_filename: &str) -> Result<ImportOutcome, OpsError> {
+        let result = self.import_listed(local, as_filename);
+        let bytes = match result {
+            Ok(ref outcome) => std::fs::metadata(outcome.path()).map_or(0, |m| m.len()),
+            Err(_) => 0,
+        };
+        let files = vec![as_filename.to_string()];
+        self.record_history(HistoryOperation::Import, files, bytes, &result);
+        result
+    }
+
+    fn import_listed(&self, local: &Path, as_filename: &str) -> Result<ImportOutcome, OpsError> {
+        let repo_files = self.repo_files(false)?;
+        let fileinfo = repo_files.get_file_info(as_filename)?;
+        let commit = latest_revision(&repo_files.data.files);
+        let local_name = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)?;
+        import::import(
+            &self.repo,
+            local,
+            fileinfo,
+            &local_name,
+            commit.as_deref(),
+            self.global_dedup,
+        )
+    }
+
+    /// Imports every file under `dir` matching a hub file, by its path relative to `dir` or
+    /// else by a file name only one hub file has, as [`ModelsCat::import_file`] does.
+    ///
+    /// Files that do not match their listing are reported rather than failing the import.
+    /// Once every file a pull would place is cached, the ref and `latest` link of the repo are
+    /// pointed at the newest snapshot, as after a pull.
+    pub fn import_dir(&self, dir: &Path) -> Result<ImportReport, OpsError> {
+        let result = self.import_dir_listed(dir);
+        let (files, bytes) = match result {
+            Ok(ref report) => (
+                report
+                    .imported
+                    .iter()
+                    .map(|(file, _)| file.clone())
+                    .collect(),
+                report
+                    .imported
+                    .iter()
+                    .map(|(_, outcome)| std::fs::metadata(outcome.path()).map_or(0, |m| m.len()))
+                    .sum(),
+            ),
+            Err(_) => (Vec::new(), 0),
+        };
+        self.record_history(HistoryOperation::Import, files, bytes, &result);
+        result
+    }
+
+    fn import_dir_listed(&self, dir: &Path) -> Result<ImportReport, OpsError> {
+        let repo_files = self.repo_files(true)?;
+        let blobs = repo_files.blobs();
+        let commit = latest_revision(&blobs);
+        let files = import::match_dir(dir, &blobs)?;
+        let mut report = ImportReport {
+            unknown: files.unknown,
+            ..Default::default()
+        };
+        for (local, fileinfo) in files.matched {
+            let imported = paths::local_name(fileinfo, self.invalid_paths, self.path_rules)
+                .and_then(|local_name| {
+                    import::import(
+                        &self.repo,
+                        &local,
+                        fileinfo,
+                        &local_name,
+                        commit.as_deref(),
+                        self.global_dedup,
+                    )
+                });
+            match imported {
+                Ok(outcome) => {
+                    self.log(format_args!("imported {}", fileinfo.path));
+                    report.imported.push((fileinfo.path.clone(), outcome));
+                }
+                Err(e @ OpsError::ChecksumMismatch { .. }) => {
+                    self.log(format_args!("rejected {}: {e}", fileinfo.path));
+                    report.rejected.push((fileinfo.path.clone(), e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let (selected, _) = PullOptions::default().select(blobs)?;
+        report.complete = import::activate_if_complete(&self.repo, &selected)?;
+        Ok(report)
+    }
+
+    /// List files in the remote repo
+    pub fn list_hub_files(&self) -> Result<Vec<String>, OpsError> {
+        let files = self.repo_files(true)?.blobs();
+        Ok(files.iter().map(|f| f.path.clone()).collect())
+    }
+
+    /// Lists the files of the remote repo with their size, sha256, revision and whether they
+    /// are stored with Git LFS, such as to show sizes before downloading.
+    pub fn list_hub_files_detailed(&self) -> Result<Vec<FileMetadata>, OpsError> {
+        let files = self.repo_files(true)?.blobs();
+        Ok(files.into_iter().map(FileMetadata::from).collect())
+    }
+
+    /// Lists the files of the remote repo that [`ModelsCat::pull_with_patterns`] would pull
+    /// with the same patterns, possibly none.
+    pub fn list_hub_files_with_patterns(
+        &self,
+        allow: &[&str],
+        ignore: &[&str],
+    ) -> Result<Vec<String>, OpsError> {
+        let options = PullOptions::default()
+            .with_allow_patterns(allow.iter().copied())
+            .with_ignore_patterns(ignore.iter().copied());
+        let files = options.filter(self.repo_files(true)?.blobs())?;
+        Ok(files.into_iter().map(|f| f.path).collect())
+    }
+
+    /// List files in the local repo
+    pub fn list_local_files(&self) -> Result<Vec<String>, OpsError> {
+        let base_path = self.repo.cache_dir().join("snapshots");
+        let mut files = Vec::new();
+
+        for entry in walkdir::WalkDir::new(&base_path)
+            .min_depth(2) // 跳过snapshots根目录
+            .max_depth(10) // 限制遍历深度 // 限制遍历深度：repo_path/<snapshot>/<file_path>
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_dir() {
+                let rel_path = entry
+                    .path()
+                    .strip_prefix(&base_path)
+                    .map_err(|e| OpsError::hub("file outside the snapshots directory", e))?
+                    .components()
+                    .skip(1) // 跳过commit hash目录
+                    .collect::<PathBuf>();
+
+                files.push(rel_path.to_string_lossy().replace('\\', "/"));
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Removes what the repo cache no longer needs and returns what was freed, or with
+    /// `dry_run`, what would be without removing anything:
+    ///
+    /// - snapshots of revisions nothing refers to: not the ones the refs and `latest` point
+    ///   at, nor the ones holding files of the configured revision, which the listing tells;
+    /// - `.lock` files no process holds, and `.tmp` files left over by interrupted writes;
+    /// - blobs no snapshot links to, under [`ModelsCat::with_blob_store`].
+    ///
+    /// Each snapshot is removed under its lock, and skipped while a download holds it.
+    pub fn prune(&self, dry_run: bool) -> Result<PruneReport, OpsError> {
+        let keep = prune::referenced(&self.repo, &self.repo_files(false)?.blobs());
+        prune::prune(&self.repo, &keep, dry_run)
+    }
+
+    /// Estimates the bytes [`ModelsCat::pull`] would transfer: the listed size of every file,
+    /// without looking at the cache.
+    pub fn estimated_pull_size(&self) -> Result<u64, OpsError> {
+        self.estimated_pull_size_with_options(&PullOptions::default(), false)
+    }
+
+    /// Estimates the bytes [`ModelsCat::pull_with_options`] would transfer with `options`,
+    /// leaving out with `subtract_cached` the files already cached with the listed sha256, or
+    /// the listed size when the hub lists no sha256.
+    ///
+    /// Subtracting the cached files hashes them, so it takes a while for large repos. Files
+    /// deduplicated from another repo during the pull are still counted.
+    pub fn estimated_pull_size_with_options(
+        &self,
+        options: &PullOptions,
+        subtract_cached: bool,
+    ) -> Result<u64, OpsError> {
+        let (blobs, _) = options.select(self.repo_files(true)?.blobs())?;
+        verify::pending_bytes(&self.repo, blobs, subtract_cached)
+    }
+
+    /// Total size in bytes of the repo cache directory: its snapshots, and the refs and
+    /// records kept next to them. 0 when nothing is cached yet.
+    ///
+    /// See [`crate::scan_cache`] for every repo in the cache directory.
+    pub fn size_on_disk(&self) -> Result<u64, OpsError> {
+        cache::size_on_disk(&self.repo.cache_dir())
+    }
+
+    /// Remove all files in the repo, doing nothing when it was never cached.
+    ///
+    /// Refuses with [`OpsError::RefusingUnsafeDelete`] when the repo cache directory does not
+    /// look like one, such as after a misconfigured cache directory or cache key pointed it at
+    /// an unrelated directory.
+    pub fn remove_all(&self) -> Result<(), OpsError> {
+        cache::check_repo_cache_dir(&self.repo.cache_dir())?;
+        self.remove_all_unchecked()
+    }
+
+    /// Remove the repo cache directory without checking what it holds, see
+    /// [`ModelsCat::remove_all`].
+    pub fn remove_all_unchecked(&self) -> Result<(), OpsError> {
+        match std::fs::remove_dir_all(self.repo.cache_dir()) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        dedup::DedupIndex::new(self.repo.cache_home()).forget(&self.repo.cache_dir())?;
+        Ok(())
+    }
+
+    /// Remove a file in the repo
+    pub fn remove(&self, filename: &str) -> Result<(), OpsError> {
+        let mut bytes = 0;
+        let result = self.remove_copies(filename, &mut bytes);
+        let files = vec![filename.to_string()];
+        self.record_history(HistoryOperation::Remove, files, bytes, &result);
+        result
+    }
+
+    /// Removes every cached copy of `filename`, adding their sizes to `bytes`.
+    fn remove_copies(&self, filename: &str, bytes: &mut u64) -> Result<(), OpsError> {
+        let base_path = self.repo.cache_dir().join("snapshots");
+
+        for entry in walkdir::WalkDir::new(&base_path)
+            .min_depth(2) // 跳过snapshots根目录
+            .max_depth(10) // 限制遍历深度 // 限制遍历深度：repo_path/<snapshot>/<file_path>
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_dir() {
+                let rel_path = entry
+                    .path()
                     .strip_prefix(&base_path)
-                    .map_err(|e| OpsError::HubError(e.to_string()))?
+                    .map_err(|e| OpsError::hub("file outside the snapshots directory", e))?
                     .components()
                     .skip(1) // 跳过commit hash目录
                     .collect::<PathBuf>();
 
-                files.push(rel_path.to_string_lossy().replace('\\', "/"));
-            }
+                if filename == rel_path.to_string_lossy().replace('\\', "/") {
+                    *bytes += std::fs::metadata(entry.path()).map_or(0, |m| m.len());
+                    std::fs::remove_file(entry.path())?;
+                    dedup::DedupIndex::new(self.repo.cache_home()).forget(entry.path())?;
+                }
+            }
+        }
+        blobs::prune(&self.repo)?;
+
+        Ok(())
+    }
+}
+
+/// Groups listed files by the revision the hub reports for each of them, which is also the
+/// snapshot they are stored in.
+pub(crate) fn group_by_revision(blobs: Vec<FileInfo>) -> BTreeMap<String, Vec<FileInfo>> {
+    let mut groups: BTreeMap<String, Vec<FileInfo>> = BTreeMap::new();
+    for fileinfo in blobs {
+        groups
+            .entry(fileinfo.revision.clone())
+            .or_default()
+            .push(fileinfo);
+    }
+    groups
+}
+
+/// Path of the file at `local_path`, a `/`-separated path, in the snapshot at `snapshot_path`.
+pub(crate) fn snapshot_file(snapshot_path: &Path, local_path: &str) -> PathBuf {
+    let mut filepath = snapshot_path.to_path_buf();
+    for part in local_path.split('/') {
+        filepath.push(part);
+    }
+    filepath
+}
+
+/// Path of the marker listing the files a pull has fully placed in the snapshot of `revision`.
+///
+/// Markers are kept per revision because the hub reports a revision per file, so one pull may
+/// fill several snapshots, and an interrupted pull must only resume the unfinished ones.
+fn completion_path(repo: &Repo, revision: &str) -> PathBuf {
+    repo.cache_dir().join("complete").join(revision)
+}
+
+/// Files recorded as complete in the snapshot of `revision`.
+pub(crate) fn completed_files(repo: &Repo, revision: &str) -> HashSet<String> {
+    std::fs::read_to_string(completion_path(repo, revision))
+        .map(|s| s.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Records that every file in `files` is in place in the snapshot of `revision`.
+pub(crate) fn mark_complete(
+    repo: &Repo,
+    revision: &str,
+    files: &[FileInfo],
+) -> Result<(), OpsError> {
+    let mut completed = completed_files(repo, revision);
+    completed.extend(files.iter().map(|f| f.path.clone()));
+    write_completed(repo, revision, completed)
+}
+
+/// Records that `filename` of the snapshot of `revision` may no longer be trusted as complete,
+/// such as after a download that skipped the sha256 check.
+pub(crate) fn unmark_complete(repo: &Repo, revision: &str, filename: &str) -> Result<(), OpsError> {
+    let mut completed = completed_files(repo, revision);
+    if !completed.remove(filename) {
+        return Ok(());
+    }
+    write_completed(repo, revision, completed)
+}
+
+fn write_completed(
+    repo: &Repo,
+    revision: &str,
+    completed: HashSet<String>,
+) -> Result<(), OpsError> {
+    let path = completion_path(repo, revision);
+    let mut completed: Vec<_> = completed.into_iter().collect();
+    completed.sort();
+
+    let parent = path
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let mut temp = NamedTempFile::new_in(parent)?;
+    for file in completed {
+        writeln!(temp, "{file}")?;
+    }
+    temp.persist(&path)
+        .map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// Whether a file recorded as complete is still in place, by its size alone. Good for
+/// estimates such as the progress of a pull; whether a cached file is current is up to
+/// [`is_verified`] under the configured [`CacheValidation`].
+pub(crate) fn is_complete(
+    completed: &HashSet<String>,
+    filepath: &Path,
+    fileinfo: &FileInfo,
+) -> bool {
+    completed.contains(&fileinfo.path)
+        && std::fs::metadata(filepath).is_ok_and(|m| m.len() == fileinfo.size as u64)
+}
+
+/// Takes the snapshot lock to place `filepath`, or returns `None` when the file is already
+/// current.
+///
+/// The check runs before locking, so hashing a large cached file never blocks processes writing
+/// other files of the snapshot. Once the lock is held, the file is checked again only when its
+/// size or modification time changed since, as when another process placed it in the meantime.
+pub(crate) fn lock_unless_current(
+    snapshot_path: PathBuf,
+    filepath: &Path,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+) -> Result<Option<fslock::FsLock>, OpsError> {
+    let checked = check(filepath, fileinfo, validation)?;
+    if checked.current {
+        return Ok(None);
+    }
+    let mut lock = fslock::FsLock::lock(snapshot_path)?;
+    if recheck(filepath, fileinfo, validation, checked)? {
+        lock.unlock();
+        return Ok(None);
+    }
+    Ok(Some(lock))
+}
+
+/// What a look at a cached file before locking its snapshot found.
+#[derive(Clone, Copy)]
+pub(crate) struct Checked {
+    observed: Option<(u64, Option<std::time::SystemTime>)>,
+    current: bool,
+}
+
+/// Checks whether the file at `filepath` is current under `validation`, noting its size and
+/// modification time for [`recheck`].
+pub(crate) fn check(
+    filepath: &Path,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+) -> Result<Checked, OpsError> {
+    let observed = observe(filepath);
+    let current = is_verified(filepath, fileinfo, validation)?;
+    Ok(Checked { observed, current })
+}
+
+/// Whether the file at `filepath`, found `checked` before its snapshot was locked, is current
+/// now. It is checked again only when its size or modification time changed since.
+pub(crate) fn recheck(
+    filepath: &Path,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+    checked: Checked,
+) -> Result<bool, OpsError> {
+    match observe(filepath) == checked.observed {
+        true => Ok(checked.current),
+        false => is_verified(filepath, fileinfo, validation),
+    }
+}
+
+/// The size and modification time of the file at `filepath`, or `None` when there is none, to
+/// tell whether it changed between two looks.
+pub(crate) fn observe(filepath: &Path) -> Option<(u64, Option<std::time::SystemTime>)> {
+    let metadata = std::fs::metadata(filepath).ok()?;
+    Some((metadata.len(), metadata.modified().ok()))
+}
+
+/// Whether the file at `filepath` exists and is current under `validation`.
+pub(crate) fn is_verified(
+    filepath: &Path,
+    fileinfo: &FileInfo,
+    validation: CacheValidation,
+) -> Result<bool, OpsError> {
+    if !std::fs::exists(filepath)? {
+        return Ok(false);
+    }
+    let metadata = std::fs::metadata(filepath)?;
+    match (validation, &fileinfo.sha256) {
+        (CacheValidation::CommitDate, _) => {
+            let modified = metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64);
+            Ok(metadata.len() == fileinfo.size as u64 && fileinfo.committed_date <= modified)
+        }
+        (CacheValidation::Sha256, Some(sha256)) => Ok(&utils::sha256(filepath)? == sha256),
+        (CacheValidation::Sha256, None) => Ok(metadata.len() == fileinfo.size as u64),
+    }
+}
+
+/// Whether a response to a request for the bytes from `offset` on sends exactly those: a
+/// `206 Partial Content` whose `Content-Range` starts at `offset`.
+pub(crate) fn resumes_at(status: StatusCode, headers: &HeaderMap, offset: u64) -> bool {
+    status == StatusCode::PARTIAL_CONTENT
+        && headers
+            .get(CONTENT_RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.strip_prefix("bytes "))
+            .and_then(|range| range.split_once('-'))
+            .is_some_and(|(start, _)| start.parse() == Ok(offset))
+}
+
+/// Returns the most recently committed revision among the listed files, which is the snapshot
+/// the `latest` link should point at after pulling them, once [`link_into_latest`] made it
+/// hold every listed file.
+pub(crate) fn latest_revision(blobs: &[FileInfo]) -> Option<String> {
+    blobs
+        .iter()
+        .max_by(|a, b| (a.committed_date, &a.revision).cmp(&(b.committed_date, &b.revision)))
+        .map(|f| f.revision.clone())
+}
+
+/// How the `latest` pointer of a repo is materialized on disk.
+#[derive(Clone, Copy)]
+enum LatestLink {
+    Symlink,
+    File,
+}
+
+/// Points the ref of the repo's branch or tag, then the `latest` link of `repo`, at the snapshot
+/// of `revision`, each atomically. Falls back to the `latest.path` text file when symlinks are
+/// unavailable.
+///
+/// Only called once a pull has placed every file, and linked those of older revisions into the
+/// snapshot with [`link_into_latest`], so neither ever resolves to a partial snapshot: after a
+/// failed pull both keep pointing at the previous, complete one, and the partial snapshot is
+/// left for the next pull to resume.
+pub(crate) fn update_latest(repo: &Repo, revision: &str) -> Result<(), OpsError> {
+    repo.create_ref(revision)?;
+    if write_latest(repo, revision, LatestLink::Symlink).is_err() {
+        write_latest(repo, revision, LatestLink::File)?;
+    }
+    Ok(())
+}
+
+/// The `files` placed in the snapshots of other revisions than `latest` that are not linked into
+/// its snapshot yet, see [`link_into_latest`].
+pub(crate) fn unlinked_into_latest(
+    repo: &Repo,
+    latest: &str,
+    files: &[FileInfo],
+    plan: &paths::PathPlan,
+) -> Vec<FileInfo> {
+    let snapshot = repo.snapshot_path(latest);
+    files
+        .iter()
+        .filter(|fileinfo| {
+            let local_path = plan.local_path(fileinfo);
+            let src = snapshot_file(&repo.snapshot_path(&fileinfo.revision), local_path);
+            fileinfo.revision != latest
+                && !is_same_file(&src, &snapshot_file(&snapshot, local_path))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Links `older`, files placed in the snapshots of other revisions, into the snapshot of
+/// `latest`, which the caller has locked.
+///
+/// The hub lists a revision per file, the commit that last changed it, so the snapshot of the
+/// newest one alone only holds the files of that commit. Linked in, it holds the whole listing
+/// the ref and `latest` link are about to resolve to.
+pub(crate) fn link_into_latest(
+    repo: &Repo,
+    latest: &str,
+    older: &[FileInfo],
+    plan: &paths::PathPlan,
+) -> Result<(), OpsError> {
+    let snapshot = repo.snapshot_path(latest);
+    for fileinfo in older {
+        let local_path = plan.local_path(fileinfo);
+        let src = snapshot_file(&repo.snapshot_path(&fileinfo.revision), local_path);
+        dedup::link_or_copy(&src, &snapshot_file(&snapshot, local_path))?;
+    }
+    paths::record_sanitized(repo, latest, older, &plan.sanitized)
+}
+
+/// Whether `a`, followed if a symlink, and `b` are links to the same file.
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+/// Whether `a`, followed if a symlink, and `b` are links to the same file, as far as their size
+/// and modification time tell.
+#[cfg(not(unix))]
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (std::fs::metadata(a), std::fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.len() == b.len() && a.modified().ok() == b.modified().ok(),
+        _ => false,
+    }
+}
+
+/// The listed files the snapshot of each revision of `blobs` holds once they are pulled: those
+/// of the revision, and in the newest one, every listed file, see [`link_into_latest`].
+pub(crate) fn snapshot_contents(blobs: Vec<FileInfo>) -> BTreeMap<String, Vec<FileInfo>> {
+    let latest = latest_revision(&blobs);
+    let mut groups = group_by_revision(blobs.clone());
+    if let Some(latest) = latest {
+        groups.insert(latest, blobs);
+    }
+    groups
+}
+
+fn write_latest(repo: &Repo, revision: &str, mode: LatestLink) -> Result<(), OpsError> {
+    let latest = repo.latest_path();
+    let latest_file = latest.with_extension("path");
+    let mut lock = fslock::FsLock::lock(latest.clone())?;
+    let result = (|| -> Result<(), OpsError> {
+        match mode {
+            LatestLink::Symlink => {
+                let staging = latest.with_extension(format!("{}.tmp", std::process::id()));
+                let _ = std::fs::remove_file(&staging);
+                let target = PathBuf::from("snapshots").join(revision);
+                #[cfg(unix)]
+                std::os::unix::fs::symlink(&target, &staging)?;
+                #[cfg(windows)]
+                std::os::windows::fs::symlink_dir(&target, &staging)?;
+                #[cfg(not(any(unix, windows)))]
+                return Err(io::Error::from(io::ErrorKind::Unsupported).into());
+                if let Err(e) = std::fs::rename(&staging, &latest) {
+                    let _ = std::fs::remove_file(&staging);
+                    return Err(e.into());
+                }
+                if std::fs::exists(&latest_file)? {
+                    std::fs::remove_file(&latest_file)?;
+                }
+            }
+            LatestLink::File => {
+                let mut temp = NamedTempFile::new_in(repo.cache_dir())?;
+                temp.write_all(repo.snapshot_path(revision).to_string_lossy().as_bytes())?;
+                temp.persist(&latest_file)
+                    .map_err(|e| OpsError::IoError(e.error))?;
+                if std::fs::symlink_metadata(&latest).is_ok() {
+                    std::fs::remove_file(&latest)?;
+                }
+            }
+        }
+        Ok(())
+    })();
+    lock.unlock();
+    result
+}
+
+/// How far back the speed of a [`ProgressUnit`] looks.
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+/// The least time between two samples of the speed of a [`ProgressUnit`]; closer updates
+/// replace the newest sample.
+const SPEED_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Represents a unit of progress for tracking file downloads.
+///
+/// This struct holds information about the file being downloaded,
+/// including its name, total size, and current progress, along with the timing to report
+/// its speed and time left.
+#[derive(Debug, Clone)]
+pub struct ProgressUnit {
+    filename: String,
+    total_size: Option<u64>,
+    current: u64,
+    started_at: Instant,
+    /// The time and progress of recent updates, oldest first, spanning up to [`SPEED_WINDOW`].
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Default for ProgressUnit {
+    fn default() -> Self {
+        Self {
+            filename: String::new(),
+            total_size: None,
+            current: 0,
+            started_at: Instant::now(),
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl ProgressUnit {
+    /// Creates a new `ProgressUnit` instance.
+    pub fn new(filename: String, total_size: u64) -> Self {
+        Self {
+            filename,
+            total_size: Some(total_size),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a `ProgressUnit` for a download whose size is unknown, such as one the server
+    /// sends without a `Content-Length`.
+    pub fn without_total(filename: String) -> Self {
+        Self {
+            filename,
+            ..Default::default()
+        }
+    }
+
+    /// Updates the current progress of the download, sampling it for
+    /// [`ProgressUnit::bytes_per_second`].
+    pub fn update(&mut self, current: u64) {
+        self.current = current;
+        let now = Instant::now();
+        let recent = match self.samples.len() {
+            0 | 1 => false,
+            n => now.duration_since(self.samples[n - 2].0) < SPEED_SAMPLE_INTERVAL,
+        };
+        if recent {
+            self.samples.pop_back();
+        }
+        self.samples.push_back((now, current));
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= SPEED_WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Retrieves the filename of the file being downloaded.
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Retrieves the total size of the file in bytes, or `None` when it is unknown.
+    pub fn total_size(&self) -> Option<u64> {
+        self.total_size
+    }
+
+    /// Retrieves the current number of bytes downloaded.
+    pub fn current(&self) -> u64 {
+        self.current
+    }
+
+    /// When the unit was created, about when its download started.
+    pub fn started_at(&self) -> Instant {
+        self.started_at
+    }
+
+    /// The time since [`ProgressUnit::started_at`].
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// The download speed over the updates of the last few seconds, or 0 before two updates.
+    ///
+    /// Bytes a resumed download had before its first update are not counted.
+    pub fn bytes_per_second(&self) -> f64 {
+        let (Some(&(first_at, first)), Some(&(last_at, last))) =
+            (self.samples.front(), self.samples.back())
+        else {
+            return 0.0;
+        };
+        let seconds = last_at.duration_since(first_at).as_secs_f64();
+        match seconds > 0.0 {
+            true => last.saturating_sub(first) as f64 / seconds,
+            false => 0.0,
+        }
+    }
+
+    /// The time left at the current [`ProgressUnit::bytes_per_second`], or `None` when the
+    /// total size is unknown or nothing arrived lately.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.total_size?.saturating_sub(self.current);
+        let speed = self.bytes_per_second();
+        match remaining {
+            0 => Some(Duration::ZERO),
+            _ if speed > 0.0 => Some(Duration::from_secs_f64(remaining as f64 / speed)),
+            _ => None,
+        }
+    }
+
+    /// The share of the total size downloaded, from 0 to 1, or 0 when the total size is
+    /// unknown.
+    pub fn fraction(&self) -> f64 {
+        match self.total_size {
+            Some(0) => 1.0,
+            Some(total) => (self.current as f64 / total as f64).min(1.0),
+            None => 0.0,
+        }
+    }
+}
+
+/// The snapshot a revision group of a pull is placed in, shared by the workers placing it.
+struct Target<'a> {
+    snapshot_path: &'a Path,
+    plan: &'a paths::PathPlan,
+    validation: CacheValidation,
+    queued: &'a HashSet<String>,
+}
+
+impl Target<'_> {
+    /// Where `fileinfo` is placed in the snapshot.
+    fn filepath(&self, fileinfo: &FileInfo) -> PathBuf {
+        snapshot_file(self.snapshot_path, self.plan.local_path(fileinfo))
+    }
+
+    /// Checks whether the cached copy of `fileinfo` is current, before locking the snapshot.
+    fn check(&self, fileinfo: &FileInfo) -> Result<Checked, OpsError> {
+        check(&self.filepath(fileinfo), fileinfo, self.validation)
+    }
+
+    /// Whether the cached copy of `fileinfo`, found `checked` before locking the snapshot, is
+    /// current now.
+    fn recheck(&self, fileinfo: &FileInfo, checked: Checked) -> Result<bool, OpsError> {
+        recheck(&self.filepath(fileinfo), fileinfo, self.validation, checked)
+    }
+
+    /// Leaves the current `fileinfo` in place, completing its queued progress.
+    fn skip(
+        &self,
+        cat: &ModelsCat,
+        fileinfo: &FileInfo,
+        progress: &mut Option<impl Progress>,
+    ) -> Result<Placement, OpsError> {
+        if self.queued.contains(&fileinfo.path) {
+            cat.finish_queued(fileinfo, progress)?;
+        }
+        Ok(Placement::Skipped)
+    }
+}
+
+/// What became of a listed file in a pull.
+enum Placement {
+    /// Its cached copy was current.
+    Skipped,
+    /// It was linked from another repo of the cache.
+    Deduplicated,
+    /// It was downloaded, with whether progress reporting stopped early.
+    Downloaded { progress_degraded: bool },
+}
+
+/// Aggregates the progress of byte ranges of one file fetched concurrently, so a segmented
+/// download feeds a single [`ProgressUnit`] (and a single bar) whose `current` is the sum across
+/// all segments.
+///
+/// Clones share the same counters, so each segment worker can hold its own clone and report
+/// the bytes received within its range.
+#[derive(Clone)]
+pub struct SegmentedProgress {
+    unit: Arc<Mutex<ProgressUnit>>,
+    segments: Arc<[AtomicU64]>,
+}
+
+impl SegmentedProgress {
+    /// Creates an aggregator for `segments` byte ranges of a file of `total_size` bytes.
+    pub fn new(filename: String, total_size: u64, segments: usize) -> Self {
+        Self {
+            unit: Arc::new(Mutex::new(ProgressUnit::new(filename, total_size))),
+            segments: (0..segments).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Records the number of bytes received so far within `segment`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `segment` is out of range.
+    pub fn update_segment(&self, segment: usize, current: u64) {
+        self.segments[segment].store(current, Ordering::Relaxed);
+    }
+
+    /// Returns the combined progress of all segments, sampling it for the speed of the unit.
+    pub fn unit(&self) -> ProgressUnit {
+        let mut unit = self.unit.lock().unwrap();
+        unit.update(
+            self.segments
+                .iter()
+                .map(|s| s.load(Ordering::Relaxed))
+                .sum(),
+        );
+        unit.clone()
+    }
+}
+
+/// An owned snapshot of a [`Progress`] callback, for forwarding progress to another thread or
+/// task, e.g. over a channel driving a UI.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// A download started.
+    Start(ProgressUnit),
+    /// A download advanced.
+    Progress(ProgressUnit),
+    /// A download finished.
+    Finish(ProgressUnit),
+}
+
+impl ProgressEvent {
+    /// The progress of the download the event is about.
+    pub fn unit(&self) -> &ProgressUnit {
+        match self {
+            Self::Start(unit) | Self::Progress(unit) | Self::Finish(unit) => unit,
+        }
+    }
+}
+
+/// A trait defining the behavior for progress tracking during file downloads.
+///
+/// This trait allows implementors to handle the start, progress updates, and finish events
+/// of a download operation. It is designed to be thread-safe (`Send + Sync`) and clonable.
+pub trait Progress: Clone + Send + Sync {
+    /// Called when a download starts.
+    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+
+    /// Called periodically to update the progress of a download.
+    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+
+    /// Called when a download finishes.
+    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+
+    /// Called by downloads in place of [`Progress::on_progress`], which stop on
+    /// [`ControlFlow::Break`] and fail with [`OpsError::Cancelled`], removing what was
+    /// transferred, whatever the [`ProgressErrorPolicy`]. Override it to offer a cancel button.
+    ///
+    /// Calls [`Progress::on_progress`] and continues by default.
+    fn on_progress_control(&mut self, unit: &ProgressUnit) -> Result<ControlFlow<()>, OpsError> {
+        self.on_progress(unit).map(|()| ControlFlow::Continue(()))
+    }
+
+    /// Called once before any download of a pull, or of a single file, starts, with every
+    /// file that may be downloaded and its listed size, e.g. to lay out all bars upfront.
+    ///
+    /// A queued file found current or deduplicated once its turn comes gets
+    /// [`Progress::on_finish`] at its full size, without [`Progress::on_start`]. Does nothing
+    /// by default.
+    fn on_queued(&mut self, _units: &[ProgressUnit]) -> Result<(), OpsError> {
+        Ok(())
+    }
+}
+
+/// `bar` moved to where `unit` starts, when it is the bar of the same file, as when a download
+/// is retried.
+pub(crate) fn resume_bar(bar: Option<ProgressBar>, unit: &ProgressUnit) -> Option<ProgressBar> {
+    let bar = bar.filter(|pb| pb.prefix() == unit.filename())?;
+    fit_bar(&bar, unit);
+    bar.set_position(unit.current());
+    Some(bar)
+}
+
+/// A bar for the download of `unit`, prefixed with its filename.
+pub(crate) fn file_bar(unit: &ProgressUnit) -> ProgressBar {
+    let pb = ProgressBar::no_length().with_finish(ProgressFinish::AndLeave);
+    fit_bar(&pb, unit);
+    pb.set_prefix(unit.filename().to_string());
+    pb
+}
+
+/// Sets the length of `pb` to the total size of `unit`, switching to a spinner counting bytes
+/// when the size is unknown.
+pub(crate) fn fit_bar(pb: &ProgressBar, unit: &ProgressUnit) {
+    match unit.total_size() {
+        Some(total_size) => {
+            pb.set_length(total_size);
+            pb.set_style(ProgressStyle::with_template("{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                .unwrap()
+                .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+                .progress_chars("#>-"));
+        }
+        None => {
+            pb.unset_length();
+            pb.set_style(
+                ProgressStyle::with_template(
+                    "{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] {bytes} ({bytes_per_sec})",
+                )
+                .unwrap(),
+            );
+        }
+    }
+}
+
+/// A wrapper around a single [`ProgressBar`] for tracking progress during file downloads.
+///
+/// This struct implements the [`Progress`] trait and provides methods to handle the start,
+/// progress updates, and finish events of a download operation.
+#[derive(Default, Clone)]
+pub struct ProgressBarWrapper(Option<ProgressBar>);
+
+impl Progress for ProgressBarWrapper {
+    /// Called when a download starts.
+    ///
+    /// Initializes the progress bar with the total size of the file being downloaded.
+    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.0 = Some(resume_bar(self.0.take(), unit).unwrap_or_else(|| file_bar(unit)));
+        Ok(())
+    }
+
+    /// Called periodically to update the progress of a download.
+    ///
+    /// Updates the position of the progress bar based on the current bytes downloaded.
+    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        if let Some(ref pb) = self.0 {
+            pb.set_position(unit.current());
+        }
+        Ok(())
+    }
+
+    /// Called when a download finishes.
+    ///
+    /// Ensures the progress bar reflects the final downloaded bytes.
+    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        if let Some(ref pb) = self.0 {
+            pb.set_position(unit.current());
+        }
+        Ok(())
+    }
+}
+
+/// A wrapper around `MultiProgressBar` for tracking multiple progress bars during file downloads.
+///
+/// This struct implements the `Progress` trait and provides methods to handle the start,
+/// progress updates, and finish events of multiple download operations simultaneously.
+#[derive(Default, Clone)]
+pub struct MultiProgressWrapper {
+    current_bar: Option<ProgressBar>,
+    /// Bars created for queued files, by filename, until their download starts.
+    queued: HashMap<String, ProgressBar>,
+    inner: MultiProgressBar,
+}
+
+impl MultiProgressWrapper {
+    /// Creates a new `MultiProgressWrapper` instance.
+    pub fn new() -> Self {
+        Self {
+            current_bar: None,
+            queued: HashMap::new(),
+            inner: MultiProgressBar::new(),
+        }
+    }
+}
+
+impl Progress for MultiProgressWrapper {
+    /// Called before any download of a pull or download starts.
+    ///
+    /// Adds a bar for every queued file, so all of them are laid out upfront.
+    fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+        for unit in units {
+            let pb = self.inner.add(file_bar(unit));
+            self.queued.insert(unit.filename().to_string(), pb);
+        }
+        Ok(())
+    }
+
+    /// Called when a download starts.
+    ///
+    /// Initializes a new progress bar within the multi-progress bar system.
+    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        let pb = match self.queued.remove(unit.filename()) {
+            Some(pb) => {
+                fit_bar(&pb, unit);
+                pb
+            }
+            None => resume_bar(self.current_bar.take(), unit)
+                .unwrap_or_else(|| self.inner.add(file_bar(unit))),
+        };
+        self.current_bar = Some(pb);
+        Ok(())
+    }
+
+    /// Called periodically to update the progress of a download.
+    ///
+    /// Updates the position of the current progress bar based on the downloaded bytes.
+    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        if let Some(ref pb) = self.current_bar {
+            pb.set_position(unit.current());
+        }
+        Ok(())
+    }
+
+    /// Called when a download finishes.
+    ///
+    /// Ensures the current progress bar reflects the final downloaded bytes, or completes the
+    /// bar of a queued file that needed no download.
+    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        if let Some(pb) = self.queued.remove(unit.filename()) {
+            pb.set_position(unit.current());
+        } else if let Some(ref pb) = self.current_bar {
+            pb.set_position(unit.current());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::{MockServer, Response};
+
+    fn test_repo(cache: &std::path::Path) -> Repo {
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache);
+        repo
+    }
+
+    /// Serves each blob's content at its resolve URL; paths listed in `truncated` drop the
+    /// connection mid-transfer.
+    fn file_server(files: &[(&str, &[u8])], truncated: &[&str]) -> MockServer {
+        let files: Vec<(String, Vec<u8>)> = files
+            .iter()
+            .map(|(p, c)| (format!("/resolve/master/{p}"), c.to_vec()))
+            .collect();
+        let truncated: Vec<String> = truncated
+            .iter()
+            .map(|p| format!("/resolve/master/{p}"))
+            .collect();
+        MockServer::start(move |req| {
+            match files.iter().find(|(p, _)| req.path.ends_with(p.as_str())) {
+                Some((p, content)) if truncated.contains(p) => {
+                    Response::ok(&content[..1]).truncated(content.len() as u64)
+                }
+                Some((_, content)) => Response::ok(content.clone()),
+                None => Response::status(404),
+            }
+        })
+    }
+
+    #[test]
+    fn test_pull_updates_latest() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa"), ("b/c.txt", b"ccc")], &[]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let blobs = vec![
+            FileInfo::test_blob("a.txt", b"aaa", "rev1"),
+            FileInfo::test_blob("b/c.txt", b"ccc", "rev1"),
+        ];
+        let report = cat
+            .pull_files(blobs, None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.downloaded.len(), 2);
+
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(cat.repo().latest_snapshot().unwrap(), snapshot);
+        #[cfg(unix)]
+        assert!(
+            std::fs::symlink_metadata(cat.repo().latest_path())
+                .unwrap()
+                .is_symlink()
+        );
+        assert_eq!(
+            std::fs::read(cat.repo().latest_path().join("b/c.txt")).unwrap(),
+            b"ccc"
+        );
+    }
+
+    #[test]
+    fn test_failed_pull_keeps_latest() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], &["b.txt"]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull_files(
+            vec![FileInfo::test_blob("a.txt", b"aaa", "rev1")],
+            None::<ProgressBarWrapper>,
+            None,
+        )
+        .unwrap();
+
+        let blobs = vec![
+            FileInfo::test_blob("a.txt", b"aaa", "rev2"),
+            FileInfo::test_blob("b.txt", b"bbb", "rev2"),
+        ];
+        assert!(
+            cat.pull_files(blobs, None::<ProgressBarWrapper>, None)
+                .is_err()
+        );
+        assert_eq!(
+            cat.repo().latest_snapshot().unwrap(),
+            cat.repo().snapshot_path("rev1")
+        );
+        // Consumers resolving the branch offline still get the old, complete snapshot.
+        assert_eq!(cat.repo().cached_commit().as_deref(), Some("rev1"));
+        let files = cat.local_files_matching("*.txt").unwrap();
+        let old = std::path::absolute(cat.repo().snapshot_path("rev1")).unwrap();
+        assert_eq!(files, [old.join("a.txt")]);
+
+        let server = file_server(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], &[]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let blobs = vec![
+            FileInfo::test_blob("a.txt", b"aaa", "rev2"),
+            FileInfo::test_blob("b.txt", b"bbb", "rev2"),
+        ];
+        cat.pull_files(blobs.clone(), None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(cat.repo().cached_commit().as_deref(), Some("rev2"));
+
+        // A pull finding every file current writes the ref all the same.
+        let ref_path = cat.repo().ref_path().unwrap();
+        std::fs::remove_file(&ref_path).unwrap();
+        let report = cat
+            .pull_files(blobs, None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.skipped, ["a.txt", "b.txt"]);
+        assert_eq!(std::fs::read_to_string(ref_path).unwrap(), "rev2");
+    }
+
+    #[test]
+    fn test_download_writes_ref() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.download("a.txt").unwrap();
+        let ref_path = cat.repo().ref_path().unwrap();
+        assert_eq!(std::fs::read_to_string(&ref_path).unwrap(), "rev1");
+
+        // A download skipped because the sha256 of the cached copy matches writes it too.
+        std::fs::remove_file(&ref_path).unwrap();
+        let options = DownloadOptions::default().with_validation(CacheValidation::Sha256);
+        cat.download_with_options("a.txt", options).unwrap();
+        assert_eq!(std::fs::read_to_string(&ref_path).unwrap(), "rev1");
+        assert_eq!(cat.repo().cached_commit().as_deref(), Some("rev1"));
+    }
+
+    #[test]
+    fn test_failed_download_keeps_old_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let filepath = dir.path().join("a.txt");
+        std::fs::write(&filepath, "old").unwrap();
+        let cat = ModelsCat::new(test_repo(&dir.path().join("cache")));
+        let file = HubFileInfo::from(&FileInfo::test_blob("a.txt", b"new content", "master"));
+        let server = file_server(&[("a.txt", b"new content")], &["a.txt"]);
+        let url = format!("{}/resolve/master/a.txt", server.url());
+
+        assert!(
+            cat.download_file(&url, &filepath, &file, &mut None::<ProgressBarWrapper>)
+                .is_err()
+        );
+        assert_eq!(std::fs::read_to_string(&filepath).unwrap(), "old");
+        // What arrived is kept next to it, with its sidecar, for the retry to resume from.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        let server = file_server(&[("a.txt", b"new content")], &[]);
+        let url = format!("{}/resolve/master/a.txt", server.url());
+        cat.download_file(&url, &filepath, &file, &mut None::<ProgressBarWrapper>)
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&filepath).unwrap(), "new content");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_resume_per_revision() {
+        let cache = tempfile::tempdir().unwrap();
+        let repo = test_repo(cache.path());
+        let blobs = || {
+            vec![
+                FileInfo::test_blob("a.txt", b"aaa", "rev-a"),
+                FileInfo::test_blob("b.txt", b"bbb", "rev-b"),
+                FileInfo::test_blob("c.txt", b"ccc", "rev-b"),
+            ]
+        };
+
+        let server = file_server(
+            &[("a.txt", b"aaa"), ("b.txt", b"bbb"), ("c.txt", b"ccc")],
+            &["c.txt"],
+        );
+        let cat = ModelsCat::new_with_endpoint(repo.clone(), server.url());
+        assert!(
+            cat.pull_files(blobs(), None::<ProgressBarWrapper>, None)
+                .is_err()
+        );
+        // rev-a finished, rev-b did not, although b.txt of rev-b was downloaded.
+        assert_eq!(
+            completed_files(&repo, "rev-a"),
+            HashSet::from(["a.txt".to_string()])
+        );
+        assert!(completed_files(&repo, "rev-b").is_empty());
+        assert!(repo.latest_snapshot().is_none());
+
+        // Corrupt a.txt without changing its size: a completed revision is still validated.
+        std::fs::write(repo.snapshot_path("rev-a").join("a.txt"), "xxx").unwrap();
+        let server = file_server(
+            &[("a.txt", b"aaa"), ("b.txt", b"bbb"), ("c.txt", b"ccc")],
+            &[],
+        );
+        let cat = ModelsCat::new_with_endpoint(repo.clone(), server.url());
+        let report = cat
+            .pull_files(blobs(), None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.skipped, ["b.txt"]);
+        assert_eq!(report.downloaded, ["a.txt", "c.txt"]);
+        assert_eq!(
+            std::fs::read(repo.snapshot_path("rev-a").join("a.txt")).unwrap(),
+            b"aaa"
+        );
+        assert_eq!(completed_files(&repo, "rev-b").len(), 2);
+        // The snapshot `latest` and the ref resolve to holds every file, not only those of rev-b.
+        let latest = repo.latest_snapshot().unwrap();
+        assert_eq!(latest, repo.snapshot_path("rev-b"));
+        assert_eq!(repo.revision_snapshot(), Some(latest.clone()));
+        for (name, content) in [("a.txt", b"aaa"), ("b.txt", b"bbb"), ("c.txt", b"ccc")] {
+            assert_eq!(std::fs::read(latest.join(name)).unwrap(), content);
+        }
+        // Nor is a.txt, linked in, an extra file of rev-b to verify or sync away.
+        let (_, extra) = verify::plan(&repo, blobs()).unwrap();
+        assert!(extra.is_empty());
+    }
+
+    #[test]
+    fn test_size_fallback_without_sha256() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa")], &[]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let blob = |content: &[u8]| FileInfo {
+            sha256: None,
+            ..FileInfo::test_blob("a.txt", content, "rev1")
+        };
+
+        let report = cat
+            .pull_files(vec![blob(b"aaa")], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.downloaded, ["a.txt"]);
+        std::fs::remove_dir_all(
+            cache
+                .path()
+                .join("models--BAAI--bge-small-zh-v1.5/complete"),
+        )
+        .unwrap();
+
+        let report = cat
+            .pull_files(vec![blob(b"aaa")], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.skipped, ["a.txt"]);
+        let report = cat
+            .pull_files(vec![blob(b"aaaa")], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.downloaded, ["a.txt"]);
+    }
+
+    #[test]
+    fn test_commit_date_validation() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa")], &[]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_cache_validation(CacheValidation::CommitDate);
+        let filepath = cat.repo().snapshot_path("rev1").join("a.txt");
+        let blob = |committed_date| FileInfo {
+            committed_date,
+            ..FileInfo::test_blob("a.txt", b"aaa", "rev1")
+        };
+
+        cat.pull_files(vec![blob(0)], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        std::fs::remove_dir_all(cat.repo().cache_dir().join("complete")).unwrap();
+        // Same size, different content: accepted without hashing.
+        std::fs::write(&filepath, "bbb").unwrap();
+        let report = cat
+            .pull_files(vec![blob(0)], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.skipped, ["a.txt"]);
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"bbb");
+
+        // Committed after the local copy was written.
+        let committed = std::time::SystemTime::now() + Duration::from_secs(3600);
+        let committed = committed.duration_since(std::time::UNIX_EPOCH).unwrap();
+        std::fs::remove_dir_all(cat.repo().cache_dir().join("complete")).unwrap();
+        let report = cat
+            .pull_files(
+                vec![blob(committed.as_secs() as i64)],
+                None::<ProgressBarWrapper>,
+                None,
+            )
+            .unwrap();
+        assert_eq!(report.downloaded, ["a.txt"]);
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+    }
+
+    #[test]
+    fn test_validation_override() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_cache_validation(CacheValidation::CommitDate);
+        cat.download("a.txt").unwrap();
+        let filepath = cat.repo().snapshot_path("rev1").join("a.txt");
+        std::fs::write(&filepath, "bbb").unwrap();
+
+        assert!(
+            cat.verify_with_level("a.txt", CacheValidation::CommitDate)
+                .unwrap()
+        );
+        assert!(
+            !cat.verify_with_level("a.txt", CacheValidation::Sha256)
+                .unwrap()
+        );
+        let report = cat.pull().unwrap();
+        assert_eq!(report.validation, CacheValidation::CommitDate);
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"bbb");
+
+        let options = DownloadOptions::default().with_validation(CacheValidation::Sha256);
+        cat.download_with_options("a.txt", options).unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+        std::fs::write(&filepath, "bbb").unwrap();
+        std::fs::remove_dir_all(cat.repo().cache_dir().join("complete")).unwrap();
+        let options = PullOptions::default().with_validation(CacheValidation::Sha256);
+        let report = cat.pull_with_options(&options).unwrap();
+        assert_eq!(report.validation, CacheValidation::Sha256);
+        assert_eq!(report.downloaded, ["a.txt"]);
+    }
+
+    #[test]
+    fn test_force_download() {
+        use crate::test_server::{MockServer, Response};
+
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let filepath = cat.download("a.txt").unwrap();
+        std::fs::write(&filepath, "bbb").unwrap();
+        cat.download("a.txt").unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"bbb");
+
+        // Restores the file even though the cached copy counts as current.
+        let options = DownloadOptions::default().with_force_download(true);
+        let progress = RecordingProgress::default();
+        cat.download_with_options_and_progress("a.txt", options.clone(), progress.clone())
+            .unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+        assert_eq!(
+            progress.take(),
+            ["queued:a.txt=3", "start:a.txt", "finish:a.txt"]
+        );
+        let offline =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_offline(true);
+        assert!(matches!(
+            offline.download_with_options("a.txt", options.clone()),
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
+
+        // Without verifying, content other than listed is kept, but no longer as complete.
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            Response::ok(b"aab".to_vec())
+        });
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        assert!(matches!(
+            cat.download_with_options("a.txt", options.clone()),
+            Err(OpsError::ChecksumMismatch { .. })
+        ));
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+        cat.download_with_options("a.txt", options.with_verify(false).with_resume(false))
+            .unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aab");
+        assert!(completed_files(cat.repo(), "rev1").is_empty());
+    }
+
+    #[test]
+    fn test_pull_to_tar() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"aaa"), ("dir/b.txt", b"bbbb")];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let entries = |archive: &mut tar::Archive<&mut dyn Read>| {
+            let mut entries = Vec::new();
+            for entry in archive.entries().unwrap() {
+                let mut entry = entry.unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                entries.push((entry.path().unwrap().display().to_string(), content));
+            }
+            entries
+        };
+        let expected = [
+            ("a.txt".into(), "aaa".into()),
+            ("dir/b.txt".into(), "bbbb".into()),
+        ];
+
+        let mut tar = Vec::new();
+        let archived = cat.pull_to_tar(&mut tar, ArchiveCompression::None).unwrap();
+        assert_eq!(archived, ["a.txt", "dir/b.txt"]);
+        assert_eq!(entries(&mut tar::Archive::new(&mut &tar[..])), expected);
+        let mut gz = Vec::new();
+        cat.pull_to_tar(&mut gz, ArchiveCompression::Gzip).unwrap();
+        let mut decoder = flate2::read::GzDecoder::new(&gz[..]);
+        assert_eq!(entries(&mut tar::Archive::new(&mut decoder)), expected);
+        // Nothing lands in the cache.
+        assert!(!cat.repo().snapshot_path("rev1").exists());
+
+        let corrupt = FileInfo {
+            sha256: Some("0".repeat(64)),
+            ..FileInfo::test_blob("a.txt", b"aaa", "rev1")
+        };
+        assert!(cat.write_tar(Vec::new(), &[corrupt]).is_err());
+    }
+
+    #[test]
+    fn test_pull_order_is_stable() {
+        let files: [(&str, &[u8]); 4] = [
+            ("b/config.json", b"{}"),
+            ("a.txt", b"aaa"),
+            ("README.md", b"readme"),
+            ("b/a.bin", b"bin"),
+        ];
+        let pull_log = |files: &[(&str, &[u8])]| {
+            let cache = tempfile::tempdir().unwrap();
+            let (server, _) = ms_hub::test_hub(files, "rev1");
+            let lines = Arc::new(Mutex::new(Vec::new()));
+            let sink = lines.clone();
+            let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+                .with_logger(Box::new(move |line| {
+                    sink.lock().unwrap().push(line.to_string())
+                }));
+            let report = cat.pull().unwrap();
+            assert_eq!(
+                report.downloaded,
+                ["README.md", "a.txt", "b/a.bin", "b/config.json"]
+            );
+            lines.lock().unwrap().clone()
+        };
+
+        let mut shuffled = files;
+        shuffled.reverse();
+        shuffled.swap(0, 2);
+        assert_eq!(pull_log(&files), pull_log(&shuffled));
+    }
+
+    #[test]
+    fn test_skip_hidden() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 4] = [
+            (".gitattributes", b"*.bin lfs"),
+            (".mdl/meta", b"meta"),
+            ("a.txt", b"aaa"),
+            ("dir/.keep", b""),
+        ];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+
+        let options = PullOptions::default().with_skip_hidden(true);
+        let report = cat.pull_with_options(&options).unwrap();
+        assert_eq!(report.downloaded, ["a.txt"]);
+        assert_eq!(report.hidden, [".gitattributes", ".mdl/meta", "dir/.keep"]);
+        assert!(
+            !cat.repo()
+                .snapshot_path("rev1")
+                .join(".gitattributes")
+                .exists()
+        );
+
+        let report = cat.pull().unwrap();
+        assert_eq!(report.downloaded.len(), 3);
+        assert!(report.hidden.is_empty());
+    }
+
+    #[test]
+    fn test_pull_with_patterns() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 5] = [
+            ("model.safetensors", b"st"),
+            ("pytorch_model.bin", b"bin"),
+            ("tokenizer.json", b"{}"),
+            ("onnx/model.onnx", b"onnx"),
+            ("onnx/config.json", b"{}"),
+        ];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+
+        // `*` matches across directories, and a trailing `/` stands for the whole directory.
+        let (allow, ignore) = (["*.safetensors", "*.json"], ["onnx/"]);
+        assert_eq!(
+            cat.list_hub_files_with_patterns(&allow, &ignore).unwrap(),
+            ["model.safetensors", "tokenizer.json"]
+        );
+        let report = cat.pull_with_patterns(&allow, &ignore).unwrap();
+        assert_eq!(report.downloaded, ["model.safetensors", "tokenizer.json"]);
+        assert!(
+            !cat.repo()
+                .snapshot_path("rev1")
+                .join("pytorch_model.bin")
+                .exists()
+        );
+        let report = cat.pull_with_patterns(&["onnx/*"], &[]).unwrap();
+        assert_eq!(report.downloaded, ["onnx/config.json", "onnx/model.onnx"]);
+
+        assert!(matches!(
+            cat.pull_with_patterns(&["*.gguf"], &[]),
+            Err(OpsError::HubError(_))
+        ));
+        assert!(
+            cat.list_hub_files_with_patterns(&["*.gguf"], &[])
+                .unwrap()
+                .is_empty()
+        );
+        assert!(matches!(
+            cat.pull_with_patterns(&["[model"], &[]),
+            Err(OpsError::BuildError(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_pull_options_serde() {
+        let options = PullOptions::default()
+            .with_validation(CacheValidation::Sha256)
+            .with_skip_hidden(true);
+        let toml = toml::to_string(&options).unwrap();
+        assert_eq!(toml, "validation = \"sha256\"\nskip_hidden = true\n");
+        assert_eq!(toml::from_str::<PullOptions>(&toml).unwrap(), options);
+        let json = serde_json::to_string(&options).unwrap();
+        assert_eq!(serde_json::from_str::<PullOptions>(&json).unwrap(), options);
+        assert_eq!(
+            serde_json::from_str::<PullOptions>("{}").unwrap(),
+            PullOptions::default()
+        );
+        let err = serde_json::from_str::<PullOptions>(r#"{"skip_hiden": true}"#).unwrap_err();
+        assert!(err.to_string().contains("unknown field `skip_hiden`"));
+    }
+
+    #[test]
+    fn test_invalid_options_fail_early() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let out = tempfile::tempdir().unwrap();
+        let options = DownloadOptions::default().with_save_as("../a.txt");
+        assert!(matches!(
+            cat.download_to("a.txt", out.path(), options),
+            Err(OpsError::BuildError(_))
+        ));
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 0);
+        assert!(!cat.repo().snapshot_path("rev1").exists());
+    }
+
+    #[test]
+    fn test_validators() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"aaa"), ("b.bin", b"EVIL")];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let seen = order.clone();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_validator(move |path: &Path, info: &HubFileInfo| {
+                assert_ne!(path.file_name().unwrap(), info.path.as_str());
+                seen.lock().unwrap().push(info.path.clone());
+                Ok(())
+            })
+            .with_validator(|path: &Path, _: &HubFileInfo| {
+                match std::fs::read(path).unwrap().starts_with(b"EVIL") {
+                    true => Err(ValidationError::new("malware found")),
+                    false => Ok(()),
+                }
+            });
+
+        cat.download("a.txt").unwrap();
+        let err = cat.download("b.bin").unwrap_err();
+        assert!(matches!(
+            err,
+            OpsError::ValidationFailed { ref file, ref reason }
+                if file == "b.bin" && reason == "malware found"
+        ));
+        assert_eq!(*order.lock().unwrap(), ["a.txt", "b.bin"]);
+        let snapshot = cat.repo().snapshot_path("rev1");
+        let names: Vec<_> = std::fs::read_dir(&snapshot)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["a.txt"]);
+    }
+
+    #[test]
+    fn test_latest_link_modes() {
+        let cache = tempfile::tempdir().unwrap();
+        let repo = test_repo(cache.path());
+        std::fs::create_dir_all(repo.snapshot_path("rev1")).unwrap();
+
+        write_latest(&repo, "rev1", LatestLink::File).unwrap();
+        assert!(std::fs::symlink_metadata(repo.latest_path()).is_err());
+        assert_eq!(repo.latest_snapshot().unwrap(), repo.snapshot_path("rev1"));
+
+        write_latest(&repo, "rev2", LatestLink::Symlink).unwrap();
+        assert!(!std::fs::exists(repo.latest_path().with_extension("path")).unwrap());
+        assert_eq!(repo.latest_snapshot().unwrap(), repo.snapshot_path("rev2"));
+
+        write_latest(&repo, "rev3", LatestLink::File).unwrap();
+        assert_eq!(repo.latest_snapshot().unwrap(), repo.snapshot_path("rev3"));
+    }
+
+    #[test]
+    fn test_segmented_progress() {
+        let progress = SegmentedProgress::new("model.safetensors".to_string(), 300, 3);
+        std::thread::scope(|scope| {
+            for segment in 0..3 {
+                let progress = progress.clone();
+                scope.spawn(move || {
+                    for current in [25, 50, 100] {
+                        progress.update_segment(segment, current);
+                    }
+                });
+            }
+        });
+        let unit = progress.unit();
+        assert_eq!(unit.filename(), "model.safetensors");
+        assert_eq!(unit.total_size(), Some(300));
+        assert_eq!(unit.current(), 300);
+    }
+
+    #[test]
+    fn test_progress_unit_speed() {
+        let mut unit = ProgressUnit::new("a.bin".into(), 4_000_000);
+        assert_eq!(unit.bytes_per_second(), 0.0);
+        assert_eq!(unit.eta(), None);
+        unit.update(1_000_000);
+        std::thread::sleep(Duration::from_millis(300));
+        unit.update(1_300_000);
+        std::thread::sleep(Duration::from_millis(300));
+        unit.update(1_600_000);
+
+        // The bytes before the first update, such as those of a resumed download, do not count.
+        let speed = unit.bytes_per_second();
+        assert!((500_000.0..=1_000_000.0).contains(&speed), "{speed}");
+        let eta = unit.eta().unwrap();
+        assert!(
+            eta > Duration::from_secs(2) && eta <= Duration::from_secs(5),
+            "{eta:?}"
+        );
+        assert_eq!(unit.fraction(), 0.4);
+        assert!(unit.elapsed() >= Duration::from_millis(600));
+
+        unit.update(4_000_000);
+        assert_eq!(unit.eta(), Some(Duration::ZERO));
+        assert_eq!(ProgressUnit::without_total("a.bin".into()).fraction(), 0.0);
+    }
+
+    #[test]
+    fn test_export_to() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa"), ("b/c.txt", b"ccc")], &[]);
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let out = tempfile::tempdir().unwrap();
+        assert!(
+            cat.export_to(out.path(), &BTreeMap::new(), ExportMode::Copy)
+                .is_err()
+        );
+
+        let blobs = vec![
+            FileInfo::test_blob("a.txt", b"aaa", "rev1"),
+            FileInfo::test_blob("b/c.txt", b"ccc", "rev1"),
+        ];
+        cat.pull_files(blobs, None::<ProgressBarWrapper>, None)
+            .unwrap();
+        let rename = BTreeMap::from([("b/c.txt".to_string(), "alias.txt".to_string())]);
+        let paths = cat
+            .export_to(out.path(), &rename, ExportMode::Copy)
+            .unwrap();
+        assert_eq!(
+            paths,
+            vec![out.path().join("a.txt"), out.path().join("alias.txt")]
+        );
+        assert_eq!(std::fs::read(&paths[1]).unwrap(), b"ccc");
+        let cached = cat.repo().snapshot_path("rev1").join("b/c.txt");
+        assert!(cached.exists());
+
+        // Writing to a copy leaves the cache alone, unlike writing to a link.
+        std::fs::write(&paths[1], "changed").unwrap();
+        assert_eq!(std::fs::read(&cached).unwrap(), b"ccc");
+        let linked = tempfile::tempdir().unwrap();
+        let paths = cat
+            .export_to(linked.path(), &rename, ExportMode::Hardlink)
+            .unwrap();
+        std::fs::write(&paths[1], "changed").unwrap();
+        assert_eq!(std::fs::read(&cached).unwrap(), b"changed");
+    }
+
+    #[test]
+    fn test_export_snapshot() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) =
+            ms_hub::test_hub(&[("a.txt", b"aaa"), ("gguf/model.gguf", b"gguf")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let out = tempfile::tempdir().unwrap();
+        assert!(
+            cat.export_snapshot(out.path(), ExportMode::Copy, false)
+                .is_err()
+        );
+        cat.pull().unwrap();
+
+        let expected = vec![out.path().join("a.txt"), out.path().join("gguf/model.gguf")];
+        for mode in [ExportMode::Copy, ExportMode::Hardlink, ExportMode::Symlink] {
+            let paths = cat.export_snapshot(out.path(), mode, false).unwrap();
+            assert_eq!(paths, expected);
+            assert_eq!(std::fs::read(&paths[1]).unwrap(), b"gguf");
+            assert_eq!(paths[1].is_symlink(), mode == ExportMode::Symlink);
         }
 
-        Ok(files)
+        // A file of other content is only replaced with `overwrite`, and nothing is placed
+        // before that is checked.
+        for path in &expected {
+            std::fs::remove_file(path).unwrap();
+        }
+        std::fs::write(&expected[1], "edited").unwrap();
+        assert!(matches!(
+            cat.export_snapshot(out.path(), ExportMode::Copy, false),
+            Err(OpsError::BuildError(_))
+        ));
+        assert!(!expected[0].exists());
+        cat.export_snapshot(out.path(), ExportMode::Copy, true)
+            .unwrap();
+        assert_eq!(std::fs::read(&expected[1]).unwrap(), b"gguf");
+        let cached = cat.repo().snapshot_path("rev1").join("gguf/model.gguf");
+        assert_eq!(std::fs::read(cached).unwrap(), b"gguf");
     }
 
-    /// Remove all files in the repo
-    pub fn remove_all(&self) -> Result<(), OpsError> {
-        std::fs::remove_dir_all(self.repo.cache_dir())?;
-        Ok(())
+    #[test]
+    fn test_download_to_writer() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let server = MockServer::start(move |req| match req.path.rsplit('/').next() {
+            Some("a.txt") => Response::ok(b"aaa".to_vec()),
+            Some("b.txt") => Response::ok(b"bab".to_vec()),
+            _ => Response::ok(listing.clone()),
+        });
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let progress = RecordingProgress::default();
+
+        let mut sink = Vec::new();
+        let written = cat
+            .download_to_writer("a.txt", &mut sink, Some(progress.clone()))
+            .unwrap();
+        assert_eq!((written, sink.as_slice()), (3, b"aaa".as_slice()));
+        assert_eq!(
+            progress.take(),
+            ["queued:a.txt=3", "start:a.txt", "finish:a.txt"]
+        );
+        let err = cat
+            .download_to_writer("b.txt", Vec::new(), None::<ProgressBarWrapper>)
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match its listed sha256"));
+        assert!(!cat.repo().cache_dir().exists());
+
+        let offline = cat.with_offline(true);
+        assert!(matches!(
+            offline.download_to_writer("a.txt", Vec::new(), None::<ProgressBarWrapper>),
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
     }
 
-    /// Remove a file in the repo
-    pub fn remove(&self, filename: &str) -> Result<(), OpsError> {
-        let base_path = self.repo.cache_dir().join("snapshots");
+    #[test]
+    fn test_list_hub_files_detailed() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b/c.txt", b"cccc")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
 
-        for entry in walkdir::WalkDir::new(&base_path)
-            .min_depth(2) // 跳过snapshots根目录
-            .max_depth(10) // 限制遍历深度 // 限制遍历深度：repo_path/<snapshot>/<file_path>
+        let files = cat.list_hub_files_detailed().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[1].path, "b/c.txt");
+        assert_eq!(files[1].size, 4);
+        assert_eq!(files[1].revision, "rev1");
+        assert_eq!(
+            files[1].sha256,
+            FileInfo::test_blob("b/c.txt", b"cccc", "rev1").sha256
+        );
+        assert!(!files[1].is_lfs);
+    }
+
+    #[test]
+    fn test_estimated_pull_size() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"aaa"), ("b.bin", b"bbbb"), ("c.bin", b"cc")];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let bins = PullOptions::default().with_allow_patterns(["*.bin"]);
+
+        assert_eq!(cat.estimated_pull_size().unwrap(), 9);
+        assert_eq!(
+            cat.estimated_pull_size_with_options(&bins, true).unwrap(),
+            6
+        );
+        cat.download("b.bin").unwrap();
+        assert_eq!(
+            cat.estimated_pull_size_with_options(&bins, true).unwrap(),
+            2
+        );
+        assert_eq!(
+            cat.estimated_pull_size_with_options(&bins, false).unwrap(),
+            6
+        );
+        std::fs::write(cat.repo.snapshot_path("rev1").join("b.bin"), b"xxxx").unwrap();
+        assert_eq!(
+            cat.estimated_pull_size_with_options(&bins, true).unwrap(),
+            6
+        );
+    }
+
+    #[test]
+    fn test_sync() {
+        let cache = tempfile::tempdir().unwrap();
+        let before: [(&str, &[u8]); 3] = [("a.txt", b"aaa"), ("dir/b.bin", b"bb"), ("c.txt", b"c")];
+        let (server, _) = ms_hub::test_hub(&before, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        let snapshot = cat.repo.snapshot_path("rev1");
+        let outside = cache.path().join("outside.txt");
+        std::fs::write(&outside, b"keep").unwrap();
+
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("c.txt", b"cc")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let report = cat.sync(true).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.pull.downloaded, ["c.txt"]);
+        assert_eq!(report.pull.skipped, ["a.txt"]);
+        assert_eq!(report.deleted, ["dir/b.bin"]);
+        assert_eq!(report.deleted_bytes, 2);
+        assert!(snapshot.join("dir/b.bin").exists());
+
+        let report = cat.sync(false).unwrap();
+        assert_eq!(report.pull.downloaded, ["c.txt"]);
+        assert_eq!(report.deleted, ["dir/b.bin"]);
+        assert!(!snapshot.join("dir").exists());
+        assert_eq!(std::fs::read(snapshot.join("c.txt")).unwrap(), b"cc");
+        assert!(outside.exists());
+        assert!(cat.sync(false).unwrap().deleted.is_empty());
+    }
+
+    #[test]
+    fn test_pull_to() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b/c.txt", b"ccc")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let out = tempfile::tempdir().unwrap();
+
+        let paths = cat.pull_to(out.path()).unwrap();
+        assert_eq!(
+            paths,
+            vec![out.path().join("a.txt"), out.path().join("b/c.txt")]
+        );
+        assert_eq!(std::fs::read(&paths[1]).unwrap(), b"ccc");
+        std::fs::write(&paths[0], "bbb").unwrap();
+        let options = DownloadOptions::default().with_save_as("alias.txt");
+        let alias = cat.download_to("b/c.txt", out.path(), options).unwrap();
+        assert_eq!(alias, out.path().join("alias.txt"));
+        // Everything is downloaded again, and nothing but the files is left behind.
+        cat.pull_to(out.path()).unwrap();
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), b"aaa");
+        let written = walkdir::WalkDir::new(out.path())
             .into_iter()
             .filter_map(|e| e.ok())
-        {
-            if entry.file_type().is_file() {
-                let rel_path = entry
-                    .path()
-                    .strip_prefix(&base_path)
-                    .map_err(|e| OpsError::HubError(e.to_string()))?
-                    .components()
-                    .skip(1) // 跳过commit hash目录
-                    .collect::<PathBuf>();
+            .filter(|e| e.file_type().is_file())
+            .count();
+        assert_eq!(written, 3);
+        assert!(!cat.repo().cache_dir().exists());
+
+        // Offline, the listing fetched above is not enough to download anything.
+        let offline = cat.with_offline(true);
+        assert!(matches!(
+            offline.download_to("a.txt", out.path(), DownloadOptions::default()),
+            Err(OpsError::OfflineMiss { ref file }) if file == "a.txt"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_path_policy() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("ok.txt", b"ok"), ("a:b.txt", b"ab")], &[]);
+        let hostile = || {
+            vec![
+                FileInfo::test_blob("ok.txt", b"ok", "rev1"),
+                FileInfo::test_blob("a:b.txt", b"ab", "rev1"),
+            ]
+        };
+        let mut cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.path_rules = PathRules {
+            windows: true,
+            case_insensitive: true,
+        };
+        let snapshot = cat.repo().snapshot_path("rev1");
+
+        let err = cat.pull_files(hostile(), None::<ProgressBarWrapper>, None);
+        assert!(err.unwrap_err().to_string().contains("`a:b.txt`"));
+        assert!(!snapshot.join("ok.txt").exists());
+
+        cat.invalid_paths = InvalidPathPolicy::Skip;
+        let report = cat
+            .pull_files(hostile(), None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.downloaded, ["ok.txt"]);
+        assert_eq!(report.invalid, ["a:b.txt"]);
+
+        cat.invalid_paths = InvalidPathPolicy::Sanitize;
+        let report = cat
+            .pull_files(hostile(), None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(report.downloaded, ["a:b.txt"]);
+        assert_eq!(std::fs::read(snapshot.join("a%3Ab.txt")).unwrap(), b"ab");
+
+        let out = tempfile::tempdir().unwrap();
+        let rename = BTreeMap::from([("a:b.txt".to_string(), "ab.txt".to_string())]);
+        cat.export_to(out.path(), &rename, ExportMode::Copy)
+            .unwrap();
+        assert_eq!(std::fs::read(out.path().join("ab.txt")).unwrap(), b"ab");
+    }
+
+    #[test]
+    fn test_memory_cache_invalidation() {
+        use crate::test_server::{MockServer, Response};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Each generation moves the branch to a new commit with new contents.
+        let contents: [&[u8]; 3] = [b"{}", br#"{"a":1}"#, b"not json"];
+        let generation = Arc::new(AtomicUsize::new(0));
+        let downloads = Arc::new(AtomicUsize::new(0));
+        let server = {
+            let generation = generation.clone();
+            let downloads = downloads.clone();
+            MockServer::start(move |req| {
+                let n = generation.load(Ordering::SeqCst);
+                let content = contents[n];
+                if req.path.contains("/repo/files?") {
+                    let revision = format!("rev{}", n + 1);
+                    let files = [("config.json", content)];
+                    return Response::ok(ms_hub::test_listing(&files, &revision));
+                }
+                if req.method == "GET" {
+                    downloads.fetch_add(1, Ordering::SeqCst);
+                }
+                Response::ok(content)
+            })
+        };
+        let cache = tempfile::tempdir().unwrap();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_memory_cache(1024);
+        let memory_cache = cat.memory_cache.as_ref().unwrap();
+
+        let bytes = cat.fetch_bytes("config.json").unwrap();
+        assert_eq!(&bytes[..], b"{}");
+        let json: serde_json::Value = cat.get_json("config.json").unwrap();
+        assert!(json.as_object().unwrap().is_empty());
+        assert_eq!(downloads.load(Ordering::SeqCst), 1);
+        assert_eq!(memory_cache.len(), 1);
+
+        // Once the listing is fetched anew, the moved branch is read from its new commit.
+        generation.store(1, Ordering::SeqCst);
+        *cat.listing.lock().unwrap() = None;
+        let bytes = cat.fetch_bytes("config.json").unwrap();
+        assert_eq!(&bytes[..], br#"{"a":1}"#);
+        assert_eq!(downloads.load(Ordering::SeqCst), 2);
+
+        // A parse failure keeps the serde error as its source.
+        generation.store(2, Ordering::SeqCst);
+        *cat.listing.lock().unwrap() = None;
+        let err = cat
+            .get_json::<serde_json::Value>("config.json")
+            .unwrap_err();
+        assert!(matches!(err, OpsError::HubErrorWithSource { .. }));
+        let source = std::error::Error::source(&err).unwrap();
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+
+        // Pulling a new revision drops the entries.
+        let blobs = vec![FileInfo::test_blob("config.json", b"not json", "rev4")];
+        cat.pull_files(blobs, None::<ProgressBarWrapper>, None)
+            .unwrap();
+        assert_eq!(memory_cache.len(), 0);
+    }
+
+    #[test]
+    fn test_with_logger() {
+        let cache = tempfile::tempdir().unwrap();
+        let server = file_server(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], &["b.txt"]);
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_retries(0)
+            .with_logger(Box::new(move |line| {
+                sink.lock().unwrap().push(line.to_string())
+            }));
+
+        let a = || FileInfo::test_blob("a.txt", b"aaa", "rev1");
+        cat.pull_files(vec![a()], None::<ProgressBarWrapper>, None)
+            .unwrap();
+        let blobs = vec![a(), FileInfo::test_blob("b.txt", b"bbb", "rev1")];
+        assert!(
+            cat.pull_files(blobs, None::<ProgressBarWrapper>, None)
+                .is_err()
+        );
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(
+            lines[..3],
+            [
+                "downloaded a.txt",
+                "latest -> rev1",
+                "skipped a.txt: up to date"
+            ]
+        );
+        assert!(lines[3].starts_with("failed b.txt: "));
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn test_download_returns_path() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("gguf/b.gguf", b"bbb")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(cat.size_on_disk().unwrap(), 0);
+
+        let path = cat.download("gguf/b.gguf").unwrap();
+        assert!(cat.size_on_disk().unwrap() >= 3);
+        assert_eq!(path, snapshot.join("gguf/b.gguf"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"bbb");
+        // A cached file is found at the same path.
+        assert_eq!(cat.download("gguf/b.gguf").unwrap(), path);
+        let path = cat
+            .download_with_options("a.txt", DownloadOptions::default())
+            .unwrap();
+        assert_eq!(path, snapshot.join("a.txt"));
+    }
+
+    #[test]
+    fn test_listing_reused_by_downloads() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_token("secret");
+
+        cat.download("a.txt").unwrap();
+        cat.download("b.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            std::fs::read(cat.repo().snapshot_path("rev1").join("b.txt")).unwrap(),
+            b"bbb"
+        );
+
+        cat.pull().unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_token_sent_with_every_request() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let requests = seen.clone();
+        let server = MockServer::start(move |req| {
+            let auth = req.header("Authorization").map(str::to_string);
+            requests.lock().unwrap().push((req.method.clone(), auth));
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            match req.path.rsplit('/').next() {
+                Some("a.txt") => Response::ok(b"aaa".to_vec()),
+                _ => Response::ok(b"bbb".to_vec()),
+            }
+        });
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_token("secret");
+
+        // The listing, the downloads and the HEAD check of a cached file.
+        cat.pull().unwrap();
+        cat.download("a.txt").unwrap();
+        cat.download("a.txt").unwrap();
+        let seen = seen.lock().unwrap();
+        assert!(seen.iter().any(|(method, _)| method == "HEAD"));
+        assert!(seen.len() >= 4);
+        assert!(
+            seen.iter()
+                .all(|(_, auth)| auth.as_deref() == Some("Bearer secret"))
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            Response::ok(b"a".to_vec())
+                .truncated(3)
+                .stalled(Duration::from_secs(2))
+        });
+        let slow = MockServer::start(|_| {
+            std::thread::sleep(Duration::from_secs(2));
+            Response::ok(Vec::new())
+        });
+        let timeout = Duration::from_millis(200);
+        let policy = RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        };
+
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), slow.url())
+            .with_timeout(timeout)
+            .unwrap();
+        assert!(matches!(
+            cat.list_hub_files(),
+            Err(OpsError::Timeout { url: Some(_) })
+        ));
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_timeout(timeout)
+            .unwrap()
+            .with_connect_timeout(timeout)
+            .unwrap()
+            .with_retry(policy);
+        let started = Instant::now();
+        assert!(matches!(
+            cat.download("a.txt"),
+            Err(OpsError::Timeout { .. })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_cancel_from_progress() {
+        /// Cancels once `after` bytes arrived.
+        #[derive(Clone)]
+        struct Cancelling {
+            after: u64,
+        }
+
+        impl Progress for Cancelling {
+            fn on_start(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+                Ok(())
+            }
+
+            fn on_progress(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+                Ok(())
+            }
+
+            fn on_finish(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+                Ok(())
+            }
+
+            fn on_progress_control(
+                &mut self,
+                unit: &ProgressUnit,
+            ) -> Result<ControlFlow<()>, OpsError> {
+                Ok(match unit.current() >= self.after {
+                    true => ControlFlow::Break(()),
+                    false => ControlFlow::Continue(()),
+                })
+            }
+        }
+
+        let cache = tempfile::tempdir().unwrap();
+        let content = vec![b'a'; 256 * 1024];
+        let (server, _) = ms_hub::test_hub(&[("a.txt", &content)], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let result = cat.download_with_progress("a.txt", Cancelling { after: 1 });
+        assert!(matches!(result, Err(OpsError::Cancelled { file }) if file == "a.txt"));
+        let snapshot = cat.repo().snapshot_path("rev1");
+        let left: Vec<_> = walkdir::WalkDir::new(&snapshot)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .collect();
+        assert!(left.is_empty());
+
+        let path = cat
+            .download_with_progress(
+                "a.txt",
+                Cancelling {
+                    after: content.len() as u64 + 1,
+                },
+            )
+            .unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_cancel_flag() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let flag = Arc::new(AtomicBool::new(true));
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_cancel_flag(flag.clone());
+        assert!(matches!(
+            cat.download("a.txt"),
+            Err(OpsError::Cancelled { file }) if file == "a.txt"
+        ));
+        assert!(matches!(
+            cat.download_to_writer("a.txt", Vec::new(), None::<ProgressBarWrapper>),
+            Err(OpsError::Cancelled { .. })
+        ));
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert!(
+            walkdir::WalkDir::new(&snapshot)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .all(|e| e.file_type().is_dir())
+        );
+
+        flag.store(false, Ordering::Relaxed);
+        assert_eq!(
+            std::fs::read(cat.download("a.txt").unwrap()).unwrap(),
+            b"aaa"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let cache = tempfile::tempdir().unwrap();
+        let content = vec![b'a'; 64 * 1024];
+        let (server, _) = ms_hub::test_hub(&[("a.bin", &content)], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_rate_limit(128 * 1024);
+        let start = Instant::now();
+        let path = cat.download("a.bin").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        assert_eq!(std::fs::read(path).unwrap(), content);
+
+        let cat = cat.with_rate_limit(0);
+        assert!(cat.throttle.is_none());
+    }
+
+    #[test]
+    fn test_custom_client() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let requests = seen.clone();
+        let server = MockServer::start(move |req| {
+            requests
+                .lock()
+                .unwrap()
+                .push(req.header("X-Client").map(str::to_string));
+            match req.path.contains("/repo/files?") {
+                true => Response::ok(listing.clone()),
+                false => Response::ok(b"aaa".to_vec()),
+            }
+        });
+        let headers =
+            HeaderMap::from_iter([("x-client".parse().unwrap(), "custom".parse().unwrap())]);
+        let client = blocking::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let cat =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_client(client);
+
+        cat.download("a.txt").unwrap();
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert!(seen.iter().all(|h| h.as_deref() == Some("custom")));
+    }
+
+    #[test]
+    fn test_endpoint_with_trailing_slash() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let paths = seen.clone();
+        let server = MockServer::start(move |req| {
+            paths.lock().unwrap().push(req.path.clone());
+            match req.path.contains("/repo/files?") {
+                true => Response::ok(listing.clone()),
+                false => Response::ok(b"aaa".to_vec()),
+            }
+        });
+        let cat =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), format!("{}/", server.url()));
+        assert_eq!(cat.endpoint(), server.url());
+
+        // Listings and downloads both go to the configured endpoint, without a doubled `/`.
+        cat.pull().unwrap();
+        let seen = seen.lock().unwrap();
+        assert!(seen.iter().any(|p| p.starts_with("/api/v1/models/")));
+        assert!(seen.iter().any(|p| p.ends_with("/a.txt")));
+        assert!(seen.iter().all(|p| !p.starts_with("//")));
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            match (
+                req.header("Authorization"),
+                req.path.contains("/repo/files?"),
+            ) {
+                (None, _) => Response::status(401).body(b"{\"Code\":401}".to_vec()),
+                (Some(_), true) => Response::ok(listing.clone()),
+                (Some(_), false) => Response::status(403),
+            }
+        });
+
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let err = cat.download("a.txt").unwrap_err();
+        assert!(
+            matches!(err, OpsError::Unauthorized { status: 401, ref url } if url.contains("/repo/files?"))
+        );
+        let cat = cat.with_token("secret");
+        let err = cat.download("a.txt").unwrap_err();
+        assert!(
+            matches!(err, OpsError::Unauthorized { status: 403, ref url } if url.ends_with("/a.txt"))
+        );
+        assert!(err.to_string().contains("403"));
+    }
+
+    #[test]
+    fn test_listing_errors() {
+        let cache = tempfile::tempdir().unwrap();
+        let repo_id = test_repo(cache.path()).repo_id().to_string();
+        let error = |code: u16, body: &str| {
+            let body = body.as_bytes().to_vec();
+            MockServer::start(move |_| Response::status(code).body(body.clone()))
+        };
+        let listing_error = |server: &MockServer| {
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+                .download("a.txt")
+                .unwrap_err()
+        };
+
+        let server = error(404, r#"{"Code":10010205001,"Message":"repo not found"}"#);
+        assert!(matches!(listing_error(&server), OpsError::RepoNotFound(ref id) if *id == repo_id));
+        let server = error(
+            200,
+            r#"{"Code":10010205002,"Message":"busy","Success":false}"#,
+        );
+        assert!(matches!(
+            listing_error(&server),
+            OpsError::ApiError { code: 10010205002, ref message } if message == "busy"
+        ));
+        for body in [
+            "<html></html>",
+            r#"{"Code":200,"Message":"success","Data":{}}"#,
+        ] {
+            let server = error(200, body);
+            assert!(matches!(
+                listing_error(&server),
+                OpsError::HubErrorWithSource { .. }
+            ));
+        }
+
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        assert!(matches!(
+            cat.download("b.txt").unwrap_err(),
+            OpsError::FileNotFound { ref file, ref repo } if file == "b.txt" && *repo == repo_id
+        ));
+    }
+
+    #[test]
+    fn test_cache_hit_ignores_snapshot_lock() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.download("a.txt").unwrap();
+        cat.pull().unwrap();
+
+        // Another process holds the snapshot lock while writing a different file.
+        let mut writer = fslock::FsLock::lock(cat.repo().snapshot_path("rev1")).unwrap();
+        let start = std::time::Instant::now();
+        cat.download("a.txt").unwrap();
+        let report = cat.pull().unwrap();
+        assert_eq!(report.skipped.len(), 2);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // Concurrent workers check the files before locking, too.
+        let start = std::time::Instant::now();
+        let report = cat.with_max_concurrency(2).pull().unwrap();
+        assert_eq!(report.skipped.len(), 2);
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        writer.unlock();
+    }
+
+    #[test]
+    fn test_lock_unless_current_rechecks_placed_file() {
+        let cache = tempfile::tempdir().unwrap();
+        let snapshot = cache.path().join("rev1");
+        let filepath = snapshot.join("a.txt");
+        let fileinfo = FileInfo::test_blob("a.txt", b"aaa", "rev1");
+        std::fs::create_dir_all(&snapshot).unwrap();
+
+        // A file that did not change while waiting for the lock still needs placing.
+        let mut lock = lock_unless_current(
+            snapshot.clone(),
+            &filepath,
+            &fileinfo,
+            CacheValidation::Sha256,
+        )
+        .unwrap()
+        .expect("missing file is not current");
+        let waiter = {
+            let (snapshot, filepath, fileinfo) =
+                (snapshot.clone(), filepath.clone(), fileinfo.clone());
+            std::thread::spawn(move || {
+                lock_unless_current(snapshot, &filepath, &fileinfo, CacheValidation::Sha256)
+                    .unwrap()
+                    .is_some()
+            })
+        };
+        // One placed by the lock holder meanwhile is checked again and found current.
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        std::fs::write(&filepath, b"aaa").unwrap();
+        lock.unlock();
+        assert!(!waiter.join().unwrap());
+    }
+
+    #[test]
+    fn test_head_check_skips_listing() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let new_cat = || ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        new_cat().download("a.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // A fresh instance has no cached listing, yet only sends a HEAD request.
+        let out = tempfile::tempdir().unwrap();
+        let options = DownloadOptions::default().with_bypass_cache(false);
+        let filepath = new_cat().download_to("a.txt", out.path(), options);
+        assert_eq!(std::fs::read(filepath.unwrap()).unwrap(), b"aaa");
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // The placed file is a copy, so writing to it leaves the cache alone.
+        std::fs::write(out.path().join("a.txt"), "changed").unwrap();
+        let snapshot = new_cat().repo().snapshot_path("rev1");
+        assert_eq!(std::fs::read(snapshot.join("a.txt")).unwrap(), b"aaa");
+
+        // A local file that no longer matches falls back to the listing.
+        std::fs::write(snapshot.join("a.txt"), "changed").unwrap();
+        new_cat().download("a.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+        assert_eq!(std::fs::read(snapshot.join("a.txt")).unwrap(), b"aaa");
+    }
+
+    #[test]
+    fn test_head_check_notices_same_size_change() {
+        use crate::test_server::{MockServer, Response};
+
+        // The revision and content of a.txt, and whether the hub sends ETags.
+        let hub = Arc::new(Mutex::new(("rev1", b"aaa", false)));
+        let listings = Arc::new(AtomicUsize::new(0));
+        let (state, counter) = (hub.clone(), listings.clone());
+        let server = MockServer::start(move |req| {
+            let (revision, content, etag) = *state.lock().unwrap();
+            if req.path.contains("/repo/files?") {
+                counter.fetch_add(1, Ordering::SeqCst);
+                return Response::ok(ms_hub::test_listing(&[("a.txt", content)], revision));
+            }
+            let response = Response::ok(content.to_vec());
+            match etag {
+                true => response.header("ETag", revision),
+                false => response,
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let new_cat = || ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        new_cat().download("a.txt").unwrap();
+
+        // Without a recorded ETag, a matching size does not confirm the file.
+        *hub.lock().unwrap() = ("rev2", b"aab", true);
+        let filepath = new_cat().download("a.txt").unwrap();
+        assert_eq!(std::fs::read(filepath).unwrap(), b"aab");
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+
+        // The ETag of that download confirms the file from then on.
+        new_cat().download("a.txt").unwrap();
+        assert_eq!(listings.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_completed_files_are_validated() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.download("a.txt").unwrap();
+        assert!(completed_files(cat.repo(), "rev1").contains("a.txt"));
+
+        // The marker does not vouch for a same-size change, which hashing finds.
+        let filepath = cat.repo().snapshot_path("rev1").join("a.txt");
+        std::fs::write(&filepath, b"aab").unwrap();
+        assert_eq!(cat.pull().unwrap().downloaded, ["a.txt"]);
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let new_cat = || ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let expected = FileInfo::test_blob("a.txt", b"aaa", "rev1");
+
+        let resolution = new_cat().resolve("a.txt").unwrap();
+        assert!(resolution.downloaded);
+        assert_eq!(std::fs::read(&resolution.path).unwrap(), b"aaa");
+        assert_eq!(resolution.size, 3);
+        assert_eq!(resolution.sha256, expected.sha256);
+        assert_eq!(resolution.revision, "rev1");
+
+        // Confirmed by a HEAD request, with the metadata recorded by the first download.
+        let again = new_cat().resolve("a.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(!again.downloaded);
+        assert_eq!(again.path, resolution.path);
+        assert_eq!(again.sha256, resolution.sha256);
+    }
+
+    #[test]
+    fn test_history() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"aaa"), ("b.txt", b"bbbb")];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+
+        cat.pull().unwrap();
+        cat.remove("a.txt").unwrap();
+        cat.download("a.txt").unwrap();
+        assert!(cat.download("missing.txt").is_err());
+
+        let history = cat.history(10).unwrap();
+        let operations: Vec<_> = history.iter().map(|e| e.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                HistoryOperation::Pull,
+                HistoryOperation::Remove,
+                HistoryOperation::Download,
+                HistoryOperation::Download,
+            ]
+        );
+        assert_eq!(history[0].files, ["a.txt", "b.txt"]);
+        assert_eq!(history[0].bytes, 7);
+        assert_eq!(history[1].bytes, 3);
+        assert_eq!(history[2].outcome, HistoryOutcome::Success);
+        assert!(matches!(history[3].outcome, HistoryOutcome::Failure { .. }));
+        assert_eq!(cat.history(1).unwrap(), history[3..]);
+    }
+
+    #[test]
+    fn test_import() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[("a.txt", b"aaa"), ("gguf/b.gguf", b"bbb")];
+        let (server, _) = ms_hub::test_hub(files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let local = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &[u8]| {
+            let path = local.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+
+        let wrong = write("wrong.txt", b"aab");
+        let err = cat.import_file(&wrong, "a.txt").unwrap_err();
+        assert!(matches!(err, OpsError::ChecksumMismatch { ref file, .. } if file == "a.txt"));
+        let short = write("short.txt", b"aa");
+        let err = cat.import_file(&short, "a.txt").unwrap_err();
+        assert!(
+            matches!(err, OpsError::ChecksumMismatch { ref actual, .. } if actual == "2 bytes")
+        );
+        assert!(matches!(
+            cat.import_file(&short, "missing.txt"),
+            Err(OpsError::FileNotFound { .. })
+        ));
+        assert!(!cat.repo().snapshot_path("rev1").join("a.txt").exists());
+
+        let a = write("a.txt", b"aaa");
+        let outcome = cat.import_file(&a, "a.txt").unwrap();
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(outcome, ImportOutcome::Verified(snapshot.join("a.txt")));
+        assert_eq!(std::fs::read(outcome.path()).unwrap(), b"aaa");
+        assert!(cat.repo().latest_snapshot().is_none());
+
+        // b.gguf matches by name, wrong.txt and short.txt by nothing.
+        write("downloads/b.gguf", b"bbb");
+        let report = cat.import_dir(local.path()).unwrap();
+        let imported: Vec<_> = report.imported.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(imported, ["a.txt", "gguf/b.gguf"]);
+        assert!(report.rejected.is_empty());
+        assert_eq!(report.unknown, [short, wrong]);
+        assert!(report.complete);
+        assert_eq!(cat.repo().latest_snapshot().unwrap(), snapshot);
+        assert!(cat.pull().unwrap().downloaded.is_empty());
+    }
+
+    #[test]
+    fn test_transfer_slots_shared_across_instances() {
+        use std::sync::atomic::Ordering;
+        let (server, peak) = transfer_slots::test_hub();
+        let slots = Arc::new(TransferSlots::new(2));
+        let downloads: Vec<_> = (0..6)
+            .map(|_| {
+                let (url, slots) = (server.url(), slots.clone());
+                std::thread::spawn(move || {
+                    let cache = tempfile::tempdir().unwrap();
+                    let mut cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), url);
+                    cat.transfer_slots = Some(slots);
+                    cat.download("a.txt").unwrap();
+                })
+            })
+            .collect();
+        for download in downloads {
+            download.join().unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_prune() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        let repo = cat.repo();
+        let old = repo.snapshot_path("old");
+        std::fs::create_dir_all(&old).unwrap();
+        std::fs::write(old.join("a.txt"), "old").unwrap();
+        std::fs::write(repo.cache_dir().join("complete/old"), "a.txt").unwrap();
+        std::fs::create_dir_all(repo.snapshot_path("busy")).unwrap();
+        let mut held = fslock::FsLock::lock(repo.snapshot_path("busy")).unwrap();
+        let stale_lock = repo.cache_dir().join("refs/main.lock");
+        std::fs::write(&stale_lock, "").unwrap();
+        let temp = repo.snapshot_path("rev1").join(".tmpAbC123");
+        std::fs::write(&temp, "partial").unwrap();
+        // A temporary file outside the snapshots is only left over once it is old.
+        let recent = repo.cache_dir().join("refs/.tmpXyZ789");
+        std::fs::write(&recent, "rev2").unwrap();
+
+        let report = cat.prune(true).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.snapshots, ["old"]);
+        assert_eq!(report.busy, ["busy"]);
+        let mut files = report.files.clone();
+        files.sort();
+        assert_eq!(files, [stale_lock.clone(), temp.clone()]);
+        assert!(old.exists() && stale_lock.exists() && temp.exists());
+
+        let report = cat.prune(false).unwrap();
+        assert_eq!(report.snapshots, ["old"]);
+        assert_eq!(report.busy, ["busy"]);
+        assert_eq!(report.freed_bytes, 3 + 7);
+        assert!(!old.exists() && !stale_lock.exists() && !temp.exists());
+        assert!(!repo.cache_dir().join("complete/old").exists());
+        assert!(recent.exists());
+        assert_eq!(
+            std::fs::read(cat.download("a.txt").unwrap()).unwrap(),
+            b"aaa"
+        );
+        held.unlock();
+    }
+
+    #[test]
+    fn test_blob_store() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[
+            ("a.txt", b"same"),
+            ("b.txt", b"other"),
+            ("c/a.txt", b"same"),
+        ];
+        let (server, _) = ms_hub::test_hub(files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_blob_store(true);
+        let report = cat.pull().unwrap();
+        assert_eq!(report.downloaded, ["a.txt", "b.txt"]);
+        assert_eq!(report.deduplicated, ["c/a.txt"]);
+
+        let snapshot = cat.repo().snapshot_path("rev1");
+        let blob = std::fs::canonicalize(snapshot.join("a.txt")).unwrap();
+        assert!(blob.starts_with(cat.repo().cache_dir().canonicalize().unwrap().join("blobs")));
+        assert_eq!(
+            std::fs::canonicalize(snapshot.join("c/a.txt")).unwrap(),
+            blob
+        );
+        assert_eq!(std::fs::read(snapshot.join("c/a.txt")).unwrap(), b"same");
+        let mut local = cat.list_local_files().unwrap();
+        local.sort();
+        assert_eq!(local, ["a.txt", "b.txt", "c/a.txt"]);
+        assert!(cat.pull().unwrap().downloaded.is_empty());
+
+        cat.remove("a.txt").unwrap();
+        assert!(blob.exists());
+        cat.remove("c/a.txt").unwrap();
+        assert!(!blob.exists());
+        assert_eq!(cat.list_local_files().unwrap(), ["b.txt"]);
+    }
+
+    #[test]
+    fn test_max_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let files: &[(&str, &[u8])] = &[
+            ("a.txt", b"a"),
+            ("b.txt", b"b"),
+            ("c.txt", b"c"),
+            ("d.txt", b"d"),
+        ];
+        let listing = ms_hub::test_listing(files, "rev1");
+        let in_flight = AtomicUsize::new(0);
+        let peak = Arc::new(AtomicUsize::new(0));
+        let observed = peak.clone();
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            observed.fetch_max(
+                in_flight.fetch_add(1, Ordering::SeqCst) + 1,
+                Ordering::SeqCst,
+            );
+            std::thread::sleep(Duration::from_millis(100));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            let name = req.path.rsplit('/').next().unwrap();
+            Response::ok(name.as_bytes()[..1].to_vec())
+        });
+
+        let cache = tempfile::tempdir().unwrap();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        assert_eq!(peak.swap(0, Ordering::SeqCst), 1);
+
+        let cache = tempfile::tempdir().unwrap();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_max_concurrency(3);
+        let report = cat.pull_with_progress(MultiProgressWrapper::new()).unwrap();
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+        assert_eq!(report.downloaded, ["a.txt", "b.txt", "c.txt", "d.txt"]);
+        let snapshot = cat.repo().snapshot_path("rev1");
+        for (name, content) in files {
+            assert_eq!(&std::fs::read(snapshot.join(name)).unwrap(), content);
+        }
+        assert!(!std::fs::exists(snapshot.with_extension("lock")).unwrap());
+        assert_eq!(cat.pull().unwrap().skipped.len(), 4);
+    }
+
+    #[test]
+    fn test_concurrent_pull_reports_every_failure() {
+        let listing = ms_hub::test_listing(
+            &[
+                ("a.txt", b"a"),
+                ("b.txt", b"b"),
+                ("c.txt", b"c"),
+                ("d.txt", b"d"),
+            ],
+            "rev1",
+        );
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            match req.path.rsplit('/').next().unwrap() {
+                "b.txt" | "d.txt" => Response::status(404),
+                name => Response::ok(name.as_bytes()[..1].to_vec()),
+            }
+        });
+        let cache = tempfile::tempdir().unwrap();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_max_concurrency(2);
+        let failures = match cat.pull() {
+            Err(OpsError::PullFailed { failures }) => failures,
+            other => panic!("{other:?}"),
+        };
+        let failed: Vec<&str> = failures.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(failed, ["b.txt", "d.txt"]);
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(std::fs::read(snapshot.join("a.txt")).unwrap(), b"a");
+        assert_eq!(std::fs::read(snapshot.join("c.txt")).unwrap(), b"c");
+        assert!(!std::fs::exists(snapshot.with_extension("lock")).unwrap());
+    }
+
+    #[test]
+    fn test_recovers_interrupted_downloads() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let snapshot = cat.repo().snapshot_path("rev1");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        // As left by a crash: a.txt complete but not yet renamed, b.txt cut short.
+        let stage = |name: &str, content: &[u8], written: u64| {
+            let staged = snapshot.join(format!("{name}.partial.0123456789ab"));
+            std::fs::write(&staged, content).unwrap();
+            let sidecar = serde_json::json!({
+                "target": name, "size": 3, "sha256": null, "written": written,
+            });
+            let sidecar_path = snapshot.join(format!("{name}.partial.0123456789ab.json"));
+            std::fs::write(sidecar_path, sidecar.to_string()).unwrap();
+        };
+        stage("a.txt", b"aaa", 3);
+        stage("b.txt", b"b", 1);
+
+        let report = cat.pull().unwrap();
+        assert_eq!(report.downloaded, ["b.txt"]);
+        assert_eq!(std::fs::read(snapshot.join("a.txt")).unwrap(), b"aaa");
+        assert_eq!(std::fs::read(snapshot.join("b.txt")).unwrap(), b"bbb");
+        assert_eq!(std::fs::read_dir(&snapshot).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_prepare_update() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        assert!(cat.prepare_update().unwrap().is_none());
 
-                if filename == rel_path.to_string_lossy().replace('\\', "/") {
-                    std::fs::remove_file(entry.path())?;
-                }
+        let old = cat.repo().snapshot_path("rev1");
+        let (server, _) = ms_hub::test_hub(&[("a.txt", b"AAA"), ("b.txt", b"BBB")], "rev2");
+        let repo = test_repo(cache.path());
+        // While the new revision downloads, the active snapshot is still the old, complete one.
+        let assert_old_active = move || {
+            assert_eq!(repo.latest_snapshot().unwrap(), old);
+            assert_eq!(repo.cached_commit().as_deref(), Some("rev1"));
+            assert_eq!(std::fs::read(old.join("a.txt")).unwrap(), b"aaa");
+            assert_eq!(std::fs::read(old.join("b.txt")).unwrap(), b"bbb");
+        };
+        let validator = {
+            let assert_old_active = assert_old_active.clone();
+            move |_: &Path, _: &HubFileInfo| {
+                assert_old_active();
+                Ok(())
             }
-        }
+        };
+        let failing = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_validator(validator.clone())
+            .with_validator(|_: &Path, info: &HubFileInfo| match info.path.as_str() {
+                "b.txt" => Err(ValidationError::new("rejected")),
+                _ => Ok(()),
+            });
+        assert!(failing.prepare_update().is_err());
+        assert_old_active();
 
-        Ok(())
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+            .with_validator(validator);
+        let prepared = cat.prepare_update().unwrap().unwrap();
+        assert_eq!(prepared.revision(), "rev2");
+        // a.txt was kept from the failed attempt.
+        assert_eq!(prepared.report().downloaded, ["b.txt"]);
+        assert_eq!(
+            std::fs::read(prepared.path().join("a.txt")).unwrap(),
+            b"AAA"
+        );
+        assert_eq!(
+            cat.repo().latest_snapshot().unwrap(),
+            cat.repo().snapshot_path("rev1")
+        );
+
+        let path = prepared.activate().unwrap();
+        assert_eq!(path, cat.repo().snapshot_path("rev2"));
+        assert_eq!(cat.repo().latest_snapshot().unwrap(), path);
+        assert_eq!(cat.repo().cached_commit().as_deref(), Some("rev2"));
+        // The old snapshot stays for processes still loading from it.
+        assert!(cat.repo().snapshot_path("rev1").join("a.txt").exists());
+        assert!(cat.prepare_update().unwrap().is_none());
     }
-}
 
-/// Downloads a file from a URL with progress tracking.
-///
-/// # Arguments
-///
-/// * `file_url` - The URL of the file to download
-/// * `filepath` - The destination path where the file will be saved
-/// * `filename` - The full filename including extension and parent directory, such as `models.gguf` or `gguf/models.gguf`
-/// * `progress` - Optional progress tracker implementing the `Progress` trait
-fn download_file(
-    file_url: &str,
-    filepath: &PathBuf,
-    filename: &str,
-    progress: &mut Option<impl Progress>,
-) -> Result<(), OpsError> {
-    let parent = filepath
-        .parent() // 直接获取父目录
-        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
-    std::fs::create_dir_all(parent)?;
-    let temp_file = NamedTempFile::new_in(&parent)?;
+    #[test]
+    fn test_rate_limit_pacing() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            let response = match req.path.contains("/repo/files?") {
+                true => Response::ok(listing.clone()),
+                false => Response::ok("aaa"),
+            };
+            response
+                .header("X-RateLimit-Limit", "100")
+                .header("X-RateLimit-Remaining", "1")
+                .header("X-RateLimit-Reset", "60")
+        });
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let logged = lines.clone();
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_logger(
+            Box::new(move |line| logged.lock().unwrap().push(line.to_string())),
+        );
+        assert_eq!(cat.rate_limit_status(), None);
+
+        let report = cat.pull().unwrap();
+        let status = cat.rate_limit_status().unwrap();
+        assert_eq!(status.limit, Some(100));
+        assert_eq!(status.remaining, Some(1));
+        assert!(status.reset_at.unwrap() > std::time::SystemTime::now());
+        assert_eq!(report.rate_limit, Some(status));
+        // Without pacing, requests are sent right away.
+        assert!(
+            !lines
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|l| l.contains("rate limit"))
+        );
+
+        let cat = cat.with_rate_limit_pacing(RateLimitPacing {
+            min_remaining: 1,
+            max_wait: std::time::Duration::from_millis(200),
+        });
+        let start = Instant::now();
+        cat.list_hub_files().unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+        assert!(
+            lines
+                .lock()
+                .unwrap()
+                .contains(&"rate limit nearly reached, waiting 0.2s".to_string())
+        );
+    }
+
+    #[test]
+    fn test_provenance() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 2] = [("a.txt", b"aaa"), ("b.txt", b"bbb")];
+        let (mirror, _) = ms_hub::test_hub(&files, "rev1");
+        let mirror_url = mirror.url();
+        // The endpoint redirects every request to the mirror that serves the bytes.
+        let hub =
+            MockServer::start(move |req| Response::redirect(&format!("{mirror_url}{}", req.path)));
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), hub.url());
+        assert!(cat.provenance("a.txt").is_err());
 
-    let response = BLOCKING_CLIENT.get(file_url).send()?;
-    let total_size = if let Some(content_length) = response.content_length() {
-        content_length
-    } else {
-        return Err(OpsError::HubError("content_length is not available".into()));
-    };
+        cat.pull().unwrap();
+        let Provenance::Recorded(record) = cat.provenance("a.txt").unwrap() else {
+            panic!("no provenance recorded");
+        };
+        assert_eq!(record.endpoint, hub.url());
+        assert!(record.url.starts_with(&mirror.url()));
+        assert!(record.url.ends_with("/a.txt"));
+        let expected = FileInfo::test_blob("a.txt", b"aaa", "rev1");
+        assert_eq!(Some(record.sha256), expected.sha256);
+        assert!(record.client_version.starts_with("models-cat/"));
+        let exported = serde_json::to_value(cat.provenance("a.txt").unwrap()).unwrap();
+        assert_eq!(exported["status"], "recorded");
+        assert_eq!(exported["endpoint"], hub.url());
 
-    let mut unit = ProgressUnit::new(filename.to_string(), total_size);
-    if let Some(prg) = progress.as_mut() {
-        prg.on_start(&unit)?;
+        // Files cached before provenance was tracked have none.
+        std::fs::remove_dir_all(cat.repo().cache_dir().join("provenance")).unwrap();
+        assert_eq!(cat.provenance("b.txt").unwrap(), Provenance::Unknown);
     }
 
-    let mut downloaded: u64 = 0;
-    let mut buf_write = io::BufWriter::new(temp_file.reopen()?);
-    let mut buf_read = io::BufReader::new(response);
-    let mut buf = vec![0u8; 8192];
+    /// Fails every progress update, counting the calls it receives.
+    #[derive(Clone, Default)]
+    struct FailingProgress(Arc<AtomicU64>);
 
-    loop {
-        let len = buf_read.read(&mut buf)?;
-        if len == 0 {
-            break;
+    impl Progress for FailingProgress {
+        fn on_start(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            Ok(())
         }
-        buf_write.write_all(&buf[..len])?;
-        downloaded += len as u64;
 
-        if let Some(prg) = progress.as_mut() {
-            unit.update(downloaded);
-            prg.on_progress(&unit)?;
+        fn on_progress(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Err(OpsError::HubError("progress bar gone".into()))
+        }
+
+        fn on_finish(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
         }
     }
 
-    buf_write.flush()?;
-    temp_file
-        .persist(filepath)
-        .map_err(|e| OpsError::IoError(e.error))?;
+    #[test]
+    fn test_progress_error_policy() {
+        let cache = tempfile::tempdir().unwrap();
+        let content = vec![1u8; 64 * 1024];
+        let (server, _) = ms_hub::test_hub(&[("a.bin", &content)], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let progress = FailingProgress::default();
+        assert!(cat.pull_with_progress(progress.clone()).is_err());
+        assert_eq!(progress.0.load(Ordering::SeqCst), 1);
 
-    if let Some(prg) = progress.as_mut() {
-        prg.on_finish(&unit)?;
+        let cat = cat.with_progress_error_policy(ProgressErrorPolicy::Ignore);
+        let progress = FailingProgress::default();
+        let report = cat.pull_with_progress(progress.clone()).unwrap();
+        assert_eq!(report.downloaded, ["a.bin"]);
+        assert_eq!(report.progress_degraded, ["a.bin"]);
+        // Neither the remaining updates nor the finish reach the failed callback.
+        assert_eq!(progress.0.load(Ordering::SeqCst), 1);
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert_eq!(std::fs::read(filepath).unwrap(), content);
     }
-    Ok(())
-}
 
-/// Represents a unit of progress for tracking file downloads.
-///
-/// This struct holds information about the file being downloaded,
-/// including its name, total size, and current progress.
-#[derive(Default, Clone)]
-pub struct ProgressUnit {
-    filename: String,
-    total_size: u64,
-    current: u64,
-}
+    /// Records every callback but `on_progress`, as `event:filename`.
+    #[derive(Clone, Default)]
+    struct RecordingProgress(Arc<Mutex<Vec<String>>>);
 
-impl ProgressUnit {
-    /// Creates a new `ProgressUnit` instance.
-    pub fn new(filename: String, total_size: u64) -> Self {
-        Self {
-            filename,
-            total_size,
-            ..Default::default()
+    impl RecordingProgress {
+        fn take(&self) -> Vec<String> {
+            std::mem::take(&mut self.0.lock().unwrap())
         }
     }
 
-    /// Updates the current progress of the download.
-    pub fn update(&mut self, current: u64) {
-        self.current = current;
-    }
+    impl Progress for RecordingProgress {
+        fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+            let files: Vec<_> = units
+                .iter()
+                .map(|u| format!("{}={}", u.filename(), u.total_size().unwrap_or_default()))
+                .collect();
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("queued:{}", files.join(",")));
+            Ok(())
+        }
 
-    /// Retrieves the filename of the file being downloaded.
-    pub fn filename(&self) -> &str {
-        &self.filename
-    }
+        fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start:{}", unit.filename()));
+            Ok(())
+        }
 
-    /// Retrieves the total size of the file in bytes.
-    pub fn total_size(&self) -> u64 {
-        self.total_size
-    }
+        fn on_progress(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            Ok(())
+        }
 
-    /// Retrieves the current number of bytes downloaded.
-    pub fn current(&self) -> u64 {
-        self.current
+        fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("finish:{}", unit.filename()));
+            Ok(())
+        }
     }
-}
 
-/// A trait defining the behavior for progress tracking during file downloads.
-///
-/// This trait allows implementors to handle the start, progress updates, and finish events
-/// of a download operation. It is designed to be thread-safe (`Send + Sync`) and clonable.
-pub trait Progress: Clone + Send + Sync {
-    /// Called when a download starts.
-    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+    #[test]
+    fn test_progress_queued_before_downloads() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 3] = [("a.txt", b"aaa"), ("b.txt", b"bb"), ("c.txt", b"c")];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let progress = RecordingProgress::default();
 
-    /// Called periodically to update the progress of a download.
-    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
+        cat.download_with_progress("c.txt", progress.clone())
+            .unwrap();
+        assert_eq!(
+            progress.take(),
+            ["queued:c.txt=1", "start:c.txt", "finish:c.txt"]
+        );
 
-    /// Called when a download finishes.
-    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError>;
-}
+        // c.txt was recorded as complete by its download, so it is not queued again.
+        cat.pull_with_progress(progress.clone()).unwrap();
+        assert_eq!(
+            progress.take(),
+            [
+                "queued:a.txt=3,b.txt=2",
+                "start:a.txt",
+                "finish:a.txt",
+                "start:b.txt",
+                "finish:b.txt",
+            ]
+        );
 
-/// A wrapper around a single [`ProgressBar`] for tracking progress during file downloads.
-///
-/// This struct implements the [`Progress`] trait and provides methods to handle the start,
-/// progress updates, and finish events of a download operation.
-#[derive(Default, Clone)]
-pub struct ProgressBarWrapper(Option<ProgressBar>);
+        cat.pull_with_progress(progress.clone()).unwrap();
+        assert_eq!(progress.take(), ["queued:"]);
+    }
 
-impl Progress for ProgressBarWrapper {
-    /// Called when a download starts.
-    ///
-    /// Initializes the progress bar with the total size of the file being downloaded.
-    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        let pb = ProgressBar::new(unit.total_size()).with_finish(ProgressFinish::AndLeave);
-        let filename = unit.filename().to_string();
-        pb.set_style(ProgressStyle::with_template("{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
-        pb.set_prefix(filename);
-        self.0 = Some(pb);
-        Ok(())
+    #[test]
+    fn test_local_files_matching() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8]); 3] = [
+            ("data/train.parquet", b"train"),
+            ("data/test.parquet", b"test"),
+            ("data/nested/extra.parquet", b"extra"),
+        ];
+        let (server, _) = ms_hub::test_hub(&files, "rev1");
+        let new_cat = || ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        assert!(new_cat().local_files_matching("data/*.parquet").is_err());
+
+        let cat = new_cat();
+        cat.pull().unwrap();
+        let snapshot = std::path::absolute(cat.repo().snapshot_path("rev1")).unwrap();
+        let expected = [
+            snapshot.join("data").join("test.parquet"),
+            snapshot.join("data").join("train.parquet"),
+        ];
+        assert_eq!(
+            cat.local_files_matching("data/*.parquet").unwrap(),
+            expected
+        );
+        assert_eq!(cat.local_files_matching("**/*.parquet").unwrap().len(), 3);
+
+        // Offline, a fresh instance finds the files through the last pulled snapshot.
+        std::fs::remove_file(&expected[0]).unwrap();
+        let found = new_cat().local_files_matching("data/*.parquet").unwrap();
+        assert_eq!(found, &expected[1..]);
+        // With a fresh listing the missing file is reported.
+        let err = cat.local_files_matching("data/*.parquet").unwrap_err();
+        assert!(err.to_string().contains("data/test.parquet"));
     }
 
-    /// Called periodically to update the progress of a download.
-    ///
-    /// Updates the position of the progress bar based on the current bytes downloaded.
-    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        if let Some(ref pb) = self.0 {
-            pb.set_position(unit.current());
-        }
-        Ok(())
+    #[test]
+    fn test_remove_all_refuses_decoy() {
+        let cache = tempfile::tempdir().unwrap();
+        let decoy = cache.path().join("documents");
+        std::fs::create_dir(&decoy).unwrap();
+        std::fs::write(decoy.join("thesis.txt"), "precious").unwrap();
+        let cat = ModelsCat::new(test_repo(cache.path()))
+            .with_cache_key(|_| "documents".into())
+            .unwrap();
+
+        let err = cat.remove_all().unwrap_err();
+        assert!(matches!(err, OpsError::RefusingUnsafeDelete(ref path) if *path == decoy));
+        assert!(decoy.join("thesis.txt").exists());
+        cat.remove_all_unchecked().unwrap();
+        assert!(!decoy.exists());
     }
 
-    /// Called when a download finishes.
-    ///
-    /// Ensures the progress bar reflects the final downloaded bytes.
-    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        if let Some(ref pb) = self.0 {
-            pb.set_position(unit.current());
-        }
-        Ok(())
+    #[test]
+    fn test_branch_and_commit_share_snapshot() {
+        let cache = tempfile::tempdir().unwrap();
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa")], commit);
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let new_cat = |revision: &str| {
+            let mut repo = test_repo(cache.path());
+            repo.set_revision(revision);
+            let sink = lines.clone();
+            ModelsCat::new_with_endpoint(repo, server.url()).with_logger(Box::new(move |line| {
+                sink.lock().unwrap().push(line.to_string())
+            }))
+        };
+
+        new_cat("master").download("a.txt").unwrap();
+        new_cat(commit).download("a.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["downloaded a.txt", "skipped a.txt: up to date"]
+        );
+        let snapshots = std::fs::read_dir(
+            cache
+                .path()
+                .join("models--BAAI--bge-small-zh-v1.5/snapshots"),
+        );
+        assert_eq!(snapshots.unwrap().count(), 1);
     }
-}
 
-/// A wrapper around `MultiProgressBar` for tracking multiple progress bars during file downloads.
-///
-/// This struct implements the `Progress` trait and provides methods to handle the start,
-/// progress updates, and finish events of multiple download operations simultaneously.
-#[derive(Default, Clone)]
-pub struct MultiProgressWrapper {
-    current_bar: Option<ProgressBar>,
-    inner: MultiProgressBar,
-}
+    #[test]
+    fn test_space_listing() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[("app.py", b"import gradio"), ("assets/logo.png", b"png")];
+        let listing = ms_hub::test_listing(files, "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/api/v1/studios/damo/demo/repo/tree?") {
+                return Response::ok(listing.clone());
+            }
+            match files
+                .iter()
+                .find(|(p, _)| req.path == format!("/spaces/damo/demo/resolve/master/{p}"))
+            {
+                Some((_, content)) => Response::ok(content.to_vec()),
+                None => Response::status(404),
+            }
+        });
+        let mut repo = Repo::new_space("damo/demo");
+        repo.set_cache_dir(cache.path());
+        let cat = ModelsCat::new_with_endpoint(repo, server.url());
 
-impl MultiProgressWrapper {
-    /// Creates a new `MultiProgressWrapper` instance.
-    pub fn new() -> Self {
-        Self {
-            current_bar: None,
-            inner: MultiProgressBar::new(),
-        }
+        let mut listed = cat.list_hub_files().unwrap();
+        listed.sort();
+        assert_eq!(listed, ["app.py", "assets/logo.png"]);
+        cat.pull().unwrap();
+        let snapshot = cat.repo().snapshot_path("rev1");
+        assert_eq!(
+            std::fs::read(snapshot.join("app.py")).unwrap(),
+            b"import gradio"
+        );
+        assert_eq!(
+            std::fs::read(snapshot.join("assets/logo.png")).unwrap(),
+            b"png"
+        );
     }
-}
 
-impl Progress for MultiProgressWrapper {
-    /// Called when a download starts.
-    ///
-    /// Initializes a new progress bar within the multi-progress bar system.
-    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        let pb = ProgressBar::new(unit.total_size()).with_finish(ProgressFinish::AndLeave);
-        self.current_bar = Some(self.inner.add(pb.clone()));
+    #[test]
+    fn test_repo_owned_by_instance() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let repo = test_repo(cache.path());
+        let mut cat = ModelsCat::new_with_endpoint(repo.clone(), server.url());
+        cat.list_hub_files().unwrap();
 
-        let filename = unit.filename().to_string();
-        pb.set_style(ProgressStyle::with_template("{prefix:.bold.cyan} {spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
-            .unwrap()
-            .with_key("eta", |state: &ProgressState, w: &mut dyn fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
-            .progress_chars("#>-"));
-        pb.set_prefix(filename);
-        Ok(())
+        // A clone kept by the caller is a separate copy.
+        let mut kept = repo.clone();
+        kept.set_revision("v2");
+        assert_eq!(cat.repo().revision().as_str(), "master");
+
+        cat.set_revision("v2");
+        assert_eq!(cat.repo().revision().as_str(), "v2");
+        // The listing of the previous revision is not reused.
+        cat.list_hub_files().unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let other = tempfile::tempdir().unwrap();
+        cat.set_cache_dir(other.path());
+        cat.download("a.txt").unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert!(cat.repo().snapshot_path("rev1").join("a.txt").exists());
+        assert!(cat.repo().cache_dir().starts_with(other.path()));
+        assert!(!repo.snapshot_path("rev1").exists());
     }
 
-    /// Called periodically to update the progress of a download.
-    ///
-    /// Updates the position of the current progress bar based on the downloaded bytes.
-    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        if let Some(ref pb) = self.current_bar {
-            pb.set_position(unit.current());
-        }
-        Ok(())
+    #[test]
+    fn test_try_new() {
+        let cat = ModelsCat::try_new("BAAI/bge-small-zh-v1.5").unwrap();
+        assert_eq!(cat.repo().repo_id(), "BAAI/bge-small-zh-v1.5");
+        let cat = ModelsCat::try_new(Repo::new_dataset("DAMO_NLP/yf_dianping")).unwrap();
+        assert_eq!(cat.repo().repo_id(), "DAMO_NLP/yf_dianping");
+        assert!(ModelsCat::try_new("bge-small").is_err());
     }
 
-    /// Called when a download finishes.
-    ///
-    /// Ensures the current progress bar reflects the final downloaded bytes.
-    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
-        if let Some(ref pb) = self.current_bar {
-            pb.set_position(unit.current());
+    #[test]
+    fn test_with_cache_key() {
+        let alias = |repo: &Repo| {
+            let name = repo.repo_id().rsplit('/').next().unwrap();
+            format!("models--shared--{name}")
+        };
+        let mut origin = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        origin.set_cache_dir("/tmp/models-cat");
+        let mut mirror = Repo::new_model("mirror/bge-small-zh-v1.5");
+        mirror.set_cache_dir("/tmp/models-cat");
+
+        let origin = ModelsCat::new(origin).with_cache_key(alias).unwrap();
+        let mirror = ModelsCat::new(mirror).with_cache_key(alias).unwrap();
+        assert_eq!(origin.repo().cache_dir(), mirror.repo().cache_dir());
+        assert_eq!(
+            origin.repo().cache_dir(),
+            PathBuf::from("/tmp/models-cat/models--shared--bge-small-zh-v1.5")
+        );
+
+        let cat = ModelsCat::new(Repo::new_model("a/b"))
+            .with_cache_key(|_| "x/y".to_string())
+            .unwrap();
+        assert!(cat.repo().cache_dir().ends_with("x--y"));
+        for invalid in ["", "..", "C:x"] {
+            let cat = ModelsCat::new(Repo::new_model("a/b")).with_cache_key(|_| invalid.into());
+            assert!(matches!(cat, Err(OpsError::BuildError(_))), "{invalid}");
         }
-        Ok(())
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
     fn test_download() {
@@ -505,9 +5584,393 @@ mod tests {
         cat.remove_all().unwrap();
     }
 
+    #[test]
+    fn test_remove_all_without_cache() {
+        let cache = tempfile::tempdir().unwrap();
+        let cat = ModelsCat::new(test_repo(cache.path()));
+        cat.remove_all().unwrap();
+        cat.remove_all_unchecked().unwrap();
+        assert!(!cat.repo().cache_dir().exists());
+    }
+
     #[test]
     fn test_remove() {
         let cat = ModelsCat::new(Repo::new_model("BAAI/bge-small-zh-v1.5"));
         cat.remove("pytorch_model.bin").unwrap();
     }
+
+    #[test]
+    fn test_verify() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[("a.txt", b"aaa"), ("b/c.txt", b"ccc"), ("d.txt", b"ddd")];
+        let (server, _) = ms_hub::test_hub(files, "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        let report = cat.verify().unwrap();
+        assert_eq!(report.verified, ["a.txt", "b/c.txt", "d.txt"]);
+        assert!(report.is_ok());
+
+        // A corrupted file of the same size and a missing one fail, an unlisted one is extra.
+        let snapshot = cat.repo().snapshot_path("rev1");
+        std::fs::write(snapshot.join("b/c.txt"), b"ccx").unwrap();
+        std::fs::remove_file(snapshot.join("d.txt")).unwrap();
+        std::fs::write(snapshot.join("e.txt"), b"eee").unwrap();
+        assert_eq!(cat.verify_file("a.txt").unwrap(), FileStatus::Verified);
+        assert_eq!(cat.verify_file("b/c.txt").unwrap(), FileStatus::Corrupt);
+        assert_eq!(cat.verify_file("d.txt").unwrap(), FileStatus::Missing);
+        let progress = RecordingProgress::default();
+        let report = cat.verify_with_progress(progress.clone()).unwrap();
+        assert_eq!(
+            (
+                report.verified,
+                report.corrupt,
+                report.missing,
+                report.extra
+            ),
+            (
+                vec!["a.txt".to_string()],
+                vec!["b/c.txt".to_string()],
+                vec!["d.txt".to_string()],
+                vec!["e.txt".to_string()]
+            )
+        );
+        assert_eq!(
+            progress.take(),
+            [
+                "queued:a.txt=3,b/c.txt=3,d.txt=3",
+                "start:a.txt",
+                "finish:a.txt",
+                "start:b/c.txt",
+                "finish:b/c.txt",
+                "finish:d.txt"
+            ]
+        );
+
+        let report = cat.repair().unwrap();
+        assert_eq!(report.repaired, ["b/c.txt", "d.txt"]);
+        assert!(report.is_ok());
+        assert_eq!(std::fs::read(snapshot.join("b/c.txt")).unwrap(), b"ccc");
+        let report = cat.verify().unwrap();
+        assert_eq!(report.verified, ["a.txt", "b/c.txt", "d.txt"]);
+        assert_eq!(report.extra, ["e.txt"]);
+    }
+
+    #[test]
+    fn test_offline() {
+        let cache = tempfile::tempdir().unwrap();
+        let (server, hits) = ms_hub::test_hub(&[("a.txt", b"aaa"), ("b/c.txt", b"ccc")], "rev1");
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        cat.pull().unwrap();
+        cat.download("a.txt").unwrap();
+        let before = hits.load(Ordering::SeqCst);
+
+        // Files placed by a download or a pull resolve without a request.
+        let offline =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_offline(true);
+        let a = offline.resolve("a.txt").unwrap();
+        assert_eq!((a.size, a.revision.as_str()), (3, "rev1"));
+        assert!(a.sha256.is_some() && !a.downloaded);
+        let c = offline.resolve("b/c.txt").unwrap();
+        assert_eq!(std::fs::read(&c.path).unwrap(), b"ccc");
+        assert_eq!(offline.download("b/c.txt").unwrap(), c.path);
+        assert_eq!(offline.pull().unwrap().skipped, ["a.txt", "b/c.txt"]);
+        assert!(matches!(
+            offline.download("d.txt"),
+            Err(OpsError::OfflineMiss { ref file }) if file == "d.txt"
+        ));
+        assert!(offline.list_hub_files().is_err());
+        assert_eq!(hits.load(Ordering::SeqCst), before);
+
+        // Nothing resolves from an empty cache.
+        let empty = tempfile::tempdir().unwrap();
+        let offline =
+            ModelsCat::new_with_endpoint(test_repo(empty.path()), server.url()).with_offline(true);
+        assert!(matches!(
+            offline.download("a.txt"),
+            Err(OpsError::OfflineMiss { .. })
+        ));
+        assert!(matches!(offline.pull(), Err(OpsError::OfflineMiss { .. })));
+    }
+
+    /// Records where each download starts, as `(current, total)`.
+    #[derive(Clone, Default)]
+    struct StartProgress(Arc<Mutex<Vec<Start>>>);
+
+    type Start = (u64, Option<u64>);
+
+    impl Progress for StartProgress {
+        fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            let start = (unit.current(), unit.total_size());
+            self.0.lock().unwrap().push(start);
+            Ok(())
+        }
+
+        fn on_progress(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            Ok(())
+        }
+
+        fn on_finish(&mut self, _: &ProgressUnit) -> Result<(), OpsError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_chunked_download() {
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa"), ("b.txt", b"bbb")], "rev1");
+        let server = MockServer::start(move |req| match req.path.rsplit('/').next() {
+            Some("a.txt") => Response::ok(b"aaa".to_vec()).chunked(),
+            Some("b.txt") => Response::ok(b"bab".to_vec()).chunked(),
+            _ => Response::ok(listing.clone()),
+        });
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let progress = StartProgress::default();
+
+        // Without a length, the size is unknown to progress, and checked through the sha256.
+        let filepath = cat
+            .download_with_progress("a.txt", progress.clone())
+            .unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), b"aaa");
+        assert_eq!(*progress.0.lock().unwrap(), [(0, None)]);
+        assert!(matches!(
+            cat.download("b.txt"),
+            Err(OpsError::ChecksumMismatch { .. })
+        ));
+        let bar = file_bar(&ProgressUnit::without_total("a.txt".into()));
+        assert_eq!(bar.length(), None);
+    }
+
+    #[test]
+    fn test_resume_download() {
+        let content = b"0123456789";
+        let listing = ms_hub::test_listing(&[("a.bin", content)], "rev1");
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        // How the server answers a range: honoured, ignored, rejected or from the wrong offset.
+        let range_mode = Arc::new(Mutex::new("honour"));
+        let (seen, mode) = (ranges.clone(), range_mode.clone());
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            let range = req.header("Range").map(str::to_string);
+            seen.lock().unwrap().push(range.clone());
+            let Some(range) = range else {
+                if seen.lock().unwrap().len() == 1 {
+                    return Response::ok(&content[..4]).truncated(content.len() as u64);
+                }
+                return Response::ok(content.to_vec());
+            };
+            let offset: usize = range["bytes=".len()..range.len() - 1].parse().unwrap();
+            let partial = |offset: usize| {
+                Response::status(206)
+                    .header("Content-Range", format!("bytes {offset}-9/10"))
+                    .body(&content[offset..])
+            };
+            match *mode.lock().unwrap() {
+                "honour" => partial(offset),
+                "ignore" => Response::ok(content.to_vec()),
+                "reject" => Response::status(416).header("Content-Range", "bytes */10"),
+                _ => partial(offset - 1),
+            }
+        });
+        let progress = StartProgress::default();
+
+        // The retry asks for the rest of the file and appends it.
+        let cache = tempfile::tempdir().unwrap();
+        let cat =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_retries(0);
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert!(
+            cat.download_with_progress("a.bin", progress.clone())
+                .is_err()
+        );
+        assert!(!filepath.exists());
+        cat.download_with_progress("a.bin", progress.clone())
+            .unwrap();
+        assert_eq!(std::fs::read(&filepath).unwrap(), content);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, Some("bytes=4-".to_string())]
+        );
+        assert_eq!(*progress.0.lock().unwrap(), [(0, Some(10)), (4, Some(10))]);
+        assert_eq!(
+            std::fs::read_dir(filepath.parent().unwrap())
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // A server ignoring the range sends the whole file, which replaces the partial one,
+        // while one rejecting it or sending other bytes is asked for the whole file.
+        for (answer, requests) in [("ignore", 2), ("reject", 3), ("misalign", 3)] {
+            let cache = tempfile::tempdir().unwrap();
+            let cat =
+                ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_retries(0);
+            let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+            *range_mode.lock().unwrap() = answer;
+            ranges.lock().unwrap().clear();
+            assert!(cat.download("a.bin").is_err());
+            cat.download("a.bin").unwrap();
+            assert_eq!(std::fs::read(&filepath).unwrap(), content, "{answer}");
+            assert_eq!(ranges.lock().unwrap().len(), requests, "{answer}");
+            assert_eq!(
+                std::fs::read_dir(filepath.parent().unwrap())
+                    .unwrap()
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        let content = b"0123456789";
+        let listing = ms_hub::test_listing(&[("a.bin", content), ("gone.bin", b"")], "rev1");
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let seen = ranges.clone();
+        let gone_hits = Arc::new(AtomicU64::new(0));
+        let gone = gone_hits.clone();
+        // A 502, then a connection dropped after 4 bytes, then the rest of the file.
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            if req.path.ends_with("/gone.bin") {
+                gone.fetch_add(1, Ordering::SeqCst);
+                return Response::status(404);
+            }
+            let mut seen = seen.lock().unwrap();
+            seen.push(req.header("Range").map(str::to_string));
+            match seen.len() % 3 {
+                1 => Response::status(502).header("Retry-After", "0"),
+                2 => Response::ok(&content[..4]).truncated(content.len() as u64),
+                _ => Response::status(206)
+                    .header("Content-Range", "bytes 4-9/10")
+                    .body(&content[4..]),
+            }
+        });
+        let policy = RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+        };
+
+        let cache = tempfile::tempdir().unwrap();
+        let cat =
+            ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url()).with_retry(policy);
+        let progress = StartProgress::default();
+        cat.download_with_progress("a.bin", progress.clone())
+            .unwrap();
+        let filepath = cat.repo().snapshot_path("rev1").join("a.bin");
+        assert_eq!(std::fs::read(&filepath).unwrap(), content);
+        assert_eq!(
+            *ranges.lock().unwrap(),
+            [None, None, Some("bytes=4-".to_string())]
+        );
+        // The resumed attempt starts from the bytes already counted.
+        assert_eq!(*progress.0.lock().unwrap(), [(0, Some(10)), (4, Some(10))]);
+
+        // Without retries, or with too few, the download fails.
+        for retries in [0, 1] {
+            let cache = tempfile::tempdir().unwrap();
+            let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url())
+                .with_retry(policy)
+                .with_retries(retries);
+            ranges.lock().unwrap().clear();
+            let err = cat.download("a.bin").unwrap_err();
+            assert_eq!(ranges.lock().unwrap().len(), 1 + retries as usize);
+            if retries == 0 {
+                assert!(matches!(err, OpsError::ServerError { status: 502, .. }));
+            }
+        }
+
+        // A missing file is not retried.
+        ranges.lock().unwrap().clear();
+        assert!(matches!(
+            cat.download("gone.bin"),
+            Err(OpsError::NotFound { .. })
+        ));
+        assert_eq!(gone_hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_quarantine() {
+        use crate::test_server::{MockServer, Response};
+
+        let cache = tempfile::tempdir().unwrap();
+        let listing = ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+        let server = MockServer::start(move |req| {
+            if req.path.contains("/repo/files?") {
+                return Response::ok(listing.clone());
+            }
+            Response::ok(b"aab".to_vec()).header("X-Cache", "HIT")
+        });
+        let quarantine = quarantine::dir(cache.path());
+        let snapshot = test_repo(cache.path()).snapshot_path("rev1");
+
+        // Without a quarantine, the download is deleted.
+        let cat = ModelsCat::new_with_endpoint(test_repo(cache.path()), server.url());
+        let err = cat.download("a.txt").unwrap_err();
+        assert!(matches!(
+            err,
+            OpsError::ChecksumMismatch {
+                quarantined: None,
+                ..
+            }
+        ));
+        assert_eq!(std::fs::read_dir(&snapshot).unwrap().count(), 0);
+        assert!(!quarantine.exists());
+
+        // With one, it is kept along with its report.
+        let cat = cat.with_quarantine(1024 * 1024);
+        let err = cat.download("a.txt").unwrap_err();
+        let OpsError::ChecksumMismatch {
+            ref expected,
+            ref actual,
+            quarantined: Some(ref first),
+            ..
+        } = err
+        else {
+            panic!("{err:?}");
+        };
+        assert!(err.to_string().contains(&first.display().to_string()));
+        assert_eq!(std::fs::read(first).unwrap(), b"aab");
+        let report: QuarantineReport =
+            serde_json::from_slice(&std::fs::read(first.with_extension("txt.json")).unwrap())
+                .unwrap();
+        assert_eq!((&report.expected, &report.actual), (expected, actual));
+        assert_eq!(report.file, "a.txt");
+        assert_eq!(report.endpoint, server.url());
+        assert!(report.url.ends_with("/a.txt"));
+        assert!(report.headers.contains(&("x-cache".into(), "HIT".into())));
+        assert_eq!(std::fs::read_dir(&snapshot).unwrap().count(), 0);
+
+        // Past the cap, the oldest entries are evicted, and larger downloads are not kept.
+        let first_size = 3 + std::fs::metadata(first.with_extension("txt.json"))
+            .unwrap()
+            .len();
+        let cat = cat.with_quarantine(first_size + 1);
+        let second = match cat.download("a.txt") {
+            Err(OpsError::ChecksumMismatch {
+                quarantined: Some(path),
+                ..
+            }) => path,
+            other => panic!("{other:?}"),
+        };
+        assert_ne!(&second, first);
+        assert!(!first.exists() && second.exists());
+        let cat = cat.with_quarantine(2);
+        assert!(matches!(
+            cat.download("a.txt"),
+            Err(OpsError::ChecksumMismatch {
+                quarantined: None,
+                ..
+            })
+        ));
+        assert_eq!(std::fs::read_dir(&quarantine).unwrap().count(), 2);
+
+        assert!(cat.clear_quarantine().unwrap() > 0);
+        assert!(!quarantine.exists());
+        assert_eq!(cat.clear_quarantine().unwrap(), 0);
+    }
 }
@@ -2,42 +2,31 @@
 
 //! 参考 [modelscope python client api](https://github.com/modelscope/modelscope/blob/master/modelscope/hub/api.py)
 //!
-//! ```
-//! curl https://modelscope.cn/api/v1/models/BAAI/bge-large-zh-v1.5/repo/files?Recursive=true
+//! ```sh
+//! curl https://www.modelscope.cn/api/v1/models/BAAI/bge-large-zh-v1.5/repo/files?Recursive=true
 //! ```
 //!
 
+use super::rate_limit::RateLimitStatus;
+use super::retry;
 use crate::repo::{Repo, RepoType};
 use crate::utils::OpsError;
+use reqwest::StatusCode;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
 
 /// 兼容两种API响应的文件信息结构体
-#[derive(Debug, Serialize, Deserialize)]
+///
+/// 只保留下载流程用到的字段。提交信息、提交者等其余字段在反序列化时直接跳过，不为每个文件分配字符串，
+/// 十万文件级别的数据集列表因此少占用数百 MB 临时内存。
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileInfo {
-    #[serde(rename(deserialize = "Id"), default)]
-    pub id: Option<String>,
-
-    #[serde(rename(deserialize = "Name"))]
-    pub name: String,
-
     #[serde(rename(deserialize = "Type"))]
     pub file_type: String,
 
     #[serde(rename(deserialize = "Path"))]
     pub path: String,
 
-    #[serde(rename(deserialize = "Mode"))]
-    pub mode: String,
-
-    #[serde(rename(deserialize = "CommitId"), default)]
-    pub commit_id: Option<String>,
-
-    #[serde(rename(deserialize = "CommitMessage"))]
-    pub commit_message: String,
-
-    #[serde(rename(deserialize = "CommitterName"))]
-    pub committer_name: String,
-
     #[serde(rename(deserialize = "CommittedDate"))]
     pub committed_date: i64,
 
@@ -50,15 +39,93 @@ pub struct FileInfo {
     #[serde(rename(deserialize = "Size"))]
     pub size: i64,
 
-    #[serde(rename(deserialize = "InCheck"))]
-    pub in_check: bool,
-
     #[serde(rename(deserialize = "Sha256"), default)]
     pub sha256: Option<String>,
 }
 
+#[cfg(test)]
+impl FileInfo {
+    /// Builds the listing entry the hub would return for `content` stored at `path`.
+    pub fn test_blob(path: &str, content: &[u8], revision: &str) -> Self {
+        use sha2::{Digest, Sha256};
+        Self {
+            file_type: "blob".to_string(),
+            path: path.to_string(),
+            committed_date: 0,
+            revision: revision.to_string(),
+            is_lfs: false,
+            size: content.len() as i64,
+            sha256: Some(format!("{:x}", Sha256::digest(content))),
+        }
+    }
+}
+
+/// Serves a hub with `files` at `revision`: the model listing API and the file contents, which
+/// resolve under any revision name, with their sha256 as ETag.
+/// Returns the server and the number of listing requests it received.
+#[cfg(test)]
+pub fn test_hub(
+    files: &[(&str, &[u8])],
+    revision: &str,
+) -> (
+    crate::test_server::MockServer,
+    std::sync::Arc<std::sync::atomic::AtomicUsize>,
+) {
+    use crate::test_server::{MockServer, Response};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let listing = test_listing(files, revision);
+    let files: Vec<(String, Vec<u8>, String)> = files
+        .iter()
+        .map(|(p, c)| {
+            let sha256 = FileInfo::test_blob(p, c, revision).sha256.unwrap();
+            (format!("/{p}"), c.to_vec(), format!("\"{sha256}\""))
+        })
+        .collect();
+
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = hits.clone();
+    let server = MockServer::start(move |req| {
+        if req.path.contains("/repo/files?") {
+            counter.fetch_add(1, Ordering::SeqCst);
+            return Response::ok(listing.clone());
+        }
+        let file = files
+            .iter()
+            .find(|(p, _, _)| req.path.contains("/resolve/") && req.path.ends_with(p.as_str()));
+        match file {
+            Some((_, content, etag)) => Response::ok(content.clone()).header("ETag", etag.clone()),
+            None => Response::status(404),
+        }
+    });
+    (server, hits)
+}
+
+/// The body of the model listing API for `files` at `revision`.
+#[cfg(test)]
+pub fn test_listing(files: &[(&str, &[u8])], revision: &str) -> String {
+    let listing: Vec<_> = files
+        .iter()
+        .map(|(path, content)| {
+            let f = FileInfo::test_blob(path, content, revision);
+            serde_json::json!({
+                "Name": path.rsplit('/').next(), "Type": f.file_type, "Path": f.path,
+                "Mode": "100644", "CommitMessage": "", "CommitterName": "",
+                "CommittedDate": f.committed_date, "Revision": f.revision, "IsLFS": f.is_lfs,
+                "Size": f.size, "InCheck": false, "Sha256": f.sha256,
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "RequestId": "test", "Code": 200, "Message": "success",
+        "Data": { "Files": listing, "TotalCount": files.len() },
+    })
+    .to_string()
+}
+
 /// 兼容两种API响应的最新提交者信息
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LatestCommitter {
     #[serde(rename(deserialize = "Id"), default)]
     pub id: Option<String>,
@@ -98,7 +165,7 @@ pub struct LatestCommitter {
 }
 
 /// 兼容两种API响应的数据结构
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseData {
     #[serde(rename(deserialize = "Files"))]
     pub files: Vec<FileInfo>,
@@ -114,7 +181,7 @@ pub struct ResponseData {
 }
 
 /// 兼容两种API响应的顶层结构
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse {
     #[serde(rename(deserialize = "RequestId"))]
     pub request_id: String,
@@ -139,16 +206,117 @@ pub struct ApiResponse {
 
     #[serde(rename(deserialize = "TotalCount"), default)]
     pub total_count: Option<i32>,
+
+    /// 响应头中的限流信息，数据集分页时取剩余次数最少的一页
+    #[serde(skip)]
+    pub rate_limit: Option<RateLimitStatus>,
+
+    /// 所列仓库的 ID，用于 [`OpsError::FileNotFound`]
+    #[serde(skip)]
+    pub repo_id: String,
+}
+
+/// 接口出错时响应体中的 `Code` 与 `Message`
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(rename = "Code")]
+    code: Option<i64>,
+    #[serde(rename = "Message")]
+    message: Option<String>,
+    #[serde(rename = "Success")]
+    success: Option<bool>,
 }
 
 impl ApiResponse {
+    /// 解析响应体，并记录响应头中的限流信息
+    fn from_response(
+        response: reqwest::blocking::Response,
+        repo_id: &str,
+    ) -> Result<Self, OpsError> {
+        let (status, url) = (response.status(), response.url().to_string());
+        let headers = response.headers().clone();
+        let body = response.bytes()?;
+        Self::parse(status, &url, &headers, &body, repo_id)
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn from_async_response(
+        response: reqwest::Response,
+        repo_id: &str,
+    ) -> Result<Self, OpsError> {
+        let (status, url) = (response.status(), response.url().to_string());
+        let headers = response.headers().clone();
+        let body = response.bytes().await?;
+        Self::parse(status, &url, &headers, &body, repo_id)
+    }
+
+    /// 按状态码与接口返回的 `Code`、`Message` 解析响应，错误映射为对应的 [`OpsError`]
+    fn parse(
+        status: StatusCode,
+        url: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+        repo_id: &str,
+    ) -> Result<Self, OpsError> {
+        if let Some(e) = OpsError::unauthorized(status, url) {
+            return Err(e);
+        }
+        if status == StatusCode::NOT_FOUND {
+            return Err(OpsError::RepoNotFound(repo_id.to_string()));
+        }
+        if let Some(e) = retry::server_error(status, headers, url) {
+            return Err(e);
+        }
+        let parsed = match serde_json::from_slice::<Self>(body) {
+            Ok(mut parsed) if status.is_success() && parsed.success => {
+                parsed.rate_limit = RateLimitStatus::from_headers(headers);
+                parsed.repo_id = repo_id.to_string();
+                return Ok(parsed);
+            }
+            parsed => parsed,
+        };
+        match serde_json::from_slice::<ErrorBody>(body) {
+            // `Code` 为 200 且未标记失败时，错误在于响应体本身
+            Ok(ErrorBody {
+                code: Some(code),
+                message,
+                success,
+            }) if code != 200 || success == Some(false) => Err(OpsError::ApiError {
+                code,
+                message: message.unwrap_or_default(),
+            }),
+            _ if !status.is_success() => Err(OpsError::HubError(format!("{status} for {url}"))),
+            _ => Err(match parsed {
+                Err(e) => OpsError::hub(format!("invalid listing of {repo_id}"), e),
+                Ok(_) => OpsError::HubError(format!("the listing of {repo_id} failed")),
+            }),
+        }
+    }
+
     pub fn get_file_info(&self, filename: &str) -> Result<&FileInfo, OpsError> {
         for f in self.data.files.iter() {
             if f.path == filename {
                 return Ok(f);
             }
         }
-        Err(OpsError::HubError("file not found".to_string()))
+        Err(OpsError::FileNotFound {
+            file: filename.to_string(),
+            repo: self.repo_id.clone(),
+        })
+    }
+
+    /// The files of the listing, without directories, sorted by path: the hub does not list
+    /// files in a stable order.
+    pub fn blobs(&self) -> Vec<FileInfo> {
+        let mut blobs: Vec<_> = self
+            .data
+            .files
+            .iter()
+            .filter(|f| f.file_type == "blob")
+            .cloned()
+            .collect();
+        blobs.sort_by(|a, b| a.path.cmp(&b.path));
+        blobs
     }
 }
 
@@ -156,182 +324,294 @@ fn default_success() -> bool {
     true
 }
 
-pub mod synchronous {
-    use super::{ApiResponse, FileInfo, Repo, RepoType};
-    use crate::utils::BLOCKING_CLIENT;
-    use reqwest::Error;
-    use std::collections::VecDeque;
+/// 分页文件树接口每页的文件数
+const PAGE_SIZE: usize = 100;
 
-    pub fn get_blob_files(repo: &Repo) -> Result<Vec<FileInfo>, Error> {
-        let repo_files = get_repo_files(repo)?;
-        let blobs = repo_files
-            .data
-            .files
-            .into_iter()
-            .filter(|f| f.file_type == "blob")
-            .collect();
-        Ok(blobs)
+/// 同时请求的分页数上限
+const PAGE_CONCURRENCY: usize = 8;
+
+/// 首页之后还需请求的页码。`TotalCount` 缺失或为 0 而首页已满时无从得知总页数，返回 `None`，
+/// 需逐页请求直到某页不满
+fn remaining_pages(first: &ApiResponse) -> Option<std::ops::Range<usize>> {
+    match first.data.total_count.filter(|&total| total > 0) {
+        Some(total) => Some(1..(total as usize).div_ceil(PAGE_SIZE)),
+        None if first.data.files.len() < PAGE_SIZE => Some(1..1),
+        None => None,
+    }
+}
+
+/// 将其余各页按页码顺序并入首页
+fn merge_pages(first: &mut ApiResponse, pages: Vec<ApiResponse>) {
+    for page in pages {
+        first.rate_limit = RateLimitStatus::tighter(first.rate_limit, page.rate_limit);
+        first.data.files.extend(page.data.files);
+    }
+    first.data.total_count = Some(first.data.files.len() as i32);
+}
+
+/// 分页文件树接口的路径：数据集为 `datasets`，创空间（Space）为 `studios`
+fn tree_api(repo: &Repo) -> &'static str {
+    match repo.repo_type() {
+        RepoType::Space => "studios",
+        _ => "datasets",
     }
+}
 
-    pub fn get_repo_files(repo: &Repo) -> Result<ApiResponse, Error> {
+pub mod synchronous {
+    use super::{
+        ApiResponse, PAGE_CONCURRENCY, PAGE_SIZE, Repo, RepoType, merge_pages, remaining_pages,
+        tree_api,
+    };
+    use crate::utils::OpsError;
+    use reqwest::blocking::Client;
+    use std::ops::Range;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    pub fn get_repo_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        repo: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
         match repo.repo_type() {
-            RepoType::Model => get_model_files(repo),
-            RepoType::Dataset => get_dataset_files(repo),
-            RepoType::Space => unimplemented!(),
+            RepoType::Model => Ok(get_model_files(client, endpoint, token, repo)?),
+            RepoType::Dataset | RepoType::Space => {
+                Ok(get_tree_files(client, endpoint, token, repo)?)
+            }
         }
     }
 
-    fn get_model_files(repo: &Repo) -> Result<ApiResponse, Error> {
+    fn get_model_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        repo: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
         let repo_id = repo.repo_id();
         let revision = repo.revision();
         let repo_url = format!(
-            "https://modelscope.cn/api/v1/models/{repo_id}/repo/files?Recursive=true&Revision={revision}"
+            "{endpoint}/api/v1/models/{repo_id}/repo/files?Recursive=true&Revision={revision}"
         );
-        Ok(BLOCKING_CLIENT.get(&repo_url).send()?.json()?)
+        let mut request = client.get(&repo_url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        ApiResponse::from_response(request.send()?, repo_id)
     }
 
-    /// 获取数据集所有分页文件
-    fn get_dataset_files(dataset: &Repo) -> Result<ApiResponse, Error> {
-        let mut all_files = VecDeque::new();
-        let page_number = 0;
-        const PAGE_SIZE: usize = 100; // 每页最大数量
-
-        // 初始请求获取第一页数据
-        let mut response = request_dataset_page(dataset, page_number, PAGE_SIZE)?;
-        all_files.extend(response.data.files);
-
-        // 计算总页数
-        let total_pages =
-            (response.data.total_count.unwrap_or(0) as f64 / PAGE_SIZE as f64).ceil() as usize;
-
-        // 并行请求剩余页数
-        let mut handles = vec![];
-        for page in 1..total_pages {
-            let dataset = dataset.clone();
-            handles.push(std::thread::spawn(move || {
-                request_dataset_page(&dataset, page, PAGE_SIZE)
-            }));
-        }
+    /// 获取数据集或创空间所有分页文件
+    fn get_tree_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        dataset: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
+        let mut response = request_tree_page(client, endpoint, token, dataset, 0, PAGE_SIZE)?;
+        let pages = match remaining_pages(&response) {
+            Some(pages) => request_tree_pages(client, endpoint, token, dataset, pages)?,
+            None => walk_tree_pages(client, endpoint, token, dataset)?,
+        };
+        merge_pages(&mut response, pages);
+        Ok(response)
+    }
 
-        // 收集所有结果
-        for handle in handles {
-            let page_response = handle.join().unwrap()?;
-            all_files.extend(page_response.data.files);
-        }
+    /// 以至多 [`PAGE_CONCURRENCY`] 个线程并行请求 `pages`，按页码顺序返回。
+    /// 任一页失败后不再派发新页，并返回页码最小的错误
+    fn request_tree_pages(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        dataset: &Repo,
+        pages: Range<usize>,
+    ) -> Result<Vec<ApiResponse>, OpsError> {
+        let next = AtomicUsize::new(pages.start);
+        let failed = AtomicBool::new(false);
+        let workers = PAGE_CONCURRENCY.min(pages.len());
+        let fetched = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut fetched = Vec::new();
+                        while !failed.load(Ordering::SeqCst) {
+                            let page = next.fetch_add(1, Ordering::SeqCst);
+                            if page >= pages.end {
+                                break;
+                            }
+                            let result = request_tree_page(
+                                client, endpoint, token, dataset, page, PAGE_SIZE,
+                            );
+                            failed.fetch_or(result.is_err(), Ordering::SeqCst);
+                            fetched.push((page, result));
+                        }
+                        fetched
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .map_err(|_| OpsError::HubError("a listing worker panicked".into()))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        let mut fetched: Vec<_> = fetched.into_iter().flatten().collect();
+        fetched.sort_by_key(|(page, _)| *page);
+        fetched.into_iter().map(|(_, result)| result).collect()
+    }
 
-        // 合并所有结果
-        response.data.files = all_files.into_iter().collect();
-        response.data.total_count = Some(response.data.files.len() as i32);
-        Ok(response)
+    /// 总页数未知时逐页请求，直到某页不满
+    fn walk_tree_pages(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        dataset: &Repo,
+    ) -> Result<Vec<ApiResponse>, OpsError> {
+        let mut pages = Vec::new();
+        for page_number in 1.. {
+            let page = request_tree_page(client, endpoint, token, dataset, page_number, PAGE_SIZE)?;
+            let last = page.data.files.len() < PAGE_SIZE;
+            pages.push(page);
+            if last {
+                break;
+            }
+        }
+        Ok(pages)
     }
 
-    /// 请求单页数据集文件
-    fn request_dataset_page(
+    /// 请求单页数据集或创空间文件
+    fn request_tree_page(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
         dataset: &Repo,
         page_number: usize,
         page_size: usize,
-    ) -> Result<ApiResponse, Error> {
+    ) -> Result<ApiResponse, OpsError> {
         let repo_id = dataset.repo_id();
         let revision = dataset.safe_revision_path();
+        let api = tree_api(dataset);
         let url = format!(
-            "https://modelscope.cn/api/v1/datasets/{repo_id}/repo/tree?Recursive=true&Revision={revision}&Root=/&PageNumber={page_number}&PageSize={page_size}",
+            "{endpoint}/api/v1/{api}/{repo_id}/repo/tree?Recursive=true&Revision={revision}&Root=/&PageNumber={page_number}&PageSize={page_size}",
         );
-        let response = BLOCKING_CLIENT.get(&url).send()?.json::<ApiResponse>()?;
+        let mut request = client.get(&url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = ApiResponse::from_response(request.send()?, repo_id)?;
         Ok(response)
     }
 }
 
 #[cfg(feature = "tokio")]
 pub mod asynchronous {
-    use super::{ApiResponse, FileInfo};
-    use reqwest::Error;
+    use super::{ApiResponse, PAGE_CONCURRENCY, PAGE_SIZE, merge_pages, remaining_pages, tree_api};
     use crate::repo::{Repo, RepoType};
-    use crate::utils::ASYNC_CLIENT;
-    use std::collections::VecDeque;
-
-    pub async fn get_blob_files(repo: &Repo) -> Result<Vec<FileInfo>, Error> {
-        let repo_files = get_repo_files(repo).await?;
-        let blobs = repo_files
-            .data
-            .files
-            .into_iter()
-            .filter(|f| f.file_type == "blob")
-            .collect();
-        Ok(blobs)
-    }
-
-    pub async fn get_repo_files(repo: &Repo) -> Result<ApiResponse, Error> {
+    use crate::utils::OpsError;
+    use futures::{StreamExt, TryStreamExt};
+    use reqwest::Client;
+
+    pub async fn get_repo_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        repo: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
         match repo.repo_type() {
-            RepoType::Model => get_model_files(repo).await,
-            RepoType::Dataset => get_dataset_files(repo).await,
-            RepoType::Space => unimplemented!(),
+            RepoType::Model => Ok(get_model_files(client, endpoint, token, repo).await?),
+            RepoType::Dataset | RepoType::Space => {
+                Ok(get_tree_files(client, endpoint, token, repo).await?)
+            }
         }
     }
 
-    async fn get_model_files(repo: &Repo) -> Result<ApiResponse, Error> {
+    async fn get_model_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        repo: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
         let repo_id = repo.repo_id();
         let revision = repo.revision();
         let repo_url = format!(
-            "https://modelscope.cn/api/v1/models/{repo_id}/repo/files?Recursive=true&Revision={revision}"
+            "{endpoint}/api/v1/models/{repo_id}/repo/files?Recursive=true&Revision={revision}"
         );
 
-        let response = ASYNC_CLIENT
-            .get(&repo_url)
-            .send()
-            .await?
-            .json::<ApiResponse>()
-            .await?;
+        let mut request = client.get(&repo_url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = ApiResponse::from_async_response(request.send().await?, repo_id).await?;
         Ok(response)
     }
 
-    async fn get_dataset_files(dataset: &Repo) -> Result<ApiResponse, Error> {
-        let mut all_files = VecDeque::new();
-        const PAGE_SIZE: usize = 100;
-
-        // 初始请求获取第一页数据
-        let mut response = request_dataset_page(dataset, 0, PAGE_SIZE).await?;
-        all_files.extend(response.data.files);
-
-        // 计算总页数
-        let total_pages =
-            (response.data.total_count.unwrap_or(0) as f64 / PAGE_SIZE as f64).ceil() as usize;
-
-        // 使用异步任务并行请求
-        let mut handles = vec![];
-        for page in 1..total_pages {
-            let dataset = dataset.clone();
-            handles.push(async move { request_dataset_page(&dataset, page, PAGE_SIZE).await });
-        }
+    /// 获取数据集或创空间所有分页文件
+    async fn get_tree_files(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        dataset: &Repo,
+    ) -> Result<ApiResponse, OpsError> {
+        let mut response =
+            request_tree_page(client, endpoint, token, dataset, 0, PAGE_SIZE).await?;
+        let pages = match remaining_pages(&response) {
+            // 至多同时请求 PAGE_CONCURRENCY 页，buffered 按页码顺序返回，与各页完成的先后无关
+            Some(pages) => {
+                futures::stream::iter(pages)
+                    .map(|page| {
+                        request_tree_page(client, endpoint, token, dataset, page, PAGE_SIZE)
+                    })
+                    .buffered(PAGE_CONCURRENCY)
+                    .try_collect()
+                    .await?
+            }
+            None => walk_tree_pages(client, endpoint, token, dataset).await?,
+        };
+        merge_pages(&mut response, pages);
+        Ok(response)
+    }
 
-        // 并行收集结果
-        let results = futures::future::join_all(handles).await;
-        for result in results {
-            let page_response = result?;
-            all_files.extend(page_response.data.files);
+    /// 总页数未知时逐页请求，直到某页不满
+    async fn walk_tree_pages(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
+        dataset: &Repo,
+    ) -> Result<Vec<ApiResponse>, OpsError> {
+        let mut pages = Vec::new();
+        for page_number in 1.. {
+            let page =
+                request_tree_page(client, endpoint, token, dataset, page_number, PAGE_SIZE).await?;
+            let last = page.data.files.len() < PAGE_SIZE;
+            pages.push(page);
+            if last {
+                break;
+            }
         }
-
-        // 合并所有结果
-        response.data.files = all_files.into_iter().collect();
-        response.data.total_count = Some(response.data.files.len() as i32);
-        Ok(response)
+        Ok(pages)
     }
 
-    async fn request_dataset_page(
+    /// 请求单页数据集或创空间文件
+    async fn request_tree_page(
+        client: &Client,
+        endpoint: &str,
+        token: Option<&str>,
         dataset: &Repo,
         page_number: usize,
         page_size: usize,
-    ) -> Result<ApiResponse, Error> {
+    ) -> Result<ApiResponse, OpsError> {
         let repo_id = dataset.repo_id();
         let revision = dataset.safe_revision_path();
+        let api = tree_api(dataset);
         let url = format!(
-            "https://modelscope.cn/api/v1/datasets/{repo_id}/repo/tree?Recursive=true&Revision={revision}&Root=/&PageNumber={page_number}&PageSize={page_size}",
+            "{endpoint}/api/v1/{api}/{repo_id}/repo/tree?Recursive=true&Revision={revision}&Root=/&PageNumber={page_number}&PageSize={page_size}",
         );
-        let response = ASYNC_CLIENT
-            .get(&url)
-            .send()
-            .await?
-            .json::<ApiResponse>()
-            .await?;
+        let mut request = client.get(&url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response = ApiResponse::from_async_response(request.send().await?, repo_id).await?;
 
         Ok(response)
     }
@@ -340,11 +620,18 @@ pub mod asynchronous {
 #[cfg(test)]
 mod synchronous_tests {
     use super::synchronous::*;
+    use crate::endpoints::MODELSCOPE_CN as DEFAULT_ENDPOINT;
     use crate::repo::Repo;
+    use crate::utils::BLOCKING_CLIENT;
 
     #[test]
     fn test_get_hub_files() {
-        let result = get_repo_files(&Repo::new_model("BAAI/bge-large-zh-v1.5".into()));
+        let result = get_repo_files(
+            &BLOCKING_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_model("BAAI/bge-large-zh-v1.5"),
+        );
 
         match result {
             Ok(response) => {
@@ -358,7 +645,12 @@ mod synchronous_tests {
             }
         }
 
-        let result = get_repo_files(&&Repo::new_dataset("DAMO_NLP/yf_dianping".into()));
+        let result = get_repo_files(
+            &BLOCKING_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_dataset("DAMO_NLP/yf_dianping"),
+        );
         match result {
             Ok(response) => {
                 assert_eq!(response.code, 200);
@@ -374,7 +666,12 @@ mod synchronous_tests {
 
     #[test]
     fn test_get_commit_hash() {
-        let result = get_repo_files(&Repo::new_model("BAAI/bge-large-zh-v1.5".into()));
+        let result = get_repo_files(
+            &BLOCKING_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_model("BAAI/bge-large-zh-v1.5"),
+        );
 
         match result {
             Ok(response) => {
@@ -402,12 +699,20 @@ mod synchronous_tests {
 #[cfg(test)]
 mod asynchronous_tests {
     use super::asynchronous::*;
+    use crate::endpoints::MODELSCOPE_CN as DEFAULT_ENDPOINT;
     use crate::repo::Repo;
+    use crate::utils::ASYNC_CLIENT;
     use tokio::test;
 
     #[test]
     async fn test_get_hub_files() {
-        let result = get_repo_files(&Repo::new_model("BAAI/bge-large-zh-v1.5")).await;
+        let result = get_repo_files(
+            &ASYNC_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_model("BAAI/bge-large-zh-v1.5"),
+        )
+        .await;
 
         match result {
             Ok(response) => {
@@ -421,7 +726,13 @@ mod asynchronous_tests {
             }
         }
 
-        let result = get_repo_files(&&Repo::new_dataset("DAMO_NLP/yf_dianping".into())).await;
+        let result = get_repo_files(
+            &ASYNC_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_dataset("DAMO_NLP/yf_dianping"),
+        )
+        .await;
         match result {
             Ok(response) => {
                 assert_eq!(response.code, 200);
@@ -437,7 +748,13 @@ mod asynchronous_tests {
 
     #[test]
     async fn test_get_commit_hash() {
-        let result = get_repo_files(&Repo::new_model("BAAI/bge-large-zh-v1.5".into())).await;
+        let result = get_repo_files(
+            &ASYNC_CLIENT,
+            DEFAULT_ENDPOINT,
+            None,
+            &Repo::new_model("BAAI/bge-large-zh-v1.5"),
+        )
+        .await;
 
         match result {
             Ok(response) => {
@@ -460,3 +777,171 @@ mod asynchronous_tests {
         }
     }
 }
+
+#[cfg(test)]
+mod listing_tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// A listing of `files` files as the hub returns it, each carrying the commit fields the
+    /// download flow does not read.
+    fn listing(files: usize) -> String {
+        let files: Vec<_> = (0..files)
+            .map(|i| {
+                let sha256 = (i % 3 != 0).then(|| format!("{i:064x}"));
+                serde_json::json!({
+                    "Id": format!("{i:040x}"), "Name": format!("{i}.parquet"), "Type": "blob",
+                    "Path": format!("data/train/{i}.parquet"), "Mode": "100644",
+                    "CommitId": format!("{i:040x}"),
+                    "CommitMessage": "Upload the processed shards of the train split with fixed labels",
+                    "CommitterName": "dataset-bot", "CommittedDate": 1_700_000_000 + i as i64,
+                    "Revision": "master", "IsLFS": i % 2 == 0, "Size": i * 1024,
+                    "InCheck": false, "Sha256": sha256,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "RequestId": "fixture", "Code": 200, "Message": "success",
+            "Data": { "Files": files, "TotalCount": files.len() },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_listing_fields() {
+        let body = listing(100);
+        let response: ApiResponse = serde_json::from_str(&body).unwrap();
+        let full: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let full = full["Data"]["Files"].as_array().unwrap();
+        assert_eq!(response.data.files.len(), full.len());
+        for (file, entry) in response.data.files.iter().zip(full) {
+            assert_eq!(file.file_type, entry["Type"]);
+            assert_eq!(file.path, entry["Path"]);
+            assert_eq!(file.committed_date, entry["CommittedDate"]);
+            assert_eq!(file.revision, entry["Revision"]);
+            assert_eq!(file.is_lfs, entry["IsLFS"]);
+            assert_eq!(file.size, entry["Size"]);
+            assert_eq!(file.sha256.as_deref(), entry["Sha256"].as_str());
+        }
+    }
+
+    /// Parses a 100k-file listing, printing the time taken and the heap held by the parsed
+    /// files. Run with `cargo test --release listing_memory -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_listing_memory() {
+        let body = listing(100_000);
+        let start = Instant::now();
+        let response: ApiResponse = serde_json::from_str(&body).unwrap();
+        let elapsed = start.elapsed();
+        let held: usize = response
+            .data
+            .files
+            .iter()
+            .map(|f| {
+                size_of::<FileInfo>()
+                    + f.file_type.capacity()
+                    + f.path.capacity()
+                    + f.revision.capacity()
+                    + f.sha256.as_ref().map_or(0, String::capacity)
+            })
+            .sum();
+        println!(
+            "{} MiB listing: parsed in {elapsed:?}, {} MiB held",
+            body.len() >> 20,
+            held >> 20
+        );
+    }
+
+    /// Serves a dataset of 250 files in pages of [`PAGE_SIZE`], listing `total` as
+    /// `TotalCount` and failing page `failing` with a 500. Returns the server and the page
+    /// numbers requested.
+    fn tree_server(
+        total: Option<usize>,
+        failing: Option<usize>,
+    ) -> (
+        crate::test_server::MockServer,
+        std::sync::Arc<std::sync::Mutex<Vec<usize>>>,
+    ) {
+        use crate::test_server::{MockServer, Response};
+
+        let requested = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let pages = requested.clone();
+        let server = MockServer::start(move |req| {
+            let page: usize = req
+                .path
+                .split(['?', '&'])
+                .find_map(|param| param.strip_prefix("PageNumber="))
+                .and_then(|n| n.parse().ok())
+                .unwrap();
+            pages.lock().unwrap().push(page);
+            if failing == Some(page) {
+                return Response::status(500);
+            }
+            let files: Vec<_> = (page * PAGE_SIZE..250.min((page + 1) * PAGE_SIZE))
+                .map(|i| {
+                    serde_json::json!({
+                        "Type": "blob", "Path": format!("{i:03}.parquet"),
+                        "CommittedDate": 0, "Revision": "master", "IsLFS": false, "Size": i,
+                    })
+                })
+                .collect();
+            Response::ok(
+                serde_json::json!({
+                    "RequestId": "fixture", "Code": 200, "Message": "success",
+                    "Data": { "Files": files, "TotalCount": total },
+                })
+                .to_string(),
+            )
+        });
+        (server, requested)
+    }
+
+    fn paths(response: &ApiResponse) -> Vec<String> {
+        response.data.files.iter().map(|f| f.path.clone()).collect()
+    }
+
+    #[test]
+    fn test_tree_pages() {
+        let dataset = Repo::new_dataset("DAMO_NLP/yf_dianping");
+        let expected: Vec<String> = (0..250).map(|i| format!("{i:03}.parquet")).collect();
+        for total in [Some(250), None, Some(0)] {
+            let (server, requested) = tree_server(total, None);
+            let client = &crate::utils::BLOCKING_CLIENT;
+            let response =
+                synchronous::get_repo_files(client, &server.url(), None, &dataset).unwrap();
+            assert_eq!(paths(&response), expected, "{total:?}");
+            assert_eq!(response.data.total_count, Some(250));
+            let mut requested = requested.lock().unwrap().clone();
+            requested.sort();
+            assert_eq!(requested, [0, 1, 2], "{total:?}");
+        }
+
+        // A failing page is an error, not a panic.
+        let (server, _) = tree_server(Some(250), Some(1));
+        let client = &crate::utils::BLOCKING_CLIENT;
+        let err = synchronous::get_repo_files(client, &server.url(), None, &dataset).unwrap_err();
+        assert!(matches!(err, OpsError::ServerError { status: 500, .. }));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_tree_pages() {
+        let dataset = Repo::new_dataset("DAMO_NLP/yf_dianping");
+        let client = &crate::utils::ASYNC_CLIENT;
+        for total in [Some(250), None] {
+            let (server, _) = tree_server(total, None);
+            let response = asynchronous::get_repo_files(client, &server.url(), None, &dataset)
+                .await
+                .unwrap();
+            assert_eq!(response.data.files.len(), 250, "{total:?}");
+            assert_eq!(response.data.files[249].path, "249.parquet");
+        }
+        let (server, _) = tree_server(Some(250), Some(2));
+        let result = asynchronous::get_repo_files(client, &server.url(), None, &dataset).await;
+        assert!(matches!(
+            result,
+            Err(OpsError::ServerError { status: 500, .. })
+        ));
+    }
+}
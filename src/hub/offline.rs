@@ -0,0 +1,73 @@
+//! Offline mode, enabled with [`crate::hub::ModelsCat::with_offline`] or the
+//! `MODELS_CAT_OFFLINE` environment variable: files are looked up in the cache only, and the
+//! hub is never queried.
+use super::matching;
+use super::report::{PullReport, Resolution};
+use super::resolved;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use glob::Pattern;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub(crate) const MODELS_CAT_OFFLINE: &str = "MODELS_CAT_OFFLINE";
+
+/// Whether the `MODELS_CAT_OFFLINE` environment variable enables offline mode: `1`, `true`,
+/// `yes` or `on`, in any case.
+pub(crate) fn from_env() -> bool {
+    std::env::var(MODELS_CAT_OFFLINE).is_ok_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        )
+    })
+}
+
+/// The cached copy of `filename`: where the last download or pull placed it, or else the file
+/// of that name in the snapshot the revision resolves to offline.
+pub(crate) fn resolve(repo: &Repo, filename: &str) -> Result<Resolution, OpsError> {
+    if let Some((filepath, file)) = resolved::cached(repo, filename) {
+        return Ok(file.resolution(filepath, false));
+    }
+    let pattern = Pattern::new(&Pattern::escape(filename))
+        .map_err(|e| OpsError::hub("invalid filename", e))?;
+    let (revision, mut files) = snapshot(repo, pattern).map_err(|_| miss(filename))?;
+    let path = files.remove(filename).ok_or_else(|| miss(filename))?;
+    Ok(Resolution {
+        size: std::fs::metadata(&path)?.len(),
+        path,
+        sha256: None,
+        revision,
+        downloaded: false,
+        progress_degraded: false,
+    })
+}
+
+/// The report of a pull finding every file of the snapshot the revision resolves to offline
+/// in place. The snapshot only resolves once a pull completed it.
+pub(crate) fn pull(repo: &Repo) -> Result<PullReport, OpsError> {
+    let (_, files) = snapshot(repo, Pattern::new("**").unwrap())
+        .map_err(|_| miss(&format!("{}@{}", repo.repo_id(), repo.revision().as_str())))?;
+    Ok(PullReport {
+        skipped: files.into_keys().collect(),
+        ..Default::default()
+    })
+}
+
+/// The revision of the offline snapshot of `repo`, with its files matching `pattern`.
+fn snapshot(
+    repo: &Repo,
+    pattern: Pattern,
+) -> Result<(String, BTreeMap<String, PathBuf>), OpsError> {
+    let files = matching::snapshot_files(repo, &pattern)?;
+    let revision = matching::offline_snapshot(repo)
+        .and_then(|snapshot| Some(snapshot.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    Ok((revision, files))
+}
+
+fn miss(file: &str) -> OpsError {
+    OpsError::OfflineMiss {
+        file: file.to_string(),
+    }
+}
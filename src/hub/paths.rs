@@ -0,0 +1,351 @@
+//! Validation of hub paths against the naming rules of the local filesystem.
+use super::ms_hub::FileInfo;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+use tempfile::NamedTempFile;
+
+/// What a pull does with hub paths that cannot be stored on the local filesystem, such as names
+/// containing `:` on Windows or names differing only by case on case-insensitive filesystems.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InvalidPathPolicy {
+    /// Fail before downloading anything, listing the problematic paths.
+    #[default]
+    Error,
+    /// Pull everything else and report the problematic paths in [`crate::PullReport::invalid`].
+    Skip,
+    /// Store the files under escaped names: every offending character, and every `%`, becomes
+    /// `%XX` (its hex byte value), while names colliding by case get all their letters escaped.
+    /// The escaped names are recorded per revision so exports can map them back.
+    Sanitize,
+}
+
+/// Naming rules of a filesystem.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PathRules {
+    /// Windows rules: no `<>:"|?*\`, control characters, trailing dots or spaces, or reserved
+    /// device names.
+    pub windows: bool,
+    /// Names differing only by case refer to the same file.
+    pub case_insensitive: bool,
+}
+
+impl PathRules {
+    /// The rules of the local operating system.
+    pub(crate) fn local() -> Self {
+        Self {
+            windows: cfg!(windows),
+            case_insensitive: cfg!(any(windows, target_os = "macos")),
+        }
+    }
+}
+
+const WINDOWS_FORBIDDEN: &[char] = &['<', '>', ':', '"', '|', '?', '*', '\\'];
+const WINDOWS_RESERVED: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// The files of a pull, with the local name of each and the paths left out.
+#[derive(Debug, Default)]
+pub(crate) struct PathPlan {
+    pub files: Vec<FileInfo>,
+    /// Local names of sanitized files, keyed by hub path.
+    pub sanitized: HashMap<String, String>,
+    /// Paths skipped under [`InvalidPathPolicy::Skip`].
+    pub invalid: Vec<String>,
+}
+
+impl PathPlan {
+    /// The local relative path of a hub file.
+    pub(crate) fn local_path<'a>(&'a self, fileinfo: &'a FileInfo) -> &'a str {
+        self.sanitized
+            .get(&fileinfo.path)
+            .map(String::as_str)
+            .unwrap_or(&fileinfo.path)
+    }
+}
+
+/// The local name of a single requested file. Unlike a pull, a download cannot skip its only
+/// file, so [`InvalidPathPolicy::Skip`] fails like [`InvalidPathPolicy::Error`].
+pub(crate) fn local_name(
+    fileinfo: &FileInfo,
+    policy: InvalidPathPolicy,
+    rules: PathRules,
+) -> Result<String, OpsError> {
+    match problem(&fileinfo.path, rules) {
+        None => Ok(fileinfo.path.clone()),
+        Some(_) if policy == InvalidPathPolicy::Sanitize && is_sanitizable(&fileinfo.path) => {
+            Ok(sanitize(&fileinfo.path, rules))
+        }
+        Some(reason) => Err(OpsError::HubError(format!(
+            "paths invalid on this platform: `{}` ({reason})",
+            fileinfo.path
+        ))),
+    }
+}
+
+/// Path of the manifest mapping the sanitized names of the snapshot of `revision` back to hub
+/// paths.
+fn manifest_path(repo: &Repo, revision: &str) -> PathBuf {
+    repo.cache_dir().join("sanitized").join(revision)
+}
+
+/// Sanitized names in the snapshot of `revision`, mapped to their hub paths.
+pub(crate) fn hub_names(repo: &Repo, revision: &str) -> HashMap<String, String> {
+    std::fs::read(manifest_path(repo, revision))
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Adds the sanitized names among `files` to the manifest of `revision`.
+pub(crate) fn record_sanitized(
+    repo: &Repo,
+    revision: &str,
+    files: &[FileInfo],
+    sanitized: &HashMap<String, String>,
+) -> Result<(), OpsError> {
+    let added: Vec<_> = files
+        .iter()
+        .filter_map(|f| Some((sanitized.get(&f.path)?.clone(), f.path.clone())))
+        .collect();
+    if added.is_empty() {
+        return Ok(());
+    }
+    let mut names: BTreeMap<_, _> = hub_names(repo, revision).into_iter().collect();
+    names.extend(added);
+
+    let path = manifest_path(repo, revision);
+    let parent = path
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let mut temp = NamedTempFile::new_in(parent)?;
+    serde_json::to_writer_pretty(&mut temp, &names)
+        .map_err(|e| OpsError::hub("failed to write sanitized names", e))?;
+    temp.flush()?;
+    temp.persist(&path)
+        .map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// Checks every listed path before anything is downloaded, applying `policy` to the ones that
+/// cannot be stored under `rules`.
+pub(crate) fn plan(
+    blobs: Vec<FileInfo>,
+    policy: InvalidPathPolicy,
+    rules: PathRules,
+) -> Result<PathPlan, OpsError> {
+    let mut plan = PathPlan::default();
+    let mut problems = Vec::new();
+    // Case-folded local paths already taken, mapped to the hub path taking them.
+    let mut taken: HashMap<String, String> = HashMap::new();
+
+    for fileinfo in blobs {
+        let mut local = match problem(&fileinfo.path, rules) {
+            None => fileinfo.path.clone(),
+            Some(_) if policy == InvalidPathPolicy::Sanitize && is_sanitizable(&fileinfo.path) => {
+                sanitize(&fileinfo.path, rules)
+            }
+            Some(reason) => {
+                problems.push((fileinfo, reason));
+                continue;
+            }
+        };
+        if rules.case_insensitive {
+            if let Some(other) = taken.get(&local.to_lowercase()) {
+                if policy != InvalidPathPolicy::Sanitize {
+                    let reason = format!("differs only by case from `{other}`");
+                    problems.push((fileinfo, reason));
+                    continue;
+                }
+                local = escape_letters(&local);
+            }
+            taken.insert(local.to_lowercase(), fileinfo.path.clone());
+        }
+        if local != fileinfo.path {
+            plan.sanitized.insert(fileinfo.path.clone(), local);
+        }
+        plan.files.push(fileinfo);
+    }
+
+    if problems.is_empty() {
+        return Ok(plan);
+    }
+    if policy == InvalidPathPolicy::Skip {
+        plan.invalid = problems.into_iter().map(|(f, _)| f.path).collect();
+        return Ok(plan);
+    }
+    let list: Vec<_> = problems
+        .iter()
+        .map(|(f, reason)| format!("`{}` ({reason})", f.path))
+        .collect();
+    Err(OpsError::HubError(format!(
+        "paths invalid on this platform: {}",
+        list.join(", ")
+    )))
+}
+
+/// Why `path` cannot be stored as is under `rules`, if it cannot.
+pub(crate) fn problem(path: &str, rules: PathRules) -> Option<String> {
+    for part in path.split('/') {
+        if part.is_empty() || part == "." || part == ".." {
+            return Some(format!("invalid component `{part}`"));
+        }
+        if let Some(c) = part.chars().find(|c| *c == '\0') {
+            return Some(format!("character {c:?} not allowed"));
+        }
+        if rules.windows {
+            if let Some(c) = part.chars().find(|c| is_windows_forbidden(*c)) {
+                return Some(format!("character {c:?} not allowed"));
+            }
+            if part.ends_with(['.', ' ']) {
+                return Some("trailing dot or space".to_string());
+            }
+            if is_windows_reserved(part) {
+                return Some("reserved name".to_string());
+            }
+        }
+    }
+    None
+}
+
+/// Whether escaping can make `path` valid: empty components and `.`/`..` stay rejected.
+fn is_sanitizable(path: &str) -> bool {
+    path.split('/')
+        .all(|part| !part.is_empty() && part != "." && part != "..")
+}
+
+/// Escapes each component of `path` so that it satisfies `rules`.
+fn sanitize(path: &str, rules: PathRules) -> String {
+    let parts: Vec<_> = path
+        .split('/')
+        .map(|part| {
+            let chars: Vec<char> = part.chars().collect();
+            let mut escaped = String::new();
+            for (i, &c) in chars.iter().enumerate() {
+                let trailing = i + 1 == chars.len() && matches!(c, '.' | ' ');
+                let reserved = i == 0 && rules.windows && is_windows_reserved(part);
+                if c == '%'
+                    || c == '\0'
+                    || (rules.windows && (is_windows_forbidden(c) || trailing || reserved))
+                {
+                    escaped.push_str(&escape(c));
+                } else {
+                    escaped.push(c);
+                }
+            }
+            escaped
+        })
+        .collect();
+    parts.join("/")
+}
+
+/// Escapes every ASCII letter of the last component of `path`, so it no longer collides by case.
+fn escape_letters(path: &str) -> String {
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (Some(dir), name),
+        None => (None, path),
+    };
+    let name: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphabetic() {
+                escape(c)
+            } else {
+                c.to_string()
+            }
+        })
+        .collect();
+    match dir {
+        Some(dir) => format!("{dir}/{name}"),
+        None => name,
+    }
+}
+
+fn escape(c: char) -> String {
+    let mut buf = [0; 4];
+    c.encode_utf8(&mut buf)
+        .bytes()
+        .map(|b| format!("%{b:02X}"))
+        .collect()
+}
+
+fn is_windows_forbidden(c: char) -> bool {
+    WINDOWS_FORBIDDEN.contains(&c) || c.is_control()
+}
+
+fn is_windows_reserved(part: &str) -> bool {
+    let stem = part.split('.').next().unwrap_or(part).trim_end();
+    WINDOWS_RESERVED
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRICT: PathRules = PathRules {
+        windows: true,
+        case_insensitive: true,
+    };
+
+    fn hostile_listing() -> Vec<FileInfo> {
+        [
+            "ok.txt",
+            "a:b.txt",
+            "dir/trailing.",
+            "CON.json",
+            "README.md",
+            "readme.md",
+        ]
+        .iter()
+        .map(|p| FileInfo::test_blob(p, b"x", "rev1"))
+        .collect()
+    }
+
+    #[test]
+    fn test_policies() {
+        let err = plan(hostile_listing(), InvalidPathPolicy::Error, STRICT).unwrap_err();
+        let msg = err.to_string();
+        for path in ["a:b.txt", "dir/trailing.", "CON.json", "readme.md"] {
+            assert!(msg.contains(&format!("`{path}`")), "{msg}");
+        }
+        assert!(!msg.contains("`ok.txt`"));
+
+        let skip = plan(hostile_listing(), InvalidPathPolicy::Skip, STRICT).unwrap();
+        let kept: Vec<_> = skip.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(kept, ["ok.txt", "README.md"]);
+        assert_eq!(skip.invalid.len(), 4);
+
+        let sanitized = plan(hostile_listing(), InvalidPathPolicy::Sanitize, STRICT).unwrap();
+        assert_eq!(sanitized.files.len(), 6);
+        let local = |p: &str| sanitized.sanitized.get(p).cloned();
+        assert_eq!(local("a:b.txt").as_deref(), Some("a%3Ab.txt"));
+        assert_eq!(local("dir/trailing.").as_deref(), Some("dir/trailing%2E"));
+        assert_eq!(local("CON.json").as_deref(), Some("%43ON.json"));
+        assert_eq!(
+            local("readme.md").as_deref(),
+            Some("%72%65%61%64%6D%65.%6D%64")
+        );
+        assert_eq!(local("ok.txt"), None);
+        for path in sanitized.sanitized.values() {
+            assert_eq!(problem(path, STRICT), None);
+        }
+
+        // Traversal is never sanitized.
+        let listing = vec![FileInfo::test_blob("../x", b"x", "rev1")];
+        assert!(plan(listing, InvalidPathPolicy::Sanitize, STRICT).is_err());
+        // Unix rules accept Windows-only problems.
+        let unix = PathRules {
+            windows: false,
+            case_insensitive: false,
+        };
+        let plan = plan(hostile_listing(), InvalidPathPolicy::Error, unix).unwrap();
+        assert_eq!(plan.files.len(), 6);
+    }
+}
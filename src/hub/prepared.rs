@@ -0,0 +1,64 @@
+//! Snapshots pulled ahead of switching to them, so serving processes can reload a model
+//! without a window where its files are mixed.
+use super::memory_cache::MemoryCache;
+use super::{PullReport, update_latest};
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A complete snapshot of a new revision, not yet pointed at by the ref and `latest` link of
+/// its repo, see [`crate::hub::ModelsCat::prepare_update`].
+pub struct PreparedSnapshot {
+    pub(crate) repo: Repo,
+    pub(crate) revision: String,
+    pub(crate) report: PullReport,
+    pub(crate) memory_cache: Option<Arc<MemoryCache>>,
+}
+
+impl PreparedSnapshot {
+    /// The revision of the snapshot.
+    pub fn revision(&self) -> &str {
+        &self.revision
+    }
+
+    /// The snapshot directory, complete and ready to be loaded from.
+    pub fn path(&self) -> PathBuf {
+        self.repo.snapshot_path(&self.revision)
+    }
+
+    /// What the pull of the snapshot did.
+    pub fn report(&self) -> &PullReport {
+        &self.report
+    }
+
+    /// Points the ref of the repo's branch or tag, then its `latest` link, at the snapshot,
+    /// each atomically, and returns the snapshot directory.
+    ///
+    /// The previous snapshot is left in place, so processes still loading from it are not
+    /// disturbed.
+    pub fn activate(self) -> Result<PathBuf, OpsError> {
+        update_latest(&self.repo, &self.revision)?;
+        if let Some(ref cache) = self.memory_cache {
+            cache.clear();
+        }
+        Ok(self.path())
+    }
+}
+
+impl fmt::Debug for PreparedSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PreparedSnapshot")
+            .field("repo", &self.repo.repo_id())
+            .field("revision", &self.revision)
+            .field("report", &self.report)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Whether the `latest` link of `repo` already points at the snapshot of `revision`.
+pub(crate) fn is_active(repo: &Repo, revision: &str) -> bool {
+    repo.latest_snapshot()
+        .is_some_and(|snapshot| snapshot.ends_with(revision))
+}
@@ -0,0 +1,337 @@
+//! Adapters between the blocking [`super::Progress`] and the async
+//! [`super::async_hub::Progress`], so a reporter written for one hub works with the other.
+use super::async_hub;
+use super::{ProgressEvent, ProgressUnit};
+use crate::utils::OpsError;
+use async_trait::async_trait;
+use std::ops::ControlFlow;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+
+/// Reports the progress of the async hub to a blocking [`super::Progress`].
+///
+/// The blocking callbacks run on tokio's blocking thread pool by default, so a slow one, such
+/// as a send to a full GUI channel, does not hold up the runtime. Each call finishes before the
+/// next starts, so callbacks see events in order.
+///
+/// Clones report to clones of the wrapped progress.
+pub struct AsyncProgressAdapter<P> {
+    progress: Arc<Mutex<P>>,
+    spawn_blocking: bool,
+}
+
+impl<P: super::Progress + 'static> AsyncProgressAdapter<P> {
+    /// Wraps `progress`, calling it on the blocking thread pool.
+    pub fn new(progress: P) -> Self {
+        Self {
+            progress: Arc::new(Mutex::new(progress)),
+            spawn_blocking: true,
+        }
+    }
+
+    /// Whether to call the wrapped progress on the blocking thread pool (the default), or
+    /// directly on the runtime, for callbacks cheap enough not to need a thread.
+    pub fn with_spawn_blocking(mut self, spawn_blocking: bool) -> Self {
+        self.spawn_blocking = spawn_blocking;
+        self
+    }
+
+    /// The wrapped progress.
+    pub fn into_inner(self) -> P {
+        match Arc::try_unwrap(self.progress) {
+            Ok(progress) => progress.into_inner().unwrap(),
+            Err(shared) => shared.lock().unwrap().clone(),
+        }
+    }
+
+    async fn call<T: Send + 'static, R: Send + 'static>(
+        &self,
+        arg: T,
+        callback: fn(&mut P, T) -> Result<R, OpsError>,
+    ) -> Result<R, OpsError> {
+        let progress = self.progress.clone();
+        let call = move || callback(&mut progress.lock().unwrap(), arg);
+        if !self.spawn_blocking {
+            return call();
+        }
+        tokio::task::spawn_blocking(call)
+            .await
+            .map_err(|e| OpsError::hub("progress callback failed", e))?
+    }
+}
+
+impl<P: Clone> Clone for AsyncProgressAdapter<P> {
+    fn clone(&self) -> Self {
+        Self {
+            progress: Arc::new(Mutex::new(self.progress.lock().unwrap().clone())),
+            spawn_blocking: self.spawn_blocking,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: super::Progress + 'static> async_hub::Progress for AsyncProgressAdapter<P> {
+    async fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(unit.clone(), |p, unit| p.on_start(&unit)).await
+    }
+
+    async fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(unit.clone(), |p, unit| p.on_progress(&unit))
+            .await
+    }
+
+    async fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(unit.clone(), |p, unit| p.on_finish(&unit)).await
+    }
+
+    async fn on_progress_control(
+        &mut self,
+        unit: &ProgressUnit,
+    ) -> Result<ControlFlow<()>, OpsError> {
+        self.call(unit.clone(), |p, unit| p.on_progress_control(&unit))
+            .await
+    }
+
+    async fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+        self.call(units.to_vec(), |p, units| p.on_queued(&units))
+            .await
+    }
+}
+
+/// A call forwarded by a [`BlockingProgressAdapter`] to its thread.
+enum Call {
+    Queued(Vec<ProgressUnit>),
+    Event(ProgressEvent),
+    Control(ProgressUnit),
+}
+
+type Request = (Call, mpsc::Sender<Result<ControlFlow<()>, OpsError>>);
+
+/// Reports the progress of the blocking hub to an async [`async_hub::Progress`].
+///
+/// The async progress runs on a dedicated thread with its own runtime, receiving calls over a
+/// channel. Each blocking call waits for its async call to finish, so events keep their order
+/// and errors are returned as usual.
+///
+/// Clones share the thread and the async progress, which stop once every clone is dropped.
+#[derive(Clone)]
+pub struct BlockingProgressAdapter {
+    calls: mpsc::Sender<Request>,
+}
+
+impl BlockingProgressAdapter {
+    /// Starts the thread running `progress`.
+    pub fn new(progress: impl async_hub::Progress) -> Self {
+        let (calls, received) = mpsc::channel::<Request>();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build();
+            let mut progress = progress;
+            for (call, reply) in received {
+                let result = match runtime {
+                    Ok(ref runtime) => runtime.block_on(async {
+                        let result = match call {
+                            Call::Control(unit) => {
+                                return progress.on_progress_control(&unit).await;
+                            }
+                            Call::Queued(units) => progress.on_queued(&units).await,
+                            Call::Event(ProgressEvent::Start(unit)) => {
+                                progress.on_start(&unit).await
+                            }
+                            Call::Event(ProgressEvent::Progress(unit)) => {
+                                progress.on_progress(&unit).await
+                            }
+                            Call::Event(ProgressEvent::Finish(unit)) => {
+                                progress.on_finish(&unit).await
+                            }
+                        };
+                        result.map(|()| ControlFlow::Continue(()))
+                    }),
+                    Err(ref e) => Err(OpsError::HubError(format!(
+                        "failed to start the progress runtime: {e}"
+                    ))),
+                };
+                let _ = reply.send(result);
+            }
+        });
+        Self { calls }
+    }
+
+    fn call(&self, call: Call) -> Result<ControlFlow<()>, OpsError> {
+        let (reply, result) = mpsc::channel();
+        let stopped = || OpsError::HubError("the progress thread stopped".into());
+        self.calls.send((call, reply)).map_err(|_| stopped())?;
+        result.recv().map_err(|_| stopped())?
+    }
+}
+
+impl super::Progress for BlockingProgressAdapter {
+    fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(Call::Event(ProgressEvent::Start(unit.clone())))
+            .map(|_| ())
+    }
+
+    fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(Call::Event(ProgressEvent::Progress(unit.clone())))
+            .map(|_| ())
+    }
+
+    fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+        self.call(Call::Event(ProgressEvent::Finish(unit.clone())))
+            .map(|_| ())
+    }
+
+    fn on_progress_control(&mut self, unit: &ProgressUnit) -> Result<ControlFlow<()>, OpsError> {
+        self.call(Call::Control(unit.clone()))
+    }
+
+    fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+        self.call(Call::Queued(units.to_vec())).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Progress as _;
+    use super::async_hub::Progress as _;
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Records events after `delay`, blocking the calling thread meanwhile.
+    #[derive(Clone, Default)]
+    struct SlowRecorder(Arc<Mutex<Vec<String>>>, Duration);
+
+    impl SlowRecorder {
+        fn record(&self, event: String) -> Result<(), OpsError> {
+            std::thread::sleep(self.1);
+            self.0.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    impl super::super::Progress for SlowRecorder {
+        fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.record(format!("start:{}", unit.filename()))
+        }
+
+        fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.record(format!("progress:{}", unit.current()))
+        }
+
+        fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.record(format!("finish:{}", unit.filename()))
+        }
+
+        fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+            self.record(format!("queued:{}", units.len()))
+        }
+    }
+
+    /// Records events after an async sleep, failing to finish `bad.txt`.
+    #[derive(Clone, Default)]
+    struct AsyncRecorder(Arc<Mutex<Vec<String>>>);
+
+    impl AsyncRecorder {
+        async fn record(&self, event: String) -> Result<(), OpsError> {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            self.0.lock().unwrap().push(event);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl async_hub::Progress for AsyncRecorder {
+        async fn on_start(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.record(format!("start:{}", unit.filename())).await
+        }
+
+        async fn on_progress(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            self.record(format!("progress:{}", unit.current())).await
+        }
+
+        async fn on_finish(&mut self, unit: &ProgressUnit) -> Result<(), OpsError> {
+            if unit.filename() == "bad.txt" {
+                return Err(OpsError::HubError("rejected".into()));
+            }
+            self.record(format!("finish:{}", unit.filename())).await
+        }
+
+        async fn on_queued(&mut self, units: &[ProgressUnit]) -> Result<(), OpsError> {
+            self.record(format!("queued:{}", units.len())).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_adapter() {
+        let recorder = SlowRecorder(Default::default(), Duration::from_millis(50));
+        let mut adapter = AsyncProgressAdapter::new(recorder.clone());
+        let mut unit = ProgressUnit::new("a.txt".into(), 3);
+        let ticks = Arc::new(Mutex::new(0));
+        let counted = ticks.clone();
+
+        // The runtime has a single thread, which the slow callbacks must leave free.
+        let calls = async {
+            adapter.on_queued(std::slice::from_ref(&unit)).await?;
+            adapter.on_start(&unit).await?;
+            unit.update(3);
+            adapter.on_progress(&unit).await?;
+            adapter.on_finish(&unit).await
+        };
+        let ticker = async {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                *counted.lock().unwrap() += 1;
+            }
+        };
+        tokio::select! {
+            result = calls => result.unwrap(),
+            _ = ticker => unreachable!(),
+        }
+        assert!(*ticks.lock().unwrap() >= 10);
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            ["queued:1", "start:a.txt", "progress:3", "finish:a.txt"]
+        );
+
+        let mut inline =
+            AsyncProgressAdapter::new(SlowRecorder::default()).with_spawn_blocking(false);
+        inline.on_start(&unit).await.unwrap();
+        assert_eq!(*inline.into_inner().0.lock().unwrap(), ["start:a.txt"]);
+    }
+
+    #[test]
+    fn test_blocking_adapter() {
+        let recorder = AsyncRecorder::default();
+        let mut adapter = BlockingProgressAdapter::new(recorder.clone());
+        let mut unit = ProgressUnit::new("a.txt".into(), 3);
+        adapter.on_queued(std::slice::from_ref(&unit)).unwrap();
+        adapter.on_start(&unit).unwrap();
+        unit.update(3);
+        adapter.on_progress(&unit).unwrap();
+        adapter.clone().on_finish(&unit).unwrap();
+        let bad = ProgressUnit::new("bad.txt".into(), 3);
+        assert!(matches!(
+            adapter.on_finish(&bad),
+            Err(OpsError::HubError(_))
+        ));
+        assert_eq!(
+            std::mem::take(&mut *recorder.0.lock().unwrap()),
+            ["queued:1", "start:a.txt", "progress:3", "finish:a.txt"]
+        );
+
+        // Reports a pull of the blocking hub.
+        let cache = tempfile::tempdir().unwrap();
+        let (server, _) = super::super::ms_hub::test_hub(&[("a.txt", b"aaa")], "rev1");
+        let mut repo = crate::repo::Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let cat = super::super::ModelsCat::new_with_endpoint(repo, server.url());
+        let start = Instant::now();
+        cat.pull_with_progress(adapter).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let events = recorder.0.lock().unwrap().clone();
+        assert_eq!(events.first().map(String::as_str), Some("queued:1"));
+        assert_eq!(events[1], "start:a.txt");
+        assert_eq!(events.last().map(String::as_str), Some("finish:a.txt"));
+    }
+}
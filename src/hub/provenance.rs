@@ -0,0 +1,115 @@
+//! Where and when each cached file was downloaded, recorded per snapshot so the origin of
+//! every artifact can be exported for audits.
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+
+/// The client version recorded with each download.
+const CLIENT_VERSION: &str = concat!("models-cat/", env!("CARGO_PKG_VERSION"));
+
+/// The origin of a cached file, see [`crate::hub::ModelsCat::provenance`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Provenance {
+    /// Recorded when the file was downloaded.
+    Recorded(ProvenanceRecord),
+    /// Not recorded, as for files downloaded by versions without provenance tracking or
+    /// linked from another repo through the global dedup index.
+    Unknown,
+}
+
+/// Where and when a file was downloaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProvenanceRecord {
+    /// The hub endpoint the download was requested from.
+    pub endpoint: String,
+    /// The URL that served the bytes, after redirects, such as a mirror or CDN.
+    pub url: String,
+    /// When the download finished, in seconds since the Unix epoch.
+    pub downloaded_at: u64,
+    /// The models-cat version that downloaded the file, such as `models-cat/0.1.0`.
+    pub client_version: String,
+    /// The sha256 of the downloaded bytes.
+    pub sha256: String,
+    /// The ETag the server sent with the bytes, if any. Missing from records written by older
+    /// versions.
+    #[serde(default)]
+    pub etag: Option<String>,
+}
+
+impl ProvenanceRecord {
+    /// A record of a download from `endpoint` just served by `url` with `etag`.
+    pub(crate) fn new(endpoint: &str, url: &str, sha256: String, etag: Option<String>) -> Self {
+        Self {
+            endpoint: endpoint.to_string(),
+            url: url.to_string(),
+            downloaded_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            client_version: CLIENT_VERSION.to_string(),
+            sha256,
+            etag,
+        }
+    }
+}
+
+/// Path of the provenance manifest of the snapshot of `revision`.
+fn manifest_path(repo: &Repo, revision: &str) -> PathBuf {
+    repo.cache_dir().join("provenance").join(revision)
+}
+
+fn load(path: &Path) -> BTreeMap<String, ProvenanceRecord> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Records where `filename` of the snapshot of `revision` was downloaded from.
+pub(crate) fn record(
+    repo: &Repo,
+    revision: &str,
+    filename: &str,
+    record: ProvenanceRecord,
+) -> Result<(), OpsError> {
+    let path = manifest_path(repo, revision);
+    let mut manifest = load(&path);
+    manifest.insert(filename.to_string(), record);
+    save(&path, &manifest)
+}
+
+/// Drops the record of `filename`, whose file was placed by other means than a download.
+pub(crate) fn forget(repo: &Repo, revision: &str, filename: &str) -> Result<(), OpsError> {
+    let path = manifest_path(repo, revision);
+    let mut manifest = load(&path);
+    if manifest.remove(filename).is_none() {
+        return Ok(());
+    }
+    save(&path, &manifest)
+}
+
+fn save(path: &Path, manifest: &BTreeMap<String, ProvenanceRecord>) -> Result<(), OpsError> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let mut temp = NamedTempFile::new_in(parent)?;
+    serde_json::to_writer_pretty(&mut temp, manifest)
+        .map_err(|e| OpsError::hub("failed to write provenance", e))?;
+    temp.flush()?;
+    temp.persist(path).map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// The recorded provenance of `filename` in the snapshot of `revision`.
+pub(crate) fn lookup(repo: &Repo, revision: &str, filename: &str) -> Provenance {
+    load(&manifest_path(repo, revision))
+        .remove(filename)
+        .map_or(Provenance::Unknown, Provenance::Recorded)
+}
@@ -0,0 +1,197 @@
+//! Removal of what the cache of a repo no longer needs, see [`crate::hub::ModelsCat::prune`]:
+//! snapshots of revisions nothing refers to, leftover temporary and lock files, and blobs no
+//! snapshot links to.
+use super::blobs;
+use super::ms_hub::FileInfo;
+use super::report::PruneReport;
+use super::resolved;
+use crate::cache;
+use crate::dedup;
+use crate::diagnostics::read_refs;
+use crate::fslock::FsLock;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How old a temporary file outside the snapshots must be to count as left over. Refs and
+/// records are written there without a lock, so a younger one may be about to be renamed.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Records kept per revision next to the snapshots, removed along with a snapshot.
+const REVISION_RECORDS: [&str; 3] = ["complete", "sanitized", "provenance"];
+
+/// The revisions whose snapshots the cache of `repo` still refers to: the ones its refs and
+/// `latest` point at, the ones holding the listed `blobs` of the configured revision, and the
+/// ones downloads of it placed files in.
+pub(crate) fn referenced(repo: &Repo, blobs: &[FileInfo]) -> HashSet<String> {
+    let mut revisions: HashSet<String> = read_refs(&repo.cache_dir().join("refs"))
+        .into_values()
+        .collect();
+    revisions.extend(
+        repo.latest_snapshot()
+            .and_then(|snapshot| Some(snapshot.file_name()?.to_string_lossy().into_owned())),
+    );
+    revisions.extend(blobs.iter().map(|f| f.revision.clone()));
+    revisions.extend(resolved::revisions(repo));
+    revisions
+}
+
+/// Prunes the cache of `repo`, keeping the snapshots of the revisions in `keep`.
+///
+/// Every other snapshot is removed under its lock, and left alone while a download holds it.
+pub(crate) fn prune(
+    repo: &Repo,
+    keep: &HashSet<String>,
+    dry_run: bool,
+) -> Result<PruneReport, OpsError> {
+    let mut report = PruneReport {
+        dry_run,
+        ..Default::default()
+    };
+    let snapshots_dir = repo.cache_dir().join("snapshots");
+    let mut dropped = Vec::new();
+    if snapshots_dir.is_dir() {
+        for entry in std::fs::read_dir(&snapshots_dir)? {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let revision = entry.file_name().to_string_lossy().into_owned();
+            let snapshot = entry.path();
+            let mut lock = match dry_run {
+                true if is_held(&snapshot)? => None,
+                true => Some(None),
+                false => FsLock::try_lock(snapshot.clone())?.map(Some),
+            };
+            let Some(ref mut lock) = lock else {
+                report.busy.push(revision);
+                continue;
+            };
+            let result = match keep.contains(&revision) {
+                true => remove_temp_files(&snapshot, &mut report),
+                false => {
+                    let size = cache::size_on_disk(&snapshot)?;
+                    let removed = match dry_run {
+                        true => Ok(()),
+                        false => remove_snapshot(repo, &revision, &snapshot),
+                    };
+                    removed.map(|()| {
+                        report.freed_bytes += size;
+                        report.snapshots.push(revision);
+                        dropped.push(snapshot);
+                    })
+                }
+            };
+            if let Some(lock) = lock {
+                lock.unlock();
+            }
+            result?;
+        }
+    }
+    report.snapshots.sort();
+    report.busy.sort();
+    remove_stale_files(repo, &mut report)?;
+
+    for blob in blobs::unlinked(repo, &dropped)? {
+        report.freed_bytes += std::fs::metadata(&blob)?.len();
+        if !dry_run {
+            std::fs::remove_file(&blob)?;
+        }
+        report.files.push(blob);
+    }
+    Ok(report)
+}
+
+fn remove_snapshot(repo: &Repo, revision: &str, snapshot: &Path) -> Result<(), OpsError> {
+    std::fs::remove_dir_all(snapshot)?;
+    dedup::DedupIndex::new(repo.cache_home()).forget(snapshot)?;
+    for record in REVISION_RECORDS {
+        match std::fs::remove_file(repo.cache_dir().join(record).join(revision)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Removes the lock files no one holds, and the temporary files outside the snapshots older
+/// than [`STALE_AFTER`].
+fn remove_stale_files(repo: &Repo, report: &mut PruneReport) -> Result<(), OpsError> {
+    let snapshots_dir = repo.cache_dir().join("snapshots");
+    let entries: Vec<PathBuf> = walkdir::WalkDir::new(repo.cache_dir())
+        .min_depth(1)
+        .into_iter()
+        .filter_entry(|e| e.path().parent() != Some(&snapshots_dir) || !e.file_type().is_dir())
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect();
+    for path in entries {
+        if path.extension().is_some_and(|ext| ext == "lock") {
+            // Taking the lock shows no one holds it; unlocking removes the file.
+            if let Some(mut lock) = FsLock::try_lock(path.clone())? {
+                if !report.dry_run {
+                    lock.unlock();
+                }
+                report.files.push(path);
+            }
+        } else if is_temp(&path) {
+            remove_temp_file(path, Some(STALE_AFTER), report)?;
+        }
+    }
+    Ok(())
+}
+
+/// Removes the temporary files under `dir`, a snapshot the caller has locked.
+fn remove_temp_files(dir: &Path, report: &mut PruneReport) -> Result<(), OpsError> {
+    let temps: Vec<PathBuf> = walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_temp(e.path()))
+        .map(|e| e.into_path())
+        .collect();
+    for path in temps {
+        remove_temp_file(path, None, report)?;
+    }
+    Ok(())
+}
+
+/// Removes the temporary file at `path` when older than `min_age`, if any.
+fn remove_temp_file(
+    path: PathBuf,
+    min_age: Option<Duration>,
+    report: &mut PruneReport,
+) -> Result<(), OpsError> {
+    let metadata = std::fs::metadata(&path)?;
+    if let Some(min_age) = min_age {
+        let age = SystemTime::now()
+            .duration_since(metadata.modified()?)
+            .unwrap_or_default();
+        if age < min_age {
+            return Ok(());
+        }
+    }
+    if !report.dry_run {
+        std::fs::remove_file(&path)?;
+    }
+    report.freed_bytes += metadata.len();
+    report.files.push(path);
+    Ok(())
+}
+
+/// Whether a download holds the lock of `snapshot`, checked without leaving a lock file.
+fn is_held(snapshot: &Path) -> Result<bool, OpsError> {
+    if !snapshot.with_extension("lock").exists() {
+        return Ok(false);
+    }
+    Ok(FsLock::try_lock(snapshot.to_path_buf())?.is_none())
+}
+
+/// Whether `path` is a temporary file: named `*.tmp`, or `.tmp*` as written before a rename.
+fn is_temp(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(".tmp") || name.starts_with(".tmp"))
+}
@@ -0,0 +1,178 @@
+//! Downloads failing their checksum, kept under `<cache_home>/quarantine` along with a report of
+//! what was expected and where they came from, so a bad CDN, disk or listing can be told apart.
+//!
+//! Each entry is `<timestamp>-<filename>` with a `<timestamp>-<filename>.json` report, the
+//! timestamp in milliseconds and `/` of the filename replaced with `_`. The area is capped in
+//! size, evicting the oldest entries first.
+use super::staging::StagedFile;
+use super::validate::HubFileInfo;
+use crate::utils::OpsError;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const QUARANTINE_DIR: &str = "quarantine";
+const REPORT_EXTENSION: &str = "json";
+
+/// The report stored next to a quarantined download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct QuarantineReport {
+    /// The hub filename.
+    pub(crate) file: String,
+    /// The revision the hub listed for the file.
+    pub(crate) revision: String,
+    /// The listed sha256.
+    pub(crate) expected: String,
+    /// The sha256 of the downloaded bytes.
+    pub(crate) actual: String,
+    /// The URL the bytes were served from, after redirects.
+    pub(crate) url: String,
+    /// The hub endpoint the download was requested from.
+    pub(crate) endpoint: String,
+    /// The headers of the response.
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+/// The headers of a response, as stored in a [`QuarantineReport`].
+pub(crate) fn headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// The quarantine area of the cache at `cache_home`.
+pub(crate) fn dir(cache_home: &Path) -> PathBuf {
+    cache_home.join(QUARANTINE_DIR)
+}
+
+/// The error of a download whose sha256 is not the listed one. With a `max_bytes` cap, the
+/// download is moved into the quarantine area first and the error holds its path; otherwise,
+/// or when it does not fit, it is deleted along with `staged`.
+pub(crate) fn mismatch(
+    cache_home: &Path,
+    max_bytes: Option<u64>,
+    staged: StagedFile,
+    file: &HubFileInfo,
+    report: QuarantineReport,
+) -> OpsError {
+    let quarantined = max_bytes.and_then(|max_bytes| {
+        store(cache_home, max_bytes, staged, &report)
+            .inspect_err(|e| log::warn!("failed to quarantine {}: {e}", file.path))
+            .ok()
+            .flatten()
+    });
+    OpsError::ChecksumMismatch {
+        file: report.file,
+        expected: report.expected,
+        actual: report.actual,
+        quarantined,
+    }
+}
+
+/// Moves `staged` into the quarantine area with `report`, then evicts the oldest entries
+/// until the area is within `max_bytes`. Returns the quarantined file, or `None` when it is
+/// larger than `max_bytes` on its own and was deleted instead.
+fn store(
+    cache_home: &Path,
+    max_bytes: u64,
+    staged: StagedFile,
+    report: &QuarantineReport,
+) -> Result<Option<PathBuf>, OpsError> {
+    let size = std::fs::metadata(staged.path())?.len();
+    if size > max_bytes {
+        return Ok(None);
+    }
+    let dir = dir(cache_home);
+    std::fs::create_dir_all(&dir)?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let name = report.file.replace(['/', '\\'], "_");
+    // A retry failing within the same millisecond gets a numbered entry of its own.
+    let mut path = dir.join(format!("{millis:013}-{name}"));
+    for n in 1.. {
+        if !std::fs::exists(&path)? && !std::fs::exists(report_path(&path))? {
+            break;
+        }
+        path = dir.join(format!("{millis:013}-{name}.{n}"));
+    }
+    let content = serde_json::to_vec_pretty(report)
+        .map_err(|e| OpsError::hub("failed to write quarantine report", e))?;
+    std::fs::write(report_path(&path), content)?;
+    if let Err(e) = staged.persist(&path) {
+        let _ = std::fs::remove_file(report_path(&path));
+        return Err(e);
+    }
+    evict(&dir, max_bytes, &path)?;
+    Ok(Some(path))
+}
+
+/// Removes the oldest entries but `keep` until the entries of `dir` total `max_bytes` or less.
+fn evict(dir: &Path, max_bytes: u64, keep: &Path) -> Result<(), OpsError> {
+    let mut entries = entries(dir)?;
+    let mut total: u64 = entries.iter().map(|(_, size)| size).sum();
+    entries.sort();
+    for (path, size) in entries {
+        if total <= max_bytes {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        remove(&path)?;
+        total -= size;
+    }
+    Ok(())
+}
+
+/// The quarantined files of `dir` with their size, including their report.
+fn entries(dir: &Path) -> Result<Vec<(PathBuf, u64)>, OpsError> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == REPORT_EXTENSION) || !path.is_file() {
+            continue;
+        }
+        let report = std::fs::metadata(report_path(&path)).map_or(0, |m| m.len());
+        entries.push((path.clone(), std::fs::metadata(&path)?.len() + report));
+    }
+    Ok(entries)
+}
+
+/// Removes the quarantine area of the cache at `cache_home`, returning the bytes freed.
+pub(crate) fn clear(cache_home: &Path) -> Result<u64, OpsError> {
+    let dir = dir(cache_home);
+    if !std::fs::exists(&dir)? {
+        return Ok(0);
+    }
+    let freed = walkdir::WalkDir::new(&dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum();
+    std::fs::remove_dir_all(&dir)?;
+    Ok(freed)
+}
+
+fn report_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{REPORT_EXTENSION}"));
+    PathBuf::from(name)
+}
+
+fn remove(path: &Path) -> Result<(), OpsError> {
+    for file in [path.to_path_buf(), report_path(path)] {
+        match std::fs::remove_file(&file) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
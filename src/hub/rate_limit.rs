@@ -0,0 +1,140 @@
+//! Rate limits reported by the hub in response headers, so long-running jobs can pace
+//! themselves instead of running into `429 Too Many Requests`.
+use reqwest::header::HeaderMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Resets above this many seconds are Unix timestamps rather than delays.
+const TIMESTAMP_THRESHOLD: u64 = 1_000_000_000;
+
+/// The rate limit last reported by the hub, see [`crate::hub::ModelsCat::rate_limit_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Requests allowed in the current window.
+    pub limit: Option<u64>,
+    /// Requests left in the current window.
+    pub remaining: Option<u64>,
+    /// When the current window ends.
+    pub reset_at: Option<SystemTime>,
+}
+
+impl RateLimitStatus {
+    /// Parses the `X-RateLimit-Limit`, `-Remaining` and `-Reset` headers, or their `RateLimit-*`
+    /// equivalents. Returns `None` when the response carries none of them.
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        Self::parse(headers, SystemTime::now())
+    }
+
+    fn parse(headers: &HeaderMap, now: SystemTime) -> Option<Self> {
+        let value = |name: &str| -> Option<u64> {
+            [format!("x-ratelimit-{name}"), format!("ratelimit-{name}")]
+                .iter()
+                .find_map(|header| headers.get(header)?.to_str().ok()?.trim().parse().ok())
+        };
+        let reset_at = value("reset").map(|reset| match reset > TIMESTAMP_THRESHOLD {
+            true => UNIX_EPOCH + Duration::from_secs(reset),
+            false => now + Duration::from_secs(reset),
+        });
+        let status = Self {
+            limit: value("limit"),
+            remaining: value("remaining"),
+            reset_at,
+        };
+        (status.limit.is_some() || status.remaining.is_some() || status.reset_at.is_some())
+            .then_some(status)
+    }
+
+    /// The more constrained of two observations, such as those of the pages of one listing.
+    pub(crate) fn tighter(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let remaining = |s: &Self| s.remaining.unwrap_or(u64::MAX);
+                Some(if remaining(&b) < remaining(&a) { b } else { a })
+            }
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Waits before requests while the hub reports few requests left in the current window, see
+/// [`crate::hub::ModelsCat::with_rate_limit_pacing`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPacing {
+    /// Wait for the window to end once this many requests or fewer are left.
+    pub min_remaining: u64,
+    /// The longest wait before a single request.
+    pub max_wait: Duration,
+}
+
+impl Default for RateLimitPacing {
+    fn default() -> Self {
+        Self {
+            min_remaining: 1,
+            max_wait: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RateLimitPacing {
+    /// How long to wait before the next request under `status`, if at all.
+    pub(crate) fn delay(&self, status: &RateLimitStatus, now: SystemTime) -> Option<Duration> {
+        if status.remaining? > self.min_remaining {
+            return None;
+        }
+        let wait = status.reset_at?.duration_since(now).ok()?;
+        (!wait.is_zero()).then(|| wait.min(self.max_wait))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_and_pace() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(RateLimitStatus::parse(&HeaderMap::new(), now), None);
+
+        let status = RateLimitStatus::parse(
+            &headers(&[
+                ("x-ratelimit-limit", "100"),
+                ("x-ratelimit-remaining", "2"),
+                ("x-ratelimit-reset", "30"),
+            ]),
+            now,
+        )
+        .unwrap();
+        assert_eq!(status.limit, Some(100));
+        assert_eq!(status.remaining, Some(2));
+        assert_eq!(status.reset_at, Some(now + Duration::from_secs(30)));
+        let timestamp =
+            RateLimitStatus::parse(&headers(&[("ratelimit-reset", "1700000010")]), now).unwrap();
+        assert_eq!(timestamp.reset_at, Some(now + Duration::from_secs(10)));
+
+        let pacing = RateLimitPacing {
+            min_remaining: 2,
+            max_wait: Duration::from_secs(20),
+        };
+        assert_eq!(pacing.delay(&status, now), Some(Duration::from_secs(20)));
+        let later = now + Duration::from_secs(25);
+        assert_eq!(pacing.delay(&status, later), Some(Duration::from_secs(5)));
+        assert_eq!(pacing.delay(&status, now + Duration::from_secs(30)), None);
+        let plenty = RateLimitStatus {
+            remaining: Some(3),
+            ..status
+        };
+        assert_eq!(pacing.delay(&plenty, now), None);
+        assert_eq!(
+            RateLimitStatus::tighter(Some(plenty), Some(status)),
+            Some(status)
+        );
+    }
+}
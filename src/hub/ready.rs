@@ -0,0 +1,218 @@
+//! The one-call entry point of [`crate::ensure_ready`]: what it is asked for and what it
+//! returns, along with the offline fallback shared by both hubs.
+use super::latest_revision;
+use super::matching;
+use super::ms_hub::FileInfo;
+use crate::endpoints::Endpoint;
+use crate::repo::{Repo, RepoType, Revision};
+use crate::utils::OpsError;
+use std::collections::{BTreeMap, HashSet};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+/// What [`crate::ensure_ready`] makes ready: a repo, and optionally which of its files and at
+/// which revision.
+///
+/// ```
+/// use models_cat::EnsureRequest;
+///
+/// let request = EnsureRequest::model("BAAI/bge-small-zh-v1.5")
+///     .with_files(["config.json", "model.safetensors"])
+///     .with_revision("v1.0");
+/// assert_eq!(request.files.len(), 2);
+/// ```
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EnsureRequest {
+    /// The repo ID, such as `BAAI/bge-small-zh-v1.5`.
+    pub repo_id: String,
+    /// Whether the repo is a model, dataset or space.
+    pub repo_type: RepoType,
+    /// The hub filenames needed. Empty, the default, for every file of the repo.
+    pub files: Vec<String>,
+    /// The revision, or the default branch of [`Repo::new`] when `None`.
+    pub revision: Option<Revision>,
+    /// The cache directory, or the default of [`Repo::new`] when `None`.
+    pub cache_dir: Option<PathBuf>,
+    /// The endpoint, or the default of [`crate::hub::ModelsCat::new`] when `None`.
+    pub endpoint: Option<Endpoint>,
+    /// Whether to draw progress bars, or only when stderr is a terminal when `None`.
+    pub progress: Option<bool>,
+}
+
+impl EnsureRequest {
+    /// Requests every file of the model repo `repo_id`.
+    pub fn model(repo_id: &str) -> Self {
+        Self::new(repo_id, RepoType::Model)
+    }
+
+    /// Requests every file of the dataset repo `repo_id`.
+    pub fn dataset(repo_id: &str) -> Self {
+        Self::new(repo_id, RepoType::Dataset)
+    }
+
+    /// Requests every file of the repo `repo_id` of type `repo_type`.
+    pub fn new(repo_id: &str, repo_type: RepoType) -> Self {
+        Self {
+            repo_id: repo_id.to_string(),
+            repo_type,
+            files: Vec::new(),
+            revision: None,
+            cache_dir: None,
+            endpoint: None,
+            progress: None,
+        }
+    }
+
+    /// Requests only `files`, by hub filename, such as `gguf/model.gguf`.
+    pub fn with_files(mut self, files: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.files = files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Requests the files at `revision` instead of the default branch.
+    pub fn with_revision(mut self, revision: impl Into<Revision>) -> Self {
+        self.revision = Some(revision.into());
+        self
+    }
+
+    /// Caches the files under `cache_dir` instead of the default cache directory.
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    /// Sends every request to `endpoint` instead of the default endpoint.
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Draws progress bars when `progress` is `true`, regardless of stderr.
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The repo the request is for, failing when its ID is not of the `owner/name` form.
+    pub(crate) fn repo(&self) -> Result<Repo, OpsError> {
+        let mut repo = Repo::try_new(&self.repo_id, self.repo_type)?;
+        if let Some(ref revision) = self.revision {
+            repo.set_revision(revision.clone());
+        }
+        if let Some(ref cache_dir) = self.cache_dir {
+            repo.set_cache_dir(cache_dir);
+        }
+        Ok(repo)
+    }
+
+    /// Whether to draw progress bars.
+    pub(crate) fn shows_progress(&self) -> bool {
+        self.progress
+            .unwrap_or_else(|| std::io::stderr().is_terminal())
+    }
+}
+
+/// A repo whose requested files are cached and verified, returned by [`crate::ensure_ready`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReadyModel {
+    /// The snapshot of the newest revision among the files. Files last changed in an older
+    /// revision live in the snapshot of that revision, see [`ReadyModel::files`].
+    pub snapshot: PathBuf,
+    /// The revision naming `snapshot`.
+    pub revision: String,
+    /// The local path of every requested file, by hub filename.
+    pub files: BTreeMap<String, PathBuf>,
+    /// Whether the hub was unreachable and the files were found in the cache instead, in which
+    /// case they could not be checked against the listing.
+    pub offline: bool,
+}
+
+impl ReadyModel {
+    /// The local path of the hub file `filename`, if it was requested.
+    pub fn path(&self, filename: &str) -> Option<&Path> {
+        self.files.get(filename).map(PathBuf::as_path)
+    }
+}
+
+/// The listed files among `blobs` named in `files`, or all of them when `files` is empty.
+pub(crate) fn select(blobs: Vec<FileInfo>, files: &[String]) -> Result<Vec<FileInfo>, OpsError> {
+    if files.is_empty() {
+        return Ok(blobs);
+    }
+    let wanted: HashSet<&str> = files.iter().map(String::as_str).collect();
+    let selected: Vec<_> = blobs
+        .into_iter()
+        .filter(|f| wanted.contains(f.path.as_str()))
+        .collect();
+    let listed: HashSet<&str> = selected.iter().map(|f| f.path.as_str()).collect();
+    let missing: Vec<&str> = files
+        .iter()
+        .map(String::as_str)
+        .filter(|f| !listed.contains(f))
+        .collect();
+    if !missing.is_empty() {
+        return Err(OpsError::HubError(format!(
+            "not in the repo: {}",
+            missing.join(", ")
+        )));
+    }
+    Ok(selected)
+}
+
+/// The ready model of `blobs`, once they are all placed in their snapshots.
+pub(crate) fn online(repo: &Repo, blobs: &[FileInfo]) -> Result<ReadyModel, OpsError> {
+    let revision = latest_revision(blobs)
+        .ok_or_else(|| OpsError::HubError(format!("{} has no files", repo.repo_id())))?;
+    let files = matching::cached_paths(repo, blobs).map_err(|missing| {
+        OpsError::HubError(format!(
+            "not in place after pulling: {}",
+            missing.join(", ")
+        ))
+    })?;
+    Ok(ReadyModel {
+        snapshot: std::path::absolute(repo.snapshot_path(&revision))?,
+        revision,
+        files: absolute(files)?,
+        offline: false,
+    })
+}
+
+/// The ready model of `files`, or every file, from the snapshot the revision resolves to
+/// offline, when the hub failed with `err`. Returns `err` unless every file is cached.
+pub(crate) fn offline(
+    repo: &Repo,
+    files: &[String],
+    err: OpsError,
+) -> Result<ReadyModel, OpsError> {
+    let Some(snapshot) = matching::offline_snapshot(repo) else {
+        return Err(err);
+    };
+    let Ok(mut cached) = matching::snapshot_files(repo, &glob::Pattern::new("**").unwrap()) else {
+        return Err(err);
+    };
+    if !files.is_empty() {
+        if !files.iter().all(|f| cached.contains_key(f)) {
+            return Err(err);
+        }
+        cached.retain(|name, _| files.contains(name));
+    }
+    log::warn!("using the cached snapshot of {}: {err}", repo.repo_id());
+    Ok(ReadyModel {
+        revision: snapshot
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        snapshot: std::path::absolute(snapshot)?,
+        files: absolute(cached)?,
+        offline: true,
+    })
+}
+
+fn absolute(files: BTreeMap<String, PathBuf>) -> Result<BTreeMap<String, PathBuf>, OpsError> {
+    files
+        .into_iter()
+        .map(|(name, path)| Ok((name, std::path::absolute(path)?)))
+        .collect()
+}
@@ -0,0 +1,242 @@
+//! Reports returned by repository operations.
+use super::ms_hub::FileInfo;
+use super::{CacheValidation, RateLimitStatus};
+use crate::locale::{Locale, locale};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Summary of a pull, listing what happened to every hub file.
+#[derive(Debug, Default, Clone)]
+pub struct PullReport {
+    /// Files fetched from the hub.
+    pub downloaded: Vec<String>,
+    /// Bytes fetched from the hub.
+    pub downloaded_bytes: u64,
+    /// Files already present and valid in the local snapshot.
+    pub skipped: Vec<String>,
+    /// Files satisfied from an identical file already cached: a blob of the repo under
+    /// [`crate::hub::ModelsCat::with_blob_store`], or another repo's file through the global
+    /// dedup index.
+    pub deduplicated: Vec<String>,
+    /// Bytes not transferred thanks to deduplication.
+    pub saved_bytes: u64,
+    /// Files left out because their paths are invalid on the local filesystem, under
+    /// [`crate::hub::InvalidPathPolicy::Skip`].
+    pub invalid: Vec<String>,
+    /// Hidden files left out under [`crate::hub::PullOptions::skip_hidden`].
+    pub hidden: Vec<String>,
+    /// Downloaded files whose progress callback failed under
+    /// [`crate::hub::ProgressErrorPolicy::Ignore`], and so reported no progress after the
+    /// failure.
+    pub progress_degraded: Vec<String>,
+    /// How cached files were confirmed as current, applied to every file of the pull.
+    pub validation: CacheValidation,
+    /// The rate limit last reported by the hub, when it reports one.
+    pub rate_limit: Option<RateLimitStatus>,
+}
+
+/// A file made available by [`crate::hub::ModelsCat::resolve`], with its hub metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The path of the file in its snapshot.
+    pub path: PathBuf,
+    /// The size in bytes.
+    pub size: u64,
+    /// The listed sha256, when the hub reports one.
+    pub sha256: Option<String>,
+    /// The revision the hub listed for the file, which names its snapshot.
+    pub revision: String,
+    /// Whether the file was fetched from the hub by this call, rather than found current in
+    /// the cache or linked from another repo's identical blob.
+    pub downloaded: bool,
+    /// Whether the progress callback failed under [`crate::hub::ProgressErrorPolicy::Ignore`]
+    /// and reported no progress after the failure.
+    pub progress_degraded: bool,
+}
+
+/// A file of the remote repo as listed by the hub, see
+/// [`crate::hub::ModelsCat::list_hub_files_detailed`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// The hub-relative filename, such as `onnx/model.onnx`.
+    pub path: String,
+    /// The size in bytes.
+    pub size: u64,
+    /// The listed sha256, when the hub reports one.
+    pub sha256: Option<String>,
+    /// The revision the hub listed for the file.
+    pub revision: String,
+    /// Whether the hub stores the file with Git LFS.
+    pub is_lfs: bool,
+}
+
+impl From<FileInfo> for FileMetadata {
+    fn from(fileinfo: FileInfo) -> Self {
+        Self {
+            path: fileinfo.path,
+            size: fileinfo.size as u64,
+            sha256: fileinfo.sha256,
+            revision: fileinfo.revision,
+            is_lfs: fileinfo.is_lfs,
+        }
+    }
+}
+
+/// How the cache of a repo compares with the hub, see
+/// [`crate::hub::ModelsCat::check_for_update`]. Files are sorted by hub filename.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpdateStatus {
+    /// Whether a pull would download nothing and leave the ref where it is.
+    pub is_up_to_date: bool,
+    /// The revision of the snapshot the cache resolves the configured revision to, if any.
+    pub local_revision: Option<String>,
+    /// The newest revision among the listed files, which a pull would point the ref at.
+    pub hub_revision: Option<String>,
+    /// Listed files the cache has no copy of, with their listed sizes.
+    pub added: Vec<FileChange>,
+    /// Listed files whose cached copy is out of date, with their listed sizes.
+    pub modified: Vec<FileChange>,
+    /// Cached files no longer listed, with their sizes on disk.
+    pub removed: Vec<FileChange>,
+}
+
+/// A file that differs between the cache and the hub, see [`UpdateStatus`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+    /// The hub-relative filename.
+    pub path: String,
+    /// The size in bytes.
+    pub size: u64,
+}
+
+/// Result of [`crate::hub::ModelsCat::sync`].
+#[derive(Debug, Default, Clone)]
+pub struct SyncReport {
+    /// Whether nothing was downloaded or deleted, only reported.
+    pub dry_run: bool,
+    /// The pull. In a dry run, the files it would download are listed as downloaded and the
+    /// current ones as skipped.
+    pub pull: PullReport,
+    /// Hub filenames of the files deleted from the snapshots because the hub no longer lists
+    /// them there, sorted.
+    pub deleted: Vec<String>,
+    /// Bytes of the deleted files.
+    pub deleted_bytes: u64,
+}
+
+/// How the cached copy of a hub file compares with the listing, see
+/// [`crate::hub::ModelsCat::verify_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The cached copy has the listed sha256, or the listed size when the hub lists no sha256.
+    Verified,
+    /// No copy is cached.
+    Missing,
+    /// The cached copy has another size or sha256.
+    Corrupt,
+}
+
+/// Result of [`crate::hub::ModelsCat::verify`] and [`crate::hub::ModelsCat::repair`], listing
+/// hub filenames, each sorted.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Files whose cached copy matches the listing.
+    pub verified: Vec<String>,
+    /// Listed files with no cached copy.
+    pub missing: Vec<String>,
+    /// Listed files whose cached copy has another size or sha256.
+    pub corrupt: Vec<String>,
+    /// Files in the snapshots the listing does not place there, such as leftovers of files
+    /// removed or moved on the hub. Reported only; neither checked nor removed.
+    pub extra: Vec<String>,
+    /// Missing and corrupt files a repair downloaded again. They stay listed as missing or
+    /// corrupt, as found before the repair.
+    pub repaired: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether every listed file is cached and matches the listing, or was repaired.
+    pub fn is_ok(&self) -> bool {
+        self.missing
+            .iter()
+            .chain(&self.corrupt)
+            .all(|f| self.repaired.contains(f))
+    }
+
+    pub(crate) fn record(&mut self, filename: &str, status: FileStatus) {
+        let files = match status {
+            FileStatus::Verified => &mut self.verified,
+            FileStatus::Missing => &mut self.missing,
+            FileStatus::Corrupt => &mut self.corrupt,
+        };
+        files.push(filename.to_string());
+    }
+}
+
+/// Summary of a [`crate::hub::ModelsCat::prune`], or of what it would remove in a dry run.
+#[derive(Debug, Default, Clone)]
+pub struct PruneReport {
+    /// Revisions whose snapshot was removed.
+    pub snapshots: Vec<String>,
+    /// Leftover `.tmp` and `.lock` files and blobs no snapshot links to anymore, removed.
+    pub files: Vec<PathBuf>,
+    /// Revisions whose snapshot was left alone because a download held its lock.
+    pub busy: Vec<String>,
+    /// Bytes freed.
+    pub freed_bytes: u64,
+    /// Whether nothing was removed, the report telling what would have been.
+    pub dry_run: bool,
+}
+
+impl PullReport {
+    pub(crate) fn record_deduplicated(&mut self, filename: &str, size: u64) {
+        self.deduplicated.push(filename.to_string());
+        self.saved_bytes += size;
+    }
+}
+
+impl fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (snapshots, files, bytes) = (self.snapshots.len(), self.files.len(), self.freed_bytes);
+        match (locale(), self.dry_run) {
+            (Locale::En, false) => write!(
+                f,
+                "{snapshots} snapshots and {files} files removed ({bytes} bytes freed)"
+            ),
+            (Locale::En, true) => write!(
+                f,
+                "{snapshots} snapshots and {files} files would be removed ({bytes} bytes)"
+            ),
+            (Locale::ZhCn, false) => write!(
+                f,
+                "已删除 {snapshots} 个快照和 {files} 个文件（释放 {bytes} 字节）"
+            ),
+            (Locale::ZhCn, true) => write!(
+                f,
+                "将删除 {snapshots} 个快照和 {files} 个文件（{bytes} 字节）"
+            ),
+        }
+    }
+}
+
+impl fmt::Display for PullReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (downloaded, skipped, deduplicated) = (
+            self.downloaded.len(),
+            self.skipped.len(),
+            self.deduplicated.len(),
+        );
+        match locale() {
+            Locale::En => write!(
+                f,
+                "{downloaded} downloaded, {skipped} up to date, {deduplicated} deduplicated ({} bytes saved)",
+                self.saved_bytes
+            ),
+            Locale::ZhCn => write!(
+                f,
+                "已下载 {downloaded} 个，已是最新 {skipped} 个，去重 {deduplicated} 个（节省 {} 字节）",
+                self.saved_bytes
+            ),
+        }
+    }
+}
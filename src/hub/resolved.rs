@@ -0,0 +1,143 @@
+//! Where each downloaded file of a revision was last placed, so a repeated download can be
+//! confirmed with a HEAD request against its resolve URL instead of fetching the whole listing.
+use super::Resolution;
+use crate::repo::{Repo, Revision};
+use crate::utils::OpsError;
+use reqwest::header::{CONTENT_LENGTH, ETAG, HeaderMap};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// A file placed by a download of the configured revision.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ResolvedFile {
+    /// The revision the hub listed for the file, which names its snapshot.
+    pub revision: String,
+    /// The path of the file relative to the snapshot.
+    pub local_path: String,
+    pub size: u64,
+    /// The listed sha256, when the hub reported one. Missing from records written by older
+    /// versions.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// The ETag the hub reported for the file when it was downloaded or confirmed against the
+    /// listing.
+    #[serde(default)]
+    pub etag: Option<String>,
+}
+
+impl ResolvedFile {
+    /// The [`Resolution`] of the file placed at `path`.
+    pub(crate) fn resolution(&self, path: PathBuf, downloaded: bool) -> Resolution {
+        Resolution {
+            path,
+            size: self.size,
+            sha256: self.sha256.clone(),
+            revision: self.revision.clone(),
+            downloaded,
+            progress_degraded: false,
+        }
+    }
+}
+
+/// Path of the index of files downloaded for `revision`.
+fn index_path(repo: &Repo, revision: &Revision) -> PathBuf {
+    let kind = match revision {
+        Revision::Branch(_) => "branches",
+        Revision::Tag(_) => "tags",
+        Revision::Commit(_) => "commits",
+    };
+    repo.cache_dir()
+        .join("resolved")
+        .join(kind)
+        .join(revision.as_str().replace('/', "%2F"))
+}
+
+fn load(path: &Path) -> BTreeMap<String, ResolvedFile> {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+/// Records where `filename` of the configured revision was placed.
+///
+/// When the revision is a branch or tag, the file is recorded for `commit` too, the commit it
+/// currently points at: both names share the snapshot, so a later download by commit hash is
+/// also confirmed without a listing.
+pub(crate) fn record(
+    repo: &Repo,
+    filename: &str,
+    file: ResolvedFile,
+    commit: Option<&str>,
+) -> Result<(), OpsError> {
+    if let Some(commit) = commit
+        && !repo.revision().is_commit()
+    {
+        let path = index_path(repo, &Revision::commit(commit));
+        write_entry(&path, filename, file.clone())?;
+    }
+    write_entry(&index_path(repo, repo.revision()), filename, file)
+}
+
+fn write_entry(path: &Path, filename: &str, file: ResolvedFile) -> Result<(), OpsError> {
+    let mut index = load(path);
+    index.insert(filename.to_string(), file);
+
+    let parent = path
+        .parent()
+        .ok_or_else(|| OpsError::HubError("Invalid file path".into()))?;
+    std::fs::create_dir_all(parent)?;
+    let mut temp = NamedTempFile::new_in(parent)?;
+    serde_json::to_writer(&mut temp, &index)
+        .map_err(|e| OpsError::hub("failed to write resolved files", e))?;
+    temp.flush()?;
+    temp.persist(path).map_err(|e| OpsError::IoError(e.error))?;
+    Ok(())
+}
+
+/// The recorded placement of `filename` and its local path, if the file is still in place
+/// with the recorded size.
+pub(crate) fn cached(repo: &Repo, filename: &str) -> Option<(PathBuf, ResolvedFile)> {
+    let file = load(&index_path(repo, repo.revision())).remove(filename)?;
+    let mut filepath = repo.snapshot_path(&file.revision);
+    for part in file.local_path.split('/') {
+        filepath.push(part);
+    }
+    let size = std::fs::metadata(&filepath).ok()?.len();
+    (size == file.size).then_some((filepath, file))
+}
+
+/// The revisions, and so the snapshots, of the files placed by downloads of the configured
+/// revision.
+pub(crate) fn revisions(repo: &Repo) -> HashSet<String> {
+    load(&index_path(repo, repo.revision()))
+        .into_values()
+        .map(|file| file.revision)
+        .collect()
+}
+
+/// What a HEAD request against the resolve URL of a file placed earlier tells.
+pub(crate) enum HeadCheck {
+    /// The file is in place and the hub still serves the same content.
+    Current(PathBuf, ResolvedFile),
+    /// The file is to be confirmed against the listing, then recorded with the ETag the hub
+    /// reported, if any.
+    Unconfirmed(Option<String>),
+}
+
+/// Whether the headers of a HEAD request against the resolve URL still describe `file`: both
+/// the sizes and the ETags must match. A file recorded without an ETag never matches, as its
+/// size alone misses content of the same size.
+pub(crate) fn matches(file: &ResolvedFile, headers: &HeaderMap) -> bool {
+    let size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+    size == Some(file.size) && file.etag.is_some() && file.etag == etag(headers)
+}
+
+pub(crate) fn etag(headers: &HeaderMap) -> Option<String> {
+    Some(headers.get(ETAG)?.to_str().ok()?.to_string())
+}
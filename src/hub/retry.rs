@@ -0,0 +1,181 @@
+//! Retries of downloads failing on the way, such as by a reset connection or a `502` from the
+//! CDN, see [`crate::hub::ModelsCat::with_retry`].
+use crate::utils::OpsError;
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use std::hash::{BuildHasher, RandomState};
+use std::io::ErrorKind;
+use std::time::Duration;
+
+/// How often and how long to wait before downloading a file again after a transient failure:
+/// a transport error, `429 Too Many Requests` or a `5xx` status.
+///
+/// The wait doubles with every retry, from `initial_backoff` up to `max_backoff`, and is
+/// randomly shortened by up to half so that clients failing together do not retry together.
+/// A `Retry-After` header in seconds is waited for instead, up to `max_backoff`.
+///
+/// ```
+/// use models_cat::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy {
+///     max_retries: 5,
+///     ..Default::default()
+/// };
+/// assert_eq!(policy.initial_backoff, Duration::from_secs(1));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    /// Retries after the first attempt.
+    pub max_retries: u32,
+    /// The wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The longest wait before a retry.
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait before retrying a download that failed with `err` after `retries`
+    /// retries, or `None` when it is not retried.
+    pub(crate) fn delay(&self, retries: u32, err: &OpsError) -> Option<Duration> {
+        if retries >= self.max_retries || !is_transient(err) {
+            return None;
+        }
+        if let OpsError::RateLimited {
+            retry_after: Some(wait),
+            ..
+        }
+        | OpsError::ServerError {
+            retry_after: Some(wait),
+            ..
+        } = err
+        {
+            return Some((*wait).min(self.max_backoff));
+        }
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retries))
+            .min(self.max_backoff);
+        Some(backoff - jitter(backoff / 2))
+    }
+}
+
+/// The error of a response to `url` with `status`, if it failed: a retried
+/// [`OpsError::RateLimited`] or [`OpsError::ServerError`] for `429` and `5xx`, a
+/// [`OpsError::NotFound`] for `404`, or else a [`OpsError::HubError`] for a `4xx` other than
+/// `416 Range Not Satisfiable`, which a resumed download recovers from.
+pub(crate) fn status_error(status: StatusCode, headers: &HeaderMap, url: &str) -> Option<OpsError> {
+    server_error(status, headers, url).or_else(|| match status {
+        StatusCode::NOT_FOUND => Some(OpsError::NotFound {
+            url: url.to_string(),
+        }),
+        StatusCode::RANGE_NOT_SATISFIABLE => None,
+        _ => status
+            .is_client_error()
+            .then(|| OpsError::HubError(format!("{status} for {url}"))),
+    })
+}
+
+/// A [`OpsError::RateLimited`] for a response to `url` with `status` `429`, or a
+/// [`OpsError::ServerError`] for a `5xx`.
+pub(crate) fn server_error(status: StatusCode, headers: &HeaderMap, url: &str) -> Option<OpsError> {
+    let url = url.to_string();
+    let retry_after = retry_after(headers);
+    match status {
+        StatusCode::TOO_MANY_REQUESTS => Some(OpsError::RateLimited { url, retry_after }),
+        _ => status.is_server_error().then(|| OpsError::ServerError {
+            status: status.as_u16(),
+            url,
+            retry_after,
+        }),
+    }
+}
+
+/// The wait a `Retry-After` header asks for, when given in seconds.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Whether `err` may pass on its own: the hub was busy, or the connection failed or dropped.
+fn is_transient(err: &OpsError) -> bool {
+    match err {
+        OpsError::RateLimited { .. } | OpsError::ServerError { .. } | OpsError::Timeout { .. } => {
+            true
+        }
+        OpsError::RequestError(e) => !e.is_builder() && !e.is_redirect(),
+        OpsError::IoError(e) => {
+            matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::TimedOut
+                    | ErrorKind::BrokenPipe
+            ) || e
+                .get_ref()
+                .is_some_and(|inner| inner.is::<reqwest::Error>())
+        }
+        _ => false,
+    }
+}
+
+/// A random duration up to `max`.
+fn jitter(max: Duration) -> Duration {
+    let random = RandomState::new().hash_one(std::time::SystemTime::now());
+    max.mul_f64((random % 1024) as f64 / 1024.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_delay() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+        let busy = server_error(StatusCode::BAD_GATEWAY, &HeaderMap::new(), "url").unwrap();
+        for (retries, backoff) in [(0, 100), (1, 200), (2, 300)] {
+            let delay = policy.delay(retries, &busy).unwrap();
+            let backoff = Duration::from_millis(backoff);
+            assert!(delay <= backoff && delay >= backoff / 2, "{delay:?}");
+        }
+        assert_eq!(policy.delay(3, &busy), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        let limited = server_error(StatusCode::TOO_MANY_REQUESTS, &headers, "url").unwrap();
+        assert_eq!(policy.delay(0, &limited), Some(Duration::from_millis(300)));
+
+        assert!(matches!(limited, OpsError::RateLimited { .. }));
+        let missing = status_error(StatusCode::NOT_FOUND, &headers, "url").unwrap();
+        assert!(matches!(missing, OpsError::NotFound { ref url } if url == "url"));
+        assert_eq!(policy.delay(0, &missing), None);
+        assert!(status_error(StatusCode::RANGE_NOT_SATISFIABLE, &headers, "url").is_none());
+        let reset = OpsError::IoError(ErrorKind::ConnectionReset.into());
+        assert!(policy.delay(0, &reset).is_some());
+        let disk_full = OpsError::IoError(ErrorKind::StorageFull.into());
+        assert_eq!(policy.delay(0, &disk_full), None);
+    }
+}
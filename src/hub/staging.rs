@@ -0,0 +1,445 @@
+//! Downloads staged as `<file>.partial.<random>` next to their target, each with a
+//! `<file>.partial.<random>.json` sidecar, so staging left behind by a crash or a dropped
+//! connection can be told apart from files of the repo and resumed, recovered or discarded.
+use super::validate::HubFileInfo;
+use crate::fslock::FsLock;
+use crate::utils::{self, OpsError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tempfile::TempPath;
+
+const STAGING_INFIX: &str = ".partial.";
+const SIDECAR_EXTENSION: &str = "json";
+const RANDOM_LEN: usize = 12;
+
+/// Bytes between two checkpoints of a download in its sidecar.
+pub(crate) const CHECKPOINT_INTERVAL: u64 = 64 * 1024 * 1024;
+
+/// The sidecar of a staged download.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct StagingRecord {
+    /// The name of the target file, in the directory of the staged file.
+    target: String,
+    /// The listed size of the file.
+    size: u64,
+    /// The listed sha256 of the file, when the hub reports one.
+    sha256: Option<String>,
+    /// The bytes known to be written to the staged file. Only reaches `size` once the file is
+    /// complete and passed the validators.
+    written: u64,
+}
+
+/// A download in progress, removed along with its sidecar unless persisted.
+pub(crate) struct StagedFile {
+    data: TempPath,
+    sidecar: TempPath,
+    record: StagingRecord,
+}
+
+impl StagedFile {
+    /// Creates an empty staged file for `file` next to `target`, and its sidecar.
+    pub(crate) fn create(target: &Path, file: &HubFileInfo) -> Result<Self, OpsError> {
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Err(OpsError::HubError("Invalid file path".into()));
+        };
+        let name = name.to_string_lossy();
+        let data = tempfile::Builder::new()
+            .prefix(&format!("{name}{STAGING_INFIX}"))
+            .rand_bytes(RANDOM_LEN)
+            .tempfile_in(parent)?
+            .into_temp_path();
+        let staged = Self {
+            sidecar: TempPath::from_path(sidecar_path(&data)),
+            data,
+            record: StagingRecord {
+                target: name.into_owned(),
+                size: file.size,
+                sha256: file.sha256.clone(),
+                written: 0,
+            },
+        };
+        staged.write_sidecar()?;
+        Ok(staged)
+    }
+
+    /// Picks up the staged file an interrupted download of `file` left next to `target`, with
+    /// the most bytes checkpointed, truncated to them. Returns `None` when there is none.
+    /// Other staged files of `target` are removed, as they can no longer be resumed.
+    ///
+    /// Must be called with the snapshot lock held, so no other download is writing to them.
+    pub(crate) fn resume(target: &Path, file: &HubFileInfo) -> Result<Option<Self>, OpsError> {
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            return Ok(None);
+        };
+        let name = name.to_string_lossy();
+        let mut best: Option<(PathBuf, StagingRecord)> = None;
+        let mut stale = Vec::new();
+        for entry in std::fs::read_dir(parent)? {
+            let path = entry?.path();
+            if staged_name(&path) != Some(false) {
+                continue;
+            }
+            let Some(record) = read_record(&path).filter(|r| r.target == name) else {
+                continue;
+            };
+            let resumable = record.size == file.size
+                && record.sha256 == file.sha256
+                && record.written > 0
+                && record.written < record.size
+                && std::fs::metadata(&path).is_ok_and(|m| m.len() >= record.written);
+            if !resumable {
+                stale.push(path);
+            } else if best
+                .as_ref()
+                .is_none_or(|(_, b)| record.written > b.written)
+            {
+                stale.extend(best.replace((path, record)).map(|(path, _)| path));
+            } else {
+                stale.push(path);
+            }
+        }
+        for path in stale {
+            std::fs::remove_file(sidecar_path(&path))?;
+            std::fs::remove_file(path)?;
+        }
+        let Some((path, record)) = best else {
+            return Ok(None);
+        };
+        // Bytes past the checkpoint may not have reached the disk.
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(record.written)?;
+        Ok(Some(Self {
+            sidecar: TempPath::from_path(sidecar_path(&path)),
+            data: TempPath::from_path(path),
+            record,
+        }))
+    }
+
+    /// Removes what interrupted downloads of `file` left next to `target`, for a download that
+    /// must start over.
+    ///
+    /// Must be called with the snapshot lock held, like [`StagedFile::resume`].
+    pub(crate) fn discard(target: &Path, file: &HubFileInfo) -> Result<(), OpsError> {
+        // Dropping the staged file removes it along with its sidecar.
+        Self::resume(target, file).map(drop)
+    }
+
+    /// The staged file.
+    pub(crate) fn path(&self) -> &Path {
+        &self.data
+    }
+
+    /// The bytes of the staged file known to be on disk.
+    pub(crate) fn written(&self) -> u64 {
+        self.record.written
+    }
+
+    /// A hasher fed with the bytes of the staged file known to be on disk, to continue
+    /// hashing a resumed download.
+    pub(crate) fn hasher(&self) -> Result<Sha256, OpsError> {
+        let mut hasher = Sha256::new();
+        let mut file = std::fs::File::open(&self.data)?.take(self.record.written);
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                return Ok(hasher);
+            }
+            hasher.update(&buf[..len]);
+        }
+    }
+
+    /// Empties the staged file, for a server that ignored the range of a resumed download.
+    pub(crate) fn restart(&mut self) -> Result<(), OpsError> {
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.data)?
+            .set_len(0)?;
+        self.checkpoint(0)
+    }
+
+    /// Keeps the staged file of an interrupted download for [`StagedFile::resume`], with the
+    /// first `written` bytes, or as many as reached the file, checkpointed.
+    pub(crate) fn suspend(mut self, written: u64) -> Result<(), OpsError> {
+        let file = std::fs::File::open(&self.data)?;
+        file.sync_all()?;
+        let written = written.min(file.metadata()?.len());
+        self.checkpoint(written)?;
+        self.data.keep().map_err(|e| OpsError::IoError(e.error))?;
+        self.sidecar
+            .keep()
+            .map_err(|e| OpsError::IoError(e.error))?;
+        Ok(())
+    }
+
+    /// Records that the first `written` bytes of the staged file are on disk.
+    pub(crate) fn checkpoint(&mut self, written: u64) -> Result<(), OpsError> {
+        self.record.written = written;
+        self.write_sidecar()
+    }
+
+    /// Moves the staged file to `target`, the target it was created for or a file of the same
+    /// filesystem, such as its blob, and removes its sidecar.
+    pub(crate) fn persist(self, target: &Path) -> Result<(), OpsError> {
+        self.data
+            .persist(target)
+            .map_err(|e| OpsError::IoError(e.error))?;
+        Ok(())
+    }
+
+    fn write_sidecar(&self) -> Result<(), OpsError> {
+        let content = serde_json::to_vec(&self.record)
+            .map_err(|e| OpsError::hub("failed to write staging record", e))?;
+        std::fs::write(&self.sidecar, content)?;
+        Ok(())
+    }
+}
+
+/// What [`reconcile`] did with a staged file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Recovery {
+    /// The staged file was complete and verified, and was moved to its target.
+    Promoted,
+    /// The staged file was corrupt, unverifiable or superseded, and was removed.
+    Discarded,
+}
+
+/// Recovers the staged files left in `snapshot` by interrupted downloads, returning the
+/// targets of the promoted ones and the staged files of the discarded ones.
+///
+/// Complete staged files whose size and sha256 match their sidecar are moved to their
+/// target, unless it already exists. Partial ones are kept for [`StagedFile::resume`] until
+/// their target is placed. Everything else is removed. Nothing is done while another download
+/// holds the snapshot lock.
+pub(crate) fn reconcile(snapshot: &Path) -> Result<Vec<(PathBuf, Recovery)>, OpsError> {
+    if staged_files(snapshot).is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut lock = match FsLock::lock(snapshot.to_path_buf()) {
+        Ok(lock) => lock,
+        Err(OpsError::LockAcquisition(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let result = staged_files(snapshot)
+        .into_iter()
+        .filter_map(|path| recover(&path).transpose())
+        .collect();
+    lock.unlock();
+    result
+}
+
+/// The staged files and sidecars under `snapshot`.
+fn staged_files(snapshot: &Path) -> Vec<PathBuf> {
+    walkdir::WalkDir::new(snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && staged_name(e.path()).is_some())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Whether `path` is a staged download or its sidecar.
+pub(crate) fn is_staged(path: &Path) -> bool {
+    staged_name(path).is_some()
+}
+
+/// Whether `path` is a sidecar, or `None` when it is neither a staged file nor a sidecar.
+fn staged_name(path: &Path) -> Option<bool> {
+    let name = path.file_name()?.to_str()?;
+    let (name, sidecar) = match name.strip_suffix(&format!(".{SIDECAR_EXTENSION}")) {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    let (target, random) = name.rsplit_once(STAGING_INFIX)?;
+    let is_random = random.len() == RANDOM_LEN && random.chars().all(|c| c.is_ascii_alphanumeric());
+    (!target.is_empty() && is_random).then_some(sidecar)
+}
+
+fn sidecar_path(data: &Path) -> PathBuf {
+    let mut name = data.as_os_str().to_owned();
+    name.push(format!(".{SIDECAR_EXTENSION}"));
+    PathBuf::from(name)
+}
+
+/// The sidecar of the staged file at `path`, if it can be read.
+fn read_record(path: &Path) -> Option<StagingRecord> {
+    let content = std::fs::read(sidecar_path(path)).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+fn recover(path: &Path) -> Result<Option<(PathBuf, Recovery)>, OpsError> {
+    let discard = |path: &Path| -> Result<Option<(PathBuf, Recovery)>, OpsError> {
+        for file in [path.to_path_buf(), sidecar_path(path)] {
+            match std::fs::remove_file(&file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(Some((path.to_path_buf(), Recovery::Discarded)))
+    };
+    if staged_name(path) == Some(true) {
+        // Sidecars go along with their staged file, unless it is gone.
+        if !std::fs::exists(path)? || std::fs::exists(path.with_extension(""))? {
+            return Ok(None);
+        }
+        std::fs::remove_file(path)?;
+        return Ok(Some((path.to_path_buf(), Recovery::Discarded)));
+    }
+    let Some(record) = read_record(path) else {
+        return discard(path);
+    };
+    let target = path.with_file_name(&record.target);
+    let resumable = record.written > 0
+        && record.written < record.size
+        && std::fs::metadata(path)?.len() >= record.written;
+    if resumable && !std::fs::exists(&target)? {
+        return Ok(None);
+    }
+    let verified = record.written == record.size
+        && std::fs::metadata(path)?.len() == record.size
+        && match record.sha256 {
+            Some(ref sha256) => &utils::sha256(path)? == sha256,
+            None => true,
+        };
+    if !verified || std::fs::exists(&target)? {
+        return discard(path);
+    }
+    std::fs::rename(path, &target)?;
+    std::fs::remove_file(sidecar_path(path))?;
+    Ok(Some((target, Recovery::Promoted)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(content: &[u8], sha256: bool) -> HubFileInfo {
+        HubFileInfo {
+            path: "model.bin".into(),
+            revision: "rev".into(),
+            size: content.len() as u64,
+            sha256: sha256.then(|| {
+                use sha2::{Digest, Sha256};
+                format!("{:x}", Sha256::digest(content))
+            }),
+        }
+    }
+
+    /// Leaves a staged file as a crash would, with `written` checkpointed.
+    fn crash(target: &Path, content: &[u8], file: &HubFileInfo, written: u64) -> PathBuf {
+        let mut staged = StagedFile::create(target, file).unwrap();
+        std::fs::write(staged.path(), content).unwrap();
+        staged.checkpoint(written).unwrap();
+        let path = staged.path().to_path_buf();
+        let StagedFile { data, sidecar, .. } = staged;
+        data.keep().unwrap();
+        sidecar.keep().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_reconcile() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("rev");
+        std::fs::create_dir_all(snapshot.join("gguf")).unwrap();
+        let content = b"weights";
+
+        // A dropped download leaves nothing behind.
+        let target = snapshot.join("model.bin");
+        drop(StagedFile::create(&target, &info(content, true)).unwrap());
+        assert_eq!(std::fs::read_dir(&snapshot).unwrap().count(), 1);
+        assert!(reconcile(&snapshot).unwrap().is_empty());
+
+        // A complete, verified download interrupted before its rename is promoted.
+        let staged = crash(&target, content, &info(content, true), 7);
+        assert!(staged_name(&staged).is_some());
+        assert_eq!(
+            reconcile(&snapshot).unwrap(),
+            [(target.clone(), Recovery::Promoted)]
+        );
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+
+        // An interrupted download is kept for resuming, while one with a corrupt body, one
+        // whose target was placed meanwhile and one without a sidecar are discarded.
+        let nested = snapshot.join("gguf/model.gguf");
+        let partial = crash(&nested, b"wei", &info(content, true), 3);
+        let corrupt = crash(&nested, b"weighs!", &info(content, true), 7);
+        let superseded = crash(&target, content, &info(content, false), 7);
+        let orphan = crash(&nested, content, &info(content, false), 7);
+        std::fs::remove_file(sidecar_path(&orphan)).unwrap();
+        let recovered = reconcile(&snapshot).unwrap();
+        let mut discarded = vec![corrupt, superseded, orphan];
+        discarded.sort();
+        let mut paths: Vec<_> = recovered.iter().map(|(path, _)| path.clone()).collect();
+        paths.sort();
+        assert_eq!(paths, discarded);
+        assert!(recovered.iter().all(|(_, r)| *r == Recovery::Discarded));
+        assert!(!nested.exists());
+        let names: Vec<_> = walkdir::WalkDir::new(&snapshot)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .map(|e| e.into_path())
+            .collect();
+        assert_eq!(names.len(), 3);
+        assert!(names.contains(&target) && names.contains(&partial));
+
+        // Once its target is placed, the partial download is superseded.
+        std::fs::write(&nested, content).unwrap();
+        assert_eq!(
+            reconcile(&snapshot).unwrap(),
+            [(partial, Recovery::Discarded)]
+        );
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("model.bin");
+        let content = b"weights";
+        let file = info(content, true);
+        assert!(StagedFile::resume(&target, &file).unwrap().is_none());
+
+        // Resumes the download with the most bytes, dropping bytes past its checkpoint, and
+        // removes the others.
+        let shorter = crash(&target, b"we", &file, 2);
+        let longest = crash(&target, b"weigXX", &file, 4);
+        let stale = crash(&target, b"wei", &info(b"other content", true), 3);
+        let staged = StagedFile::resume(&target, &file).unwrap().unwrap();
+        assert_eq!(staged.path(), longest);
+        assert!(!shorter.exists() && !stale.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+        assert_eq!(staged.written(), 4);
+        assert_eq!(std::fs::read(staged.path()).unwrap(), b"weig");
+        let mut hasher = staged.hasher().unwrap();
+        hasher.update(b"hts");
+        assert_eq!(
+            file.sha256.as_deref(),
+            Some(format!("{:x}", hasher.finalize()).as_str())
+        );
+
+        // Suspending keeps it for the next try, with what reached the file.
+        std::fs::write(staged.path(), b"weigh").unwrap();
+        staged.suspend(6).unwrap();
+        let mut staged = StagedFile::resume(&target, &file).unwrap().unwrap();
+        assert_eq!(staged.written(), 5);
+
+        // Restarting empties it, and it is no longer resumable.
+        staged.restart().unwrap();
+        assert_eq!(std::fs::metadata(staged.path()).unwrap().len(), 0);
+        staged.suspend(0).unwrap();
+        assert!(StagedFile::resume(&target, &file).unwrap().is_none());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // Discarding removes it without resuming.
+        crash(&target, b"we", &file, 2);
+        StagedFile::discard(&target, &file).unwrap();
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
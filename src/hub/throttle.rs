@@ -0,0 +1,67 @@
+//! A cap on the download throughput of a hub instance, see
+//! [`crate::hub::ModelsCat::with_rate_limit`], so a pull does not starve other jobs of a shared
+//! connection.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Paces the chunks of every download of an instance to stay under `bytes_per_sec` together.
+///
+/// Each chunk takes its share of time after the chunks before it, so a transfer that slept
+/// longer than its share, such as while waiting on the network, gains no burst afterwards.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    bytes_per_sec: u64,
+    /// When the chunks paced so far have had their time.
+    next: Mutex<Option<Instant>>,
+}
+
+impl Throttle {
+    /// A cap of `bytes_per_sec`, or `None` for 0, which means unlimited.
+    pub(crate) fn new(bytes_per_sec: u64) -> Option<Self> {
+        (bytes_per_sec > 0).then(|| Self {
+            bytes_per_sec,
+            next: Mutex::new(None),
+        })
+    }
+
+    /// How long to wait after a chunk of `bytes` to stay under the cap.
+    pub(crate) fn delay(&self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let mut next = self.next.lock().unwrap();
+        let start = next.map_or(now, |next| next.max(now));
+        let until = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        *next = Some(until);
+        until.saturating_duration_since(now)
+    }
+
+    /// Waits out [`Throttle::delay`] of a chunk of `bytes`, blocking the thread.
+    pub(crate) fn pace(&self, bytes: u64) {
+        std::thread::sleep(self.delay(bytes));
+    }
+
+    /// Waits out [`Throttle::delay`] of a chunk of `bytes`.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn pace_async(&self, bytes: u64) {
+        tokio::time::sleep(self.delay(bytes)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        assert!(Throttle::new(0).is_none());
+        let throttle = Throttle::new(1000).unwrap();
+        let first = throttle.delay(500);
+        assert!(first <= Duration::from_millis(500) && first > Duration::from_millis(450));
+        // The second chunk waits for the first one's share too.
+        let second = throttle.delay(500);
+        assert!(second <= Duration::from_secs(1) && second > Duration::from_millis(950));
+
+        std::thread::sleep(Duration::from_millis(1100));
+        let idle = throttle.delay(100);
+        assert!(idle <= Duration::from_millis(100));
+    }
+}
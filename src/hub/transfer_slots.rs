@@ -0,0 +1,145 @@
+//! A process-wide limit on concurrent file transfers, shared by every hub instance, so pulling
+//! many models at once does not open more connections than a CDN tolerates.
+//!
+//! The limit is `max_concurrent_transfers` in the [config file](crate::config); without it,
+//! transfers are not limited.
+use crate::config;
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+
+/// Slots for concurrent transfers, taken by both sync and async downloads.
+#[derive(Debug)]
+pub(crate) struct TransferSlots {
+    limit: usize,
+    in_use: Mutex<usize>,
+    released: Condvar,
+    #[cfg(feature = "tokio")]
+    released_async: tokio::sync::Notify,
+}
+
+/// A taken slot, given back when dropped.
+#[derive(Debug)]
+pub(crate) struct TransferSlot {
+    slots: Arc<TransferSlots>,
+}
+
+impl Drop for TransferSlot {
+    fn drop(&mut self) {
+        *self.slots.in_use.lock().unwrap() -= 1;
+        self.slots.released.notify_one();
+        #[cfg(feature = "tokio")]
+        self.slots.released_async.notify_one();
+    }
+}
+
+impl TransferSlots {
+    /// Slots for `limit` concurrent transfers, at least one.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            in_use: Mutex::new(0),
+            released: Condvar::new(),
+            #[cfg(feature = "tokio")]
+            released_async: tokio::sync::Notify::new(),
+        }
+    }
+
+    /// Takes a slot if one is free.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Option<TransferSlot> {
+        let mut in_use = self.in_use.lock().unwrap();
+        (*in_use < self.limit).then(|| {
+            *in_use += 1;
+            TransferSlot {
+                slots: self.clone(),
+            }
+        })
+    }
+
+    /// Takes a slot, blocking until one is free.
+    pub(crate) fn acquire(self: &Arc<Self>) -> TransferSlot {
+        let mut in_use = self.in_use.lock().unwrap();
+        while *in_use >= self.limit {
+            in_use = self.released.wait(in_use).unwrap();
+        }
+        *in_use += 1;
+        TransferSlot {
+            slots: self.clone(),
+        }
+    }
+
+    /// Takes a slot, waiting until one is free.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn acquire_async(self: &Arc<Self>) -> TransferSlot {
+        loop {
+            // Created before checking, so a slot given back in between is not missed.
+            let released = self.released_async.notified();
+            if let Some(slot) = self.try_acquire() {
+                return slot;
+            }
+            released.await;
+        }
+    }
+}
+
+static GLOBAL: LazyLock<Option<Arc<TransferSlots>>> = LazyLock::new(|| {
+    config::max_concurrent_transfers().map(|limit| Arc::new(TransferSlots::new(limit)))
+});
+
+/// The slots shared by every hub instance of the process, if the config file sets a limit.
+pub(crate) fn global() -> Option<Arc<TransferSlots>> {
+    GLOBAL.clone()
+}
+
+/// Serves a hub with `a.txt`, holding each file transfer open for a moment. Returns the server
+/// and the most transfers it served at once.
+#[cfg(test)]
+pub(crate) fn test_hub() -> (
+    crate::test_server::MockServer,
+    Arc<std::sync::atomic::AtomicUsize>,
+) {
+    use crate::test_server::{MockServer, Response};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let listing = super::ms_hub::test_listing(&[("a.txt", b"aaa")], "rev1");
+    let in_flight = AtomicUsize::new(0);
+    let peak = Arc::new(AtomicUsize::new(0));
+    let observed = peak.clone();
+    let server = MockServer::start(move |req| {
+        if req.path.contains("/repo/files?") {
+            return Response::ok(listing.clone());
+        }
+        observed.fetch_max(
+            in_flight.fetch_add(1, Ordering::SeqCst) + 1,
+            Ordering::SeqCst,
+        );
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        in_flight.fetch_sub(1, Ordering::SeqCst);
+        Response::ok(b"aaa".to_vec())
+    });
+    (server, peak)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots() {
+        let slots = Arc::new(TransferSlots::new(2));
+        let first = slots.acquire();
+        let second = slots.try_acquire().unwrap();
+        assert!(slots.try_acquire().is_none());
+        drop(first);
+        let third = slots.try_acquire().unwrap();
+
+        let waiting = {
+            let slots = slots.clone();
+            std::thread::spawn(move || drop(slots.acquire()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        assert!(!waiting.is_finished());
+        drop(second);
+        waiting.join().unwrap();
+        drop(third);
+        assert_eq!(*slots.in_use.lock().unwrap(), 0);
+    }
+}
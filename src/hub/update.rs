@@ -0,0 +1,250 @@
+//! Comparison of the cached snapshots of a repo with the hub listing, see
+//! [`crate::hub::ModelsCat::check_for_update`], and removal of the files it no longer lists,
+//! see [`crate::hub::ModelsCat::sync`].
+use super::matching;
+use super::ms_hub::FileInfo;
+use super::paths;
+use super::report::{FileChange, FileStatus, PullReport, UpdateStatus};
+use super::{CacheValidation, is_verified, latest_revision};
+use super::{snapshot_contents, staging, unmark_complete, verify};
+use crate::dedup;
+use crate::fslock::FsLock;
+use crate::repo::Repo;
+use crate::utils::OpsError;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Compares the listed `blobs` with the cache of `repo`, without downloading anything.
+///
+/// A listed file is unchanged when its snapshot holds a copy with the listed sha256, or when
+/// the snapshot the revision resolves to offline has a copy with the listed sha256 under
+/// another revision. The others are modified when either has a
+/// file by that name, and added otherwise. The files of the offline snapshot no longer listed
+/// are removed.
+pub(crate) fn check(repo: &Repo, blobs: Vec<FileInfo>) -> Result<UpdateStatus, OpsError> {
+    let hub_revision = latest_revision(&blobs);
+    let snapshot = matching::offline_snapshot(repo);
+    let local_revision = snapshot
+        .as_ref()
+        .and_then(|s| Some(s.file_name()?.to_string_lossy().into_owned()));
+    let mut local = match (&snapshot, &local_revision) {
+        (Some(snapshot), Some(revision)) => local_files(repo, snapshot, revision)?,
+        _ => BTreeMap::new(),
+    };
+
+    let (checks, _) = verify::plan(repo, blobs)?;
+    let mut status = UpdateStatus {
+        is_up_to_date: false,
+        local_revision,
+        hub_revision,
+        added: Vec::new(),
+        modified: Vec::new(),
+        removed: Vec::new(),
+    };
+    for check in checks {
+        let (fileinfo, filepath) = (&check.fileinfo, &check.filepath);
+        let known = local.remove(&fileinfo.path);
+        if is_verified(filepath, fileinfo, CacheValidation::Sha256)? {
+            continue;
+        }
+        if let Some((path, _)) = &known
+            && verify::status(path, fileinfo)? == FileStatus::Verified
+        {
+            continue;
+        }
+        let change = FileChange {
+            path: fileinfo.path.clone(),
+            size: fileinfo.size as u64,
+        };
+        match known.is_some() || filepath.exists() {
+            true => status.modified.push(change),
+            false => status.added.push(change),
+        }
+    }
+    status.removed = local
+        .into_iter()
+        .map(|(path, (_, size))| FileChange { path, size })
+        .collect();
+    status.is_up_to_date = status.added.is_empty()
+        && status.modified.is_empty()
+        && status.removed.is_empty()
+        && status.local_revision == status.hub_revision;
+    Ok(status)
+}
+
+/// The report of a pull of `blobs` that has not run: the files whose cached copy is not
+/// current under `validation` as downloaded, the others as skipped.
+pub(crate) fn plan_pull(
+    repo: &Repo,
+    blobs: Vec<FileInfo>,
+    validation: CacheValidation,
+) -> Result<PullReport, OpsError> {
+    let mut report = PullReport {
+        validation,
+        ..Default::default()
+    };
+    let (checks, _) = verify::plan(repo, blobs)?;
+    for check in checks {
+        let (fileinfo, filepath) = (check.fileinfo, check.filepath);
+        if is_verified(&filepath, &fileinfo, validation)? {
+            report.skipped.push(fileinfo.path);
+        } else {
+            report.downloaded_bytes += fileinfo.size as u64;
+            report.downloaded.push(fileinfo.path);
+        }
+    }
+    Ok(report)
+}
+
+/// Deletes the files of the snapshots of the listed `blobs` that the listing does not place
+/// there, such as files removed on the hub, along with the directories left empty. Returns
+/// the hub filenames deleted, sorted, and the bytes freed, or with `dry_run`, what would be
+/// without deleting anything.
+///
+/// Each snapshot is cleaned under its lock. Staged downloads are left alone, and so are the
+/// snapshots of revisions no longer listed, which [`crate::hub::ModelsCat::prune`] removes.
+pub(crate) fn remove_unlisted(
+    repo: &Repo,
+    blobs: &[FileInfo],
+    dry_run: bool,
+) -> Result<(Vec<String>, u64), OpsError> {
+    let mut deleted = Vec::new();
+    let mut freed = 0;
+    for (revision, files) in snapshot_contents(blobs.to_vec()) {
+        let snapshot = repo.snapshot_path(&revision);
+        if !snapshot.is_dir() {
+            continue;
+        }
+        let mut lock = match dry_run {
+            true => None,
+            false => Some(FsLock::lock(snapshot.clone())?),
+        };
+        let result = remove_from_snapshot(repo, &revision, &snapshot, &files, dry_run);
+        if let Some(ref mut lock) = lock {
+            lock.unlock();
+        }
+        for (hub_name, size) in result? {
+            deleted.push(hub_name);
+            freed += size;
+        }
+    }
+    deleted.sort();
+    Ok((deleted, freed))
+}
+
+/// Deletes the files of `snapshot`, the one of `revision` locked by the caller, not among the
+/// listed `files`, returning their hub filenames and sizes.
+fn remove_from_snapshot(
+    repo: &Repo,
+    revision: &str,
+    snapshot: &Path,
+    files: &[FileInfo],
+    dry_run: bool,
+) -> Result<Vec<(String, u64)>, OpsError> {
+    let listed: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+    let mut removed = Vec::new();
+    for (hub_name, (path, size)) in local_files(repo, snapshot, revision)? {
+        if listed.contains(hub_name.as_str()) {
+            continue;
+        }
+        if !dry_run {
+            std::fs::remove_file(&path)?;
+            dedup::DedupIndex::new(repo.cache_home()).forget(&path)?;
+            unmark_complete(repo, revision, &hub_name)?;
+            remove_empty_parents(snapshot, &path)?;
+        }
+        removed.push((hub_name, size));
+    }
+    Ok(removed)
+}
+
+/// Removes the directories between the deleted file at `path` and `snapshot` left empty.
+fn remove_empty_parents(snapshot: &Path, path: &Path) -> Result<(), OpsError> {
+    let mut dir = path.parent();
+    while let Some(parent) = dir
+        && parent != snapshot
+        && parent.starts_with(snapshot)
+    {
+        if std::fs::read_dir(parent)?.next().is_some() {
+            break;
+        }
+        std::fs::remove_dir(parent)?;
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// The paths and sizes of the files in `snapshot`, the one of `revision`, by hub filename.
+fn local_files(
+    repo: &Repo,
+    snapshot: &Path,
+    revision: &str,
+) -> Result<BTreeMap<String, (PathBuf, u64)>, OpsError> {
+    let hub_names = paths::hub_names(repo, revision);
+    let mut files = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(snapshot)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_dir() || staging::is_staged(entry.path()) {
+            continue;
+        }
+        let local_name = matching::relative_name(snapshot, entry.path())?;
+        let hub_name = hub_names.get(&local_name).unwrap_or(&local_name).clone();
+        let size = std::fs::metadata(entry.path()).map_or(0, |m| m.len());
+        files.insert(hub_name, (entry.into_path(), size));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes(files: &[FileChange]) -> Vec<(&str, u64)> {
+        files.iter().map(|f| (f.path.as_str(), f.size)).collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let cache = tempfile::tempdir().unwrap();
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_dir(cache.path());
+        let listing = |files: &[(&str, &[u8])], revision| -> Vec<FileInfo> {
+            files
+                .iter()
+                .map(|(path, content)| FileInfo::test_blob(path, content, revision))
+                .collect()
+        };
+        let pulled: [(&str, &[u8]); 2] = [("a.txt", b"aaa"), ("b.txt", b"bb")];
+
+        let status = check(&repo, listing(&pulled, "rev1")).unwrap();
+        assert!(!status.is_up_to_date);
+        assert_eq!(status.local_revision, None);
+        assert_eq!(changes(&status.added), [("a.txt", 3), ("b.txt", 2)]);
+
+        for (path, content) in pulled {
+            let filepath = repo.snapshot_path("rev1").join(path);
+            std::fs::create_dir_all(filepath.parent().unwrap()).unwrap();
+            std::fs::write(filepath, content).unwrap();
+        }
+        repo.create_ref("rev1").unwrap();
+        let status = check(&repo, listing(&pulled, "rev1")).unwrap();
+        assert!(status.is_up_to_date);
+        assert_eq!(status.local_revision.as_deref(), Some("rev1"));
+
+        // b.txt is unchanged under the new revision, so only the revision differs.
+        let status = check(&repo, listing(&[("b.txt", b"bb")], "rev2")).unwrap();
+        assert!(!status.is_up_to_date);
+        assert_eq!(status.hub_revision.as_deref(), Some("rev2"));
+        assert!(status.added.is_empty() && status.modified.is_empty());
+        assert_eq!(changes(&status.removed), [("a.txt", 3)]);
+
+        let changed: [(&str, &[u8]); 3] = [("a.txt", b"aaa"), ("b.txt", b"bbb"), ("c.txt", b"c")];
+        let status = check(&repo, listing(&changed, "rev2")).unwrap();
+        assert_eq!(changes(&status.added), [("c.txt", 1)]);
+        assert_eq!(changes(&status.modified), [("b.txt", 3)]);
+        assert!(status.removed.is_empty());
+    }
+}
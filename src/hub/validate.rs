@@ -0,0 +1,96 @@
+//! Hooks checking each downloaded file before it is moved into the cache, for virus scanning or
+//! custom validation.
+use super::ms_hub::FileInfo;
+use crate::utils::OpsError;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A downloaded file as described by the hub listing, passed to a [`Validator`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HubFileInfo {
+    /// The hub-relative filename, such as `gguf/model.gguf`.
+    pub path: String,
+    /// The revision the hub listed for the file.
+    pub revision: String,
+    /// The listed size in bytes.
+    pub size: u64,
+    /// The listed sha256, when the hub reports one.
+    pub sha256: Option<String>,
+}
+
+impl From<&FileInfo> for HubFileInfo {
+    fn from(fileinfo: &FileInfo) -> Self {
+        Self {
+            path: fileinfo.path.clone(),
+            revision: fileinfo.revision.clone(),
+            size: fileinfo.size as u64,
+            sha256: fileinfo.sha256.clone(),
+        }
+    }
+}
+
+/// Why a [`Validator`] rejected a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    reason: String,
+}
+
+impl ValidationError {
+    /// Creates a new `ValidationError` with `reason`.
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+        }
+    }
+
+    /// Retrieves why the file was rejected.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// A check run on every downloaded file, see [`crate::hub::ModelsCat::with_validator`].
+pub trait Validator: Send + Sync {
+    /// Checks the fully written download at `temp_path`. An error keeps the file out of the
+    /// cache.
+    fn validate(&self, temp_path: &Path, info: &HubFileInfo) -> Result<(), ValidationError>;
+}
+
+impl<F> Validator for F
+where
+    F: Fn(&Path, &HubFileInfo) -> Result<(), ValidationError> + Send + Sync,
+{
+    fn validate(&self, temp_path: &Path, info: &HubFileInfo) -> Result<(), ValidationError> {
+        self(temp_path, info)
+    }
+}
+
+/// Runs `validators` in order on the download at `temp_path`, stopping at the first rejection.
+pub(crate) fn run(
+    validators: &[Arc<dyn Validator>],
+    temp_path: &Path,
+    info: &HubFileInfo,
+) -> Result<(), OpsError> {
+    for validator in validators {
+        validator
+            .validate(temp_path, info)
+            .map_err(|e| rejected(info, e))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn rejected(info: &HubFileInfo, e: ValidationError) -> OpsError {
+    OpsError::ValidationFailed {
+        file: info.path.clone(),
+        reason: e.reason,
+    }
+}
@@ -0,0 +1,171 @@
+//! Audits of the cached files of a repo against the listing, see
+//! [`crate::hub::ModelsCat::verify`] and [`crate::hub::ModelsCat::repair`].
+use super::matching;
+use super::ms_hub::FileInfo;
+use super::paths;
+use super::report::FileStatus;
+use super::{group_by_revision, snapshot_contents, snapshot_file, staging};
+use crate::repo::Repo;
+use crate::utils::{self, OpsError};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Bytes hashed between two progress updates.
+const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// A listed file and where its cached copy belongs.
+pub(crate) struct Check {
+    pub fileinfo: FileInfo,
+    pub filepath: PathBuf,
+}
+
+/// Where the cached copy of each of `blobs` belongs, sorted by hub filename, and the hub
+/// filenames of the files in their snapshots the listing does not place there.
+pub(crate) fn plan(
+    repo: &Repo,
+    blobs: Vec<FileInfo>,
+) -> Result<(Vec<Check>, Vec<String>), OpsError> {
+    let mut checks = Vec::with_capacity(blobs.len());
+    let mut extra = Vec::new();
+    let contents = snapshot_contents(blobs.clone());
+    for (revision, files) in group_by_revision(blobs) {
+        let hub_names = paths::hub_names(repo, &revision);
+        let local_names: HashMap<&str, &str> = hub_names
+            .iter()
+            .map(|(local, hub)| (hub.as_str(), local.as_str()))
+            .collect();
+        let snapshot_path = repo.snapshot_path(&revision);
+        let listed: HashSet<&str> = contents[&revision]
+            .iter()
+            .map(|f| f.path.as_str())
+            .collect();
+        for entry in walkdir::WalkDir::new(&snapshot_path)
+            .min_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if entry.file_type().is_dir() || staging::is_staged(entry.path()) {
+                continue;
+            }
+            let local_name = matching::relative_name(&snapshot_path, entry.path())?;
+            let hub_name = hub_names.get(&local_name).unwrap_or(&local_name);
+            if !listed.contains(hub_name.as_str()) {
+                extra.push(hub_name.clone());
+            }
+        }
+        for fileinfo in &files {
+            let local_name = local_names
+                .get(fileinfo.path.as_str())
+                .copied()
+                .unwrap_or(&fileinfo.path);
+            checks.push(Check {
+                filepath: snapshot_file(&snapshot_path, local_name),
+                fileinfo: fileinfo.clone(),
+            });
+        }
+    }
+    checks.sort_by(|a, b| a.fileinfo.path.cmp(&b.fileinfo.path));
+    extra.sort();
+    Ok((checks, extra))
+}
+
+/// The listed bytes of `blobs`, leaving out with `subtract_cached` the files whose cached copy
+/// is verified, see [`crate::hub::ModelsCat::estimated_pull_size_with_options`].
+pub(crate) fn pending_bytes(
+    repo: &Repo,
+    blobs: Vec<FileInfo>,
+    subtract_cached: bool,
+) -> Result<u64, OpsError> {
+    if !subtract_cached {
+        return Ok(blobs.iter().map(|f| f.size as u64).sum());
+    }
+    let (checks, _) = plan(repo, blobs)?;
+    let mut pending = 0;
+    for check in checks {
+        if status(&check.filepath, &check.fileinfo)? != FileStatus::Verified {
+            pending += check.fileinfo.size as u64;
+        }
+    }
+    Ok(pending)
+}
+
+/// The status of the cached copy at `filepath` of `fileinfo` when it is settled without
+/// hashing: missing, of another size, or of the listed size when the hub lists no sha256.
+pub(crate) fn status_by_size(
+    filepath: &Path,
+    fileinfo: &FileInfo,
+) -> Result<Option<FileStatus>, OpsError> {
+    if !std::fs::exists(filepath)? {
+        return Ok(Some(FileStatus::Missing));
+    }
+    if std::fs::metadata(filepath)?.len() != fileinfo.size as u64 {
+        return Ok(Some(FileStatus::Corrupt));
+    }
+    Ok(fileinfo.sha256.is_none().then_some(FileStatus::Verified))
+}
+
+/// The status of the cached copy at `filepath` of `fileinfo`.
+pub(crate) fn status(filepath: &Path, fileinfo: &FileInfo) -> Result<FileStatus, OpsError> {
+    if let Some(status) = status_by_size(filepath, fileinfo)? {
+        return Ok(status);
+    }
+    Ok(match fileinfo.sha256 == Some(utils::sha256(filepath)?) {
+        true => FileStatus::Verified,
+        false => FileStatus::Corrupt,
+    })
+}
+
+/// Removes the corrupt copy at `filepath` before a repair, along with the blob it links to, so
+/// the repair cannot link the corrupt content back in place.
+pub(crate) fn remove_corrupt(filepath: &Path) -> Result<(), OpsError> {
+    let target = std::fs::canonicalize(filepath)?;
+    std::fs::remove_file(filepath)?;
+    if target != filepath {
+        std::fs::remove_file(target)?;
+    }
+    Ok(())
+}
+
+/// Hashes a file a chunk at a time, so progress can be reported in between.
+pub(crate) struct Hashing {
+    file: File,
+    hasher: Sha256,
+    buffer: Vec<u8>,
+    hashed: u64,
+}
+
+impl Hashing {
+    pub(crate) fn open(path: &Path) -> Result<Self, OpsError> {
+        Ok(Self {
+            file: File::open(path)?,
+            hasher: Sha256::new(),
+            buffer: vec![0; CHUNK_SIZE],
+            hashed: 0,
+        })
+    }
+
+    /// Hashes the next chunk and returns the bytes hashed so far, or `None` at the end.
+    pub(crate) fn step(&mut self) -> Result<Option<u64>, OpsError> {
+        let mut filled = 0;
+        while filled < self.buffer.len() {
+            match self.file.read(&mut self.buffer[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        if filled == 0 {
+            return Ok(None);
+        }
+        self.hasher.update(&self.buffer[..filled]);
+        self.hashed += filled as u64;
+        Ok(Some(self.hashed))
+    }
+
+    /// Whether the file hashed to the listed sha256 of `fileinfo`.
+    pub(crate) fn matches(self, fileinfo: &FileInfo) -> bool {
+        fileinfo.sha256 == Some(format!("{:x}", self.hasher.finalize()))
+    }
+}
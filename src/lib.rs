@@ -1,19 +1,72 @@
 #![deny(missing_docs)]
 #![doc = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/README.md"))]
 
+mod dedup;
 mod fslock;
 
+pub mod cache;
+pub mod config;
+pub mod diagnostics;
+pub mod endpoints;
 pub mod hub;
+pub mod locale;
 pub mod repo;
+#[cfg(test)]
+mod test_server;
 pub mod utils;
 
-pub use hub::{ModelsCat, MultiProgressWrapper, Progress, ProgressBarWrapper, ProgressUnit};
-pub use repo::{Repo, RepoType};
-pub use utils::OpsError;
+pub use cache::{CacheReport, CachedRepo, list_cached_repos, scan_cache};
+pub use config::Config;
+pub use diagnostics::{Diagnostics, diagnostics};
+pub use endpoints::Endpoint;
+pub use hub::{
+    ArchiveCompression, CacheValidation, DownloadOptions, EnsureRequest, ExportMode, FileChange,
+    FileMetadata, FileStatus, HistoryEntry, HistoryOperation, HistoryOutcome, HubFileInfo,
+    ImportOutcome, ImportReport, InvalidPathPolicy, Logger, ModelsCat, MultiProgressWrapper,
+    PreparedSnapshot, Progress, ProgressBarWrapper, ProgressErrorPolicy, ProgressEvent,
+    ProgressUnit, Provenance, ProvenanceRecord, PruneReport, PullOptions, PullReport,
+    RateLimitPacing, RateLimitStatus, ReadyModel, Resolution, RetryPolicy, SegmentedProgress,
+    SyncReport, UpdateStatus, ValidationError, Validator, VerifyReport,
+};
+pub use locale::{Locale, set_locale};
+pub use repo::{Repo, RepoType, Revision};
+pub use utils::{ClientOptions, OpsError};
+
+use std::path::PathBuf;
+
+/// Makes a repo, or some of its files, ready for use in one call, and returns where they are.
+///
+/// Lists the repo at the requested revision, or the default branch, then places the requested
+/// files, or every file, in the cache: cached copies are confirmed against the listing and
+/// everything else is downloaded and verified against its sha256. Pulling every file also
+/// points the `latest` link at the new snapshot. Progress bars are drawn when stderr is a
+/// terminal, unless [`EnsureRequest::with_progress`] says otherwise.
+///
+/// When the hub cannot be reached, the files are taken from the snapshot the revision last
+/// resolved to, and [`ReadyModel::offline`] is set; the error is returned if any of them is
+/// not cached.
+///
+/// ```no_run
+/// use models_cat::{EnsureRequest, ensure_ready};
+///
+/// let model = ensure_ready(
+///     EnsureRequest::model("BAAI/bge-small-zh-v1.5").with_files(["config.json", "model.safetensors"]),
+/// )?;
+/// let weights = model.path("model.safetensors").unwrap();
+/// # Ok::<(), models_cat::OpsError>(())
+/// ```
+pub fn ensure_ready(request: EnsureRequest) -> Result<ReadyModel, OpsError> {
+    let mut cat = ModelsCat::new(request.repo()?);
+    if let Some(ref endpoint) = request.endpoint {
+        cat = cat.with_endpoint(endpoint.clone());
+    }
+    let progress = request.shows_progress().then(MultiProgressWrapper::default);
+    cat.ensure_ready(&request.files, progress)
+}
 
 /// Shortcut for downloading a model
-pub fn download_model(repo_id: &str, filename: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_model(repo_id)).download(filename)
+pub fn download_model(repo_id: &str, filename: &str) -> Result<PathBuf, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Model)?).download(filename)
 }
 
 /// Shortcut for downloading a model with progress
@@ -22,13 +75,14 @@ pub fn download_model_with_progress(
     repo_id: &str,
     filename: &str,
     progress: impl Progress,
-) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_model(repo_id)).download_with_progress(filename, progress)
+) -> Result<PathBuf, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Model)?)
+        .download_with_progress(filename, progress)
 }
 
 /// Shortcut for downloading a dataset
-pub fn download_dataset(repo_id: &str, filename: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_dataset(repo_id)).download(filename)
+pub fn download_dataset(repo_id: &str, filename: &str) -> Result<PathBuf, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Dataset)?).download(filename)
 }
 
 /// Shortcut for downloading a dataset with progress
@@ -36,38 +90,39 @@ pub fn download_dataset_with_progress(
     repo_id: &str,
     filename: &str,
     progress: impl Progress,
-) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_dataset(repo_id)).download_with_progress(filename, progress)
+) -> Result<PathBuf, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Dataset)?)
+        .download_with_progress(filename, progress)
 }
 
 /// Shortcut pulling a model repo
-pub fn pull_model(repo_id: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_model(repo_id)).pull()
+pub fn pull_model(repo_id: &str) -> Result<PullReport, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Model)?).pull()
 }
 
 /// Shortcut pulling a dataset repo
-pub fn pull_dataset(repo_id: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_dataset(repo_id)).pull()
+pub fn pull_dataset(repo_id: &str) -> Result<PullReport, OpsError> {
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Dataset)?).pull()
 }
 
 /// Shortcut removing a local model repo
 pub fn remove_model_repo(repo_id: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_model(repo_id)).remove_all()
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Model)?).remove_all()
 }
 
 /// Shortcut removing a local dataset repo
 pub fn remove_dataset_repo(repo_id: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_dataset(repo_id)).remove_all()
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Dataset)?).remove_all()
 }
 
 /// Shortcut removing a local model file
 pub fn remove_model_file(repo_id: &str, filname: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_model(repo_id)).remove(filname)
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Model)?).remove(filname)
 }
 
 /// Shortcut removing a local dataset file
 pub fn remove_dataset_file(repo_id: &str, filname: &str) -> Result<(), OpsError> {
-    ModelsCat::new(Repo::new_dataset(repo_id)).remove(filname)
+    ModelsCat::new(Repo::try_new(repo_id, RepoType::Dataset)?).remove(filname)
 }
 
 #[cfg(test)]
@@ -84,6 +139,57 @@ mod tests {
         .unwrap();
     }
 
+    #[test]
+    fn test_ensure_ready() {
+        let cache = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[("a.txt", b"aaa"), ("gguf/b.gguf", b"bbb")];
+        let (server, _) = hub::test_hub(files, "rev1");
+        let request = EnsureRequest::model("BAAI/bge-small-zh-v1.5")
+            .with_cache_dir(cache.path())
+            .with_endpoint(Endpoint::new(server.url()).unwrap())
+            .with_progress(false);
+        let repo = request.repo().unwrap();
+
+        // Only the requested files are placed, leaving `latest` alone.
+        let model = ensure_ready(request.clone().with_files(["gguf/b.gguf"])).unwrap();
+        assert_eq!(model.revision, "rev1");
+        assert!(model.snapshot.is_absolute() && model.snapshot.ends_with("snapshots/rev1"));
+        assert_eq!(model.files.len(), 1);
+        let b = model.path("gguf/b.gguf").unwrap();
+        assert_eq!(b, model.snapshot.join("gguf/b.gguf"));
+        assert_eq!(std::fs::read(b).unwrap(), b"bbb");
+        assert!(!model.offline);
+        assert!(repo.latest_snapshot().is_none());
+
+        let model = ensure_ready(request.clone()).unwrap();
+        assert_eq!(
+            model.files.keys().collect::<Vec<_>>(),
+            ["a.txt", "gguf/b.gguf"]
+        );
+        assert_eq!(std::fs::read(model.path("a.txt").unwrap()).unwrap(), b"aaa");
+        assert_eq!(repo.latest_snapshot(), Some(repo.snapshot_path("rev1")));
+
+        let err = ensure_ready(request.clone().with_files(["c.txt"])).unwrap_err();
+        assert!(matches!(err, OpsError::HubError(ref m) if m.contains("c.txt")));
+
+        // Without the hub, cached files are served from the snapshot of the revision.
+        let unreachable = request.with_endpoint(Endpoint::new("http://127.0.0.1:9").unwrap());
+        let offline = ensure_ready(unreachable.clone().with_files(["a.txt"])).unwrap();
+        assert!(offline.offline);
+        assert_eq!(offline.revision, "rev1");
+        assert_eq!(offline.files.len(), 1);
+        assert_eq!(offline.path("a.txt"), model.path("a.txt"));
+        assert_eq!(
+            ensure_ready(unreachable.clone()).unwrap().files,
+            model.files
+        );
+        std::fs::remove_file(model.path("a.txt").unwrap()).unwrap();
+        assert!(matches!(
+            ensure_ready(unreachable.with_files(["a.txt"])),
+            Err(OpsError::RequestError(_))
+        ));
+    }
+
     #[test]
     fn test_cache_dir_env() {
         unsafe {
@@ -104,16 +210,40 @@ mod tests {
 #[cfg(feature = "tokio")]
 pub mod asynchronous {
     pub use crate::hub::async_hub::{
-        ModelsCat, MultiProgressWrapper, Progress, ProgressBarWrapper, ProgressUnit,
+        AsyncProgressAdapter, BlockingProgressAdapter, CacheValidation, DownloadOptions,
+        EnsureRequest, ExportMode, HistoryEntry, HistoryOperation, HistoryOutcome, HubFileInfo,
+        ImportOutcome, ImportReport, InvalidPathPolicy, Logger, ModelsCat, MultiProgressWrapper,
+        PreparedSnapshot, Progress, ProgressBarWrapper, ProgressErrorPolicy, ProgressEvent,
+        ProgressUnit, Provenance, ProvenanceRecord, RateLimitPacing, RateLimitStatus, ReadyModel,
+        RetryPolicy, SegmentedProgress, ValidationError, Validator,
+    };
+    pub use crate::hub::facade::{Hub, HubBuilder};
+    pub use crate::hub::{
+        FileChange, FileMetadata, FileStatus, PruneReport, PullReport, Resolution, SyncReport,
+        UpdateStatus, VerifyReport,
     };
-    pub use crate::repo::{Repo, RepoType};
-    pub use crate::utils::OpsError;
+    pub use crate::repo::{Repo, RepoType, Revision};
+    pub use crate::utils::{ClientOptions, OpsError};
+
+    use crate::hub::facade::default_hub;
+    use std::path::PathBuf;
+
+    /// Makes a repo, or some of its files, ready for use in one call, and returns where they
+    /// are.
+    ///
+    /// See [`crate::ensure_ready`].
+    pub async fn ensure_ready(request: EnsureRequest) -> Result<ReadyModel, OpsError> {
+        let mut cat = ModelsCat::new(request.repo()?);
+        if let Some(ref endpoint) = request.endpoint {
+            cat = cat.with_endpoint(endpoint.clone());
+        }
+        let progress = request.shows_progress().then(MultiProgressWrapper::default);
+        cat.ensure_ready(&request.files, progress).await
+    }
 
     /// Shortcut for downloading a model
-    pub async fn download_model(repo_id: &str, filename: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_model(repo_id))
-            .download(filename)
-            .await
+    pub async fn download_model(repo_id: &str, filename: &str) -> Result<PathBuf, OpsError> {
+        default_hub().download_model(repo_id, filename).await
     }
 
     /// Shortcut for downloading a model with progress
@@ -121,17 +251,15 @@ pub mod asynchronous {
         repo_id: &str,
         filename: &str,
         progress: impl Progress,
-    ) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_model(repo_id))
-            .download_with_progress(filename, progress)
+    ) -> Result<PathBuf, OpsError> {
+        default_hub()
+            .download_model_with_progress(repo_id, filename, progress)
             .await
     }
 
     /// Shortcut for downloading a dataset
-    pub async fn download_dataset(repo_id: &str, filename: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_dataset(repo_id))
-            .download(filename)
-            .await
+    pub async fn download_dataset(repo_id: &str, filename: &str) -> Result<PathBuf, OpsError> {
+        default_hub().download_dataset(repo_id, filename).await
     }
 
     /// Shortcut for downloading a dataset with progress
@@ -139,46 +267,40 @@ pub mod asynchronous {
         repo_id: &str,
         filename: &str,
         progress: impl Progress,
-    ) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_dataset(repo_id))
-            .download_with_progress(filename, progress)
+    ) -> Result<PathBuf, OpsError> {
+        default_hub()
+            .download_dataset_with_progress(repo_id, filename, progress)
             .await
     }
 
     /// Shortcut pulling a model repo
-    pub async fn pull_model(repo_id: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_model(repo_id)).pull().await
+    pub async fn pull_model(repo_id: &str) -> Result<PullReport, OpsError> {
+        default_hub().pull_model(repo_id).await
     }
 
     /// Shortcut pulling a dataset repo
-    pub async fn pull_dataset(repo_id: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_dataset(repo_id)).pull().await
+    pub async fn pull_dataset(repo_id: &str) -> Result<PullReport, OpsError> {
+        default_hub().pull_dataset(repo_id).await
     }
 
     /// Shortcut removing a local model repo
     pub async fn remove_model_repo(repo_id: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_model(repo_id)).remove_all().await
+        default_hub().remove_model_repo(repo_id).await
     }
 
     /// Shortcut removing a local dataset repo
     pub async fn remove_dataset_repo(repo_id: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_dataset(repo_id))
-            .remove_all()
-            .await
+        default_hub().remove_dataset_repo(repo_id).await
     }
 
     /// Shortcut removing a local model file
     pub async fn remove_model_file(repo_id: &str, filname: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_model(repo_id))
-            .remove(filname)
-            .await
+        default_hub().remove_model_file(repo_id, filname).await
     }
 
     /// Shortcut removing a local dataset file
     pub async fn remove_dataset_file(repo_id: &str, filname: &str) -> Result<(), OpsError> {
-        ModelsCat::new(Repo::new_dataset(repo_id))
-            .remove(filname)
-            .await
+        default_hub().remove_dataset_file(repo_id, filname).await
     }
 
     #[cfg(test)]
@@ -186,6 +308,35 @@ pub mod asynchronous {
         use super::*;
         use tokio::test;
 
+        #[test]
+        async fn test_ensure_ready() {
+            let cache = tempfile::tempdir().unwrap();
+            let files: &[(&str, &[u8])] = &[("a.txt", b"aaa"), ("gguf/b.gguf", b"bbb")];
+            let (server, _) = crate::hub::test_hub(files, "rev1");
+            let request = EnsureRequest::model("BAAI/bge-small-zh-v1.5")
+                .with_cache_dir(cache.path())
+                .with_endpoint(crate::Endpoint::new(server.url()).unwrap())
+                .with_progress(false);
+
+            let model = ensure_ready(request.clone().with_files(["a.txt"]))
+                .await
+                .unwrap();
+            assert_eq!(model.files.len(), 1);
+            assert_eq!(std::fs::read(model.path("a.txt").unwrap()).unwrap(), b"aaa");
+            let model = ensure_ready(request.clone()).await.unwrap();
+            assert_eq!(model.files.len(), 2);
+            assert_eq!(
+                request.repo().unwrap().latest_snapshot(),
+                Some(model.snapshot.clone())
+            );
+
+            let unreachable =
+                request.with_endpoint(crate::Endpoint::new("http://127.0.0.1:9").unwrap());
+            let offline = ensure_ready(unreachable).await.unwrap();
+            assert!(offline.offline);
+            assert_eq!(offline.files, model.files);
+        }
+
         #[test]
         async fn test_download_model() {
             download_model_with_progress(
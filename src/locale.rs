@@ -0,0 +1,67 @@
+//! Language of user-facing messages.
+//!
+//! Only `Display` output is localized; error variants and `Debug` output stay the same in
+//! every locale so they can be matched programmatically.
+use std::sync::atomic::{AtomicU8, Ordering};
+
+static LOCALE: AtomicU8 = AtomicU8::new(Locale::En as u8);
+
+/// A language for user-facing messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    /// English, the default.
+    #[default]
+    En,
+    /// Simplified Chinese.
+    ZhCn,
+}
+
+/// Sets the language of user-facing messages for the whole process.
+pub fn set_locale(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+/// Returns the language of user-facing messages.
+pub fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        x if x == Locale::ZhCn as u8 => Locale::ZhCn,
+        _ => Locale::En,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OpsError, PullReport};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_catalogs() {
+        let lock = OpsError::LockAcquisition(PathBuf::from("a.lock"));
+        let not_found = OpsError::HubError("file not found".into());
+        let report = PullReport {
+            downloaded: vec!["a".into()],
+            skipped: vec!["b".into(), "c".into()],
+            deduplicated: vec![],
+            saved_bytes: 0,
+            invalid: vec![],
+            ..Default::default()
+        };
+
+        assert_eq!(locale(), Locale::En);
+        assert_eq!(lock.to_string(), "Lock acquisition failed: a.lock");
+        assert_eq!(not_found.to_string(), "Hub error file not found");
+        assert_eq!(
+            report.to_string(),
+            "1 downloaded, 2 up to date, 0 deduplicated (0 bytes saved)"
+        );
+
+        set_locale(Locale::ZhCn);
+        let zh = (lock.to_string(), not_found.to_string(), report.to_string());
+        set_locale(Locale::En);
+        assert_eq!(zh.0, "获取文件锁失败：a.lock");
+        assert_eq!(zh.1, "模型中心错误：file not found");
+        assert_eq!(zh.2, "已下载 1 个，已是最新 2 个，去重 0 个（节省 0 字节）");
+        assert!(format!("{lock:?}").starts_with("LockAcquisition"));
+    }
+}
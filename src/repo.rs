@@ -1,12 +1,27 @@
 //! The representation of a repo on the hub.
+use crate::config;
+use crate::utils::OpsError;
+use std::fmt;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 
-const MODELS_CAT_CACHE_DIR: &str = "MODELS_CAT_CACHE_DIR";
-fn default_cache_dir() -> PathBuf {
+pub(crate) const MODELS_CAT_CACHE_DIR: &str = "MODELS_CAT_CACHE_DIR";
+pub(crate) fn default_cache_dir() -> PathBuf {
+    cache_dir_for(None)
+}
+
+/// The cache directory of `repo_id`: `$MODELS_CAT_CACHE_DIR`, then the config file, then
+/// `~/.cache/modelscope/hub`.
+fn cache_dir_for(repo_id: Option<&str>) -> PathBuf {
     if let Ok(dir) = std::env::var(MODELS_CAT_CACHE_DIR) {
         return PathBuf::from(dir);
     }
+    if let Some(dir) = repo_id
+        .map_or_else(config::defaults, config::settings_for)
+        .cache_dir
+    {
+        return dir;
+    }
     let mut path = dirs::home_dir().expect("Home directory cannot be found");
     path.push(".cache");
     path.push("modelscope");
@@ -19,8 +34,9 @@ fn default_cache_dir() -> PathBuf {
 pub struct Repo {
     repo_id: String,
     repo_type: RepoType,
-    revision: String,
+    revision: Revision,
     cache_dir: PathBuf,
+    cache_key: Option<String>,
 }
 
 impl Repo {
@@ -31,14 +47,25 @@ impl Repo {
         Self {
             repo_id: repo_id.to_string(),
             repo_type,
-            revision: Self::REVISION_MAIN.to_string(),
-            cache_dir: default_cache_dir(),
+            revision: Revision::Branch(Self::REVISION_MAIN.to_string()),
+            cache_dir: cache_dir_for(Some(repo_id)),
+            cache_key: None,
         }
     }
 
+    /// Creates a new `Repo` like [`Repo::new`], checking first that `repo_id` has the
+    /// `owner/name` form, so a typo fails here instead of as a 404 from the hub.
+    pub fn try_new(repo_id: &str, repo_type: RepoType) -> Result<Self, OpsError> {
+        validate_repo_id(repo_id)?;
+        Ok(Self::new(repo_id, repo_type))
+    }
+
     /// Sets the revision of the repository.
-    pub fn set_revision(&mut self, revision: &str) {
-        self.revision = revision.to_string();
+    ///
+    /// A plain string is classified by [`Revision::from`]; use [`Revision::tag`] and friends
+    /// to be explicit.
+    pub fn set_revision(&mut self, revision: impl Into<Revision>) {
+        self.revision = revision.into();
     }
 
     /// Sets the cache directory for the repository.
@@ -46,6 +73,27 @@ impl Repo {
         self.cache_dir = cache_dir.into();
     }
 
+    /// Overrides the name of the repository directory under the cache home, which defaults to
+    /// `{type}--{owner}--{name}`.
+    ///
+    /// Repos sharing a key share their cache storage, which lets mirrored repo IDs with
+    /// identical content reuse each other's downloads. Path separators in the key are replaced
+    /// with `--` so it always stays a single directory. A key that still does not name one, such
+    /// as an empty key, `..` or `C:x`, is refused with [`OpsError::BuildError`].
+    pub fn set_cache_key(&mut self, cache_key: &str) -> Result<(), OpsError> {
+        let key = cache_key.replace(['/', '\\'], "--");
+        let mut components = Path::new(&key).components();
+        let single =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !single || key.contains(':') {
+            return Err(OpsError::BuildError(format!(
+                "invalid cache key `{cache_key}`, expected a single directory name"
+            )));
+        }
+        self.cache_key = Some(key);
+        Ok(())
+    }
+
     /// Shortcut for creating a new model repository.
     pub fn new_model(repo_id: &str) -> Self {
         Self::new(repo_id, RepoType::Model)
@@ -77,7 +125,7 @@ impl Repo {
     }
 
     /// Get the revision.
-    pub fn revision(&self) -> &str {
+    pub fn revision(&self) -> &Revision {
         &self.revision
     }
 
@@ -86,9 +134,11 @@ impl Repo {
     /// This function generates a unique cache directory path based on the repository type and ID.
     /// The path is constructed to ensure compatibility with filesystem path conventions.
     pub fn cache_dir(&self) -> PathBuf {
-        let prefix = self.repo_type.to_path_part();
         let mut path = self.cache_dir.clone();
-        path.push(format!("{prefix}--{}", self.repo_id).replace('/', "--"));
+        match self.cache_key {
+            Some(ref cache_key) => path.push(cache_key),
+            None => path.push(cache_dir_name(&self.repo_type, &self.repo_id)),
+        }
         path
     }
 
@@ -120,34 +170,85 @@ impl Repo {
 
     /// Revision needs to be url escaped before being used in a URL
     pub fn safe_revision_path(&self) -> String {
-        self.revision.replace('/', "%2F")
+        match self.revision {
+            Revision::Branch(ref name) | Revision::Tag(ref name) => name.replace('/', "%2F"),
+            Revision::Commit(ref hash) => hash.clone(),
+        }
     }
 
-    /// Get ref path, such as
-    pub fn ref_path(&self) -> PathBuf {
+    /// Get the ref path of a branch or tag, such as `refs/master` or `refs/tags/v1.0`.
+    ///
+    /// Commits need no ref and return `None`.
+    pub fn ref_path(&self) -> Option<PathBuf> {
         let mut ref_path = self.cache_dir();
         ref_path.push("refs");
-        ref_path.push(self.revision());
-        ref_path
+        match self.revision {
+            Revision::Branch(ref name) => ref_path.push(name),
+            Revision::Tag(ref name) => {
+                ref_path.push("tags");
+                ref_path.push(name);
+            }
+            Revision::Commit(_) => return None,
+        }
+        Some(ref_path)
+    }
+
+    /// Resolves the revision to a commit hash without network access: commits resolve to
+    /// themselves, branches and tags through their ref in the cache.
+    pub fn cached_commit(&self) -> Option<String> {
+        match self.revision {
+            Revision::Commit(ref hash) => Some(hash.clone()),
+            _ => {
+                let commit = std::fs::read_to_string(self.ref_path()?).ok()?;
+                Some(commit.trim().to_string())
+            }
+        }
+    }
+
+    /// Returns the snapshot directory the revision resolves to offline, see
+    /// [`Repo::cached_commit`].
+    pub fn revision_snapshot(&self) -> Option<PathBuf> {
+        self.cached_commit()
+            .map(|commit| self.snapshot_path(&commit))
     }
 
     /// Creates a reference in the cache directory that points branches to the correct
-    /// commits within the blobs.
+    /// commits within the blobs. Commits need no ref, so this does nothing for them.
+    ///
+    /// The ref is replaced atomically, so readers see either the old or the new commit.
     pub fn create_ref(&self, commit_hash: &str) -> Result<(), std::io::Error> {
-        let ref_path = self.ref_path();
+        let Some(ref_path) = self.ref_path() else {
+            return Ok(());
+        };
         // Needs to be done like this because revision might contain `/` creating subfolders here.
-        std::fs::create_dir_all(ref_path.parent().unwrap())?;
-        let mut file = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&ref_path)?;
-        file.write_all(commit_hash.trim().as_bytes())?;
+        let parent = ref_path.parent().unwrap();
+        std::fs::create_dir_all(parent)?;
+        let mut temp = tempfile::NamedTempFile::new_in(parent)?;
+        temp.write_all(commit_hash.trim().as_bytes())?;
+        temp.persist(&ref_path).map_err(|e| e.error)?;
         Ok(())
     }
 
+    /// Path of the `latest` symlink, which points at the snapshot of the last fully successful
+    /// pull. Where symlinks are unavailable, the snapshot path is written to a `latest.path`
+    /// text file next to it instead.
+    pub fn latest_path(&self) -> PathBuf {
+        self.cache_dir().join("latest")
+    }
+
+    /// Resolves the snapshot directory of the last fully successful pull, through the `latest`
+    /// symlink or the `latest.path` fallback file.
+    pub fn latest_snapshot(&self) -> Option<PathBuf> {
+        let latest = self.latest_path();
+        if let Ok(target) = std::fs::read_link(&latest) {
+            return Some(self.cache_dir().join(target));
+        }
+        let path = std::fs::read_to_string(latest.with_extension("path")).ok()?;
+        Some(PathBuf::from(path.trim()))
+    }
+
     /// Returns the path to the snapshot directory for a specific commit hash.
-    /// 
+    ///
     /// The snapshot directory is located within the repository's cache directory under the `snapshots` folder.
     /// This function constructs the full path by appending the `snapshots` folder and the provided `commit_hash`.
     pub fn snapshot_path(&self, commit_hash: &str) -> PathBuf {
@@ -156,10 +257,141 @@ impl Repo {
         pointer_path.push(commit_hash);
         pointer_path
     }
+
+    /// Returns the path to the blob of `sha256` in the content-addressed blob store, see
+    /// [`crate::hub::ModelsCat::with_blob_store`].
+    ///
+    /// Blobs are stored under the `blobs` folder of the repository's cache directory.
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.cache_dir().join("blobs").join(sha256)
+    }
+}
+
+impl TryFrom<&str> for Repo {
+    type Error = OpsError;
+
+    /// Parses a bare `owner/name` repo ID as a model repository.
+    fn try_from(repo_id: &str) -> Result<Self, Self::Error> {
+        Self::try_new(repo_id, RepoType::Model)
+    }
+}
+
+/// Checks that `repo_id` has the `owner/name` form.
+pub(crate) fn validate_repo_id(repo_id: &str) -> Result<(), OpsError> {
+    let valid = match repo_id.split_once('/') {
+        Some((owner, name)) => {
+            !owner.is_empty()
+                && !name.is_empty()
+                && !name.contains('/')
+                && !repo_id.chars().any(char::is_whitespace)
+        }
+        None => false,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(OpsError::BuildError(format!(
+            "invalid repo id `{repo_id}`, expected `owner/name`"
+        )))
+    }
+}
+
+/// A revision of a repository: a branch, a tag, or a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Revision {
+    /// A branch, such as `master`; follows the branch head.
+    Branch(String),
+    /// A tag, such as `v1.0`.
+    Tag(String),
+    /// A full commit hash.
+    Commit(String),
+}
+
+impl Revision {
+    /// Creates a branch revision.
+    pub fn branch(name: impl Into<String>) -> Self {
+        Self::Branch(name.into())
+    }
+
+    /// Creates a tag revision.
+    pub fn tag(name: impl Into<String>) -> Self {
+        Self::Tag(name.into())
+    }
+
+    /// Creates a commit revision.
+    pub fn commit(hash: impl Into<String>) -> Self {
+        Self::Commit(hash.into())
+    }
+
+    /// The branch name, tag name, or commit hash.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Branch(s) | Self::Tag(s) | Self::Commit(s) => s,
+        }
+    }
+
+    /// Whether this revision is pinned to a commit.
+    pub fn is_commit(&self) -> bool {
+        matches!(self, Self::Commit(_))
+    }
+}
+
+impl From<&str> for Revision {
+    /// Best-effort classification: 40 hex digits is a [`Revision::Commit`], anything else a
+    /// [`Revision::Branch`]. Tags cannot be told apart from branches by name, use
+    /// [`Revision::tag`] for them.
+    ///
+    /// # Examples
+    /// ```
+    /// use models_cat::repo::Revision;
+    /// assert!(Revision::from("0123456789abcdef0123456789abcdef01234567").is_commit());
+    /// assert_eq!(Revision::from("master"), Revision::branch("master"));
+    /// ```
+    fn from(revision: &str) -> Self {
+        if revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit()) {
+            Self::Commit(revision.to_ascii_lowercase())
+        } else {
+            Self::Branch(revision.to_string())
+        }
+    }
+}
+
+impl From<String> for Revision {
+    fn from(revision: String) -> Self {
+        Self::from(revision.as_str())
+    }
+}
+
+impl fmt::Display for Revision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The default name of a repo's directory under the cache home: `{type}--{owner}--{name}`.
+pub(crate) fn cache_dir_name(repo_type: &RepoType, repo_id: &str) -> String {
+    format!("{}--{repo_id}", repo_type.to_path_part()).replace('/', "--")
+}
+
+/// Decodes a directory name produced by [`cache_dir_name`] back into the repo type and ID.
+///
+/// The owner is everything up to the first `--` after the type, so names containing `--` decode
+/// correctly, while directories named by a custom cache key are not recognized.
+pub(crate) fn parse_cache_dir_name(dir_name: &str) -> Option<(RepoType, String)> {
+    let (prefix, repo_id) = dir_name.split_once("--")?;
+    let repo_type = [RepoType::Model, RepoType::Dataset, RepoType::Space]
+        .into_iter()
+        .find(|t| t.to_path_part() == prefix)?;
+    let (owner, name) = repo_id.split_once("--")?;
+    if owner.is_empty() || name.is_empty() {
+        return None;
+    }
+    Some((repo_type, format!("{owner}/{name}")))
 }
 
 /// The type of repo to interact with
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RepoType {
     /// This is a model, usually it consists of weight files and some configuration
     /// files
@@ -175,10 +407,10 @@ impl RepoType {
     ///
     /// # Examples
     /// ```
-    /// use models_hub::RepoType;
-    /// assert_eq!(RepoType::Model.root_dir(), "models");
-    /// assert_eq!(RepoType::Dataset.root_dir(), "datasets");
-    /// assert_eq!(RepoType::Space.root_dir(), "spaces");
+    /// use models_cat::RepoType;
+    /// assert_eq!(RepoType::Model.to_path_part(), "models");
+    /// assert_eq!(RepoType::Dataset.to_path_part(), "datasets");
+    /// assert_eq!(RepoType::Space.to_path_part(), "spaces");
     /// ```
     pub fn to_path_part(&self) -> &'static str {
         match self {
@@ -188,3 +420,97 @@ impl RepoType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_str() {
+        let repo = Repo::try_from("BAAI/bge-small-zh-v1.5").unwrap();
+        assert_eq!(repo.repo_id(), "BAAI/bge-small-zh-v1.5");
+        assert!(matches!(repo.repo_type(), RepoType::Model));
+
+        for invalid in ["bge-small", "/name", "owner/", "a/b/c", "owner/na me", ""] {
+            assert!(
+                matches!(Repo::try_from(invalid), Err(OpsError::BuildError(_))),
+                "{invalid}"
+            );
+        }
+        let dataset = Repo::try_new("DAMO_NLP/yf_dianping", RepoType::Dataset).unwrap();
+        assert!(matches!(dataset.repo_type(), RepoType::Dataset));
+        assert!(Repo::try_new("yf_dianping", RepoType::Dataset).is_err());
+        assert!(matches!(
+            crate::download_model("bge-small", "config.json"),
+            Err(OpsError::BuildError(_))
+        ));
+    }
+
+    #[test]
+    fn test_set_cache_key() {
+        let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+        repo.set_cache_key("x/y").unwrap();
+        assert!(repo.cache_dir().ends_with("x--y"));
+        for invalid in ["", ".", "..", "C:x", "C:", "a:b"] {
+            assert!(
+                matches!(repo.set_cache_key(invalid), Err(OpsError::BuildError(_))),
+                "{invalid}"
+            );
+        }
+        assert!(repo.cache_dir().ends_with("x--y"));
+    }
+
+    #[test]
+    fn test_revision_from_str() {
+        let hash = "0123456789ABCDEF0123456789abcdef01234567";
+        assert_eq!(Revision::from(hash), Revision::commit(hash.to_lowercase()));
+        assert_eq!(Revision::from(&hash[1..]), Revision::branch(&hash[1..]));
+        assert_eq!(Revision::from("release/v1"), Revision::branch("release/v1"));
+        assert_eq!(Revision::from("v1.0".to_string()), Revision::branch("v1.0"));
+        assert_eq!(Revision::tag("v1.0").to_string(), "v1.0");
+    }
+
+    #[test]
+    fn test_revision_paths() {
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        // (revision, URL segment, ref path under refs/)
+        let cases = [
+            (Revision::branch("master"), "master", Some("master")),
+            (
+                Revision::branch("release/v1"),
+                "release%2Fv1",
+                Some("release/v1"),
+            ),
+            (Revision::tag("v1.0"), "v1.0", Some("tags/v1.0")),
+            (Revision::tag("ms/v1.0"), "ms%2Fv1.0", Some("tags/ms/v1.0")),
+            (Revision::commit(hash), hash, None),
+        ];
+        let cache = tempfile::tempdir().unwrap();
+        for (revision, url_part, ref_part) in cases {
+            let mut repo = Repo::new_model("BAAI/bge-small-zh-v1.5");
+            repo.set_cache_dir(cache.path());
+            repo.set_revision(revision.clone());
+            assert_eq!(repo.safe_revision_path(), url_part);
+            assert_eq!(
+                repo.url_path_with_revision(),
+                format!("models/BAAI/bge-small-zh-v1.5/revision/{url_part}")
+            );
+            assert_eq!(
+                repo.url_path_with_resolve(),
+                format!("models/BAAI/bge-small-zh-v1.5/resolve/{url_part}")
+            );
+            let refs = repo.cache_dir().join("refs");
+            assert_eq!(repo.ref_path(), ref_part.map(|p| refs.join(p)));
+
+            // Offline resolution: commits resolve directly, branches and tags via their ref.
+            if revision.is_commit() {
+                assert_eq!(repo.revision_snapshot(), Some(repo.snapshot_path(hash)));
+            } else {
+                assert_eq!(repo.revision_snapshot(), None);
+                repo.create_ref("abc").unwrap();
+                assert_eq!(repo.cached_commit().as_deref(), Some("abc"));
+                assert_eq!(repo.revision_snapshot(), Some(repo.snapshot_path("abc")));
+            }
+        }
+    }
+}
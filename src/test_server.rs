@@ -0,0 +1,185 @@
+//! A minimal HTTP/1.1 server for tests, serving responses produced by a handler closure.
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A request received by the [`MockServer`].
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// Returns the value of the first header named `name`, case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A response returned by the handler of a [`MockServer`].
+#[derive(Debug, Clone)]
+pub(crate) struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Advertised `Content-Length`; defaults to the body length. A larger value simulates a
+    /// connection dropped mid-transfer.
+    pub content_length: Option<u64>,
+    /// Sends the body with chunked transfer encoding, without a `Content-Length`.
+    pub chunked: bool,
+    /// How long the connection is kept open after the body, as by a server that hangs.
+    pub stall: Option<Duration>,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Self {
+        Self::status(200).body(body)
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+            content_length: None,
+            chunked: false,
+            stall: None,
+        }
+    }
+
+    /// A `302 Found` pointing at `location`.
+    pub fn redirect(location: &str) -> Self {
+        let mut response = Self::status(302);
+        response.headers.push(("Location".into(), location.into()));
+        response
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Advertises `len` bytes but sends only the body, then closes the connection.
+    pub fn truncated(mut self, len: u64) -> Self {
+        self.content_length = Some(len);
+        self
+    }
+
+    /// Keeps the connection open for `duration` after the body. Along with
+    /// [`Response::truncated`], the rest of the body never arrives.
+    pub fn stalled(mut self, duration: Duration) -> Self {
+        self.stall = Some(duration);
+        self
+    }
+
+    /// Sends the body in a single chunk, as gateways stripping `Content-Length` do.
+    pub fn chunked(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// A background HTTP server bound to an ephemeral localhost port.
+pub(crate) struct MockServer {
+    url: String,
+}
+
+impl MockServer {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handler: Arc<Handler> = Arc::new(handler);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let handler = handler.clone();
+                std::thread::spawn(move || serve(stream, handler.as_ref()));
+            }
+        });
+        Self { url }
+    }
+
+    /// The base URL of the server, without a trailing slash.
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some((k, v)) = line.trim_end().split_once(':') {
+            headers.push((k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+    let request = Request {
+        method,
+        path,
+        headers,
+    };
+    if let Some(len) = request
+        .header("content-length")
+        .and_then(|v| v.parse::<u64>().ok())
+    {
+        let _ = std::io::copy(&mut (&mut reader).take(len), &mut std::io::sink());
+    }
+
+    let response = handler(&request);
+    let mut stream = stream;
+    let mut head = format!("HTTP/1.1 {} MOCK\r\n", response.status);
+    for (k, v) in &response.headers {
+        head.push_str(&format!("{k}: {v}\r\n"));
+    }
+    if response.chunked {
+        head.push_str("Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n");
+    } else {
+        let content_length = response
+            .content_length
+            .unwrap_or(response.body.len() as u64);
+        head.push_str(&format!(
+            "Content-Length: {content_length}\r\nConnection: close\r\n\r\n"
+        ));
+    }
+    let _ = stream.write_all(head.as_bytes());
+    if request.method != "HEAD" {
+        if response.chunked {
+            if !response.body.is_empty() {
+                let _ = write!(stream, "{:x}\r\n", response.body.len());
+                let _ = stream.write_all(&response.body);
+                let _ = stream.write_all(b"\r\n");
+            }
+            let _ = stream.write_all(b"0\r\n\r\n");
+        } else {
+            let _ = stream.write_all(&response.body);
+        }
+    }
+    let _ = stream.flush();
+    if let Some(stall) = response.stall {
+        std::thread::sleep(stall);
+    }
+}
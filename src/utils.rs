@@ -1,62 +1,578 @@
 //! Some utility
+use crate::locale::{Locale, locale};
 use reqwest::blocking;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use sha2::{Digest, Sha256};
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::LazyLock;
+use std::time::Duration;
 use std::{fs::File, io::Read};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 /// All errors the API can throw
+///
+/// The `Display` output follows [`crate::set_locale`]. New variants may be added in minor
+/// releases.
+#[non_exhaustive]
 pub enum OpsError {
     /// We failed to acquire lock for file `f`. Meaning
     /// Someone else is writing/downloading said file
-    #[error("Lock acquisition failed: {0}")]
     LockAcquisition(PathBuf),
 
     /// Build error
-    #[error("Build error {0}")]
     BuildError(String),
 
     /// Hub error
-    #[error("Hub error {0}")]
     HubError(String),
 
+    /// Hub error caused by another error, such as a failure to deserialize a response, which is
+    /// kept as the [`std::error::Error::source`] of this one
+    HubErrorWithSource {
+        /// What failed
+        message: String,
+        /// The underlying error
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     /// I/O Error
-    #[error("I/O error {0}")]
-    IoError(#[from] std::io::Error),
+    IoError(#[source] std::io::Error),
 
     /// request error
-    #[error("Request error {0}")]
-    RequestError(#[from] reqwest::Error),
+    RequestError(#[source] reqwest::Error),
+
+    /// A directory about to be deleted does not look like a repo cache directory, see
+    /// [`crate::hub::ModelsCat::remove_all`]
+    RefusingUnsafeDelete(PathBuf),
+
+    /// A request was redirected more often than [`ClientOptions::max_redirects`] allows
+    TooManyRedirects {
+        /// Every URL visited, starting with the requested one
+        chain: Vec<String>,
+    },
+
+    /// A validator rejected a downloaded file, see [`crate::hub::ModelsCat::with_validator`]
+    ValidationFailed {
+        /// The hub filename of the rejected file
+        file: String,
+        /// Why the validator rejected it
+        reason: String,
+    },
+
+    /// A download or local file does not match the listing of the hub file it stands for, see
+    /// [`crate::hub::ModelsCat::import_file`] and [`crate::hub::ModelsCat::with_quarantine`]
+    ChecksumMismatch {
+        /// The hub filename
+        file: String,
+        /// The listed sha256, or size when the sizes differ
+        expected: String,
+        /// The sha256, or size, of the local file
+        actual: String,
+        /// Where the mismatching download was quarantined, if it was
+        quarantined: Option<PathBuf>,
+    },
+
+    /// The hub refused a request with `401 Unauthorized` or `403 Forbidden`, as for a private
+    /// repo without a valid token, see [`crate::hub::ModelsCat::with_token`]
+    Unauthorized {
+        /// The response status
+        status: u16,
+        /// The URL requested
+        url: String,
+    },
+
+    /// The hub answered a request with `404 Not Found`, as for a file removed from the repo
+    NotFound {
+        /// The URL requested
+        url: String,
+    },
+
+    /// The hub answered with `429 Too Many Requests`, which downloads retry under a
+    /// [`crate::hub::RetryPolicy`], see [`crate::hub::ModelsCat::with_rate_limit_pacing`]
+    RateLimited {
+        /// The URL requested
+        url: String,
+        /// The wait the `Retry-After` header asked for, if any
+        retry_after: Option<std::time::Duration>,
+    },
+
+    /// The hub answered with a `5xx` status, which downloads retry under a
+    /// [`crate::hub::RetryPolicy`]
+    ServerError {
+        /// The response status
+        status: u16,
+        /// The URL requested
+        url: String,
+        /// The wait the `Retry-After` header asked for, if any
+        retry_after: Option<std::time::Duration>,
+    },
+
+    /// A request got no response, or a response stopped sending data, within the timeouts of
+    /// its client, see [`ClientOptions::timeout`] and [`ClientOptions::connect_timeout`]
+    Timeout {
+        /// The URL requested, when known
+        url: Option<String>,
+    },
+
+    /// A download was cancelled through its progress callback, see
+    /// [`crate::hub::Progress::on_progress_control`], its cancellation token or the flag of
+    /// [`crate::hub::ModelsCat::with_cancel_flag`]
+    Cancelled {
+        /// The hub filename, or the repo ID of a cancelled pull
+        file: String,
+    },
+
+    /// The hub has no repo with this ID, or hides it from the token in use
+    RepoNotFound(String),
+
+    /// The listing of a repo has no file of this name
+    FileNotFound {
+        /// The hub filename asked for
+        file: String,
+        /// The repo ID
+        repo: String,
+    },
+
+    /// The hub answered a listing request with an error code of its API
+    ApiError {
+        /// The `Code` of the response
+        code: i64,
+        /// The `Message` of the response
+        message: String,
+    },
+
+    /// A file is not cached and offline mode keeps the hub from being queried, see
+    /// [`crate::hub::ModelsCat::with_offline`]
+    OfflineMiss {
+        /// The hub filename, or the repo and revision for a pull
+        file: String,
+    },
+
+    /// Files of a pull with [`crate::hub::ModelsCat::with_max_concurrency`] failed, each with
+    /// its error, while the others were placed
+    PullFailed {
+        /// The hub filename of each failed file and its error
+        failures: Vec<(String, OpsError)>,
+    },
+}
+
+impl fmt::Display for OpsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (locale(), self) {
+            (Locale::En, Self::LockAcquisition(path)) => {
+                write!(f, "Lock acquisition failed: {}", path.display())
+            }
+            (Locale::En, Self::BuildError(e)) => write!(f, "Build error {e}"),
+            (Locale::En, Self::HubError(e)) => write!(f, "Hub error {e}"),
+            (Locale::En, Self::HubErrorWithSource { message, .. }) => {
+                write!(f, "Hub error {message}")
+            }
+            (Locale::En, Self::IoError(e)) => write!(f, "I/O error {e}"),
+            (Locale::En, Self::RequestError(e)) => write!(f, "Request error {e}"),
+            (Locale::En, Self::RefusingUnsafeDelete(path)) => write!(
+                f,
+                "Refusing to delete {}: not a models-cat repo cache directory",
+                path.display()
+            ),
+            (Locale::En, Self::TooManyRedirects { chain }) => {
+                write!(f, "Too many redirects: {}", chain.join(" -> "))
+            }
+            (Locale::En, Self::ValidationFailed { file, reason }) => {
+                write!(f, "Validation of {file} failed: {reason}")
+            }
+            (
+                Locale::En,
+                Self::ChecksumMismatch {
+                    file,
+                    expected,
+                    actual,
+                    quarantined,
+                },
+            ) => {
+                write!(
+                    f,
+                    "Checksum mismatch for {file}: expected {expected}, got {actual}"
+                )?;
+                match quarantined {
+                    Some(path) => write!(f, " (quarantined at {})", path.display()),
+                    None => Ok(()),
+                }
+            }
+            (Locale::En, Self::Unauthorized { status, url }) => {
+                write!(
+                    f,
+                    "Unauthorized ({status}) for {url}, check the access token"
+                )
+            }
+            (Locale::En, Self::NotFound { url }) => write!(f, "Not found: {url}"),
+            (Locale::En, Self::Cancelled { file }) => write!(f, "Download of {file} cancelled"),
+            (Locale::En, Self::Timeout { url }) => match url {
+                Some(url) => write!(f, "Request timed out: {url}"),
+                None => write!(f, "Request timed out"),
+            },
+            (Locale::En, Self::RateLimited { url, .. }) => {
+                write!(f, "Rate limited by the hub for {url}")
+            }
+            (Locale::En, Self::ServerError { status, url, .. }) => {
+                write!(f, "Server error ({status}) for {url}")
+            }
+            (Locale::En, Self::RepoNotFound(repo)) => write!(f, "Repo {repo} not found"),
+            (Locale::En, Self::FileNotFound { file, repo }) => {
+                write!(f, "File {file} not found in {repo}")
+            }
+            (Locale::En, Self::ApiError { code, message }) => {
+                write!(f, "Hub API error {code}: {message}")
+            }
+            (Locale::En, Self::OfflineMiss { file }) => {
+                write!(
+                    f,
+                    "{file} is not cached, and the hub is not queried offline"
+                )
+            }
+            (Locale::En, Self::PullFailed { failures }) => {
+                write!(f, "Pull failed for {} files: ", failures.len())?;
+                write_failures(f, failures)
+            }
+            (Locale::ZhCn, Self::LockAcquisition(path)) => {
+                write!(f, "获取文件锁失败：{}", path.display())
+            }
+            (Locale::ZhCn, Self::BuildError(e)) => write!(f, "构建错误：{e}"),
+            (Locale::ZhCn, Self::HubError(e)) => write!(f, "模型中心错误：{e}"),
+            (Locale::ZhCn, Self::HubErrorWithSource { message, .. }) => {
+                write!(f, "模型中心错误：{message}")
+            }
+            (Locale::ZhCn, Self::IoError(e)) => write!(f, "I/O 错误：{e}"),
+            (Locale::ZhCn, Self::RequestError(e)) => write!(f, "请求错误：{e}"),
+            (Locale::ZhCn, Self::RefusingUnsafeDelete(path)) => {
+                write!(
+                    f,
+                    "拒绝删除 {}：不是 models-cat 仓库缓存目录",
+                    path.display()
+                )
+            }
+            (Locale::ZhCn, Self::TooManyRedirects { chain }) => {
+                write!(f, "重定向次数过多：{}", chain.join(" -> "))
+            }
+            (Locale::ZhCn, Self::ValidationFailed { file, reason }) => {
+                write!(f, "文件 {file} 校验未通过：{reason}")
+            }
+            (
+                Locale::ZhCn,
+                Self::ChecksumMismatch {
+                    file,
+                    expected,
+                    actual,
+                    quarantined,
+                },
+            ) => {
+                write!(
+                    f,
+                    "文件 {file} 校验和不匹配：应为 {expected}，实为 {actual}"
+                )?;
+                match quarantined {
+                    Some(path) => write!(f, "（已隔离至 {}）", path.display()),
+                    None => Ok(()),
+                }
+            }
+            (Locale::ZhCn, Self::Unauthorized { status, url }) => {
+                write!(f, "无权访问 {url}（{status}），请检查访问令牌")
+            }
+            (Locale::ZhCn, Self::NotFound { url }) => write!(f, "未找到：{url}"),
+            (Locale::ZhCn, Self::Cancelled { file }) => write!(f, "已取消 {file} 的下载"),
+            (Locale::ZhCn, Self::Timeout { url }) => match url {
+                Some(url) => write!(f, "请求超时：{url}"),
+                None => write!(f, "请求超时"),
+            },
+            (Locale::ZhCn, Self::RateLimited { url, .. }) => {
+                write!(f, "请求过于频繁，已被模型中心限流：{url}")
+            }
+            (Locale::ZhCn, Self::ServerError { status, url, .. }) => {
+                write!(f, "服务器错误（{status}）：{url}")
+            }
+            (Locale::ZhCn, Self::RepoNotFound(repo)) => write!(f, "仓库 {repo} 不存在"),
+            (Locale::ZhCn, Self::FileNotFound { file, repo }) => {
+                write!(f, "仓库 {repo} 中不存在文件 {file}")
+            }
+            (Locale::ZhCn, Self::ApiError { code, message }) => {
+                write!(f, "模型中心接口错误 {code}：{message}")
+            }
+            (Locale::ZhCn, Self::OfflineMiss { file }) => {
+                write!(f, "{file} 未缓存，离线模式下不访问模型中心")
+            }
+            (Locale::ZhCn, Self::PullFailed { failures }) => {
+                write!(f, "拉取时 {} 个文件失败：", failures.len())?;
+                write_failures(f, failures)
+            }
+        }
+    }
+}
+
+/// Writes the `failures` of [`OpsError::PullFailed`] as `path (error)`, comma separated.
+fn write_failures(f: &mut fmt::Formatter<'_>, failures: &[(String, OpsError)]) -> fmt::Result {
+    for (i, (path, e)) in failures.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{path} ({e})")?;
+    }
+    Ok(())
+}
+
+impl OpsError {
+    /// A [`OpsError::Unauthorized`] for a response to `url` with `status`, when it is `401` or
+    /// `403`.
+    pub(crate) fn unauthorized(status: reqwest::StatusCode, url: &str) -> Option<Self> {
+        matches!(
+            status,
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN
+        )
+        .then(|| Self::Unauthorized {
+            status: status.as_u16(),
+            url: url.to_string(),
+        })
+    }
+
+    /// A [`OpsError::HubErrorWithSource`] describing what failed and keeping its cause.
+    pub(crate) fn hub(
+        message: impl Into<String>,
+        source: impl Into<Box<dyn std::error::Error + Send + Sync>>,
+    ) -> Self {
+        Self::HubErrorWithSource {
+            message: message.into(),
+            source: source.into(),
+        }
+    }
+}
+
+/// User agent sent with every request.
+pub(crate) const USER_AGENT: &str = "curl/7.79.1";
+
+impl From<std::convert::Infallible> for OpsError {
+    fn from(e: std::convert::Infallible) -> Self {
+        match e {}
+    }
+}
+
+impl From<std::io::Error> for OpsError {
+    /// Reading a response body reports a timeout of its client as an I/O error wrapping it.
+    fn from(e: std::io::Error) -> Self {
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<reqwest::Error>())
+        {
+            Some(inner) if inner.is_timeout() => Self::Timeout {
+                url: inner.url().map(|url| url.to_string()),
+            },
+            _ => Self::IoError(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for OpsError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            return Self::Timeout {
+                url: e.url().map(|url| url.to_string()),
+            };
+        }
+        let chain = std::error::Error::source(&e)
+            .and_then(|source| source.downcast_ref::<RedirectChain>())
+            .map(|redirects| redirects.0.clone());
+        match chain {
+            Some(chain) => Self::TooManyRedirects { chain },
+            None => Self::RequestError(e),
+        }
+    }
+}
+
+/// The URLs visited by a request that exceeded its redirect limit, carried through reqwest's
+/// error so it can surface as [`OpsError::TooManyRedirects`].
+#[derive(Debug)]
+struct RedirectChain(Vec<String>);
+
+impl fmt::Display for RedirectChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "too many redirects: {}", self.0.join(" -> "))
+    }
 }
 
-/// A static HTTP client for making blocking requests.
+impl std::error::Error for RedirectChain {}
+
+/// Settings of the HTTP client a [`crate::ModelsCat`] sends its requests with.
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// Redirects followed per request before failing with [`OpsError::TooManyRedirects`].
+    /// Defaults to 10.
+    pub max_redirects: usize,
+    /// A proxy URL for every request, on top of the `HTTP_PROXY` family of variables.
+    pub proxy: Option<String>,
+    /// Headers added to every request.
+    pub headers: Vec<(String, String)>,
+    /// How long a request may wait for its response, and then for each read of the body,
+    /// before failing with [`OpsError::Timeout`]. A slow download keeps going as long as data
+    /// arrives. Defaults to 30 seconds for the blocking client and none for the async one.
+    pub timeout: Option<Duration>,
+    /// How long connecting may take before failing with [`OpsError::Timeout`]. Defaults to
+    /// none, leaving it to [`ClientOptions::timeout`].
+    pub connect_timeout: Option<Duration>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            max_redirects: 10,
+            proxy: None,
+            headers: Vec::new(),
+            timeout: None,
+            connect_timeout: None,
+        }
+    }
+}
+
+impl ClientOptions {
+    /// Sets the number of redirects followed per request.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Sends every request through the proxy at `url`.
+    pub fn with_proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Sets how long a request may wait for its response and each read of the body.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long connecting may take.
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Adds a header to every request.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    fn default_headers(&self) -> Result<HeaderMap, OpsError> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let invalid =
+                |e: &dyn fmt::Display| OpsError::BuildError(format!("header `{name}`: {e}"));
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| invalid(&e))?;
+            let value = HeaderValue::try_from(value.as_str()).map_err(|e| invalid(&e))?;
+            headers.append(name, value);
+        }
+        Ok(headers)
+    }
+
+    /// A redirect policy failing with the visited URLs once `max_redirects` is exceeded.
+    fn redirect_policy(&self) -> reqwest::redirect::Policy {
+        let max_redirects = self.max_redirects;
+        reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() > max_redirects {
+                let chain = attempt
+                    .previous()
+                    .iter()
+                    .chain([attempt.url()])
+                    .map(|url| url.to_string())
+                    .collect();
+                attempt.error(RedirectChain(chain))
+            } else {
+                attempt.follow()
+            }
+        })
+    }
+
+    pub(crate) fn blocking_client(&self) -> Result<blocking::Client, OpsError> {
+        let mut builder = blocking::Client::builder()
+            .user_agent(USER_AGENT)
+            .redirect(self.redirect_policy())
+            .default_headers(self.default_headers()?);
+        if let Some(ref proxy) = self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn async_client(&self) -> Result<reqwest::Client, OpsError> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .redirect(self.redirect_policy())
+            .default_headers(self.default_headers()?);
+        if let Some(ref proxy) = self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        // A total timeout would cut off downloads of large files, so it applies per read, as
+        // it does for the blocking client.
+        if let Some(timeout) = self.timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// A static HTTP client for making blocking requests with the default [`ClientOptions`].
 ///
-/// Uses a custom user agent and allows up to 10 redirects.
 /// The client is lazily initialized using `LazyLock` to ensure
 /// it is only created when first accessed.
 pub(crate) static BLOCKING_CLIENT: LazyLock<blocking::Client> = LazyLock::new(|| {
-    blocking::Client::builder()
-        .user_agent("curl/7.79.1")
-        .redirect(reqwest::redirect::Policy::limited(10)) // 自定义重定向次数
-        .build()
+    ClientOptions::default()
+        .blocking_client()
         .expect("Failed to build reqwest client")
 });
 
 #[cfg(feature = "tokio")]
 pub(crate) static ASYNC_CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
-    reqwest::Client::builder()
-        .user_agent("curl/7.79.1")
-        .redirect(reqwest::redirect::Policy::limited(10))
-        .build()
+    ClientOptions::default()
+        .async_client()
         .expect("Failed to build async reqwest client")
 });
 
+/// Read buffer for hashing files below [`PIPELINE_THRESHOLD`], unless `sha256_buffer_size` is
+/// set in the [config file](crate::config).
+const DEFAULT_SHA256_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Files at least this large are hashed while a reader thread fetches the next chunks.
+const PIPELINE_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Size of the chunks handed from the reader thread to the hasher, unless `sha256_buffer_size`
+/// is set in the [config file](crate::config).
+const PIPELINE_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Chunks in flight between the reader thread and the hasher.
+const PIPELINE_DEPTH: usize = 4;
+
 pub(crate) fn sha256(file_path: impl AsRef<Path>) -> Result<String, std::io::Error> {
-    let mut file = File::open(file_path)?;
+    let file = File::open(file_path)?;
+    let configured = crate::config::sha256_buffer_size().filter(|&size| size > 0);
+    if file.metadata()?.len() >= PIPELINE_THRESHOLD {
+        return sha256_pipelined(file, configured.unwrap_or(PIPELINE_CHUNK_SIZE));
+    }
+    sha256_sequential(file, configured.unwrap_or(DEFAULT_SHA256_BUFFER_SIZE))
+}
+
+fn sha256_sequential(mut file: File, buffer_size: usize) -> Result<String, std::io::Error> {
     let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024 * 8];
+    let mut buffer = vec![0; buffer_size];
 
     loop {
         let bytes_read = file.read(&mut buffer)?;
@@ -68,8 +584,131 @@ pub(crate) fn sha256(file_path: impl AsRef<Path>) -> Result<String, std::io::Err
     Ok(format!("{:x}", hasher.finalize()))
 }
 
+/// Hashes `file` while a reader thread reads ahead, so disk reads overlap with hashing instead
+/// of alternating with it. Chunks reach the single hasher in file order, so the digest is the
+/// standard whole-file sha256. Falls back to [`sha256_sequential`] if no thread can be spawned.
+fn sha256_pipelined(file: File, chunk_size: usize) -> Result<String, std::io::Error> {
+    use std::sync::mpsc;
+
+    let (full_tx, full_rx) = mpsc::sync_channel::<std::io::Result<Vec<u8>>>(PIPELINE_DEPTH);
+    // Hashed buffers go back to the reader, bounding memory to PIPELINE_DEPTH + 1 chunks.
+    let (empty_tx, empty_rx) = mpsc::channel::<Vec<u8>>();
+    for _ in 0..=PIPELINE_DEPTH {
+        let _ = empty_tx.send(Vec::new());
+    }
+    // Keep a handle to the file for the fallback, which needs it if the thread never starts.
+    let fallback = file.try_clone();
+    let mut reader_file = file;
+    let reader = std::thread::Builder::new()
+        .name("models-cat-sha256".into())
+        .spawn(move || {
+            while let Ok(mut buffer) = empty_rx.recv() {
+                buffer.resize(chunk_size, 0);
+                let chunk = reader_file.read(&mut buffer).map(|n| {
+                    buffer.truncate(n);
+                    buffer
+                });
+                let done = matches!(chunk, Ok(ref b) if b.is_empty()) || chunk.is_err();
+                if full_tx.send(chunk).is_err() || done {
+                    break;
+                }
+            }
+        });
+    let reader = match (reader, fallback) {
+        (Ok(reader), _) => reader,
+        (Err(e), Ok(file)) => {
+            log::debug!("hashing without a reader thread: {e}");
+            return sha256_sequential(file, chunk_size);
+        }
+        (Err(e), Err(_)) => return Err(e),
+    };
+
+    let mut hasher = Sha256::new();
+    for chunk in full_rx.iter() {
+        let chunk = chunk?;
+        if chunk.is_empty() {
+            break;
+        }
+        hasher.update(&chunk);
+        let _ = empty_tx.send(chunk);
+    }
+    drop(empty_tx);
+    reader
+        .join()
+        .map_err(|_| std::io::Error::other("sha256 reader thread panicked"))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::test_server::{MockServer, Response};
+
+    /// Bounces between `/a` and `/b` forever.
+    fn redirect_loop() -> MockServer {
+        MockServer::start(|req| match req.path.as_str() {
+            "/a" => Response::redirect("/b"),
+            _ => Response::redirect("/a"),
+        })
+    }
+
+    fn assert_loop(err: OpsError, url: &str) {
+        let OpsError::TooManyRedirects { ref chain } = err else {
+            panic!("unexpected error {err:?}");
+        };
+        let expected: Vec<_> = ["a", "b", "a", "b"]
+            .iter()
+            .map(|p| format!("{url}/{p}"))
+            .collect();
+        assert_eq!(chain, &expected);
+        assert!(err.to_string().contains(&format!("{url}/a -> {url}/b")));
+    }
+
+    #[test]
+    fn test_too_many_redirects() {
+        let server = redirect_loop();
+        let client = ClientOptions::default()
+            .with_max_redirects(2)
+            .blocking_client()
+            .unwrap();
+        let err = OpsError::from(
+            client
+                .get(format!("{}/a", server.url()))
+                .send()
+                .unwrap_err(),
+        );
+        assert_loop(err, &server.url());
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let server = MockServer::start(|req| Response::ok(req.header("x-team").unwrap_or("none")));
+        let client = ClientOptions::default()
+            .with_header("X-Team", "search")
+            .blocking_client()
+            .unwrap();
+        let body = client.get(server.url()).send().unwrap().text().unwrap();
+        assert_eq!(body, "search");
+        let invalid = ClientOptions::default().with_header("bad header", "x");
+        assert!(matches!(
+            invalid.blocking_client(),
+            Err(OpsError::BuildError(_))
+        ));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_too_many_redirects_async() {
+        let server = redirect_loop();
+        let client = ClientOptions::default()
+            .with_max_redirects(2)
+            .async_client()
+            .unwrap();
+        let url = format!("{}/a", server.url());
+        let err = OpsError::from(client.get(url).send().await.unwrap_err());
+        assert_loop(err, &server.url());
+    }
+
     #[test]
     fn test_sha256() {
         let testfile = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/sha256-testfile.txt");
@@ -79,4 +718,60 @@ mod tests {
             "c2aeccc42d2a579c281daae7e464a14d747924159e28617ad01850f0dd1bd135"
         );
     }
+
+    #[test]
+    fn test_sha256_pipelined() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let content: Vec<u8> = (0..100_003u32).map(|i| (i % 251) as u8).collect();
+        std::io::Write::write_all(&mut file, &content).unwrap();
+        let expected = format!("{:x}", Sha256::digest(&content));
+        // Chunk sizes not dividing the length, and larger than it.
+        for chunk_size in [1, 4096, 7919, 1 << 20] {
+            let sha256 = sha256_pipelined(file.reopen().unwrap(), chunk_size).unwrap();
+            assert_eq!(sha256, expected);
+        }
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        assert_eq!(
+            sha256_pipelined(empty.reopen().unwrap(), 4096).unwrap(),
+            format!("{:x}", Sha256::digest([]))
+        );
+    }
+
+    /// Hashes a sparse 2 GiB file of zeros both ways, checking the known digest and printing the
+    /// throughput of each. Run with `cargo test --release sha256_large -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_sha256_large_sparse_file() {
+        const ZEROS_2G: &str = "a7c744c13cc101ed66c29f672f92455547889cc586ce6d44fe76ae824958ea51";
+        let file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(2 << 30).unwrap();
+
+        let start = std::time::Instant::now();
+        let sequential = sha256_sequential(file.reopen().unwrap(), DEFAULT_SHA256_BUFFER_SIZE);
+        assert_eq!(sequential.unwrap(), ZEROS_2G);
+        let sequential = start.elapsed();
+        let start = std::time::Instant::now();
+        assert_eq!(sha256(file.path()).unwrap(), ZEROS_2G);
+        let pipelined = start.elapsed();
+        println!("sha256 of 2 GiB: sequential {sequential:?}, pipelined {pipelined:?}");
+    }
+
+    /// Hashes a sparse 256 MiB file of zeros with the former 8 KiB buffer and the default one,
+    /// printing the throughput of each. Run with
+    /// `cargo test --release sha256_buffer -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn test_sha256_buffer_throughput() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        file.as_file().set_len(256 << 20).unwrap();
+        let expected = sha256_sequential(file.reopen().unwrap(), DEFAULT_SHA256_BUFFER_SIZE);
+        for buffer_size in [8 * 1024, DEFAULT_SHA256_BUFFER_SIZE] {
+            let start = std::time::Instant::now();
+            let sha256 = sha256_sequential(file.reopen().unwrap(), buffer_size).unwrap();
+            let elapsed = start.elapsed();
+            assert_eq!(&sha256, expected.as_ref().unwrap());
+            let throughput = 256.0 / elapsed.as_secs_f64();
+            println!("buffer {buffer_size}: {elapsed:?}, {throughput:.0} MiB/s");
+        }
+    }
 }